@@ -0,0 +1,6 @@
+fn main() {
+    // Only the gRPC server uses the generated types
+    #[cfg(feature = "server")]
+    tonic_build::compile_protos("proto/simulator.proto")
+        .expect("proto/simulator.proto must compile");
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::abi::{Abi, Event};
+use ethers::utils::hex;
+use eyre::Result;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use warp::hyper::StatusCode;
+use warp::reply::Json;
+use warp::{Rejection, Reply};
+
+use crate::errors::{AbiNotFoundError, AbiStoreError, InvalidAbiError};
+
+use super::config::Config;
+
+/// Largest body of `POST /abis`.
+pub const MAX_ABI_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbiRegistration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// JSON ABI, only its events are kept.
+    pub abi: Abi,
+}
+
+/// Events registered with `POST /abis`, decoding the logs of the simulations of the API key which
+/// registered them on top of those identified from Etherscan.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RegisteredAbi {
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Unix timestamp in seconds.
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    pub events: Vec<Event>,
+}
+
+/// Where registered ABIs are persisted, by owner. Implementations block, they are expected to be
+/// fast.
+pub trait AbiStore: Send + Sync {
+    fn insert(&self, owner: &str, abi: &RegisteredAbi) -> Result<()>;
+
+    /// The ABIs of `owner`, oldest first.
+    fn list(&self, owner: &str) -> Result<Vec<RegisteredAbi>>;
+
+    /// Whether `owner` had an ABI `id`.
+    fn delete(&self, owner: &str, id: Uuid) -> Result<bool>;
+}
+
+#[derive(Default)]
+pub struct MemoryAbiStore {
+    abis: Mutex<HashMap<String, Vec<RegisteredAbi>>>,
+}
+
+impl AbiStore for MemoryAbiStore {
+    fn insert(&self, owner: &str, abi: &RegisteredAbi) -> Result<()> {
+        self.abis
+            .lock()
+            .unwrap()
+            .entry(owner.to_string())
+            .or_default()
+            .push(abi.clone());
+        Ok(())
+    }
+
+    fn list(&self, owner: &str) -> Result<Vec<RegisteredAbi>> {
+        Ok(self
+            .abis
+            .lock()
+            .unwrap()
+            .get(owner)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn delete(&self, owner: &str, id: Uuid) -> Result<bool> {
+        let mut abis = self.abis.lock().unwrap();
+        let Some(owned) = abis.get_mut(owner) else {
+            return Ok(false);
+        };
+        let count = owned.len();
+        owned.retain(|abi| abi.id != id);
+        Ok(owned.len() < count)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct SqliteAbiStore {
+    connection: Mutex<Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteAbiStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS abis (
+                id TEXT PRIMARY KEY,
+                owner TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                abi TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS abis_owner ON abis (owner, created_at);",
+        )?;
+
+        Ok(SqliteAbiStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl AbiStore for SqliteAbiStore {
+    fn insert(&self, owner: &str, abi: &RegisteredAbi) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO abis (id, owner, created_at, abi) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                abi.id.to_string(),
+                owner,
+                abi.created_at,
+                serde_json::to_string(abi)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn list(&self, owner: &str) -> Result<Vec<RegisteredAbi>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT abi FROM abis WHERE owner = ?1 ORDER BY created_at, id")?;
+        let rows = statement.query_map(params![owner], |row| row.get::<_, String>(0))?;
+
+        rows.map(|row| Ok(serde_json::from_str(&row?)?)).collect()
+    }
+
+    fn delete(&self, owner: &str, id: Uuid) -> Result<bool> {
+        let deleted = self.connection.lock().unwrap().execute(
+            "DELETE FROM abis WHERE owner = ?1 AND id = ?2",
+            params![owner, id.to_string()],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+/// ABIs registered per API key, in the `SIMULATION_DB` database if set and in memory otherwise.
+/// Keys are stored hashed, and requests without a key share the same ABIs.
+#[derive(Clone)]
+pub struct AbiRegistry {
+    store: Arc<dyn AbiStore>,
+}
+
+impl AbiRegistry {
+    pub fn new(store: Arc<dyn AbiStore>) -> Self {
+        AbiRegistry { store }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        match &config.simulation_db {
+            #[cfg(feature = "sqlite")]
+            Some(path) => Self::new(Arc::new(
+                SqliteAbiStore::open(path).expect("SIMULATION_DB must be a valid SQLite database."),
+            )),
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => panic!("SIMULATION_DB requires the sqlite feature."),
+            None => Self::new(Arc::new(MemoryAbiStore::default())),
+        }
+    }
+
+    /// The events registered by `api_key`, to decode its simulations with. Failing to read them
+    /// is logged rather than failing the request.
+    pub fn events(&self, api_key: Option<&str>) -> Vec<Event> {
+        match self.store.list(&owner(api_key)) {
+            Ok(abis) => abis.into_iter().flat_map(|abi| abi.events).collect(),
+            Err(err) => {
+                log::warn!(target: "ts::abis", "Failed to read the registered ABIs: {err}");
+                vec![]
+            }
+        }
+    }
+}
+
+fn owner(api_key: Option<&str>) -> String {
+    match api_key {
+        Some(api_key) => hex::encode(Sha256::digest(api_key.as_bytes())),
+        None => String::new(),
+    }
+}
+
+pub async fn register_abi(
+    registration: AbiRegistration,
+    api_key: Option<String>,
+    abis: AbiRegistry,
+) -> Result<Json, Rejection> {
+    let events: Vec<Event> = registration.abi.events().cloned().collect();
+    if events.is_empty() {
+        return Err(InvalidAbiError("the ABI has no events".to_string()).into());
+    }
+
+    let abi = RegisteredAbi {
+        id: Uuid::new_v4(),
+        name: registration.name,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        events,
+    };
+    abis.store
+        .insert(&owner(api_key.as_deref()), &abi)
+        .map_err(AbiStoreError)?;
+
+    Ok(warp::reply::json(&abi))
+}
+
+pub async fn list_abis(api_key: Option<String>, abis: AbiRegistry) -> Result<Json, Rejection> {
+    let abis = abis
+        .store
+        .list(&owner(api_key.as_deref()))
+        .map_err(AbiStoreError)?;
+
+    Ok(warp::reply::json(&abis))
+}
+
+pub async fn delete_abi(
+    id: Uuid,
+    api_key: Option<String>,
+    abis: AbiRegistry,
+) -> Result<impl Reply, Rejection> {
+    let deleted = abis
+        .store
+        .delete(&owner(api_key.as_deref()), id)
+        .map_err(AbiStoreError)?;
+    if !deleted {
+        return Err(AbiNotFoundError.into());
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,85 @@
+use ethers::types::transaction::eip2930::AccessList;
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::ExecutionRevertedError;
+use crate::evm::CallOptions;
+use crate::simulation::{
+    apply_state_overrides, call_raw_request, chain_id_to_fork_url, check_gas_limit, with_timeout,
+    SimulationRequest,
+};
+
+use super::config::Config;
+use super::pool::EvmPool;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccessListResponse {
+    #[serde(rename = "accessList")]
+    pub access_list: AccessList,
+    /// Gas used without any access list.
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    #[serde(rename = "gasUsedWithAccessList")]
+    pub gas_used_with_access_list: u64,
+    /// `gasUsed - gasUsedWithAccessList`, negative if the access list costs more than it saves.
+    #[serde(rename = "gasSaved")]
+    pub gas_saved: i64,
+    #[serde(rename = "blockNumber")]
+    pub block_number: u64,
+}
+
+/// Generates an EIP-2930 access list like `eth_createAccessList`, from every account and storage
+/// slot the transaction loads.
+pub async fn create_access_list(
+    transaction: SimulationRequest,
+    config: Config,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let mut request = call_raw_request(&transaction)?;
+    request.access_list = None;
+    if let Some(state_overrides) = transaction.state_overrides {
+        apply_state_overrides(&mut evm, state_overrides)?;
+    }
+    if let Some(block_overrides) = &transaction.block_overrides {
+        evm.override_block(block_overrides);
+    }
+
+    let options = CallOptions {
+        access_list: true,
+        ..Default::default()
+    };
+    check_gas_limit(&evm, request.gas_limit)?;
+
+    let timeout = evm.timeout();
+    let (result, access_list, with_access_list) = with_timeout(timeout, async {
+        let result = evm.call_raw(&request, options).await?;
+        if !result.success {
+            return Err(ExecutionRevertedError(result.revert_reason).into());
+        }
+        let access_list = result.access_list.clone().unwrap_or_default();
+
+        request.access_list = Some(access_list.clone());
+        let with_access_list = evm.call_raw(&request, CallOptions::default()).await?;
+        Ok::<_, Rejection>((result, access_list, with_access_list))
+    })
+    .await?;
+
+    Ok(warp::reply::json(&AccessListResponse {
+        access_list,
+        gas_used: result.gas_used,
+        gas_used_with_access_list: with_access_list.gas_used,
+        gas_saved: result.gas_used as i64 - with_access_list.gas_used as i64,
+        block_number: result.block_number,
+    }))
+}
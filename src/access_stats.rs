@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+
+use ethers::abi::{Address, Uint};
+use foundry_evm::debug::{DebugArena, Instruction};
+use foundry_evm::CallKind;
+use revm::{Env, TransactTo};
+use serde::{Deserialize, Serialize};
+
+use crate::evm::{is_precompile, uint_to_hash};
+
+/// EIP-2929 costs, the cold ones including the warm read.
+const COLD_ACCOUNT_ACCESS_COST: u64 = 2600;
+const COLD_SLOAD_COST: u64 = 2100;
+const WARM_STORAGE_READ_COST: u64 = 100;
+
+const BALANCE: u8 = 0x31;
+const EXTCODESIZE: u8 = 0x3b;
+const EXTCODECOPY: u8 = 0x3c;
+const EXTCODEHASH: u8 = 0x3f;
+const SLOAD: u8 = 0x54;
+const SSTORE: u8 = 0x55;
+const CALL: u8 = 0xf1;
+const CALLCODE: u8 = 0xf2;
+const DELEGATECALL: u8 = 0xf4;
+const STATICCALL: u8 = 0xfa;
+const SELFDESTRUCT: u8 = 0xff;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AccessCounts {
+    pub cold: u64,
+    pub warm: u64,
+    /// Gas the cold accesses were charged for accessing, 2600 per account and 2100 per slot.
+    #[serde(rename = "coldGas")]
+    pub cold_gas: u64,
+    /// Gas the warm accesses were charged for accessing, 100 each, nothing for warm `SSTORE`s
+    /// and `SELFDESTRUCT`s which only pay a surcharge when cold.
+    #[serde(rename = "warmGas")]
+    pub warm_gas: u64,
+}
+
+impl AccessCounts {
+    fn record(&mut self, cold: bool, cold_gas: u64, warm_gas: u64) {
+        if cold {
+            self.cold += 1;
+            self.cold_gas += cold_gas;
+        } else {
+            self.warm += 1;
+            self.warm_gas += warm_gas;
+        }
+    }
+}
+
+/// Account and storage accesses of a transaction by whether they were cold or warm, as defined
+/// by EIP-2929.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AccessStats {
+    /// `BALANCE`, `EXTCODESIZE`, `EXTCODECOPY`, `EXTCODEHASH`, calls and `SELFDESTRUCT`.
+    pub accounts: AccessCounts,
+    /// `SLOAD` and `SSTORE`.
+    pub storage: AccessCounts,
+}
+
+/// Replays the opcodes of the debugger's call frames in execution order, starting with the
+/// accounts and slots warm at the start of the transaction: the sender, the recipient, the
+/// coinbase, the precompiles and the access list.
+pub(crate) fn access_stats(debug: &DebugArena, env: &Env) -> AccessStats {
+    let mut accounts: HashSet<Address> = HashSet::from([env.tx.caller, env.block.coinbase]);
+    if let TransactTo::Call(to) = env.tx.transact_to {
+        accounts.insert(to);
+    }
+    let mut slots = HashSet::new();
+    for (address, keys) in &env.tx.access_list {
+        accounts.insert(*address);
+        slots.extend(keys.iter().map(|slot| (*address, *slot)));
+    }
+
+    let mut stats = AccessStats::default();
+    if let Some(root) = debug.arena.first() {
+        // The deployed contract for deployments
+        accounts.insert(root.address);
+        count_accesses(
+            debug,
+            0,
+            root.address,
+            &mut accounts,
+            &mut slots,
+            &mut stats,
+        );
+    }
+    stats
+}
+
+fn count_accesses(
+    debug: &DebugArena,
+    idx: usize,
+    address: Address,
+    accounts: &mut HashSet<Address>,
+    slots: &mut HashSet<(Address, Uint)>,
+    stats: &mut AccessStats,
+) {
+    let node = &debug.arena[idx];
+    for (i, step) in node.steps.iter().enumerate() {
+        if let Instruction::OpCode(opcode) = step.instruction {
+            let stack = &step.stack;
+            match opcode {
+                BALANCE | EXTCODESIZE | EXTCODECOPY | EXTCODEHASH | SELFDESTRUCT => {
+                    if let Some(target) = stack.last() {
+                        let target = to_address(*target);
+                        let cold = !is_precompile(&target) && accounts.insert(target);
+                        let warm_gas = if opcode == SELFDESTRUCT {
+                            0
+                        } else {
+                            WARM_STORAGE_READ_COST
+                        };
+                        stats
+                            .accounts
+                            .record(cold, COLD_ACCOUNT_ACCESS_COST, warm_gas);
+                    }
+                }
+                // The address is below the gas
+                CALL | CALLCODE | DELEGATECALL | STATICCALL => {
+                    if let Some(target) = stack.iter().rev().nth(1) {
+                        let target = to_address(*target);
+                        let cold = !is_precompile(&target) && accounts.insert(target);
+                        stats.accounts.record(
+                            cold,
+                            COLD_ACCOUNT_ACCESS_COST,
+                            WARM_STORAGE_READ_COST,
+                        );
+                    }
+                }
+                SLOAD | SSTORE => {
+                    if let Some(slot) = stack.last() {
+                        let cold = slots.insert((address, *slot));
+                        let warm_gas = if opcode == SLOAD {
+                            WARM_STORAGE_READ_COST
+                        } else {
+                            0
+                        };
+                        stats.storage.record(cold, COLD_SLOAD_COST, warm_gas);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Like `push_storage_accesses`, calls made by this opcode start right after it
+        for child in &node.children {
+            let child_node = &debug.arena[*child];
+            if child_node.location == i + 1 {
+                let storage_address = match child_node.kind {
+                    CallKind::DelegateCall | CallKind::CallCode => address,
+                    CallKind::Create | CallKind::Create2 => {
+                        accounts.insert(child_node.address);
+                        child_node.address
+                    }
+                    _ => child_node.address,
+                };
+                count_accesses(debug, *child, storage_address, accounts, slots, stats);
+            }
+        }
+    }
+}
+
+fn to_address(value: Uint) -> Address {
+    Address::from_slice(&uint_to_hash(value).as_bytes()[12..])
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use warp::{Filter, Rejection};
+
+use crate::errors::QueueFullError;
+use crate::metrics::{record_queue_depth, record_queue_rejection};
+
+/// Seconds clients are told to wait before retrying when the queue is full.
+const RETRY_AFTER: u64 = 1;
+
+/// Bounds the requests in flight to `MAX_CONCURRENCY` running and `MAX_QUEUE_SIZE` waiting for
+/// them, so that bursts are turned away upfront rather than timing out in the queue.
+#[derive(Clone)]
+pub struct AdmissionQueue {
+    admitted: Arc<AtomicUsize>,
+    max_concurrency: usize,
+    max_queue_size: usize,
+}
+
+/// Counts towards the queue until dropped.
+pub struct Admitted(AdmissionQueue);
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        let admitted = self.0.admitted.fetch_sub(1, Ordering::AcqRel) - 1;
+        record_queue_depth(admitted.saturating_sub(self.0.max_concurrency));
+    }
+}
+
+impl AdmissionQueue {
+    /// A `max_queue_size` of `0` admits every request.
+    pub fn new(max_concurrency: usize, max_queue_size: usize) -> Self {
+        AdmissionQueue {
+            admitted: Arc::new(AtomicUsize::new(0)),
+            max_concurrency: max_concurrency.max(1),
+            max_queue_size,
+        }
+    }
+
+    /// Admits a request, or returns how many seconds to wait before retrying if the queue is
+    /// full.
+    pub fn admit(&self) -> Result<Admitted, u64> {
+        let capacity = self.max_concurrency + self.max_queue_size;
+        let admitted =
+            self.admitted
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |admitted| {
+                    (self.max_queue_size == 0 || admitted < capacity).then_some(admitted + 1)
+                });
+        match admitted {
+            Ok(admitted) => {
+                record_queue_depth((admitted + 1).saturating_sub(self.max_concurrency));
+                Ok(Admitted(self.clone()))
+            }
+            Err(_) => {
+                record_queue_rejection();
+                Err(RETRY_AFTER)
+            }
+        }
+    }
+}
+
+/// Rejects requests with a `503` once the queue is full, the others hold their place until their
+/// reply is ready.
+pub fn with_admission(
+    queue: AdmissionQueue,
+) -> impl Filter<Extract = (Admitted,), Error = Rejection> + Clone {
+    warp::any().and_then(move || {
+        let admitted = queue
+            .admit()
+            .map_err(|retry_after| warp::reject::custom(QueueFullError { retry_after }));
+        async move { admitted }
+    })
+}
@@ -0,0 +1,182 @@
+use ethers::abi::{encode, Address, Hash, Token, Uint};
+use ethers::utils::{id, keccak256};
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall};
+use foundry_evm::CallKind;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{EvmError, SimulationError};
+use crate::evm::{CallOptions, CallRawRequest, Evm};
+use crate::simulation::{apply_state_overrides, call_raw_request, SimulationRequest};
+
+/// Selector of `transferFrom(address,address,uint256)`.
+const TRANSFER_FROM: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd];
+
+/// Approvals injected for a single transaction, each one needing the transaction to run again.
+const MAX_APPROVALS: usize = 8;
+
+/// Storage slots searched for the allowances mapping of a token.
+const MAX_ALLOWANCE_SLOT: u64 = 100;
+
+/// Gas limit of the `allowance` calls.
+const ALLOWANCE_GAS_LIMIT: u64 = 100_000;
+
+/// An ERC-20 allowance the transaction was simulated with, set in the token's storage since the
+/// owner hadn't approved the spender.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AssumedApproval {
+    pub token: Address,
+    pub owner: Address,
+    pub spender: Address,
+    /// Allowance set, the amount of the `transferFrom` which failed without it.
+    pub amount: Uint,
+    /// Storage slot of the allowance in the token.
+    pub slot: Hash,
+}
+
+/// A failed `transferFrom` whose spender's allowance doesn't cover the amount.
+struct MissingAllowance {
+    token: Address,
+    owner: Address,
+    spender: Address,
+    amount: Uint,
+}
+
+/// Runs the transaction, without committing it, until it stops failing on `transferFrom`s the
+/// owner didn't approve, setting the allowance each one is missing. Applies the state and block
+/// overrides of the transaction, which the simulation applies again. At most one approval is
+/// assumed per token, owner and spender.
+pub(crate) async fn assume_approvals(
+    evm: &mut Evm,
+    transaction: &SimulationRequest,
+) -> Result<Vec<AssumedApproval>, SimulationError> {
+    if let Some(state_overrides) = transaction.state_overrides.clone() {
+        apply_state_overrides(evm, state_overrides)?;
+    }
+    if let Some(block_overrides) = &transaction.block_overrides {
+        evm.override_block(block_overrides);
+    }
+    let request = call_raw_request(transaction)?;
+
+    let mut approvals: Vec<AssumedApproval> = vec![];
+    while approvals.len() < MAX_APPROVALS {
+        let result = evm.call_raw(&request, CallOptions::default()).await?;
+        if result.success {
+            break;
+        }
+        let Some(missing) = missing_allowance(evm, result.trace.as_ref()).await? else {
+            break;
+        };
+        let approved = approvals.iter().any(|approval| {
+            (approval.token, approval.owner, approval.spender)
+                == (missing.token, missing.owner, missing.spender)
+        });
+        if approved {
+            break;
+        }
+        match approve(evm, &missing).await? {
+            Some(approval) => approvals.push(approval),
+            None => break,
+        }
+    }
+
+    Ok(approvals)
+}
+
+/// The first failed `transferFrom` of the trace whose allowance is too low, if any.
+async fn missing_allowance(
+    evm: &mut Evm,
+    trace: Option<&CallTraceArena>,
+) -> Result<Option<MissingAllowance>, EvmError> {
+    for node in trace.map(|trace| &trace.arena[..]).unwrap_or_default() {
+        let trace = &node.trace;
+        let RawOrDecodedCall::Raw(input) = &trace.data else {
+            continue;
+        };
+        if trace.success
+            || trace.kind != CallKind::Call
+            || input.len() < 100
+            || input[..4] != TRANSFER_FROM
+        {
+            continue;
+        }
+        let missing = MissingAllowance {
+            token: trace.address,
+            owner: Address::from_slice(&input[16..36]),
+            spender: trace.caller,
+            amount: Uint::from_big_endian(&input[68..100]),
+        };
+        match allowance(evm, missing.token, missing.owner, missing.spender).await? {
+            Some(allowance) if allowance < missing.amount => return Ok(Some(missing)),
+            _ => {}
+        }
+    }
+
+    Ok(None)
+}
+
+async fn allowance(
+    evm: &mut Evm,
+    token: Address,
+    owner: Address,
+    spender: Address,
+) -> Result<Option<Uint>, EvmError> {
+    let mut data = id("allowance(address,address)").to_vec();
+    data.extend(encode(&[Token::Address(owner), Token::Address(spender)]));
+    let request = CallRawRequest {
+        to: Some(token),
+        data: Some(data.into()),
+        gas_limit: ALLOWANCE_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&request, CallOptions::default()).await?;
+
+    Ok(match result.output.get(..32) {
+        Some(output) if result.success => Some(Uint::from_big_endian(output)),
+        _ => None,
+    })
+}
+
+/// Sets the allowance like `deal` sets balances: the allowances mapping is found by writing a
+/// marker to the slot the allowance would have for each candidate mapping slot, with both
+/// Solidity and Vyper layouts, until `allowance` returns it.
+async fn approve(
+    evm: &mut Evm,
+    missing: &MissingAllowance,
+) -> Result<Option<AssumedApproval>, EvmError> {
+    let marker = Uint::from(keccak256("ts::approve"));
+    let owner = Token::Address(missing.owner);
+    let spender = Token::Address(missing.spender);
+
+    for mapping_slot in 0..MAX_ALLOWANCE_SLOT {
+        let mapping_slot = Token::Uint(mapping_slot.into());
+        let solidity = keccak256(encode(&[owner.clone(), mapping_slot.clone()]));
+        let vyper = keccak256(encode(&[mapping_slot, owner.clone()]));
+        let layouts = [
+            encode(&[spender.clone(), Token::FixedBytes(solidity.to_vec())]),
+            encode(&[Token::FixedBytes(vyper.to_vec()), spender.clone()]),
+        ];
+
+        for layout in layouts {
+            let slot = Uint::from(keccak256(layout));
+            let original = evm.storage(missing.token, slot)?;
+            evm.set_storage(missing.token, slot, marker)?;
+
+            let allowance = allowance(evm, missing.token, missing.owner, missing.spender).await?;
+            if allowance == Some(marker) {
+                evm.set_storage(missing.token, slot, missing.amount)?;
+                let mut bytes = [0u8; 32];
+                slot.to_big_endian(&mut bytes);
+                return Ok(Some(AssumedApproval {
+                    token: missing.token,
+                    owner: missing.owner,
+                    spender: missing.spender,
+                    amount: missing.amount,
+                    slot: Hash::from(bytes),
+                }));
+            }
+            evm.set_storage(missing.token, slot, original)?;
+        }
+    }
+
+    Ok(None)
+}
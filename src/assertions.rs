@@ -0,0 +1,181 @@
+use ethers::abi::{encode, Address, Token, Uint};
+use ethers::utils::id;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::EvmError;
+use crate::evm::{CallOptions, CallRawRequest, Evm};
+use crate::quantity;
+use crate::simulation::SimulationResponse;
+
+/// Gas limit of the `balanceOf` calls.
+const BALANCE_OF_GAS_LIMIT: u64 = 100_000;
+
+/// Inclusive bounds a value must be within.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Bounds {
+    #[serde(
+        default,
+        deserialize_with = "quantity::deserialize_option_uint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min: Option<Uint>,
+    #[serde(
+        default,
+        deserialize_with = "quantity::deserialize_option_uint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max: Option<Uint>,
+    #[serde(
+        default,
+        deserialize_with = "quantity::deserialize_option_uint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub equals: Option<Uint>,
+}
+
+impl Bounds {
+    fn contains(&self, value: Uint) -> bool {
+        self.min.map_or(true, |min| value >= min)
+            && self.max.map_or(true, |max| value <= max)
+            && self.equals.map_or(true, |equals| value == equals)
+    }
+}
+
+/// What a transaction of a bundle is expected to do, checked once it executed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Assertion {
+    /// The transaction succeeded, or reverted if `expected` is `false`.
+    Success {
+        #[serde(default = "default_expected")]
+        expected: bool,
+    },
+    /// The 32 byte word `word` of the return data, read as a `uint256`.
+    ReturnValue {
+        #[serde(default)]
+        word: usize,
+        #[serde(flatten)]
+        bounds: Bounds,
+    },
+    /// The balance of `address` after the transaction, of the ERC-20 `token` if set, in ether
+    /// otherwise.
+    Balance {
+        address: Address,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<Address>,
+        #[serde(flatten)]
+        bounds: Bounds,
+    },
+    GasUsed {
+        #[serde(flatten)]
+        bounds: Bounds,
+    },
+}
+
+fn default_expected() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AssertionResult {
+    #[serde(flatten)]
+    pub assertion: Assertion,
+    pub passed: bool,
+    /// Value checked against the bounds, not set for `success`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<Uint>,
+    /// Why the value could not be read, the assertion then fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AssertionResult {
+    fn failed(assertion: &Assertion, error: String) -> Self {
+        AssertionResult {
+            assertion: assertion.clone(),
+            passed: false,
+            actual: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Checks the assertions of a transaction against its result and the state it left.
+pub(crate) async fn evaluate_assertions(
+    evm: &mut Evm,
+    assertions: &[Assertion],
+    response: &SimulationResponse,
+) -> Vec<AssertionResult> {
+    let mut results = Vec::with_capacity(assertions.len());
+    for assertion in assertions {
+        let (bounds, actual) = match assertion {
+            Assertion::Success { expected } => {
+                results.push(AssertionResult {
+                    assertion: assertion.clone(),
+                    passed: response.success == *expected,
+                    actual: None,
+                    error: None,
+                });
+                continue;
+            }
+            Assertion::ReturnValue { word, bounds } => {
+                let value = word
+                    .checked_mul(32)
+                    .and_then(|start| response.return_data.get(start..start.checked_add(32)?))
+                    .map(Uint::from_big_endian)
+                    .ok_or_else(|| format!("no return data word {word}"));
+                (bounds, value)
+            }
+            Assertion::Balance {
+                address,
+                token,
+                bounds,
+            } => {
+                let balance = match token {
+                    Some(token) => balance_of(evm, *token, *address).await,
+                    None => evm.basic(*address).map(|info| Ok(info.balance)),
+                };
+                let balance = match balance {
+                    Ok(balance) => balance,
+                    Err(err) => Err(err.0.to_string()),
+                };
+                (bounds, balance)
+            }
+            Assertion::GasUsed { bounds } => (bounds, Ok(Uint::from(response.gas_used))),
+        };
+
+        results.push(match actual {
+            Ok(actual) => AssertionResult {
+                assertion: assertion.clone(),
+                passed: bounds.contains(actual),
+                actual: Some(actual),
+                error: None,
+            },
+            Err(error) => AssertionResult::failed(assertion, error),
+        });
+    }
+
+    results
+}
+
+/// The ERC-20 balance of `holder`, or why it could not be read.
+async fn balance_of(
+    evm: &mut Evm,
+    token: Address,
+    holder: Address,
+) -> Result<Result<Uint, String>, EvmError> {
+    let mut data = id("balanceOf(address)").to_vec();
+    data.extend(encode(&[Token::Address(holder)]));
+    let request = CallRawRequest {
+        to: Some(token),
+        data: Some(data.into()),
+        gas_limit: BALANCE_OF_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&request, CallOptions::default()).await?;
+
+    Ok(match result.output.get(..32) {
+        Some(output) if result.success => Ok(Uint::from_big_endian(output)),
+        _ => Err(format!("balanceOf failed on {token:?}")),
+    })
+}
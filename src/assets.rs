@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use ethers::abi::{decode, Address, Hash, ParamType, Token, Uint};
+use ethers::types::Log;
+use ethers::utils::{id, keccak256};
+use foundry_evm::trace::CallTraceArena;
+use foundry_evm::CallKind;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::EvmError;
+use crate::evm::{CallOptions, CallRawRequest, Evm};
+
+/// Token metadata resolved so far, keyed by `(chain_id, token)`.
+static TOKEN_INFO: Lazy<Mutex<LruCache<(u64, Address), TokenInfo>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(10_000).unwrap())));
+
+/// Gas limit of the calls resolving token metadata.
+const TOKEN_INFO_GAS_LIMIT: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetType {
+    Native,
+    Erc20,
+    Erc721,
+    Erc1155,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AssetChange {
+    pub address: Address,
+    #[serde(rename = "assetType")]
+    pub asset_type: AssetType,
+    /// Token contract, not set for the native asset.
+    pub token: Option<Address>,
+    #[serde(rename = "tokenId")]
+    pub token_id: Option<Uint>,
+    pub sent: Uint,
+    pub received: Uint,
+    /// Metadata of the token contract, not set for the native asset.
+    #[serde(rename = "tokenInfo", default, skip_serializing_if = "Option::is_none")]
+    pub token_info: Option<TokenInfo>,
+    /// USD price of one whole token, only with `prices` and if the asset could be priced.
+    #[serde(rename = "priceUsd", default, skip_serializing_if = "Option::is_none")]
+    pub price_usd: Option<f64>,
+    /// USD value of `received` minus `sent`, negative if the address lost value.
+    #[serde(rename = "valueUsd", default, skip_serializing_if = "Option::is_none")]
+    pub value_usd: Option<f64>,
+}
+
+/// Native value moved by a call frame below the top level call.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InternalTransfer {
+    pub from: Address,
+    pub to: Address,
+    pub value: Uint,
+    /// Depth of the frame, the top level call being 0.
+    pub depth: usize,
+    #[serde(rename = "callType")]
+    pub call_type: CallKind,
+}
+
+/// Each field is only set if the token implements it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+type AssetKey = (Address, AssetType, Option<Address>, Option<Uint>);
+
+type FlowKey = (Address, Address, AssetType, Option<Address>, Option<Uint>);
+
+/// An asset moved from one address to another, summed over the transaction. The zero address is
+/// the source of mints and the sink of burns.
+pub(crate) struct Transfer {
+    pub from: Address,
+    pub to: Address,
+    pub asset_type: AssetType,
+    pub token: Option<Address>,
+    pub token_id: Option<Uint>,
+    pub amount: Uint,
+}
+
+#[derive(Default)]
+struct AssetChanges {
+    /// Sent and received per holder and asset.
+    changes: BTreeMap<AssetKey, (Uint, Uint)>,
+    /// Moved per sender, recipient and asset.
+    flows: BTreeMap<FlowKey, Uint>,
+}
+
+impl AssetChanges {
+    fn transfer(
+        &mut self,
+        asset_type: AssetType,
+        token: Option<Address>,
+        token_id: Option<Uint>,
+        from: Address,
+        to: Address,
+        amount: Uint,
+    ) {
+        if amount.is_zero() || from == to {
+            return;
+        }
+        let moved = self
+            .flows
+            .entry((from, to, asset_type, token, token_id))
+            .or_default();
+        *moved = moved.saturating_add(amount);
+        // The zero address is the source of mints and the sink of burns, not a holder.
+        if !from.is_zero() {
+            let (sent, _) = self
+                .changes
+                .entry((from, asset_type, token, token_id))
+                .or_default();
+            *sent = sent.saturating_add(amount);
+        }
+        if !to.is_zero() {
+            let (_, received) = self
+                .changes
+                .entry((to, asset_type, token, token_id))
+                .or_default();
+            *received = received.saturating_add(amount);
+        }
+    }
+
+    fn native_transfers(&mut self, arena: &CallTraceArena, idx: usize) {
+        let node = &arena.arena[idx];
+        // Nothing a failed frame or its children did persists.
+        if !node.trace.success {
+            return;
+        }
+        if matches!(
+            node.trace.kind,
+            CallKind::Call | CallKind::Create | CallKind::Create2
+        ) {
+            self.transfer(
+                AssetType::Native,
+                None,
+                None,
+                node.trace.caller,
+                node.trace.address,
+                node.trace.value,
+            );
+        }
+        for child in &node.children {
+            self.native_transfers(arena, *child);
+        }
+    }
+
+    fn token_transfers(&mut self, log: &Log) {
+        let Some(signature) = log.topics.first() else {
+            return;
+        };
+        let token = Some(log.address);
+
+        if *signature == event_signature("Transfer(address,address,uint256)") {
+            match log.topics.len() {
+                3 if log.data.len() >= 32 => self.transfer(
+                    AssetType::Erc20,
+                    token,
+                    None,
+                    topic_address(&log.topics[1]),
+                    topic_address(&log.topics[2]),
+                    Uint::from_big_endian(&log.data[..32]),
+                ),
+                4 => self.transfer(
+                    AssetType::Erc721,
+                    token,
+                    Some(Uint::from_big_endian(log.topics[3].as_bytes())),
+                    topic_address(&log.topics[1]),
+                    topic_address(&log.topics[2]),
+                    Uint::one(),
+                ),
+                _ => {}
+            }
+        } else if *signature
+            == event_signature("TransferSingle(address,address,address,uint256,uint256)")
+        {
+            if log.topics.len() == 4 && log.data.len() >= 64 {
+                self.transfer(
+                    AssetType::Erc1155,
+                    token,
+                    Some(Uint::from_big_endian(&log.data[..32])),
+                    topic_address(&log.topics[2]),
+                    topic_address(&log.topics[3]),
+                    Uint::from_big_endian(&log.data[32..64]),
+                );
+            }
+        } else if *signature
+            == event_signature("TransferBatch(address,address,address,uint256[],uint256[])")
+        {
+            if log.topics.len() != 4 {
+                return;
+            }
+            let uint_array = ParamType::Array(Box::new(ParamType::Uint(256)));
+            let Ok(tokens) = decode(&[uint_array.clone(), uint_array], &log.data) else {
+                return;
+            };
+            let (Some(ids), Some(values)) = (
+                tokens[0].clone().into_array(),
+                tokens[1].clone().into_array(),
+            ) else {
+                return;
+            };
+            for (id, value) in ids.into_iter().zip(values) {
+                if let (Some(id), Some(value)) = (id.into_uint(), value.into_uint()) {
+                    self.transfer(
+                        AssetType::Erc1155,
+                        token,
+                        Some(id),
+                        topic_address(&log.topics[2]),
+                        topic_address(&log.topics[3]),
+                        value,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn event_signature(signature: &str) -> Hash {
+    Hash::from(keccak256(signature))
+}
+
+fn topic_address(topic: &Hash) -> Address {
+    Address::from_slice(&topic.as_bytes()[12..])
+}
+
+fn collect_asset_changes(arena: &CallTraceArena, logs: &[Log]) -> AssetChanges {
+    let mut changes = AssetChanges::default();
+
+    if !arena.arena.is_empty() {
+        changes.native_transfers(arena, 0);
+    }
+    for log in logs {
+        changes.token_transfers(log);
+    }
+    changes
+}
+
+/// Native value moved by successful call frames and ERC-20/721/1155 transfer events, summed
+/// per address and asset. Assets an address sent and received in equal amounts are omitted.
+pub fn asset_changes(arena: &CallTraceArena, logs: &[Log]) -> Vec<AssetChange> {
+    collect_asset_changes(arena, logs)
+        .changes
+        .into_iter()
+        .filter(|(_, (sent, received))| sent != received)
+        .map(
+            |((address, asset_type, token, token_id), (sent, received))| AssetChange {
+                address,
+                asset_type,
+                token,
+                token_id,
+                sent,
+                received,
+                token_info: None,
+                price_usd: None,
+                value_usd: None,
+            },
+        )
+        .collect()
+}
+
+/// The same moves as `asset_changes`, summed per sender, recipient and asset rather than per
+/// holder, ordered by sender.
+pub(crate) fn transfers(arena: &CallTraceArena, logs: &[Log]) -> Vec<Transfer> {
+    collect_asset_changes(arena, logs)
+        .flows
+        .into_iter()
+        .map(
+            |((from, to, asset_type, token, token_id), amount)| Transfer {
+                from,
+                to,
+                asset_type,
+                token,
+                token_id,
+                amount,
+            },
+        )
+        .collect()
+}
+
+fn collect_internal_transfers(
+    arena: &CallTraceArena,
+    idx: usize,
+    transfers: &mut Vec<InternalTransfer>,
+) {
+    let node = &arena.arena[idx];
+    if !node.trace.success {
+        return;
+    }
+    let moves_value = matches!(
+        node.trace.kind,
+        CallKind::Call | CallKind::Create | CallKind::Create2
+    );
+    if idx != 0 && moves_value && !node.trace.value.is_zero() {
+        transfers.push(InternalTransfer {
+            from: node.trace.caller,
+            to: node.trace.address,
+            value: node.trace.value,
+            depth: node.trace.depth,
+            call_type: node.trace.kind,
+        });
+    }
+    for child in &node.children {
+        collect_internal_transfers(arena, *child, transfers);
+    }
+}
+
+/// Native value moved by the successful call and create frames below the top level call, in
+/// execution order. Selfdestructs are not traced with their beneficiary, so are not included.
+pub fn internal_transfers(arena: &CallTraceArena) -> Vec<InternalTransfer> {
+    let mut transfers = vec![];
+    if !arena.arena.is_empty() {
+        collect_internal_transfers(arena, 0, &mut transfers);
+    }
+    transfers
+}
+
+/// Calls a metadata getter of `token`, returning its output if it succeeded.
+async fn call_getter(
+    evm: &mut Evm,
+    token: Address,
+    signature: &str,
+) -> Result<Option<Vec<u8>>, EvmError> {
+    let request = CallRawRequest {
+        to: Some(token),
+        data: Some(id(signature).to_vec().into()),
+        gas_limit: TOKEN_INFO_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&request, CallOptions::default()).await?;
+
+    Ok(result.success.then(|| result.output.to_vec()))
+}
+
+/// Decodes `string` names and symbols, and the `bytes32` ones of older tokens like MKR.
+fn decode_string(output: &[u8]) -> Option<String> {
+    if let Ok(mut tokens) = decode(&[ParamType::String], output) {
+        if let Some(Token::String(value)) = tokens.pop() {
+            return Some(value);
+        }
+    }
+    if output.len() == 32 {
+        let value = String::from_utf8(output.to_vec()).ok()?;
+        return Some(value.trim_end_matches('\0').to_string());
+    }
+    None
+}
+
+pub(crate) async fn token_info(evm: &mut Evm, token: Address) -> Result<TokenInfo, EvmError> {
+    let key = (evm.chain_id(), token);
+    if let Some(info) = TOKEN_INFO.lock().unwrap().get(&key) {
+        return Ok(info.clone());
+    }
+
+    let name = call_getter(evm, token, "name()").await?;
+    let symbol = call_getter(evm, token, "symbol()").await?;
+    let decimals = call_getter(evm, token, "decimals()").await?;
+    let info = TokenInfo {
+        name: name.as_deref().and_then(decode_string),
+        symbol: symbol.as_deref().and_then(decode_string),
+        decimals: decimals
+            .filter(|output| output.len() == 32)
+            .map(|output| Uint::from_big_endian(&output))
+            .filter(|decimals| *decimals <= Uint::from(u8::MAX))
+            .map(|decimals| decimals.as_u32() as u8),
+    };
+
+    TOKEN_INFO.lock().unwrap().put(key, info.clone());
+    Ok(info)
+}
+
+/// Resolves the name, symbol and decimals of the tokens in `changes` on the state of `evm`.
+/// Tokens whose metadata can't be resolved are left without.
+pub(crate) async fn resolve_token_info(evm: &mut Evm, changes: &mut [AssetChange]) {
+    for change in changes {
+        let Some(token) = change.token else {
+            continue;
+        };
+        match token_info(evm, token).await {
+            Ok(info) => change.token_info = Some(info),
+            Err(err) => {
+                log::warn!(target: "ts::assets", "Failed to resolve token {token:?}: {}", err.0)
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use warp::{Filter, Rejection};
+
+use crate::errors::{InvalidApiKeyError, MissingApiKeyError};
+
+/// Requires a known key in the `X-API-KEY` header, lets every request through if `api_keys` is
+/// empty.
+pub fn with_api_key(
+    api_keys: HashSet<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let api_keys = Arc::new(api_keys);
+
+    warp::header::optional::<String>("x-api-key")
+        .and_then(move |key: Option<String>| {
+            let api_keys = api_keys.clone();
+            async move { check_api_key(&api_keys, key) }
+        })
+        .untuple_one()
+}
+
+/// Limits of an API key of `API_KEY_POLICIES_FILE`, on top of those of the server.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiKeyPolicy {
+    /// Chains the key may simulate on, every chain of the server if empty.
+    #[serde(default)]
+    pub chains: Vec<u64>,
+    /// Lowers `MAX_GAS_LIMIT` for the key.
+    #[serde(rename = "maxGasLimit", default)]
+    pub max_gas_limit: Option<u64>,
+    /// Lowers `MAX_BUNDLE_SIZE` for the key.
+    #[serde(rename = "maxBundleSize", default)]
+    pub max_bundle_size: Option<usize>,
+    /// Whether the key may create forks with `POST /fork`.
+    #[serde(rename = "persistentForks", default = "default_persistent_forks")]
+    pub persistent_forks: bool,
+}
+
+impl Default for ApiKeyPolicy {
+    fn default() -> Self {
+        ApiKeyPolicy {
+            chains: vec![],
+            max_gas_limit: None,
+            max_bundle_size: None,
+            persistent_forks: default_persistent_forks(),
+        }
+    }
+}
+
+fn default_persistent_forks() -> bool {
+    true
+}
+
+impl ApiKeyPolicy {
+    pub fn allows_chain(&self, chain_id: u64) -> bool {
+        self.chains.is_empty() || self.chains.contains(&chain_id)
+    }
+}
+
+pub(crate) fn check_api_key(
+    api_keys: &HashSet<String>,
+    key: Option<String>,
+) -> Result<(), Rejection> {
+    if api_keys.is_empty() {
+        return Ok(());
+    }
+
+    match key {
+        None => Err(warp::reject::custom(MissingApiKeyError)),
+        Some(key) if api_keys.contains(&key) => Ok(()),
+        Some(_) => Err(warp::reject::custom(InvalidApiKeyError)),
+    }
+}
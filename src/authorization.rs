@@ -0,0 +1,152 @@
+use ethers::abi::{Address, Uint};
+use ethers::types::{Bytes, Signature, H256};
+use ethers::utils::keccak256;
+use ethers::utils::rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::SimulationError;
+use crate::evm::Evm;
+use crate::simulation::SimulationRequest;
+
+/// Prefix of the message an EIP-7702 authorization signs.
+const AUTHORIZATION_MAGIC: u8 = 0x05;
+
+/// Prefix of the code of an account delegated with EIP-7702.
+pub(crate) const DELEGATION_DESIGNATOR: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// An EIP-7702 authorization tuple, signed by the account delegating to `address`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Authorization {
+    /// 0 to be valid on any chain.
+    #[serde(rename = "chainId")]
+    pub chain_id: Uint,
+    /// Contract whose code the authority executes, the zero address clears the delegation.
+    pub address: Address,
+    pub nonce: u64,
+    #[serde(rename = "yParity")]
+    pub y_parity: Option<u64>,
+    pub r: Option<Uint>,
+    pub s: Option<Uint>,
+    /// Delegates this account without a signature, to simulate authorizations not signed yet.
+    pub authority: Option<Address>,
+}
+
+/// What became of an authorization of the transaction, in the order of `authorizationList`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Delegation {
+    /// Not set if the signature could not be recovered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authority: Option<Address>,
+    pub address: Address,
+    pub applied: bool,
+    /// Why the authorization was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Code the authority has on chain once delegated, `0xef0100` followed by `address`. The
+    /// simulation gives it the code of `address` instead, which `EXTCODESIZE`, `EXTCODEHASH` and
+    /// `EXTCODECOPY` of the authority return rather than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub designator: Option<Bytes>,
+}
+
+impl Authorization {
+    fn signature_hash(&self) -> H256 {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&self.chain_id);
+        stream.append(&self.address);
+        stream.append(&self.nonce);
+
+        let mut message = vec![AUTHORIZATION_MAGIC];
+        message.extend_from_slice(&stream.out());
+        H256::from(keccak256(message))
+    }
+
+    fn authority(&self) -> Option<Address> {
+        if self.authority.is_some() {
+            return self.authority;
+        }
+        let signature = Signature {
+            r: self.r?,
+            s: self.s?,
+            v: self.y_parity? + 27,
+        };
+        signature.recover(self.signature_hash()).ok()
+    }
+}
+
+/// Applies the valid authorizations of the transaction in order, like EIP-7702 does before
+/// executing it, and skips the others. The authority gets the code of the delegate rather than a
+/// delegation designator, which this EVM can't execute, and is recorded as delegated on `evm`.
+pub(crate) fn apply_authorizations(
+    evm: &mut Evm,
+    transaction: &SimulationRequest,
+) -> Result<Option<Vec<Delegation>>, SimulationError> {
+    let Some(authorizations) = &transaction.authorization_list else {
+        return Ok(None);
+    };
+    if transaction.to.is_none() {
+        return Err(SimulationError::InvalidAuthorizationList(
+            "transactions with authorizations cannot deploy contracts",
+        ));
+    }
+    if authorizations.is_empty() {
+        return Err(SimulationError::InvalidAuthorizationList(
+            "authorizationList cannot be empty",
+        ));
+    }
+
+    let from = transaction.from;
+    let chain_id = Uint::from(evm.chain_id());
+    let mut delegations = Vec::with_capacity(authorizations.len());
+
+    for authorization in authorizations {
+        let authority = authorization.authority();
+        let skip = |reason: &str| Delegation {
+            authority,
+            address: authorization.address,
+            applied: false,
+            reason: Some(reason.to_string()),
+            designator: None,
+        };
+
+        if !authorization.chain_id.is_zero() && authorization.chain_id != chain_id {
+            delegations.push(skip("chain ID mismatch"));
+            continue;
+        }
+        let Some(authority) = authority else {
+            delegations.push(skip("invalid signature"));
+            continue;
+        };
+        // The sender's nonce is incremented before the authorizations are processed
+        let info = evm.basic(authority)?;
+        let expected = info.nonce + u64::from(authority == from);
+        if authorization.nonce != expected {
+            delegations.push(skip("nonce mismatch"));
+            continue;
+        }
+
+        let (code, designator) = if authorization.address.is_zero() {
+            (Bytes::default(), None)
+        } else {
+            let mut designator = DELEGATION_DESIGNATOR.to_vec();
+            designator.extend_from_slice(authorization.address.as_bytes());
+            (
+                evm.account_code(authorization.address)?,
+                Some(designator.into()),
+            )
+        };
+        evm.set_code(authority, code)?;
+        evm.set_nonce(authority, info.nonce + 1)?;
+        evm.set_delegate(authority, authorization.address);
+
+        delegations.push(Delegation {
+            authority: Some(authority),
+            address: authorization.address,
+            applied: true,
+            reason: None,
+            designator,
+        });
+    }
+
+    Ok(Some(delegations))
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{error_message, BundleTooLargeError, ErrorMessage, SimulationError};
+use crate::quantity;
+use crate::simulation::{chain_id_to_fork_url, run, SimulationRequest, SimulationResponse};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// Outcome of one simulation of a batch, either its result or the error it failed with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BatchResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<SimulationResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorMessage>,
+}
+
+/// Simulates the transaction on its own fork, like `/simulate`.
+async fn simulate_one(
+    transaction: SimulationRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<SimulationResponse, SimulationError> {
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let response = run(&mut evm, transaction.clone(), false).await?;
+    history.record(&transaction, &response);
+
+    Ok(response)
+}
+
+/// Simulates unrelated transactions concurrently, each on its own fork, and answers with their
+/// results in order. A failing simulation doesn't fail the others.
+pub async fn simulate_batch(
+    transactions: Vec<SimulationRequest>,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    if transactions.len() > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+
+    let quantity_format = transactions
+        .first()
+        .and_then(|transaction| transaction.quantity_format);
+    let handles: Vec<_> = transactions
+        .into_iter()
+        .map(|transaction| {
+            tokio::spawn(simulate_one(
+                transaction,
+                config.clone(),
+                pool.clone(),
+                history.clone(),
+            ))
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        let result = match handle.await {
+            Ok(Ok(response)) => BatchResult {
+                result: Some(response),
+                error: None,
+            },
+            Ok(Err(err)) => BatchResult {
+                result: None,
+                error: Some(error_message(&err.into()).0),
+            },
+            // The simulation panicked
+            Err(err) => BatchResult {
+                result: None,
+                error: Some(error_message(&SimulationError::Evm(err.into()).into()).0),
+            },
+        };
+        results.push(result);
+    }
+
+    Ok(quantity::json(&results, quantity_format))
+}
@@ -0,0 +1,76 @@
+use ethers::abi::Uint;
+
+use crate::errors::SimulationError;
+use crate::simulation::SimulationRequest;
+
+/// Blob gas used by every blob.
+pub const GAS_PER_BLOB: u64 = 1 << 17;
+
+/// Most blobs a block, and so a transaction, can carry since Cancun.
+pub const MAX_BLOBS_PER_BLOCK: usize = 6;
+
+const MIN_BLOB_BASE_FEE: u64 = 1;
+
+const BLOB_BASE_FEE_UPDATE_FRACTION: u64 = 3_338_477;
+
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+
+/// Blob base fee of a block with `excess_blob_gas`, as defined by EIP-4844.
+pub fn blob_base_fee(excess_blob_gas: u64) -> Uint {
+    fake_exponential(
+        MIN_BLOB_BASE_FEE.into(),
+        excess_blob_gas.into(),
+        BLOB_BASE_FEE_UPDATE_FRACTION.into(),
+    )
+}
+
+/// Approximates `factor * e ** (numerator / denominator)` with its Taylor expansion. Stops once
+/// the output saturates, which a large `numerator` would otherwise take as many terms to reach as
+/// `numerator / denominator`.
+fn fake_exponential(factor: Uint, numerator: Uint, denominator: Uint) -> Uint {
+    let mut i = Uint::one();
+    let mut output = Uint::zero();
+    let mut numerator_accum = factor * denominator;
+    while !numerator_accum.is_zero() && output != Uint::MAX {
+        output = output.saturating_add(numerator_accum);
+        numerator_accum = numerator_accum.saturating_mul(numerator) / (denominator * i);
+        i += Uint::one();
+    }
+    output / denominator
+}
+
+/// Blob gas used by the transaction, if it carries blobs, after checking it is a valid blob
+/// transaction.
+pub(crate) fn blob_gas_used(
+    transaction: &SimulationRequest,
+) -> Result<Option<u64>, SimulationError> {
+    let Some(hashes) = &transaction.blob_versioned_hashes else {
+        return Ok(None);
+    };
+
+    if transaction.to.is_none() {
+        return Err(SimulationError::InvalidBlobTransaction(
+            "blob transactions cannot deploy contracts",
+        ));
+    }
+    if hashes.is_empty() {
+        return Err(SimulationError::InvalidBlobTransaction(
+            "blob transactions must carry at least one blob",
+        ));
+    }
+    if hashes.len() > MAX_BLOBS_PER_BLOCK {
+        return Err(SimulationError::InvalidBlobTransaction(
+            "blob transactions carry at most 6 blobs",
+        ));
+    }
+    if hashes
+        .iter()
+        .any(|hash| hash.as_bytes()[0] != VERSIONED_HASH_VERSION_KZG)
+    {
+        return Err(SimulationError::InvalidBlobTransaction(
+            "blob versioned hashes must start with 0x01",
+        ));
+    }
+
+    Ok(Some(GAS_PER_BLOB * hashes.len() as u64))
+}
@@ -0,0 +1,56 @@
+use ethers::abi::{Address, Hash, Uint};
+use revm::SpecId;
+use serde::{Deserialize, Serialize};
+
+use crate::evm::Evm;
+
+/// What the EVM executed a transaction with, `blockOverrides` applied, for it to be reproduced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct BlockEnvironment {
+    pub number: u64,
+    pub timestamp: u64,
+    #[serde(rename = "baseFee")]
+    pub base_fee: Uint,
+    #[serde(rename = "gasLimit")]
+    pub gas_limit: Uint,
+    pub coinbase: Address,
+    /// Not set before the merge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prevrandao: Option<Hash>,
+    #[serde(rename = "blobBaseFee")]
+    pub blob_base_fee: Uint,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    /// Rules of the EVM, e.g. `london` or `merge`, `latest` being the newest it implements.
+    pub hardfork: String,
+}
+
+pub(crate) fn block_environment(evm: &Evm) -> BlockEnvironment {
+    let block = evm.block_env();
+    BlockEnvironment {
+        number: block.number.as_u64(),
+        timestamp: block.timestamp.as_u64(),
+        base_fee: block.basefee,
+        gas_limit: block.gas_limit,
+        coinbase: block.coinbase,
+        prevrandao: block.prevrandao,
+        blob_base_fee: evm.blob_base_fee(),
+        chain_id: evm.chain_id(),
+        hardfork: hardfork_name(evm.spec_id()),
+    }
+}
+
+/// `MUIR_GLACIER` as `muirGlacier`.
+pub(crate) fn hardfork_name(spec_id: SpecId) -> String {
+    let name = format!("{spec_id:?}").to_lowercase();
+    let mut words = name.split('_');
+    let first = words.next().unwrap_or_default().to_string();
+    words.fold(first, |mut name, word| {
+        let mut chars = word.chars();
+        if let Some(initial) = chars.next() {
+            name.extend(initial.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+        name
+    })
+}
@@ -0,0 +1,734 @@
+use std::collections::BTreeMap;
+
+use ethers::abi::{Address, Uint};
+use ethers::types::I256;
+use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use warp::http::header::CONTENT_TYPE;
+use warp::http::Response;
+use warp::hyper::Body;
+use warp::reply::Json;
+use warp::{Rejection, Reply};
+
+use crate::assertions::{evaluate_assertions, AssertionResult};
+use crate::errors::{BundleTooLargeError, ErrorMessage, InvalidRequestError, SimulationError};
+use crate::evm::Evm;
+use crate::prefetch::prefetch;
+use crate::quantity::{self, QuantityFormat};
+use crate::simulation::{
+    chain_id_to_fork_url, run, AccountDiff, SimulationRequest, SimulationResponse, ValueDiff,
+};
+use crate::validation::FieldError;
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// A bundle is either a plain list of transactions, answered with a list of results, an object
+/// which is answered with the results and a summary of the bundle, or bundles on several chains.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BundleRequest {
+    Transactions(Vec<SimulationRequest>),
+    Bundle(Bundle),
+    MultiChain(MultiChainBundle),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub transactions: Vec<SimulationRequest>,
+    #[serde(rename = "bundleOptions")]
+    pub bundle_options: Option<BundleOptions>,
+    /// Where the result is POSTed once an async bundle finished.
+    #[serde(rename = "callbackUrl")]
+    pub callback_url: Option<String>,
+}
+
+/// Bundles each run on a fork of their own chain, e.g. a bridge deposit on one chain and the swap
+/// of the bridged tokens on another.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiChainBundle {
+    pub chains: Vec<ChainBundle>,
+    /// Where the result is POSTed once an async bundle finished.
+    #[serde(rename = "callbackUrl")]
+    pub callback_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainBundle {
+    /// That of every transaction of the bundle.
+    #[serde(rename = "chainId", deserialize_with = "quantity::deserialize_u64")]
+    pub chain_id: u64,
+    pub transactions: Vec<SimulationRequest>,
+    #[serde(rename = "bundleOptions")]
+    pub bundle_options: Option<BundleOptions>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BundleOptions {
+    /// Roll the whole bundle back if any transaction fails, the remaining ones are skipped.
+    pub atomically: Option<bool>,
+    /// Keep executing after a transaction failed, `true` by default. Ignored if `atomically`.
+    #[serde(rename = "continueOnFailure")]
+    pub continue_on_failure: Option<bool>,
+    /// Set `stateDiff` on every transaction, each reporting only what it changed on top of the
+    /// previous ones, and report the diff of the whole bundle.
+    #[serde(rename = "stateDiffs")]
+    pub state_diffs: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BundleResponse {
+    /// Results of the transactions executed, in order.
+    pub results: Vec<SimulationResponse>,
+    /// Status of every transaction of the bundle, in order.
+    pub statuses: Vec<BundleTransactionStatus>,
+    /// Whether the bundle was atomic and a transaction failed.
+    #[serde(rename = "rolledBack")]
+    pub rolled_back: bool,
+    #[serde(rename = "bundleSummary")]
+    pub bundle_summary: BundleSummary,
+    /// Everything the executed transactions changed together, if `stateDiffs` is set.
+    #[serde(rename = "stateDiff", default, skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<Vec<AccountDiff>>,
+    /// Whether every assertion of the executed transactions passed, only if any has some.
+    #[serde(
+        rename = "assertionsPassed",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub assertions_passed: Option<bool>,
+}
+
+/// Element of the response to a list of transactions: the result of the transaction, or why it
+/// could not be simulated.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum BundleResult {
+    Error { error: ErrorMessage },
+    Result(Box<SimulationResponse>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MultiChainBundleResponse {
+    /// Responses of the bundles, in the order of the request.
+    pub chains: Vec<ChainBundleResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChainBundleResponse {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    #[serde(flatten)]
+    pub bundle: BundleResponse,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TransactionStatus {
+    Success,
+    Reverted,
+    /// The transaction could not be simulated, see `error`.
+    Error,
+    /// Not executed as an earlier transaction failed.
+    Skipped,
+    /// Succeeded, then rolled back with the rest of an atomic bundle.
+    RolledBack,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BundleTransactionStatus {
+    pub status: TransactionStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorMessage>,
+    /// Results of the assertions of the transaction, in order, if it executed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assertions: Option<Vec<AssertionResult>>,
+}
+
+impl From<TransactionStatus> for BundleTransactionStatus {
+    fn from(status: TransactionStatus) -> Self {
+        BundleTransactionStatus {
+            status,
+            error: None,
+            assertions: None,
+        }
+    }
+}
+
+/// Lines of a bundle streamed as JSON lines, each only holding what the previous ones didn't.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BundleEvent {
+    /// Sent once the transaction at index `transaction` executed or was skipped, `result` is only
+    /// set if it executed.
+    Transaction {
+        transaction: usize,
+        status: TransactionStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<ErrorMessage>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        assertions: Option<Vec<AssertionResult>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Box<SimulationResponse>>,
+    },
+    /// Last line, the `BundleResponse` but the results and statuses already streamed.
+    Summary {
+        #[serde(rename = "rolledBack")]
+        rolled_back: bool,
+        #[serde(rename = "bundleSummary")]
+        bundle_summary: Box<BundleSummary>,
+        #[serde(rename = "stateDiff", default, skip_serializing_if = "Option::is_none")]
+        state_diff: Option<Vec<AccountDiff>>,
+        #[serde(
+            rename = "assertionsPassed",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        assertions_passed: Option<bool>,
+    },
+    /// Last line if the bundle failed as a whole.
+    Error { error: ErrorMessage },
+}
+
+impl BundleEvent {
+    fn line(&self, format: Option<QuantityFormat>) -> String {
+        let mut line = quantity::to_value(self, format).to_string();
+        line.push('\n');
+        line
+    }
+}
+
+/// Profitability of a bundle for the block builder and the searcher, like `eth_callBundle`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BundleSummary {
+    pub coinbase: Address,
+    /// Balance increase of the coinbase over the whole bundle.
+    #[serde(rename = "coinbaseDiff")]
+    pub coinbase_diff: Uint,
+    /// Gas fees paid by the senders of the bundle.
+    #[serde(rename = "gasFees")]
+    pub gas_fees: Uint,
+    #[serde(rename = "totalGasUsed")]
+    pub total_gas_used: u64,
+    /// `coinbaseDiff / totalGasUsed`, the price builders rank bundles by.
+    #[serde(rename = "bundleGasPrice")]
+    pub bundle_gas_price: Uint,
+    /// Signed change of the native balances of all senders in the bundle, in wei.
+    #[serde(rename = "netProfit")]
+    pub net_profit: String,
+    pub transactions: Vec<TransactionSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransactionSummary {
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    #[serde(rename = "effectiveGasPrice")]
+    pub effective_gas_price: Uint,
+    #[serde(rename = "gasFees")]
+    pub gas_fees: Uint,
+    #[serde(rename = "coinbaseDiff")]
+    pub coinbase_diff: Uint,
+}
+
+pub async fn simulate_bundle(
+    request: BundleRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    Ok(warp::reply::json(
+        &run_bundle(request, config, pool, history).await?,
+    ))
+}
+
+/// Simulates a bundle like `simulate_bundle`, streaming a JSON line per transaction as soon as it
+/// executed, then the summary of the bundle. Bundles on several chains are answered as a whole.
+pub async fn stream_bundle(
+    request: BundleRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Response<Body>, Rejection> {
+    if request.len() > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+    request.check_not_empty()?;
+    let (transactions, options) = match request {
+        BundleRequest::Transactions(transactions) => (transactions, BundleOptions::default()),
+        BundleRequest::Bundle(bundle) => (
+            bundle.transactions,
+            bundle.bundle_options.unwrap_or_default(),
+        ),
+        BundleRequest::MultiChain(_) => {
+            let response = run_bundle(request, config, pool, history).await?;
+            return Ok(warp::reply::json(&response).into_response());
+        }
+    };
+
+    let quantity_format = transactions.first().and_then(|t| t.quantity_format);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let bundle = tokio::spawn(execute_bundle(
+        transactions,
+        options,
+        config,
+        pool,
+        Some(history),
+        Some(sender),
+    ));
+
+    // The channel closes once the bundle is done, which then gives its summary or why it failed
+    let transactions = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((event, receiver))
+    });
+    let outcome = stream::once(async move {
+        let response = bundle
+            .await
+            .unwrap_or_else(|err| Err(SimulationError::Evm(err.into())));
+        match response {
+            Ok(response) => BundleEvent::Summary {
+                rolled_back: response.rolled_back,
+                bundle_summary: Box::new(response.bundle_summary),
+                state_diff: response.state_diff,
+                assertions_passed: response.assertions_passed,
+            },
+            Err(err) => BundleEvent::Error {
+                error: ErrorMessage::from(&err),
+            },
+        }
+    });
+    let lines = transactions
+        .chain(outcome)
+        .map(move |event| Ok::<_, std::io::Error>(event.line(quantity_format)));
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::wrap_stream(lines))
+        .expect("bundle stream response must build"))
+}
+
+impl BundleResponse {
+    /// The result or error of every transaction, as bundles sent as a list are answered. Lists
+    /// always continue on failure, so every transaction of theirs has one or the other.
+    pub(crate) fn into_list(self) -> Vec<BundleResult> {
+        let mut results = self.results.into_iter();
+        self.statuses
+            .into_iter()
+            .map(|status| match status.error {
+                Some(error) => BundleResult::Error { error },
+                None => BundleResult::Result(Box::new(
+                    results.next().expect("executed transactions have a result"),
+                )),
+            })
+            .collect()
+    }
+}
+
+impl BundleRequest {
+    pub fn len(&self) -> usize {
+        match self {
+            BundleRequest::Transactions(transactions) => transactions.len(),
+            BundleRequest::Bundle(bundle) => bundle.transactions.len(),
+            BundleRequest::MultiChain(bundle) => bundle
+                .chains
+                .iter()
+                .map(|chain| chain.transactions.len())
+                .sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rejects bundles, or chains of a bundle, without any transaction, which would have nothing
+    /// to fork from.
+    pub fn check_not_empty(&self) -> Result<(), InvalidRequestError> {
+        let empty = |field: String| FieldError {
+            field,
+            message: "must have at least one transaction".to_string(),
+        };
+        let errors: Vec<FieldError> = match self {
+            BundleRequest::Transactions(transactions) if transactions.is_empty() => {
+                vec![empty("transactions".to_string())]
+            }
+            BundleRequest::Bundle(bundle) if bundle.transactions.is_empty() => {
+                vec![empty("transactions".to_string())]
+            }
+            BundleRequest::MultiChain(bundle) if bundle.chains.is_empty() => vec![FieldError {
+                field: "chains".to_string(),
+                message: "must have at least one chain".to_string(),
+            }],
+            BundleRequest::MultiChain(bundle) => bundle
+                .chains
+                .iter()
+                .enumerate()
+                .filter(|(_, chain)| chain.transactions.is_empty())
+                .map(|(index, _)| empty(format!("chains.{index}.transactions")))
+                .collect(),
+            _ => vec![],
+        };
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidRequestError(errors))
+        }
+    }
+
+    /// That of the bundle object, or of the first transaction of a list.
+    pub fn callback_url(&self) -> Option<&str> {
+        match self {
+            BundleRequest::Transactions(transactions) => transactions
+                .first()
+                .and_then(|transaction| transaction.callback_url.as_deref()),
+            BundleRequest::Bundle(bundle) => bundle.callback_url.as_deref(),
+            BundleRequest::MultiChain(bundle) => bundle.callback_url.as_deref(),
+        }
+    }
+}
+
+/// Simulates a bundle and serializes its response, in the format of its first transaction.
+pub(crate) async fn run_bundle(
+    request: BundleRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Value, Rejection> {
+    if request.len() > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+    request.check_not_empty()?;
+
+    match request {
+        BundleRequest::Transactions(transactions) => {
+            let quantity_format = transactions.first().and_then(|t| t.quantity_format);
+            let response = execute_bundle(
+                transactions,
+                BundleOptions::default(),
+                config,
+                pool,
+                Some(history),
+                None,
+            )
+            .await?;
+            Ok(quantity::to_value(&response.into_list(), quantity_format))
+        }
+        BundleRequest::Bundle(bundle) => {
+            let quantity_format = bundle.transactions.first().and_then(|t| t.quantity_format);
+            let response = execute_bundle(
+                bundle.transactions,
+                bundle.bundle_options.unwrap_or_default(),
+                config,
+                pool,
+                Some(history),
+                None,
+            )
+            .await?;
+            Ok(quantity::to_value(&response, quantity_format))
+        }
+        BundleRequest::MultiChain(bundle) => {
+            let quantity_format = bundle
+                .chains
+                .first()
+                .and_then(|chain| chain.transactions.first())
+                .and_then(|transaction| transaction.quantity_format);
+            for chain in &bundle.chains {
+                if chain
+                    .transactions
+                    .iter()
+                    .any(|transaction| transaction.chain_id != chain.chain_id)
+                {
+                    return Err(warp::reject::custom(SimulationError::MultipleChainIds));
+                }
+            }
+
+            // Every chain has its own fork, so they are simulated concurrently
+            let responses = join_all(bundle.chains.into_iter().map(|chain| {
+                let chain_id = chain.chain_id;
+                let response = execute_bundle(
+                    chain.transactions,
+                    chain.bundle_options.unwrap_or_default(),
+                    config.clone(),
+                    pool.clone(),
+                    Some(history.clone()),
+                    None,
+                );
+                async move {
+                    Ok::<_, Rejection>(ChainBundleResponse {
+                        chain_id,
+                        bundle: response.await?,
+                    })
+                }
+            }))
+            .await;
+            let chains = responses.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+            Ok(quantity::to_value(
+                &MultiChainBundleResponse { chains },
+                quantity_format,
+            ))
+        }
+    }
+}
+
+/// Runs the transactions one after the other on a fork of the chain of the first one. Errors of
+/// a transaction are reported in its status, the bundle only fails as a whole if it's malformed
+/// or the fork can't be read. Executed transactions are recorded in `history` if set. The status
+/// of every transaction is sent to `progress` as soon as it's known.
+pub(crate) async fn execute_bundle(
+    transactions: Vec<SimulationRequest>,
+    options: BundleOptions,
+    config: Config,
+    pool: EvmPool,
+    history: Option<History>,
+    progress: Option<UnboundedSender<BundleEvent>>,
+) -> Result<BundleResponse, SimulationError> {
+    let atomically = options.atomically.unwrap_or_default();
+    let continue_on_failure = !atomically && options.continue_on_failure.unwrap_or(true);
+    let state_diffs = options.state_diffs.unwrap_or_default();
+    let Some(first) = transactions.first() else {
+        return Err(SimulationError::EmptyBundle);
+    };
+    let first_chain_id = first.chain_id;
+    let first_block_number = first.block_number;
+
+    let fork_url = chain_id_to_fork_url(first_chain_id, &config)?;
+    let mut evm = pool.get_at(
+        first_chain_id,
+        fork_url.clone(),
+        first_block_number,
+        first.block_hash,
+        first.gas_limit,
+        config.etherscan_key,
+    )?;
+    // The first transaction runs right away, the state of the others is fetched meanwhile.
+    // Speculative executions fork by number, which may not be the block of a hash.
+    let _prefetch = prefetch(
+        &pool,
+        first_chain_id,
+        &fork_url,
+        evm.block_number(),
+        &transactions[1..],
+        if first.block_hash.is_some() {
+            0
+        } else {
+            config.bundle_prefetch
+        },
+    );
+
+    let coinbase = evm.coinbase();
+    let mut senders = BTreeMap::new();
+    for transaction in &transactions {
+        if !senders.contains_key(&transaction.from) {
+            senders.insert(transaction.from, evm.basic(transaction.from)?.balance);
+        }
+    }
+
+    let mut block_number = evm.block_number();
+    let mut results = Vec::with_capacity(transactions.len());
+    let mut summaries = Vec::with_capacity(transactions.len());
+    let mut statuses = Vec::with_capacity(transactions.len());
+    let mut bundle_diff = BTreeMap::new();
+    let mut failed = false;
+    for mut transaction in transactions {
+        if failed && !continue_on_failure {
+            statuses.push(TransactionStatus::Skipped.into());
+            report(&progress, &statuses, None);
+            continue;
+        }
+        if transaction.chain_id != first_chain_id {
+            return Err(SimulationError::MultipleChainIds);
+        }
+        // Transactions without a block number are included in the same block as the previous one
+        if let Some(next_block_number) = transaction.block_number {
+            if next_block_number < block_number {
+                return Err(SimulationError::BlockNumberDecreasing);
+            }
+            evm.roll_block(next_block_number);
+            block_number = next_block_number;
+        }
+
+        if state_diffs {
+            transaction.state_diff = Some(true);
+        }
+
+        let coinbase_before = evm.basic(coinbase)?.balance;
+        let result = match run(&mut evm, transaction.clone(), true).await {
+            Ok(result) => result,
+            Err(err) => {
+                statuses.push(BundleTransactionStatus {
+                    status: TransactionStatus::Error,
+                    error: Some(ErrorMessage::from(&err)),
+                    assertions: None,
+                });
+                report(&progress, &statuses, None);
+                failed = true;
+                continue;
+            }
+        };
+        if let Some(history) = &history {
+            history.record(&transaction, &result);
+        }
+        let coinbase_after = evm.basic(coinbase)?.balance;
+        let assertions = match &transaction.assertions {
+            Some(assertions) => Some(evaluate_assertions(&mut evm, assertions, &result).await),
+            None => None,
+        };
+        let status = if result.success {
+            TransactionStatus::Success
+        } else {
+            failed = true;
+            TransactionStatus::Reverted
+        };
+        statuses.push(BundleTransactionStatus {
+            status,
+            error: None,
+            assertions,
+        });
+        report(&progress, &statuses, Some(&result));
+
+        summaries.push(TransactionSummary {
+            gas_used: result.gas_used,
+            effective_gas_price: result.effective_gas_price,
+            gas_fees: result.effective_gas_price * result.gas_used,
+            coinbase_diff: coinbase_after.saturating_sub(coinbase_before),
+        });
+        if state_diffs {
+            merge_state_diff(&mut bundle_diff, result.state_diff.iter().flatten());
+        }
+        results.push(result);
+    }
+
+    let rolled_back = atomically && failed;
+    if rolled_back {
+        for status in &mut statuses {
+            if status.status == TransactionStatus::Success {
+                status.status = TransactionStatus::RolledBack;
+            }
+        }
+    }
+
+    let bundle_summary = summarize_bundle(&evm, coinbase, senders, summaries)?;
+    let assertions_passed = statuses
+        .iter()
+        .filter_map(|status| status.assertions.as_ref())
+        .flatten()
+        .map(|assertion| assertion.passed)
+        .reduce(|passed, assertion_passed| passed && assertion_passed);
+
+    Ok(BundleResponse {
+        results,
+        statuses,
+        rolled_back,
+        bundle_summary,
+        state_diff: state_diffs.then(|| bundle_diff.into_values().collect()),
+        assertions_passed,
+    })
+}
+
+/// Sends the status of the last transaction of `statuses` to `progress`, with its result if it
+/// executed.
+fn report(
+    progress: &Option<UnboundedSender<BundleEvent>>,
+    statuses: &[BundleTransactionStatus],
+    result: Option<&SimulationResponse>,
+) {
+    let (Some(progress), Some(status)) = (progress, statuses.last()) else {
+        return;
+    };
+    // The bundle still runs to completion if the client went away
+    progress
+        .send(BundleEvent::Transaction {
+            transaction: statuses.len() - 1,
+            status: status.status,
+            error: status.error.clone(),
+            assertions: status.assertions.clone(),
+            result: result.cloned().map(Box::new),
+        })
+        .ok();
+}
+
+/// Adds the diff of a transaction to that of the previous ones: values keep their first `pre`
+/// and take the last `post`, and are dropped once they are back to where they started.
+fn merge_state_diff<'a>(
+    bundle_diff: &mut BTreeMap<Address, AccountDiff>,
+    transaction_diff: impl IntoIterator<Item = &'a AccountDiff>,
+) {
+    for account in transaction_diff {
+        let Some(merged) = bundle_diff.get_mut(&account.address) else {
+            bundle_diff.insert(account.address, account.clone());
+            continue;
+        };
+        merged.balance = merge_value(merged.balance.take(), &account.balance);
+        merged.nonce = merge_value(merged.nonce.take(), &account.nonce);
+        merged.code = merge_value(merged.code.take(), &account.code);
+        for (slot, diff) in &account.storage {
+            let value = merge_value(merged.storage.remove(slot), &Some(diff.clone()));
+            if let Some(value) = value {
+                merged.storage.insert(*slot, value);
+            }
+        }
+
+        if merged.balance.is_none()
+            && merged.nonce.is_none()
+            && merged.code.is_none()
+            && merged.storage.is_empty()
+        {
+            bundle_diff.remove(&account.address);
+        }
+    }
+}
+
+fn merge_value<T: Clone + PartialEq>(
+    before: Option<ValueDiff<T>>,
+    diff: &Option<ValueDiff<T>>,
+) -> Option<ValueDiff<T>> {
+    match (before, diff) {
+        (Some(before), Some(diff)) => (before.pre != diff.post).then(|| ValueDiff {
+            pre: before.pre,
+            post: diff.post.clone(),
+        }),
+        (before, diff) => before.or_else(|| diff.clone()),
+    }
+}
+
+fn summarize_bundle(
+    evm: &Evm,
+    coinbase: Address,
+    senders: BTreeMap<Address, Uint>,
+    transactions: Vec<TransactionSummary>,
+) -> Result<BundleSummary, SimulationError> {
+    let mut net_profit = I256::zero();
+    for (sender, balance_before) in senders {
+        let balance_after = evm.basic(sender)?.balance;
+        net_profit = net_profit + I256::from_raw(balance_after) - I256::from_raw(balance_before);
+    }
+
+    let coinbase_diff = transactions
+        .iter()
+        .fold(Uint::zero(), |total, tx| total + tx.coinbase_diff);
+    let gas_fees = transactions
+        .iter()
+        .fold(Uint::zero(), |total, tx| total + tx.gas_fees);
+    let total_gas_used = transactions.iter().map(|tx| tx.gas_used).sum::<u64>();
+    let bundle_gas_price = if total_gas_used > 0 {
+        coinbase_diff / total_gas_used
+    } else {
+        Uint::zero()
+    };
+
+    Ok(BundleSummary {
+        coinbase,
+        coinbase_diff,
+        gas_fees,
+        total_gas_used,
+        bundle_gas_price,
+        net_profit: net_profit.to_string(),
+        transactions,
+    })
+}
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use warp::hyper::StatusCode;
+use warp::reply::Json;
+use warp::{Rejection, Reply};
+
+use crate::errors::{ChainAlreadyRegisteredError, InvalidChainError, SimulationError};
+use crate::health::rpc_chain_id;
+use crate::public_url::resolve_public;
+
+use super::config::Config;
+
+/// How long the RPC of a chain being registered may take to answer `eth_chainId`.
+const DETECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ChainRegistration {
+    /// Detected with `eth_chainId` if not set, checked against it otherwise.
+    #[serde(rename = "chainId", default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    #[serde(rename = "rpcUrl")]
+    pub rpc_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(
+        rename = "nativeCurrency",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub native_currency: Option<NativeCurrency>,
+}
+
+/// A chain as listed by `GET /chains`, without its RPC URL which may hold credentials.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChainInfo {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    /// Whether the chain was added with `POST /chains` rather than configured at startup.
+    pub registered: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(
+        rename = "nativeCurrency",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub native_currency: Option<NativeCurrency>,
+}
+
+/// Chains registered at runtime, in addition to the configured ones which they can't replace.
+/// Keyed by the API key which registered them, `None` without one, and only used by requests
+/// made with that key so that a client can't point the others at its RPC. Kept in memory, so
+/// they have to be registered again after a restart. Cheap to clone, clones share the registry.
+#[derive(Debug, Clone, Default)]
+pub struct ChainRegistry(Arc<RwLock<HashMap<(Option<String>, u64), ChainRegistration>>>);
+
+impl ChainRegistry {
+    pub fn fork_url(&self, api_key: Option<&str>, chain_id: u64) -> Option<String> {
+        self.0
+            .read()
+            .unwrap()
+            .get(&(api_key.map(str::to_string), chain_id))
+            .map(|chain| chain.rpc_url.clone())
+    }
+
+    /// Registers the chain for `api_key` unless it already registered one with the same ID,
+    /// returns whether it was. `chain_id` must be set.
+    pub fn insert(&self, api_key: Option<&str>, chain: ChainRegistration) -> bool {
+        let chain_id = chain.chain_id.expect("registered chains have an ID");
+        let mut chains = self.0.write().unwrap();
+        let key = (api_key.map(str::to_string), chain_id);
+        if chains.contains_key(&key) {
+            return false;
+        }
+        chains.insert(key, chain);
+        true
+    }
+
+    fn all(&self, api_key: Option<&str>) -> Vec<ChainRegistration> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((key, _), _)| key.as_deref() == api_key)
+            .map(|(_, chain)| chain.clone())
+            .collect()
+    }
+}
+
+impl From<&ChainRegistration> for ChainInfo {
+    fn from(chain: &ChainRegistration) -> Self {
+        ChainInfo {
+            chain_id: chain.chain_id.unwrap_or_default(),
+            registered: true,
+            name: chain.name.clone(),
+            native_currency: chain.native_currency.clone(),
+        }
+    }
+}
+
+fn invalid_chain(reason: impl Into<String>) -> Rejection {
+    warp::reject::custom(InvalidChainError(reason.into()))
+}
+
+/// Checks the RPC is public and answers, detects or checks its chain ID and registers the chain
+/// for the API key of the request if it isn't configured nor registered by the key yet, and the
+/// key may use it. Answers with a `201` and the chain.
+pub async fn register_chain(
+    mut chain: ChainRegistration,
+    config: Config,
+) -> Result<impl Reply, Rejection> {
+    let url = reqwest::Url::parse(&chain.rpc_url)
+        .map_err(|_| invalid_chain("rpcUrl must be an HTTP(S) URL"))?;
+    if !config.allow_private_urls {
+        resolve_public(&url)
+            .await
+            .map_err(|reason| invalid_chain(format!("rpcUrl {reason}")))?;
+    } else if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid_chain("rpcUrl must be an HTTP(S) URL"));
+    }
+
+    // Redirects could lead to a private address
+    let client = reqwest::Client::builder()
+        .timeout(DETECT_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("HTTP client must build");
+    let detected = rpc_chain_id(&client, &chain.rpc_url)
+        .await
+        .map_err(|err| invalid_chain(format!("eth_chainId failed: {err}")))?;
+    match chain.chain_id {
+        Some(chain_id) if chain_id != detected => {
+            return Err(invalid_chain(format!(
+                "chainId is {chain_id} but the RPC is for chain {detected}"
+            )))
+        }
+        _ => chain.chain_id = Some(detected),
+    }
+    if let Some(policy) = &config.api_key_policy {
+        if !policy.allows_chain(detected) {
+            return Err(SimulationError::ChainNotAllowed(detected).into());
+        }
+    }
+    if config.chains.contains_key(&detected)
+        || !config
+            .registry
+            .insert(config.api_key.as_deref(), chain.clone())
+    {
+        return Err(warp::reject::custom(ChainAlreadyRegisteredError(detected)));
+    }
+
+    log::info!(
+        target: "ts::chains",
+        "Registered chain {detected}{}",
+        chain.name.as_deref().map(|name| format!(" ({name})")).unwrap_or_default()
+    );
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ChainInfo::from(&chain)),
+        StatusCode::CREATED,
+    ))
+}
+
+/// Configured chains and those registered by the API key of the request, ordered by chain ID.
+pub async fn list_chains(config: Config) -> Result<Json, Rejection> {
+    let mut chains: BTreeMap<u64, ChainInfo> = config
+        .chains
+        .keys()
+        .map(|chain_id| {
+            let info = ChainInfo {
+                chain_id: *chain_id,
+                registered: false,
+                name: None,
+                native_currency: None,
+            };
+            (*chain_id, info)
+        })
+        .collect();
+    for chain in config.registry.all(config.api_key.as_deref()) {
+        let info = ChainInfo::from(&chain);
+        chains.insert(info.chain_id, info);
+    }
+
+    Ok(warp::reply::json(&chains.into_values().collect::<Vec<_>>()))
+}
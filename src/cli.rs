@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+
+use serde_json::Value;
+
+use super::bundle::BundleOptions;
+use super::errors::ErrorMessage;
+use super::quantity::format_quantities;
+use super::simulation::SimulationRequest;
+use super::simulator::Simulator;
+
+pub const USAGE: &str = "Usage: transaction-simulator [simulate [FILE]]
+
+Without a command, starts the server.
+
+simulate    Simulates the request of FILE, or of stdin if FILE is missing or `-`, and prints the
+            response. A JSON array is simulated as a bundle.";
+
+/// What the arguments of the binary, without the binary itself, ask for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve,
+    /// Simulates the request of the file, or of stdin if `None`.
+    Simulate(Option<String>),
+    Help,
+}
+
+/// The command of `args`, `None` if they aren't valid and `USAGE` should be printed.
+pub fn parse_args(args: &[String]) -> Option<Command> {
+    match args.first().map(String::as_str) {
+        None => Some(Command::Serve),
+        Some("simulate") if args.len() <= 2 => Some(Command::Simulate(
+            args.get(1).filter(|path| *path != "-").cloned(),
+        )),
+        Some("-h" | "--help") => Some(Command::Help),
+        Some(_) => None,
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    InvalidJson(serde_json::Error),
+    InvalidRequest(serde_json::Error),
+    InvalidBundle(serde_json::Error),
+    /// The simulation failed, as the API would have answered it.
+    Simulation(ErrorMessage),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::InvalidJson(err) => write!(f, "Invalid JSON: {err}"),
+            CommandError::InvalidRequest(err) => write!(f, "Invalid request: {err}"),
+            CommandError::InvalidBundle(err) => write!(f, "Invalid bundle: {err}"),
+            CommandError::Simulation(error) => write!(
+                f,
+                "{:#}",
+                serde_json::to_value(error).expect("errors must be serializable")
+            ),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+/// Simulates `input`, a request or a JSON array of them simulated as a bundle, like the API,
+/// without the server. Returns the response in the quantity format of the request.
+pub async fn simulate(simulator: &Simulator, input: &str) -> Result<Value, CommandError> {
+    let result = match serde_json::from_str::<Value>(input).map_err(CommandError::InvalidJson)? {
+        transactions @ Value::Array(_) => {
+            let transactions = serde_json::from_value::<Vec<SimulationRequest>>(transactions)
+                .map_err(CommandError::InvalidBundle)?;
+            let format = transactions.first().and_then(|t| t.quantity_format);
+            simulator
+                .simulate_bundle(transactions, BundleOptions::default())
+                .await
+                .map(|response| (serde_json::to_value(response.into_list()), format))
+        }
+        transaction => {
+            let transaction = serde_json::from_value::<SimulationRequest>(transaction)
+                .map_err(CommandError::InvalidRequest)?;
+            let format = transaction.quantity_format;
+            simulator
+                .simulate(transaction)
+                .await
+                .map(|response| (serde_json::to_value(response), format))
+        }
+    };
+
+    match result {
+        Ok((response, format)) => {
+            let mut response = response.expect("responses must be serializable");
+            if let Some(format) = format {
+                format_quantities(&mut response, format);
+            }
+            Ok(response)
+        }
+        Err(err) => Err(CommandError::Simulation(ErrorMessage::from(&err))),
+    }
+}
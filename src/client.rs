@@ -0,0 +1,406 @@
+use std::error::Error;
+use std::fmt;
+
+use ethers::abi::{Address, Hash, Uint};
+use ethers::types::Bytes;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::access_list::AccessListResponse;
+use crate::batch::BatchResult;
+use crate::bundle::{
+    Bundle, BundleResponse, BundleResult, MultiChainBundle, MultiChainBundleResponse,
+};
+use crate::chains::ChainInfo;
+use crate::errors::ErrorMessage;
+use crate::estimate::GasEstimateResponse;
+use crate::fork::{
+    BalanceResponse, CodeResponse, DealRequest, DealResponse, ForkRequest, ForkResponse,
+    RevertRequest, SetBalanceRequest, SetCodeRequest, SetStorageRequest, SnapshotResponse,
+    StorageResponse, TokenBalancesRequest, TokenBalancesResponse,
+};
+use crate::history::SimulationRecord;
+use crate::jobs::{Job, JobRequest};
+use crate::replay::{ReplayRequest, ReplayResponse};
+use crate::simulation::{SimulationRequest, SimulationResponse};
+use crate::user_operation::{UserOperationRequest, UserOperationResponse};
+use crate::watch::{Watch, WatchRequest};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server could not be reached, or answered something else than the expected JSON.
+    Http(reqwest::Error),
+    /// The server answered with an error, e.g. a `404` with `FORK_NOT_FOUND`.
+    Api {
+        status: StatusCode,
+        error: ErrorMessage,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "HTTP error: {err}"),
+            ClientError::Api { status, error } => write!(f, "{status}: {}", error.message),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+/// Typed client of the HTTP API, sending and parsing the same structs as the server. Responses
+/// are parsed with the default quantity format, so requests must not set `quantityFormat`.
+/// Cheap to clone, clones share their connections.
+#[derive(Debug, Clone)]
+pub struct SimulatorClient {
+    http: reqwest::Client,
+    /// E.g. `http://localhost:8080/api/v1`, without a trailing slash.
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl SimulatorClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        SimulatorClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    /// Sends `key` as `X-API-Key` with every request.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// For timeouts, proxies or TLS settings of its own.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.authorized(self.http.get(format!("{}{path}", self.base_url)))
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.authorized(self.http.post(format!("{}{path}", self.base_url)))
+    }
+
+    fn delete(&self, path: &str) -> RequestBuilder {
+        self.authorized(self.http.delete(format!("{}{path}", self.base_url)))
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.header("x-api-key", key),
+            None => request,
+        }
+    }
+
+    /// Sends the request, turning error responses into `ClientError::Api`.
+    async fn send(request: RequestBuilder) -> Result<reqwest::Response, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            let error = response.json().await?;
+            return Err(ClientError::Api { status, error });
+        }
+
+        Ok(response)
+    }
+
+    async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ClientError> {
+        Ok(Self::send(request).await?.json().await?)
+    }
+
+    async fn post_json<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        Self::json(self.post(path).json(body)).await
+    }
+
+    /// POST /simulate
+    pub async fn simulate(
+        &self,
+        transaction: &SimulationRequest,
+    ) -> Result<SimulationResponse, ClientError> {
+        self.post_json("/simulate", transaction).await
+    }
+
+    /// POST /simulate-bundle with a list of transactions, each answered with its result or why
+    /// it could not be simulated.
+    pub async fn simulate_bundle(
+        &self,
+        transactions: &[SimulationRequest],
+    ) -> Result<Vec<BundleResult>, ClientError> {
+        self.post_json("/simulate-bundle", transactions).await
+    }
+
+    /// POST /simulate-bundle with `bundleOptions`, answered with a summary of the bundle.
+    pub async fn simulate_bundle_with_options(
+        &self,
+        bundle: &Bundle,
+    ) -> Result<BundleResponse, ClientError> {
+        self.post_json("/simulate-bundle", bundle).await
+    }
+
+    /// POST /simulate-bundle with bundles on several chains.
+    pub async fn simulate_multi_chain_bundle(
+        &self,
+        bundle: &MultiChainBundle,
+    ) -> Result<MultiChainBundleResponse, ClientError> {
+        self.post_json("/simulate-bundle", bundle).await
+    }
+
+    /// POST /simulate-batch
+    pub async fn simulate_batch(
+        &self,
+        transactions: &[SimulationRequest],
+    ) -> Result<Vec<BatchResult>, ClientError> {
+        self.post_json("/simulate-batch", transactions).await
+    }
+
+    /// POST /simulate-async
+    pub async fn simulate_async(&self, request: &JobRequest) -> Result<Job, ClientError> {
+        self.post_json("/simulate-async", request).await
+    }
+
+    /// GET /jobs/{id}
+    pub async fn get_job(&self, job_id: Uuid) -> Result<Job, ClientError> {
+        Self::json(self.get(&format!("/jobs/{job_id}"))).await
+    }
+
+    /// POST /estimate
+    pub async fn estimate(
+        &self,
+        transaction: &SimulationRequest,
+    ) -> Result<GasEstimateResponse, ClientError> {
+        self.post_json("/estimate", transaction).await
+    }
+
+    /// POST /access-list
+    pub async fn create_access_list(
+        &self,
+        transaction: &SimulationRequest,
+    ) -> Result<AccessListResponse, ClientError> {
+        self.post_json("/access-list", transaction).await
+    }
+
+    /// POST /replay
+    pub async fn replay(&self, request: &ReplayRequest) -> Result<ReplayResponse, ClientError> {
+        self.post_json("/replay", request).await
+    }
+
+    /// POST /user-operation
+    pub async fn simulate_user_operation(
+        &self,
+        request: &UserOperationRequest,
+    ) -> Result<UserOperationResponse, ClientError> {
+        self.post_json("/user-operation", request).await
+    }
+
+    /// POST /watch
+    pub async fn create_watch(&self, request: &WatchRequest) -> Result<Watch, ClientError> {
+        self.post_json("/watch", request).await
+    }
+
+    /// GET /watch/{id}
+    pub async fn get_watch(&self, watch_id: Uuid) -> Result<Watch, ClientError> {
+        Self::json(self.get(&format!("/watch/{watch_id}"))).await
+    }
+
+    /// DELETE /watch/{id}
+    pub async fn delete_watch(&self, watch_id: Uuid) -> Result<(), ClientError> {
+        Self::send(self.delete(&format!("/watch/{watch_id}"))).await?;
+        Ok(())
+    }
+
+    /// POST /fork
+    pub async fn create_fork(
+        &self,
+        chain_id: u64,
+        block_number: Option<u64>,
+    ) -> Result<ForkResponse, ClientError> {
+        let request = ForkRequest {
+            chain_id,
+            block_number,
+        };
+        self.post_json("/fork", &request).await
+    }
+
+    /// POST /fork/{id}/simulate
+    pub async fn simulate_on_fork(
+        &self,
+        fork_id: Uuid,
+        transaction: &SimulationRequest,
+    ) -> Result<SimulationResponse, ClientError> {
+        self.post_json(&format!("/fork/{fork_id}/simulate"), transaction)
+            .await
+    }
+
+    /// POST /fork/{id}/set-balance
+    pub async fn set_balance(
+        &self,
+        fork_id: Uuid,
+        address: Address,
+        balance: Uint,
+    ) -> Result<(), ClientError> {
+        let request = SetBalanceRequest { address, balance };
+        Self::send(
+            self.post(&format!("/fork/{fork_id}/set-balance"))
+                .json(&request),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// POST /fork/{id}/set-storage
+    pub async fn set_storage(
+        &self,
+        fork_id: Uuid,
+        address: Address,
+        slot: Hash,
+        value: Hash,
+    ) -> Result<(), ClientError> {
+        let request = SetStorageRequest {
+            address,
+            slot,
+            value,
+        };
+        Self::send(
+            self.post(&format!("/fork/{fork_id}/set-storage"))
+                .json(&request),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// POST /fork/{id}/set-code
+    pub async fn set_code(
+        &self,
+        fork_id: Uuid,
+        address: Address,
+        code: Bytes,
+    ) -> Result<(), ClientError> {
+        let request = SetCodeRequest { address, code };
+        Self::send(
+            self.post(&format!("/fork/{fork_id}/set-code"))
+                .json(&request),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// POST /fork/{id}/deal
+    pub async fn deal(
+        &self,
+        fork_id: Uuid,
+        request: &DealRequest,
+    ) -> Result<DealResponse, ClientError> {
+        self.post_json(&format!("/fork/{fork_id}/deal"), request)
+            .await
+    }
+
+    /// POST /fork/{id}/token-balances
+    pub async fn token_balances(
+        &self,
+        fork_id: Uuid,
+        request: &TokenBalancesRequest,
+    ) -> Result<TokenBalancesResponse, ClientError> {
+        self.post_json(&format!("/fork/{fork_id}/token-balances"), request)
+            .await
+    }
+
+    /// POST /fork/{id}/snapshot
+    pub async fn snapshot_fork(&self, fork_id: Uuid) -> Result<SnapshotResponse, ClientError> {
+        Self::json(self.post(&format!("/fork/{fork_id}/snapshot"))).await
+    }
+
+    /// POST /fork/{id}/revert
+    pub async fn revert_fork(&self, fork_id: Uuid, snapshot_id: u64) -> Result<(), ClientError> {
+        let request = RevertRequest { snapshot_id };
+        Self::send(self.post(&format!("/fork/{fork_id}/revert")).json(&request)).await?;
+        Ok(())
+    }
+
+    /// GET /fork/{id}/balance?address={address}
+    pub async fn get_fork_balance(
+        &self,
+        fork_id: Uuid,
+        address: Address,
+    ) -> Result<BalanceResponse, ClientError> {
+        let request = self
+            .get(&format!("/fork/{fork_id}/balance"))
+            .query(&[("address", format!("{address:?}"))]);
+        Self::json(request).await
+    }
+
+    /// GET /fork/{id}/code?address={address}
+    pub async fn get_fork_code(
+        &self,
+        fork_id: Uuid,
+        address: Address,
+    ) -> Result<CodeResponse, ClientError> {
+        let request = self
+            .get(&format!("/fork/{fork_id}/code"))
+            .query(&[("address", format!("{address:?}"))]);
+        Self::json(request).await
+    }
+
+    /// GET /fork/{id}/storage?address={address}&slot={slot}
+    pub async fn get_fork_storage(
+        &self,
+        fork_id: Uuid,
+        address: Address,
+        slot: Uint,
+    ) -> Result<StorageResponse, ClientError> {
+        let request = self.get(&format!("/fork/{fork_id}/storage")).query(&[
+            ("address", format!("{address:?}")),
+            ("slot", format!("{slot:#x}")),
+        ]);
+        Self::json(request).await
+    }
+
+    /// DELETE /fork/{id}
+    pub async fn delete_fork(&self, fork_id: Uuid) -> Result<(), ClientError> {
+        Self::send(self.delete(&format!("/fork/{fork_id}"))).await?;
+        Ok(())
+    }
+
+    /// GET /simulations/{id}
+    pub async fn get_simulation(&self, id: Uuid) -> Result<SimulationRecord, ClientError> {
+        Self::json(self.get(&format!("/simulations/{id}"))).await
+    }
+
+    /// GET /simulations?from={address}, newest first.
+    pub async fn list_simulations(
+        &self,
+        from: Address,
+        limit: Option<usize>,
+    ) -> Result<Vec<SimulationRecord>, ClientError> {
+        let mut request = self
+            .get("/simulations")
+            .query(&[("from", format!("{from:?}"))]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Self::json(request).await
+    }
+
+    /// GET /chains
+    pub async fn list_chains(&self) -> Result<Vec<ChainInfo>, ClientError> {
+        Self::json(self.get("/chains")).await
+    }
+}
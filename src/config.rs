@@ -1,33 +1,493 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::time::Duration;
+
 use dotenvy::dotenv;
+use serde::Deserialize;
+
+use crate::auth::ApiKeyPolicy;
+use crate::chains::ChainRegistry;
+use crate::policy::PolicyRule;
+use crate::precompiles::PrecompileStub;
+
+/// RPC URL templates used when neither `CHAINS_FILE` nor `RPC_URL_<chainId>` configure a chain.
+const DEFAULT_CHAINS: &[(u64, &str)] = &[
+    // ethereum
+    (1, "https://eth-mainnet.g.alchemy.com/v2/${ALCHEMY_KEY}"),
+    (5, "https://eth-goerli.g.alchemy.com/v2/${ALCHEMY_KEY}"),
+    // polygon
+    (
+        137,
+        "https://polygon-mainnet.g.alchemy.com/v2/${ALCHEMY_KEY}",
+    ),
+    (
+        80001,
+        "https://polygon-mumbai.g.alchemy.com/v2/${ALCHEMY_KEY}",
+    ),
+    // avalanche
+    (43114, "https://api.avax.network/ext/bc/C/rpc"),
+    (43113, "https://api.avax-test.network/ext/bc/C/rpc"),
+    // fantom
+    (250, "https://rpcapi.fantom.network/"),
+    (4002, "https://rpc.testnet.fantom.network/"),
+    // xdai
+    (100, "https://rpc.xdaichain.com/"),
+    // bsc
+    (56, "https://bsc-dataseed.binance.org/"),
+    (97, "https://data-seed-prebsc-1-s1.binance.org:8545/"),
+    // arbitrum
+    (42161, "https://arb1.arbitrum.io/rpc"),
+    (421613, "https://goerli-rollup.arbitrum.io/rpc"),
+    // optimism
+    (10, "https://mainnet.optimism.io/"),
+    (420, "https://goerli.optimism.io/"),
+];
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub alchemy_key: String,
+    /// Address the server listens on, every interface by default.
+    pub bind_address: IpAddr,
     pub port: u16,
+    /// Port the gRPC service listens on, on `bind_address`, not served if not set.
+    pub grpc_port: Option<u16>,
+    /// Origins browsers may call the API from, `*` for any, CORS is disabled if empty.
+    pub cors_origins: Vec<String>,
+    /// Headers browsers may send, the content type, API key and request ID if empty.
+    pub cors_headers: Vec<String>,
+    /// Paths of the PEM certificate chain and private key the server serves HTTPS with, plain
+    /// HTTP unless both are set.
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// How long in-flight requests and async jobs may take to finish once shutting down.
+    pub shutdown_timeout: Duration,
     pub etherscan_key: Option<String>,
+    /// Keys accepted in the `X-API-KEY` header, the API is open if empty.
+    pub api_keys: HashSet<String>,
+    /// Limits of the keys of `API_KEY_POLICIES_FILE`, which are accepted too.
+    pub api_key_policies: HashMap<String, ApiKeyPolicy>,
+    /// API key of the request, set by `for_api_key`, whose registered chains it may use.
     pub api_key: Option<String>,
+    /// Policy of the API key of the request, applied by `for_api_key`.
+    pub api_key_policy: Option<ApiKeyPolicy>,
+    pub pool_size: usize,
+    /// Most EVM executions running at once, further requests wait for one to finish.
+    pub max_concurrency: usize,
+    /// Most requests waiting for one of `max_concurrency` to finish, further ones are rejected
+    /// with a `503`. Unbounded if `0`.
+    pub max_queue_size: usize,
+    pub gas_estimate_buffer: u64,
+    /// Requests per minute per API key or IP, unlimited if `0`.
+    pub rate_limit: u32,
+    /// Most transactions accepted in one bundle.
+    pub max_bundle_size: usize,
+    /// Most watches of `POST /watch` running at once.
+    pub max_watches: usize,
+    /// Transactions of a bundle executed speculatively at once to prefetch their state, `0`
+    /// disabling it.
+    pub bundle_prefetch: usize,
+    /// Highest gas limit a transaction is executed with.
+    pub max_gas_limit: u64,
+    /// Wall-clock time a simulation may take, including waiting for the fork RPC.
+    pub simulation_timeout: Duration,
+    /// Path of the SQLite database fork RPC responses are cached in, not cached if not set.
+    pub fork_cache: Option<String>,
+    /// Path of the SQLite database contracts identified by Etherscan are cached in, kept in
+    /// memory if not set.
+    pub etherscan_cache: Option<String>,
+    /// How long contracts identified by Etherscan are cached.
+    pub etherscan_cache_ttl: Duration,
+    /// How long responses of `/simulate` are cached, not cached if zero.
+    pub simulation_cache_ttl: Duration,
+    /// Most responses of `/simulate` cached at once.
+    pub simulation_cache_size: usize,
+    /// Path of the SQLite database simulations are persisted to, kept in memory if not set.
+    pub simulation_db: Option<String>,
+    /// Jobs of `/simulate-async` running at once.
+    pub async_workers: usize,
+    /// Key callbacks are signed with, unsigned if not set.
+    pub webhook_secret: Option<String>,
+    /// Whether callback URLs and the RPCs of `POST /chains` may be on loopback, private or
+    /// link-local addresses, for deployments where they run next to the simulator.
+    pub allow_private_urls: bool,
+    /// Price API URL template with `{chainId}` and `{token}` placeholders, prices are read from
+    /// Chainlink on the fork if not set.
+    pub price_api_url: Option<String>,
+    /// Rules of `POLICY_FILE` simulations are flagged or rejected by.
+    pub policy_rules: Vec<PolicyRule>,
+    /// Stubs the precompiles of L2s which their forks lack, like Arbitrum's `ArbSys`.
+    pub system_contracts: bool,
+    /// Stubs of `PRECOMPILE_STUBS_FILE`, replacing the system contracts at the same address.
+    pub precompile_stubs: Vec<PrecompileStub>,
+    /// Fork RPC URLs per chain ID in order of preference, with templates already resolved.
+    pub chains: HashMap<u64, Vec<String>>,
+    /// Chains registered with `POST /chains`, shared by every clone of the config.
+    pub registry: ChainRegistry,
+}
+
+#[derive(Deserialize)]
+struct ChainsFile {
+    chains: HashMap<String, ChainUrls>,
+}
+
+#[derive(Deserialize)]
+struct ApiKeyPoliciesFile {
+    #[serde(default)]
+    keys: HashMap<String, ApiKeyPolicy>,
+}
+
+#[derive(Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    rules: Vec<PolicyRule>,
+}
+
+#[derive(Deserialize)]
+struct PrecompileStubsFile {
+    #[serde(default)]
+    stubs: Vec<PrecompileStub>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChainUrls {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Splits comma separated URLs, as a URL can't contain an unescaped comma.
+fn split_urls(urls: &str) -> Vec<String> {
+    urls.split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+/// Replaces every `${NAME}` in the template with the value of the `NAME` env var, returns `None`
+/// if any of them is not set.
+fn resolve_template(template: &str) -> Option<String> {
+    let mut resolved = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let end = start + rest[start..].find('}')?;
+        let value = std::env::var(&rest[start + 2..end])
+            .ok()
+            .filter(|v| !v.is_empty())?;
+        resolved.push_str(&rest[..start]);
+        resolved.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    resolved.push_str(rest);
+    Some(resolved)
+}
+
+/// Comma separated values of an env var, empty if not set.
+fn get_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// API keys from the comma separated `API_KEY` and from `API_KEYS_FILE`, one key per line.
+fn get_api_keys() -> HashSet<String> {
+    let mut keys: HashSet<String> = get_list("API_KEY").into_iter().collect();
+
+    if let Some(path) = std::env::var("API_KEYS_FILE")
+        .ok()
+        .filter(|p| !p.is_empty())
+    {
+        let contents = std::fs::read_to_string(&path).expect("API_KEYS_FILE must be readable.");
+        keys.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+    }
+
+    keys
+}
+
+fn get_api_key_policies() -> HashMap<String, ApiKeyPolicy> {
+    let Some(path) = std::env::var("API_KEY_POLICIES_FILE")
+        .ok()
+        .filter(|p| !p.is_empty())
+    else {
+        return HashMap::new();
+    };
+    let contents = std::fs::read_to_string(&path).expect("API_KEY_POLICIES_FILE must be readable.");
+    let file: ApiKeyPoliciesFile =
+        toml::from_str(&contents).expect("API_KEY_POLICIES_FILE must be valid TOML.");
+    file.keys
+}
+
+fn get_policy_rules() -> Vec<PolicyRule> {
+    let Some(path) = std::env::var("POLICY_FILE").ok().filter(|p| !p.is_empty()) else {
+        return vec![];
+    };
+    let contents = std::fs::read_to_string(&path).expect("POLICY_FILE must be readable.");
+    let file: PolicyFile = toml::from_str(&contents).expect("POLICY_FILE must be valid TOML.");
+    assert!(
+        file.rules
+            .iter()
+            .flat_map(|rule| &rule.selectors)
+            .all(|selector| selector.len() == 4),
+        "POLICY_FILE selectors must be 4 bytes."
+    );
+    file.rules
+}
+
+fn get_precompile_stubs() -> Vec<PrecompileStub> {
+    let Some(path) = std::env::var("PRECOMPILE_STUBS_FILE")
+        .ok()
+        .filter(|p| !p.is_empty())
+    else {
+        return vec![];
+    };
+    let contents = std::fs::read_to_string(&path).expect("PRECOMPILE_STUBS_FILE must be readable.");
+    let file: PrecompileStubsFile =
+        toml::from_str(&contents).expect("PRECOMPILE_STUBS_FILE must be valid TOML.");
+    file.stubs
+}
+
+fn get_chains() -> HashMap<u64, Vec<String>> {
+    let mut templates: HashMap<u64, Vec<String>> = DEFAULT_CHAINS
+        .iter()
+        .map(|(chain_id, template)| (*chain_id, vec![template.to_string()]))
+        .collect();
+
+    if let Some(path) = std::env::var("CHAINS_FILE").ok().filter(|p| !p.is_empty()) {
+        let contents = std::fs::read_to_string(&path).expect("CHAINS_FILE must be readable.");
+        let file: ChainsFile = toml::from_str(&contents).expect("CHAINS_FILE must be valid TOML.");
+        for (chain_id, urls) in file.chains {
+            let chain_id = chain_id
+                .parse::<u64>()
+                .expect("CHAINS_FILE chain IDs must be numbers.");
+            let urls = match urls {
+                ChainUrls::One(urls) => split_urls(&urls),
+                ChainUrls::Many(urls) => urls,
+            };
+            templates.insert(chain_id, urls);
+        }
+    }
+
+    for (key, template) in std::env::vars() {
+        if let Some(chain_id) = key.strip_prefix("RPC_URL_") {
+            let chain_id = chain_id
+                .parse::<u64>()
+                .expect("RPC_URL_<chainId> must end with a number.");
+            templates.insert(chain_id, split_urls(&template));
+        }
+    }
+
+    templates
+        .into_iter()
+        .filter_map(|(chain_id, templates)| {
+            // URLs referencing an unset env var are skipped, the chain only if all of them do
+            let urls: Vec<String> = templates
+                .iter()
+                .filter_map(|template| resolve_template(template))
+                .collect();
+            if urls.is_empty() {
+                log::warn!(
+                    target: "ts::config",
+                    "Chain {chain_id} disabled, its RPC URLs reference unset env vars"
+                );
+                return None;
+            }
+            Some((chain_id, urls))
+        })
+        .collect()
 }
 
 pub fn get_config() -> Config {
     dotenv().ok();
 
-    let alchemy_key = std::env::var("ALCHEMY_KEY")
-        .ok()
-        .filter(|k| !k.is_empty())
-        .expect("ALCHEMY_KEY must be set.");
+    let bind_address = std::env::var("BIND_ADDRESS")
+        .unwrap_or("0.0.0.0".to_string())
+        .parse::<IpAddr>()
+        .expect("BIND_ADDRESS must be an IP address.");
     let port = std::env::var("PORT")
         .unwrap_or("8080".to_string())
         .parse::<u16>()
         .expect("PORT must be a number.");
+    let grpc_port = std::env::var("GRPC_PORT")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse::<u16>().expect("GRPC_PORT must be a number."));
+    let cors_origins = get_list("CORS_ORIGINS");
+    let cors_headers = get_list("CORS_HEADERS");
+    let tls_cert = std::env::var("TLS_CERT").ok().filter(|p| !p.is_empty());
+    let tls_key = std::env::var("TLS_KEY").ok().filter(|p| !p.is_empty());
+    assert_eq!(
+        tls_cert.is_some(),
+        tls_key.is_some(),
+        "TLS_CERT and TLS_KEY must be set together."
+    );
+    let shutdown_timeout = std::env::var("SHUTDOWN_TIMEOUT")
+        .unwrap_or("30".to_string())
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .expect("SHUTDOWN_TIMEOUT must be a number.");
     let etherscan_key = std::env::var("ETHERSCAN_KEY")
         .ok()
         .filter(|k| !k.is_empty());
-    let api_key = std::env::var("API_KEY").ok().filter(|k| !k.is_empty());
+    let api_key_policies = get_api_key_policies();
+    let mut api_keys = get_api_keys();
+    api_keys.extend(api_key_policies.keys().cloned());
+    let pool_size = std::env::var("POOL_SIZE")
+        .unwrap_or("16".to_string())
+        .parse::<usize>()
+        .expect("POOL_SIZE must be a number.");
+    let max_concurrency = match std::env::var("MAX_CONCURRENCY")
+        .ok()
+        .filter(|c| !c.is_empty())
+    {
+        Some(max_concurrency) => max_concurrency
+            .parse::<usize>()
+            .expect("MAX_CONCURRENCY must be a number."),
+        None => std::thread::available_parallelism().map_or(4, |cpus| cpus.get()),
+    };
+    let max_queue_size = std::env::var("MAX_QUEUE_SIZE")
+        .unwrap_or("100".to_string())
+        .parse::<usize>()
+        .expect("MAX_QUEUE_SIZE must be a number.");
+    let gas_estimate_buffer = std::env::var("GAS_ESTIMATE_BUFFER")
+        .unwrap_or("10".to_string())
+        .parse::<u64>()
+        .expect("GAS_ESTIMATE_BUFFER must be a number.");
+    let rate_limit = std::env::var("RATE_LIMIT")
+        .unwrap_or("0".to_string())
+        .parse::<u32>()
+        .expect("RATE_LIMIT must be a number.");
+    let max_bundle_size = std::env::var("MAX_BUNDLE_SIZE")
+        .unwrap_or("100".to_string())
+        .parse::<usize>()
+        .expect("MAX_BUNDLE_SIZE must be a number.");
+    let max_watches = std::env::var("MAX_WATCHES")
+        .unwrap_or("100".to_string())
+        .parse::<usize>()
+        .expect("MAX_WATCHES must be a number.");
+    let bundle_prefetch = std::env::var("BUNDLE_PREFETCH")
+        .unwrap_or("4".to_string())
+        .parse::<usize>()
+        .expect("BUNDLE_PREFETCH must be a number.");
+    let max_gas_limit = std::env::var("MAX_GAS_LIMIT")
+        .unwrap_or("30000000".to_string())
+        .parse::<u64>()
+        .expect("MAX_GAS_LIMIT must be a number.");
+    let simulation_timeout = std::env::var("SIMULATION_TIMEOUT")
+        .unwrap_or("30".to_string())
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .expect("SIMULATION_TIMEOUT must be a number.");
+    let fork_cache = std::env::var("FORK_CACHE").ok().filter(|p| !p.is_empty());
+    let etherscan_cache = std::env::var("ETHERSCAN_CACHE")
+        .ok()
+        .filter(|p| !p.is_empty());
+    let etherscan_cache_ttl = std::env::var("ETHERSCAN_CACHE_TTL")
+        .unwrap_or("86400".to_string())
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .expect("ETHERSCAN_CACHE_TTL must be a number.");
+    let simulation_cache_ttl = std::env::var("SIMULATION_CACHE_TTL")
+        .unwrap_or("0".to_string())
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .expect("SIMULATION_CACHE_TTL must be a number.");
+    let simulation_cache_size = std::env::var("SIMULATION_CACHE_SIZE")
+        .unwrap_or("1000".to_string())
+        .parse::<usize>()
+        .expect("SIMULATION_CACHE_SIZE must be a number.");
+    let simulation_db = std::env::var("SIMULATION_DB")
+        .ok()
+        .filter(|p| !p.is_empty());
+    let async_workers = std::env::var("ASYNC_WORKERS")
+        .unwrap_or("4".to_string())
+        .parse::<usize>()
+        .expect("ASYNC_WORKERS must be a number.");
+    let webhook_secret = std::env::var("WEBHOOK_SECRET")
+        .ok()
+        .filter(|s| !s.is_empty());
+    let allow_private_urls = std::env::var("ALLOW_PRIVATE_URLS")
+        .unwrap_or("false".to_string())
+        .parse::<bool>()
+        .expect("ALLOW_PRIVATE_URLS must be true or false.");
+    let price_api_url = std::env::var("PRICE_API_URL")
+        .ok()
+        .filter(|u| !u.is_empty());
+    let policy_rules = get_policy_rules();
+    let system_contracts = std::env::var("SYSTEM_CONTRACTS")
+        .unwrap_or("true".to_string())
+        .parse::<bool>()
+        .expect("SYSTEM_CONTRACTS must be true or false.");
+    let precompile_stubs = get_precompile_stubs();
+    let chains = get_chains();
 
     Config {
-        alchemy_key,
+        bind_address,
         port,
+        grpc_port,
+        cors_origins,
+        cors_headers,
+        tls_cert,
+        tls_key,
+        shutdown_timeout,
         etherscan_key,
-        api_key,
+        api_keys,
+        api_key_policies,
+        api_key: None,
+        api_key_policy: None,
+        pool_size,
+        max_concurrency,
+        max_queue_size,
+        gas_estimate_buffer,
+        rate_limit,
+        max_bundle_size,
+        max_watches,
+        bundle_prefetch,
+        max_gas_limit,
+        simulation_timeout,
+        fork_cache,
+        etherscan_cache,
+        etherscan_cache_ttl,
+        simulation_cache_ttl,
+        simulation_cache_size,
+        simulation_db,
+        async_workers,
+        webhook_secret,
+        allow_private_urls,
+        price_api_url,
+        policy_rules,
+        system_contracts,
+        precompile_stubs,
+        chains,
+        registry: ChainRegistry::default(),
+    }
+}
+
+impl Config {
+    /// The config of a request made with `api_key`, lowered to the limits of its policy.
+    pub fn for_api_key(&self, api_key: Option<&str>) -> Config {
+        let Some(policy) = api_key.and_then(|key| self.api_key_policies.get(key)) else {
+            return Config {
+                api_key: api_key.map(str::to_string),
+                ..self.clone()
+            };
+        };
+        Config {
+            api_key: api_key.map(str::to_string),
+            max_gas_limit: policy
+                .max_gas_limit
+                .map_or(self.max_gas_limit, |max| max.min(self.max_gas_limit)),
+            max_bundle_size: policy
+                .max_bundle_size
+                .map_or(self.max_bundle_size, |max| max.min(self.max_bundle_size)),
+            api_key_policy: Some(policy.clone()),
+            ..self.clone()
+        }
     }
 }
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use ethers::abi::{decode, Address, ParamType, Token};
+use ethers::types::I256;
+use ethers::utils::{hex, id, to_checksum};
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall};
+use once_cell::sync::Lazy;
+
+/// Address `console.sol` of Hardhat and Foundry calls, "console.log" in ASCII.
+pub const CONSOLE_ADDRESS: Address = Address([
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0x63, 0x6f, 0x6e, 0x73, 0x6f, 0x6c, 0x65, 0x2e, 0x6c, 0x6f, 0x67,
+]);
+
+/// Parameter types of every `console.log` overload by selector. Older versions of `console.sol`
+/// hash the signatures with `uint` and `int` rather than `uint256` and `int256`, both are known.
+static SIGNATURES: Lazy<HashMap<[u8; 4], Vec<ParamType>>> = Lazy::new(|| {
+    let mut signatures = HashMap::new();
+    let mut add = |name: &str, params: &[(&str, ParamType)]| {
+        let names: Vec<&str> = params.iter().map(|(name, _)| *name).collect();
+        let kinds = params.iter().map(|(_, kind)| kind.clone()).collect();
+        signatures.insert(id(format!("{name}({})", names.join(","))), kinds);
+    };
+
+    let string = ("string", ParamType::String);
+    let bool = ("bool", ParamType::Bool);
+    let address = ("address", ParamType::Address);
+    let bytes = ("bytes", ParamType::Bytes);
+
+    add("log", &[]);
+    for (uint, int) in [("uint256", "int256"), ("uint", "int")] {
+        let uint = (uint, ParamType::Uint(256));
+        let int = (int, ParamType::Int(256));
+        add("log", &[int.clone()]);
+        add("logUint", &[uint.clone()]);
+        add("logInt", &[int]);
+
+        // Up to 4 parameters of these types, in any order
+        let kinds = [uint, string.clone(), bool.clone(), address.clone()];
+        let mut combinations: Vec<Vec<(&str, ParamType)>> = vec![vec![]];
+        for _ in 0..4 {
+            combinations = combinations
+                .iter()
+                .flat_map(|combination| {
+                    kinds.iter().map(move |kind| {
+                        let mut combination = combination.clone();
+                        combination.push(kind.clone());
+                        combination
+                    })
+                })
+                .collect();
+            for combination in &combinations {
+                add("log", combination);
+            }
+        }
+    }
+    add("log", &[bytes.clone()]);
+    add("logString", &[string]);
+    add("logBool", &[bool]);
+    add("logAddress", &[address]);
+    add("logBytes", &[bytes]);
+    for size in 1..=32 {
+        let name = format!("bytes{size}");
+        add(
+            &format!("logBytes{size}"),
+            &[(name.as_str(), ParamType::FixedBytes(size))],
+        );
+    }
+
+    signatures
+});
+
+fn format_token(token: &Token) -> String {
+    match token {
+        Token::String(value) => value.clone(),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(*value).to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::Address(value) => to_checksum(value, None),
+        Token::Bytes(value) | Token::FixedBytes(value) => format!("0x{}", hex::encode(value)),
+        token => token.to_string(),
+    }
+}
+
+/// Replaces the `%s`, `%d`, `%i` and `%o` specifiers of `format` by the next values.
+fn substitute(format: &str, values: &mut impl Iterator<Item = String>) -> String {
+    let mut output = String::with_capacity(format.len());
+    let mut chars = format.chars().peekable();
+    while let Some(char) = chars.next() {
+        match (char, chars.peek()) {
+            ('%', Some('s' | 'd' | 'i' | 'o')) => {
+                let specifier = chars.next().unwrap_or_default();
+                match values.next() {
+                    Some(value) => output.push_str(&value),
+                    None => {
+                        output.push('%');
+                        output.push(specifier);
+                    }
+                }
+            }
+            ('%', Some('%')) => {
+                chars.next();
+                output.push('%');
+            }
+            (char, _) => output.push(char),
+        }
+    }
+    output
+}
+
+/// Formats the arguments like Foundry and Hardhat print them: a leading string is a format string
+/// if more values follow, values left over are appended separated by spaces.
+fn format_log(tokens: &[Token]) -> String {
+    let mut values = tokens.iter().map(format_token);
+    let mut output = match tokens.first() {
+        Some(Token::String(format)) if tokens.len() > 1 => {
+            values.next();
+            substitute(format, &mut values)
+        }
+        _ => values.next().unwrap_or_default(),
+    };
+    for value in values {
+        output.push(' ');
+        output.push_str(&value);
+    }
+    output
+}
+
+/// Messages of the `console.log` calls in the trace, in the order they were made, including
+/// those of frames which reverted.
+pub fn console_logs(arena: &CallTraceArena) -> Vec<String> {
+    arena
+        .arena
+        .iter()
+        .filter(|node| node.trace.address == CONSOLE_ADDRESS)
+        .filter_map(|node| {
+            let RawOrDecodedCall::Raw(input) = &node.trace.data else {
+                return None;
+            };
+            let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+            let kinds = SIGNATURES.get(&selector)?;
+            let tokens = decode(kinds, &input[4..]).ok()?;
+            Some(format_log(&tokens))
+        })
+        .collect()
+}
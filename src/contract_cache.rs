@@ -0,0 +1,305 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::abi::{Abi, Address};
+use eyre::Result;
+use foundry_evm::trace::identifier::{AddressIdentity, EtherscanIdentifier, TraceIdentifier};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::config::Config;
+use super::metrics::record_etherscan_request;
+
+/// How long an address Etherscan knew nothing about is cached, shorter than verified contracts as
+/// contracts get verified later on and failed requests look the same.
+const UNKNOWN_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// What Etherscan knows of a contract, `None` fields if it isn't verified.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContractInfo {
+    pub name: Option<String>,
+    pub label: Option<String>,
+    pub abi: Option<Abi>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    /// `None` if Etherscan didn't identify the address.
+    info: Option<ContractInfo>,
+    fetched_at: SystemTime,
+}
+
+/// Contracts identified by Etherscan for trace formatting, shared by every `Evm` of a pool so that
+/// a contract is fetched once per TTL rather than once per simulation. Optionally persisted in
+/// SQLite, keyed by chain and address.
+pub struct ContractCache {
+    entries: Mutex<HashMap<(u64, Address), Entry>>,
+    /// Held while an address is fetched, so that concurrent simulations of the same contracts wait
+    /// for the first request rather than each making their own.
+    fetching: Mutex<HashMap<(u64, Address), Arc<Mutex<()>>>>,
+    #[cfg(feature = "sqlite")]
+    connection: Option<Mutex<Connection>>,
+    ttl: Duration,
+}
+
+impl ContractCache {
+    pub fn new(ttl: Duration) -> Self {
+        ContractCache {
+            entries: Default::default(),
+            fetching: Default::default(),
+            #[cfg(feature = "sqlite")]
+            connection: None,
+            ttl,
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn open(path: &str, ttl: Duration) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS contract_cache (
+                chain_id INTEGER NOT NULL,
+                address TEXT NOT NULL,
+                info TEXT,
+                fetched_at INTEGER NOT NULL,
+                PRIMARY KEY (chain_id, address)
+            );",
+        )?;
+
+        Ok(ContractCache {
+            connection: Some(Mutex::new(connection)),
+            ..Self::new(ttl)
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        match &config.etherscan_cache {
+            #[cfg(feature = "sqlite")]
+            Some(path) => {
+                log::info!(target: "ts::contract_cache", "Caching Etherscan contracts in {path}");
+                Self::open(path, config.etherscan_cache_ttl)
+                    .expect("ETHERSCAN_CACHE must be a valid SQLite database.")
+            }
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => panic!("ETHERSCAN_CACHE requires the sqlite feature."),
+            None => Self::new(config.etherscan_cache_ttl),
+        }
+    }
+
+    /// Wraps `identifier` so that it only asks Etherscan for the contracts not cached.
+    pub(crate) fn identifier<'a>(
+        &'a self,
+        chain_id: u64,
+        identifier: &'a mut EtherscanIdentifier,
+    ) -> CachedIdentifier<'a> {
+        CachedIdentifier {
+            cache: self,
+            chain_id,
+            identifier,
+        }
+    }
+
+    fn is_fresh(&self, entry: &Entry) -> bool {
+        let ttl = match entry.info {
+            Some(_) => self.ttl,
+            None => self.ttl.min(UNKNOWN_TTL),
+        };
+        entry
+            .fetched_at
+            .elapsed()
+            .map_or(true, |elapsed| elapsed < ttl)
+    }
+
+    fn get(&self, chain_id: u64, address: Address) -> Option<Option<ContractInfo>> {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&(chain_id, address))
+            .cloned();
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                let entry = match self.read(chain_id, address) {
+                    Ok(entry) => entry?,
+                    Err(err) => {
+                        log::warn!(target: "ts::contract_cache", "Failed to read cache: {err}");
+                        return None;
+                    }
+                };
+                self.entries
+                    .lock()
+                    .unwrap()
+                    .insert((chain_id, address), entry.clone());
+                entry
+            }
+        };
+        self.is_fresh(&entry).then_some(entry.info)
+    }
+
+    fn insert(&self, chain_id: u64, address: Address, info: Option<ContractInfo>) {
+        let entry = Entry {
+            info,
+            fetched_at: SystemTime::now(),
+        };
+        if let Err(err) = self.write(chain_id, address, &entry) {
+            log::warn!(target: "ts::contract_cache", "Failed to write cache: {err}");
+        }
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((chain_id, address), entry);
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn read(&self, _chain_id: u64, _address: Address) -> Result<Option<Entry>> {
+        Ok(None)
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn write(&self, _chain_id: u64, _address: Address, _entry: &Entry) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn read(&self, chain_id: u64, address: Address) -> Result<Option<Entry>> {
+        let Some(connection) = &self.connection else {
+            return Ok(None);
+        };
+        let row: Option<(Option<String>, u64)> = connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT info, fetched_at FROM contract_cache WHERE chain_id = ?1 AND address = ?2",
+                params![chain_id, format!("{address:?}")],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((info, fetched_at)) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(Entry {
+            info: info.map(|info| serde_json::from_str(&info)).transpose()?,
+            fetched_at: UNIX_EPOCH + Duration::from_secs(fetched_at),
+        }))
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn write(&self, chain_id: u64, address: Address, entry: &Entry) -> Result<()> {
+        let Some(connection) = &self.connection else {
+            return Ok(());
+        };
+        let info = entry.info.as_ref().map(serde_json::to_string).transpose()?;
+        let fetched_at = entry.fetched_at.duration_since(UNIX_EPOCH)?.as_secs();
+        connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO contract_cache (chain_id, address, info, fetched_at)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![chain_id, format!("{address:?}"), info, fetched_at],
+        )?;
+        Ok(())
+    }
+
+    fn lock(&self, chain_id: u64, address: Address) -> Arc<Mutex<()>> {
+        self.fetching
+            .lock()
+            .unwrap()
+            .entry((chain_id, address))
+            .or_default()
+            .clone()
+    }
+}
+
+/// Trace identifier answering from a [`ContractCache`], and from Etherscan for the rest.
+pub(crate) struct CachedIdentifier<'a> {
+    cache: &'a ContractCache,
+    chain_id: u64,
+    identifier: &'a mut EtherscanIdentifier,
+}
+
+impl CachedIdentifier<'_> {
+    fn identity(address: Address, info: ContractInfo) -> AddressIdentity<'static> {
+        AddressIdentity {
+            address,
+            label: info.label,
+            contract: info.name,
+            abi: info.abi.map(Cow::Owned),
+            artifact_id: None,
+        }
+    }
+}
+
+impl TraceIdentifier for CachedIdentifier<'_> {
+    fn identify_addresses(
+        &mut self,
+        addresses: Vec<(&Address, Option<&[u8]>)>,
+    ) -> Vec<AddressIdentity> {
+        let chain_id = self.chain_id;
+        let mut identities = vec![];
+        let mut missing = vec![];
+        for (address, code) in addresses {
+            match self.cache.get(chain_id, *address) {
+                Some(info) => {
+                    record_etherscan_request(chain_id, "hit");
+                    identities.extend(info.map(|info| Self::identity(*address, info)));
+                }
+                None => missing.push((address, code)),
+            }
+        }
+        if missing.is_empty() {
+            return identities;
+        }
+
+        // Locked in address order so that two simulations can't wait on each other
+        missing.sort_by_key(|(address, _)| **address);
+        missing.dedup_by_key(|(address, _)| **address);
+        let locks: Vec<_> = missing
+            .iter()
+            .map(|(address, _)| self.cache.lock(chain_id, **address))
+            .collect();
+        let _guards: Vec<_> = locks.iter().map(|lock| lock.lock().unwrap()).collect();
+
+        // Fetched by another simulation while waiting
+        let mut to_fetch = vec![];
+        for (address, code) in missing {
+            match self.cache.get(chain_id, *address) {
+                Some(info) => {
+                    record_etherscan_request(chain_id, "hit");
+                    identities.extend(info.map(|info| Self::identity(*address, info)));
+                }
+                None => to_fetch.push((address, code)),
+            }
+        }
+        if to_fetch.is_empty() {
+            return identities;
+        }
+
+        let fetched_addresses: Vec<Address> =
+            to_fetch.iter().map(|(address, _)| **address).collect();
+        let fetched: HashMap<Address, ContractInfo> = self
+            .identifier
+            .identify_addresses(to_fetch)
+            .into_iter()
+            .map(|identity| {
+                let info = ContractInfo {
+                    name: identity.contract,
+                    label: identity.label,
+                    abi: identity.abi.map(Cow::into_owned),
+                };
+                (identity.address, info)
+            })
+            .collect();
+
+        for address in fetched_addresses {
+            record_etherscan_request(chain_id, "miss");
+            let info = fetched.get(&address).cloned();
+            self.cache.insert(chain_id, address, info.clone());
+            identities.extend(info.map(|info| Self::identity(address, info)));
+        }
+
+        identities
+    }
+}
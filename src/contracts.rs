@@ -0,0 +1,64 @@
+use ethers::abi::{Address, Hash};
+use ethers::utils::keccak256;
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall, RawOrDecodedReturnData};
+use foundry_evm::CallKind;
+use serde::{Deserialize, Serialize};
+
+/// A contract deployed by a create frame, including the top level call of a deployment.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CreatedContract {
+    /// Account which ran the create, the sender for a deployment transaction.
+    pub creator: Address,
+    pub address: Address,
+    #[serde(rename = "callType")]
+    pub call_type: CallKind,
+    #[serde(rename = "initCodeHash")]
+    pub init_code_hash: Hash,
+    /// Size of the runtime bytecode, 0 if the deployment failed.
+    #[serde(rename = "codeSize")]
+    pub code_size: usize,
+    /// Whether the contract is left deployed, false if its create frame or any frame above it
+    /// reverted.
+    pub success: bool,
+}
+
+fn collect_created_contracts(
+    arena: &CallTraceArena,
+    idx: usize,
+    parent_success: bool,
+    contracts: &mut Vec<CreatedContract>,
+) {
+    let node = &arena.arena[idx];
+    let success = parent_success && node.trace.success;
+    if matches!(node.trace.kind, CallKind::Create | CallKind::Create2) {
+        let init_code: &[u8] = match &node.trace.data {
+            RawOrDecodedCall::Raw(init_code) => init_code,
+            RawOrDecodedCall::Decoded(..) => &[],
+        };
+        // The tracer records the runtime bytecode as the output of a create frame
+        let code_size = match &node.trace.output {
+            RawOrDecodedReturnData::Raw(code) if node.trace.success => code.len(),
+            _ => 0,
+        };
+        contracts.push(CreatedContract {
+            creator: node.trace.caller,
+            address: node.trace.address,
+            call_type: node.trace.kind,
+            init_code_hash: Hash::from(keccak256(init_code)),
+            code_size,
+            success,
+        });
+    }
+    for child in &node.children {
+        collect_created_contracts(arena, *child, success, contracts);
+    }
+}
+
+/// Every contract the transaction created, in execution order, whether or not it was kept.
+pub fn created_contracts(arena: &CallTraceArena) -> Vec<CreatedContract> {
+    let mut contracts = vec![];
+    if !arena.arena.is_empty() {
+        collect_created_contracts(arena, 0, true, &mut contracts);
+    }
+    contracts
+}
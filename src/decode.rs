@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use ethers::abi::{Event, Function, Hash, RawLog, Token};
+use ethers::types::{Log, I256};
+use ethers::utils::hex;
+
+use crate::simulation::{DecodedCall, DecodedCallParam, DecodedLog, DecodedLogParam};
+
+/// Formats a decoded ABI value the way it is written in Solidity, e.g. addresses as checksummed
+/// hex and integers as decimals.
+pub fn format_token(token: &Token) -> String {
+    match token {
+        Token::Address(address) => ethers::utils::to_checksum(address, None),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(*value).to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => value.clone(),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        Token::Array(tokens) | Token::FixedArray(tokens) => format!("[{}]", format_tokens(tokens)),
+        Token::Tuple(tokens) => format!("({})", format_tokens(tokens)),
+    }
+}
+
+fn format_tokens(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(format_token)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Decodes a log against the known events, keyed by `(topic0, number of indexed params)` as the
+/// trace decoder stores them. Logs without a matching event are returned undecoded.
+pub fn decode_log(events: &BTreeMap<(Hash, usize), Vec<Event>>, log: &Log) -> DecodedLog {
+    let candidates = log
+        .topics
+        .first()
+        .and_then(|topic| events.get(&(*topic, log.topics.len() - 1)));
+
+    let decoded = candidates.into_iter().flatten().find_map(|event| {
+        let parsed = event
+            .parse_log(RawLog {
+                topics: log.topics.clone(),
+                data: log.data.to_vec(),
+            })
+            .ok()?;
+        let params = parsed
+            .params
+            .into_iter()
+            .zip(&event.inputs)
+            .map(|(param, input)| DecodedLogParam {
+                name: param.name,
+                kind: input.kind.to_string(),
+                value: format_token(&param.value),
+                indexed: input.indexed,
+            })
+            .collect();
+        Some((event.name.clone(), params))
+    });
+
+    let (name, params) = match decoded {
+        Some((name, params)) => (Some(name), params),
+        None => (None, vec![]),
+    };
+
+    DecodedLog {
+        name,
+        params,
+        raw: log.clone(),
+    }
+}
+
+/// Decodes the output of a call against the functions matching the selector of its calldata.
+pub fn decode_return_data(
+    functions: &BTreeMap<[u8; 4], Vec<Function>>,
+    calldata: &[u8],
+    output: &[u8],
+) -> Option<Vec<String>> {
+    let selector: [u8; 4] = calldata.get(..4)?.try_into().ok()?;
+
+    functions.get(&selector)?.iter().find_map(|function| {
+        let tokens = function.decode_output(output).ok()?;
+        Some(tokens.iter().map(format_token).collect())
+    })
+}
+
+/// Decodes calldata against the first of the functions sharing its selector its arguments
+/// decode with.
+pub fn decode_call(functions: &[Function], input: &[u8]) -> Option<DecodedCall> {
+    let args = input.get(4..)?;
+
+    functions.iter().find_map(|function| {
+        let tokens = function.decode_input(args).ok()?;
+        let params = tokens
+            .iter()
+            .zip(&function.inputs)
+            .map(|(token, input)| DecodedCallParam {
+                name: input.name.clone(),
+                kind: input.kind.to_string(),
+                value: format_token(token),
+            })
+            .collect();
+        Some(DecodedCall {
+            name: function.name.clone(),
+            signature: function.signature(),
+            params,
+        })
+    })
+}
@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use ethers::abi::{Address, Hash, Uint};
+use ethers::types::{Bytes, Log};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::assets::AssetChange;
+use crate::simulation::{
+    chain_id_to_fork_url, run, AccountDiff, CallTrace, SimulationRequest, SimulationResponse,
+};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// A stored simulation, or a request simulated for the diff.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DiffTarget {
+    SimulationId(Uuid),
+    Request(Box<SimulationRequest>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffRequest {
+    pub base: DiffTarget,
+    pub compare: DiffTarget,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Comparison<T> {
+    pub base: T,
+    pub compare: T,
+}
+
+/// Items only found in one of the simulations, duplicates are counted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ListDiff<T> {
+    /// Only in the base simulation.
+    pub removed: Vec<T>,
+    /// Only in the compared simulation.
+    pub added: Vec<T>,
+}
+
+/// Values an account ends up with which differ between the simulations. A side is `null` if its
+/// simulation left the value unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccountComparison {
+    pub address: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<Comparison<Option<Uint>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<Comparison<Option<u64>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Comparison<Option<Bytes>>>,
+    #[serde(default)]
+    pub storage: BTreeMap<Hash, Comparison<Option<Hash>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimulationDiff {
+    #[serde(rename = "simulationIds")]
+    pub simulation_ids: Comparison<Uuid>,
+    pub success: Comparison<bool>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: Comparison<u64>,
+    /// Gas used by the compared simulation minus the base one.
+    #[serde(rename = "gasDelta")]
+    pub gas_delta: i64,
+    pub calls: ListDiff<CallTrace>,
+    pub logs: ListDiff<Log>,
+    #[serde(rename = "assetChanges")]
+    pub asset_changes: ListDiff<AssetChange>,
+    /// Only set if both simulations have a `stateDiff`.
+    #[serde(
+        rename = "stateChanges",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub state_changes: Option<Vec<AccountComparison>>,
+}
+
+/// Compares items by their JSON, which all of them have, rather than requiring `Hash`.
+fn list_diff<T: Serialize + Clone>(base: &[T], compare: &[T]) -> ListDiff<T> {
+    let key = |item: &T| serde_json::to_string(item).unwrap_or_default();
+
+    let mut unmatched: HashMap<String, usize> = HashMap::new();
+    for item in compare {
+        *unmatched.entry(key(item)).or_default() += 1;
+    }
+
+    let mut removed = vec![];
+    for item in base {
+        match unmatched.get_mut(&key(item)) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => removed.push(item.clone()),
+        }
+    }
+
+    let mut added = vec![];
+    for item in compare {
+        if let Some(count) = unmatched.get_mut(&key(item)).filter(|count| **count > 0) {
+            *count -= 1;
+            added.push(item.clone());
+        }
+    }
+
+    ListDiff { removed, added }
+}
+
+fn compare_value<T: PartialEq + Clone>(
+    base: Option<&T>,
+    compare: Option<&T>,
+) -> Option<Comparison<Option<T>>> {
+    (base != compare).then(|| Comparison {
+        base: base.cloned(),
+        compare: compare.cloned(),
+    })
+}
+
+fn compare_state(base: &[AccountDiff], compare: &[AccountDiff]) -> Vec<AccountComparison> {
+    let base: BTreeMap<Address, &AccountDiff> = base.iter().map(|a| (a.address, a)).collect();
+    let compare: BTreeMap<Address, &AccountDiff> = compare.iter().map(|a| (a.address, a)).collect();
+    let addresses: BTreeSet<Address> = base.keys().chain(compare.keys()).copied().collect();
+
+    addresses
+        .into_iter()
+        .filter_map(|address| {
+            let base = base.get(&address);
+            let compare = compare.get(&address);
+
+            let balance = compare_value(
+                base.and_then(|a| a.balance.as_ref()).map(|diff| &diff.post),
+                compare
+                    .and_then(|a| a.balance.as_ref())
+                    .map(|diff| &diff.post),
+            );
+            let nonce = compare_value(
+                base.and_then(|a| a.nonce.as_ref()).map(|diff| &diff.post),
+                compare
+                    .and_then(|a| a.nonce.as_ref())
+                    .map(|diff| &diff.post),
+            );
+            let code = compare_value(
+                base.and_then(|a| a.code.as_ref()).map(|diff| &diff.post),
+                compare.and_then(|a| a.code.as_ref()).map(|diff| &diff.post),
+            );
+            let slots: BTreeSet<&Hash> = base
+                .iter()
+                .chain(compare.iter())
+                .flat_map(|a| a.storage.keys())
+                .collect();
+            let storage: BTreeMap<Hash, Comparison<Option<Hash>>> = slots
+                .into_iter()
+                .filter_map(|slot| {
+                    let value = compare_value(
+                        base.and_then(|a| a.storage.get(slot))
+                            .map(|diff| &diff.post),
+                        compare
+                            .and_then(|a| a.storage.get(slot))
+                            .map(|diff| &diff.post),
+                    )?;
+                    Some((*slot, value))
+                })
+                .collect();
+
+            if balance.is_none() && nonce.is_none() && code.is_none() && storage.is_empty() {
+                return None;
+            }
+            Some(AccountComparison {
+                address,
+                balance,
+                nonce,
+                code,
+                storage,
+            })
+        })
+        .collect()
+}
+
+fn diff_simulations(base: &SimulationResponse, compare: &SimulationResponse) -> SimulationDiff {
+    let state_changes = match (&base.state_diff, &compare.state_diff) {
+        (Some(base), Some(compare)) => Some(compare_state(base, compare)),
+        _ => None,
+    };
+
+    SimulationDiff {
+        simulation_ids: Comparison {
+            base: base.simulation_id,
+            compare: compare.simulation_id,
+        },
+        success: Comparison {
+            base: base.success,
+            compare: compare.success,
+        },
+        gas_used: Comparison {
+            base: base.gas_used,
+            compare: compare.gas_used,
+        },
+        gas_delta: compare.gas_used as i64 - base.gas_used as i64,
+        calls: list_diff(&base.trace, &compare.trace),
+        logs: list_diff(&base.logs, &compare.logs),
+        asset_changes: list_diff(&base.asset_changes, &compare.asset_changes),
+        state_changes,
+    }
+}
+
+/// Loads a stored simulation, or simulates a request with its state diff and stores it.
+async fn resolve(
+    target: DiffTarget,
+    config: &Config,
+    pool: &EvmPool,
+    history: &History,
+) -> Result<SimulationResponse, Rejection> {
+    let transaction = match target {
+        DiffTarget::SimulationId(id) => return Ok(history.get(id)?.response),
+        DiffTarget::Request(transaction) => SimulationRequest {
+            state_diff: Some(true),
+            ..*transaction
+        },
+    };
+
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
+        config.etherscan_key.clone(),
+    )?;
+
+    let response = run(&mut evm, transaction.clone(), false).await?;
+    history.record(&transaction, &response);
+
+    Ok(response)
+}
+
+pub async fn diff(
+    request: DiffRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    let base = resolve(request.base, &config, &pool, &history).await?;
+    let compare = resolve(request.compare, &config, &pool, &history).await?;
+
+    Ok(warp::reply::json(&diff_simulations(&base, &compare)))
+}
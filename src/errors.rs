@@ -1,98 +1,650 @@
+use ethers::abi::{Address, Hash, Uint};
 use eyre::Report;
 use serde::{Deserialize, Serialize};
-use std::{convert::Infallible, error::Error};
+use serde_json::{json, Value};
+use std::{convert::Infallible, error::Error, fmt, time::Duration};
 
+use warp::http::header::{HeaderValue, RETRY_AFTER};
 use warp::{body::BodyDeserializeError, hyper::StatusCode, reject::Reject, Rejection, Reply};
 
-#[derive(Serialize, Deserialize)]
+use crate::policy::{PolicyAction, PolicyDecision};
+use crate::validation::{FieldError, SUPPORTED_API_VERSIONS};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ErrorMessage {
     pub code: u16,
     pub message: String,
+    /// Context of the error, e.g. the unsupported chain ID or the revert reason.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
 }
 
 #[derive(Debug)]
-pub struct FromHexError;
+pub struct ForkNotFoundError;
+
+impl Reject for ForkNotFoundError {}
+
+#[derive(Debug)]
+pub struct SnapshotNotFoundError;
+
+impl Reject for SnapshotNotFoundError {}
+
+#[derive(Debug)]
+pub struct ChainIdMismatchError;
+
+impl Reject for ChainIdMismatchError {}
+
+#[derive(Debug)]
+pub struct InvalidRawTransactionError;
+
+impl Reject for InvalidRawTransactionError {}
+
+#[derive(Debug)]
+pub struct ExecutionRevertedError(pub Option<String>);
+
+impl Reject for ExecutionRevertedError {}
+
+#[derive(Debug)]
+pub struct SimulationNotFoundError;
 
-impl Reject for FromHexError {}
+impl Reject for SimulationNotFoundError {}
 
 #[derive(Debug)]
-pub struct FromDecStrError;
+pub struct InvalidCallbackUrlError(pub String);
 
-impl Reject for FromDecStrError {}
+impl Reject for InvalidCallbackUrlError {}
 
 #[derive(Debug)]
-pub struct NoURLForChainIdError;
+pub struct JobNotFoundError;
+
+impl Reject for JobNotFoundError {}
+
+#[derive(Debug)]
+pub struct InvalidChainError(pub String);
+
+impl Reject for InvalidChainError {}
+
+#[derive(Debug)]
+pub struct ChainAlreadyRegisteredError(pub u64);
+
+impl Reject for ChainAlreadyRegisteredError {}
+
+#[derive(Debug)]
+pub struct HistoryError(pub Report);
+
+impl Reject for HistoryError {}
+
+#[derive(Debug)]
+pub struct AbiStoreError(pub Report);
+
+impl Reject for AbiStoreError {}
+
+#[derive(Debug)]
+pub struct AbiNotFoundError;
+
+impl Reject for AbiNotFoundError {}
+
+#[derive(Debug)]
+pub struct InvalidAbiError(pub String);
+
+impl Reject for InvalidAbiError {}
+
+#[derive(Debug)]
+pub struct TransactionNotFoundError;
+
+impl Reject for TransactionNotFoundError {}
+
+#[derive(Debug)]
+pub struct BlockNotFoundError(pub Hash);
+
+impl Reject for BlockNotFoundError {}
+
+#[derive(Debug)]
+pub struct ForksNotAllowedError;
+
+impl Reject for ForksNotAllowedError {}
+
+#[derive(Debug)]
+pub struct RpcError(pub Report);
+
+impl Reject for RpcError {}
+
+#[derive(Debug)]
+pub struct BalanceSlotNotFoundError;
+
+impl Reject for BalanceSlotNotFoundError {}
+
+#[derive(Debug)]
+pub struct MissingApiKeyError;
+
+impl Reject for MissingApiKeyError {}
+
+#[derive(Debug)]
+pub struct InvalidApiKeyError;
+
+impl Reject for InvalidApiKeyError {}
+
+#[derive(Debug)]
+pub struct RateLimitedError {
+    /// Seconds until the next request is accepted.
+    pub retry_after: u64,
+}
+
+impl Reject for RateLimitedError {}
+
+/// `MAX_QUEUE_SIZE` requests are already waiting.
+#[derive(Debug)]
+pub struct QueueFullError {
+    /// Seconds until the request should be retried.
+    pub retry_after: u64,
+}
 
-impl Reject for NoURLForChainIdError {}
+impl Reject for QueueFullError {}
 
 #[derive(Debug)]
-pub struct MultipleChainIdsError();
+pub struct BundleTooLargeError;
 
-impl Reject for MultipleChainIdsError {}
+impl Reject for BundleTooLargeError {}
 
 #[derive(Debug)]
-pub struct MultipleBlockNumbersError();
+pub struct WatchNotFoundError;
 
-impl Reject for MultipleBlockNumbersError {}
+impl Reject for WatchNotFoundError {}
+
+/// `MAX_WATCHES` watches are already running.
+#[derive(Debug)]
+pub struct TooManyWatchesError;
+
+impl Reject for TooManyWatchesError {}
+
+#[derive(Debug)]
+pub struct InvalidSweepError(pub String);
+
+impl Reject for InvalidSweepError {}
+
+/// A body which isn't valid JSON or a valid unversioned request.
+#[derive(Debug)]
+pub struct InvalidBodyError(pub serde_json::Error);
+
+impl Reject for InvalidBodyError {}
+
+/// Every field of a versioned request which failed validation.
+#[derive(Debug)]
+pub struct InvalidRequestError(pub Vec<FieldError>);
+
+impl Reject for InvalidRequestError {}
+
+#[derive(Debug)]
+pub struct UnsupportedApiVersionError(pub Value);
+
+impl Reject for UnsupportedApiVersionError {}
 
 #[derive(Debug)]
 pub struct EvmError(pub Report);
 
 impl Reject for EvmError {}
 
+/// Errors of the simulation itself, independent of the HTTP server so that the simulator can be
+/// embedded as a library.
+#[derive(Debug)]
+pub enum SimulationError {
+    FromHex,
+    FromDecStr,
+    ChainIdNotSupported(u64),
+    /// The policy of the API key doesn't allow the chain.
+    ChainNotAllowed(u64),
+    MultipleChainIds,
+    BlockNumberDecreasing,
+    /// A bundle without any transaction, which has no chain to fork.
+    EmptyBundle,
+    NonceTooLow {
+        nonce: u64,
+        /// Nonce of the sender.
+        expected: u64,
+    },
+    NonceTooHigh {
+        nonce: u64,
+        /// Nonce of the sender.
+        expected: u64,
+    },
+    InsufficientFunds {
+        balance: Uint,
+        /// Value plus the gas limit at the highest gas price the transaction may pay.
+        cost: Uint,
+    },
+    GasLimitExceeded {
+        gas_limit: u64,
+        block_gas_limit: Uint,
+    },
+    GasLimitTooHigh {
+        gas_limit: u64,
+        max_gas_limit: u64,
+    },
+    Timeout(Duration),
+    InvalidBlobTransaction(&'static str),
+    InvalidAuthorizationList(&'static str),
+    BlobFeeTooLow {
+        max_fee_per_blob_gas: Uint,
+        blob_base_fee: Uint,
+    },
+    /// The sender has code, which EIP-3607 forbids.
+    SenderNotEoa(Address),
+    /// `blockHash` isn't a block the RPC knows.
+    BlockNotFound(Hash),
+    /// A policy rejected the simulation, with the decisions of every policy.
+    PolicyViolation(Vec<PolicyDecision>),
+    /// The RPC of the fork failed, e.g. fetching its pending transactions.
+    Rpc(Report),
+    Evm(Report),
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::FromHex => write!(f, "invalid hex value"),
+            SimulationError::FromDecStr => write!(f, "invalid decimal value"),
+            SimulationError::ChainIdNotSupported(chain_id) => {
+                write!(f, "chain {chain_id} is not supported")
+            }
+            SimulationError::ChainNotAllowed(chain_id) => {
+                write!(f, "chain {chain_id} is not allowed for the API key")
+            }
+            SimulationError::MultipleChainIds => write!(f, "transactions are on several chains"),
+            SimulationError::BlockNumberDecreasing => write!(f, "block numbers are decreasing"),
+            SimulationError::EmptyBundle => write!(f, "bundle has no transactions"),
+            SimulationError::NonceTooLow { nonce, expected } => {
+                write!(f, "nonce {nonce} too low, expected {expected}")
+            }
+            SimulationError::NonceTooHigh { nonce, expected } => {
+                write!(f, "nonce {nonce} too high, expected {expected}")
+            }
+            SimulationError::InsufficientFunds { balance, cost } => {
+                write!(f, "insufficient funds, balance {balance} but costs {cost}")
+            }
+            SimulationError::GasLimitExceeded {
+                gas_limit,
+                block_gas_limit,
+            } => write!(
+                f,
+                "gas limit {gas_limit} exceeds the block gas limit {block_gas_limit}"
+            ),
+            SimulationError::GasLimitTooHigh {
+                gas_limit,
+                max_gas_limit,
+            } => write!(
+                f,
+                "gas limit {gas_limit} above the maximum of {max_gas_limit}"
+            ),
+            SimulationError::Timeout(timeout) => {
+                write!(f, "simulation timed out after {}ms", timeout.as_millis())
+            }
+            SimulationError::BlockNotFound(block_hash) => {
+                write!(f, "block {block_hash:?} not found")
+            }
+            SimulationError::InvalidBlobTransaction(reason) => {
+                write!(f, "invalid blob transaction: {reason}")
+            }
+            SimulationError::InvalidAuthorizationList(reason) => {
+                write!(f, "invalid authorization list: {reason}")
+            }
+            SimulationError::BlobFeeTooLow {
+                max_fee_per_blob_gas,
+                blob_base_fee,
+            } => write!(
+                f,
+                "max fee per blob gas {max_fee_per_blob_gas} below the blob base fee {blob_base_fee}"
+            ),
+            SimulationError::SenderNotEoa(from) => {
+                write!(f, "sender {from:?} is a contract")
+            }
+            SimulationError::PolicyViolation(decisions) => {
+                let rejected: Vec<&str> = decisions
+                    .iter()
+                    .filter(|decision| decision.action == PolicyAction::Reject)
+                    .map(|decision| decision.reason.as_str())
+                    .collect();
+                write!(f, "rejected by policy: {}", rejected.join(", "))
+            }
+            SimulationError::Rpc(err) => write!(f, "RPC error: {err}"),
+            SimulationError::Evm(err) => write!(f, "EVM error: {err}"),
+        }
+    }
+}
+
+impl Error for SimulationError {}
+
+impl From<EvmError> for SimulationError {
+    fn from(err: EvmError) -> Self {
+        SimulationError::Evm(err.0)
+    }
+}
+
+impl From<BlockNotFoundError> for SimulationError {
+    fn from(err: BlockNotFoundError) -> Self {
+        SimulationError::BlockNotFound(err.0)
+    }
+}
+
+impl Reject for SimulationError {}
+
+/// How the API answers the error, for those reported in a response rather than rejecting it,
+/// e.g. the status of a transaction of a bundle.
+impl From<&SimulationError> for ErrorMessage {
+    fn from(err: &SimulationError) -> Self {
+        let (code, message, details) = simulation_error_message(err);
+        ErrorMessage {
+            code: code.as_u16(),
+            message,
+            details,
+        }
+    }
+}
+
+/// The status, message and details a simulation error is answered with.
+fn simulation_error_message(err: &SimulationError) -> (StatusCode, String, Option<Value>) {
+    match err {
+        SimulationError::FromHex => (StatusCode::BAD_REQUEST, "FROM_HEX_ERROR".to_string(), None),
+        SimulationError::FromDecStr => (
+            StatusCode::BAD_REQUEST,
+            "FROM_DEC_STR_ERROR".to_string(),
+            None,
+        ),
+        SimulationError::ChainIdNotSupported(chain_id) => (
+            StatusCode::BAD_REQUEST,
+            "CHAIN_ID_NOT_SUPPORTED".to_string(),
+            Some(json!({ "chainId": chain_id })),
+        ),
+        SimulationError::ChainNotAllowed(chain_id) => (
+            StatusCode::FORBIDDEN,
+            "CHAIN_NOT_ALLOWED".to_string(),
+            Some(json!({ "chainId": chain_id })),
+        ),
+        SimulationError::MultipleChainIds => (
+            StatusCode::BAD_REQUEST,
+            "MULTIPLE_CHAIN_IDS".to_string(),
+            None,
+        ),
+        SimulationError::BlockNumberDecreasing => (
+            StatusCode::BAD_REQUEST,
+            "BLOCK_NUMBER_DECREASING".to_string(),
+            None,
+        ),
+        // Answered like the bundles the API rejects before executing them
+        SimulationError::EmptyBundle => (
+            StatusCode::BAD_REQUEST,
+            "INVALID_REQUEST".to_string(),
+            Some(json!({ "errors": [{
+                "field": "transactions",
+                "message": "must have at least one transaction",
+            }] })),
+        ),
+        SimulationError::NonceTooLow { nonce, expected } => (
+            StatusCode::BAD_REQUEST,
+            "NONCE_TOO_LOW".to_string(),
+            Some(json!({ "nonce": nonce, "expected": expected })),
+        ),
+        SimulationError::NonceTooHigh { nonce, expected } => (
+            StatusCode::BAD_REQUEST,
+            "NONCE_TOO_HIGH".to_string(),
+            Some(json!({ "nonce": nonce, "expected": expected })),
+        ),
+        SimulationError::InsufficientFunds { balance, cost } => (
+            StatusCode::BAD_REQUEST,
+            "INSUFFICIENT_FUNDS".to_string(),
+            Some(json!({ "balance": balance, "cost": cost })),
+        ),
+        SimulationError::GasLimitExceeded {
+            gas_limit,
+            block_gas_limit,
+        } => (
+            StatusCode::BAD_REQUEST,
+            "GAS_LIMIT_EXCEEDS_BLOCK".to_string(),
+            Some(json!({ "gasLimit": gas_limit, "blockGasLimit": block_gas_limit })),
+        ),
+        SimulationError::GasLimitTooHigh {
+            gas_limit,
+            max_gas_limit,
+        } => (
+            StatusCode::BAD_REQUEST,
+            "GAS_LIMIT_TOO_HIGH".to_string(),
+            Some(json!({ "gasLimit": gas_limit, "maxGasLimit": max_gas_limit })),
+        ),
+        SimulationError::Timeout(timeout) => (
+            StatusCode::GATEWAY_TIMEOUT,
+            "SIMULATION_TIMEOUT".to_string(),
+            Some(json!({ "timeoutMs": timeout.as_millis() as u64 })),
+        ),
+        SimulationError::BlockNotFound(block_hash) => (
+            StatusCode::NOT_FOUND,
+            "BLOCK_NOT_FOUND".to_string(),
+            Some(json!({ "blockHash": block_hash })),
+        ),
+        SimulationError::InvalidBlobTransaction(reason) => (
+            StatusCode::BAD_REQUEST,
+            "INVALID_BLOB_TRANSACTION".to_string(),
+            Some(json!({ "reason": reason })),
+        ),
+        SimulationError::InvalidAuthorizationList(reason) => (
+            StatusCode::BAD_REQUEST,
+            "INVALID_AUTHORIZATION_LIST".to_string(),
+            Some(json!({ "reason": reason })),
+        ),
+        SimulationError::BlobFeeTooLow {
+            max_fee_per_blob_gas,
+            blob_base_fee,
+        } => (
+            StatusCode::BAD_REQUEST,
+            "MAX_FEE_PER_BLOB_GAS_TOO_LOW".to_string(),
+            Some(json!({ "maxFeePerBlobGas": max_fee_per_blob_gas, "blobBaseFee": blob_base_fee })),
+        ),
+        SimulationError::SenderNotEoa(from) => (
+            StatusCode::BAD_REQUEST,
+            "SENDER_NOT_EOA".to_string(),
+            Some(json!({ "from": from })),
+        ),
+        SimulationError::PolicyViolation(decisions) => (
+            StatusCode::FORBIDDEN,
+            "POLICY_VIOLATION".to_string(),
+            Some(json!({ "policyDecisions": decisions })),
+        ),
+        SimulationError::Rpc(err) => (
+            StatusCode::BAD_GATEWAY,
+            "RPC_ERROR".to_string(),
+            Some(json!({ "error": err.to_string() })),
+        ),
+        SimulationError::Evm(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "EVM_ERROR".to_string(),
+            Some(json!({ "error": err.to_string() })),
+        ),
+    }
+}
+
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let (error, retry_after) = error_message(&err);
+    let code = StatusCode::from_u16(error.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+    let mut response = warp::reply::with_status(warp::reply::json(&error), code).into_response();
+    if let Some(retry_after) = retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+
+    Ok(response)
+}
+
+/// The error body of a rejection, and the seconds to wait before retrying if it is rate limited.
+pub fn error_message(err: &Rejection) -> (ErrorMessage, Option<u64>) {
     let code;
     let message: String;
+    let mut details = None;
+    let mut retry_after = None;
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
         message = "NOT_FOUND".to_string();
-    } else if let Some(FromHexError) = err.find() {
+    } else if let Some(e) = err.find::<SimulationError>() {
+        (code, message, details) = simulation_error_message(e);
+    } else if let Some(ForkNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "FORK_NOT_FOUND".to_string();
+    } else if let Some(SnapshotNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "SNAPSHOT_NOT_FOUND".to_string();
+    } else if let Some(ChainIdMismatchError) = err.find() {
         code = StatusCode::BAD_REQUEST;
-        message = "FROM_HEX_ERROR".to_string();
-    } else if let Some(FromDecStrError) = err.find() {
+        message = "CHAIN_ID_MISMATCH".to_string();
+    } else if let Some(InvalidRawTransactionError) = err.find() {
         code = StatusCode::BAD_REQUEST;
-        message = "FROM_DEC_STR_ERROR".to_string();
-    } else if let Some(NoURLForChainIdError) = err.find() {
+        message = "INVALID_RAW_TRANSACTION".to_string();
+    } else if let Some(e) = err.find::<ExecutionRevertedError>() {
         code = StatusCode::BAD_REQUEST;
-        message = "CHAIN_ID_NOT_SUPPORTED".to_string();
-    } else if let Some(_e) = err.find::<MultipleChainIdsError>() {
+        message = match &e.0 {
+            Some(reason) => format!("EXECUTION_REVERTED: {reason}"),
+            None => "EXECUTION_REVERTED".to_string(),
+        };
+        details = e.0.as_ref().map(|reason| json!({ "reason": reason }));
+    } else if let Some(SimulationNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "SIMULATION_NOT_FOUND".to_string();
+    } else if let Some(e) = err.find::<InvalidCallbackUrlError>() {
         code = StatusCode::BAD_REQUEST;
-        message = "MULTIPLE_CHAIN_IDS".to_string();
-    } else if let Some(_e) = err.find::<MultipleBlockNumbersError>() {
+        message = "INVALID_CALLBACK_URL".to_string();
+        details = Some(json!({ "reason": e.0 }));
+    } else if let Some(JobNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "JOB_NOT_FOUND".to_string();
+    } else if let Some(e) = err.find::<InvalidChainError>() {
         code = StatusCode::BAD_REQUEST;
-        message = "MULTIPLE_BLOCK_NUMBERS".to_string();
-    } else if let Some(_e) = err.find::<EvmError>() {
+        message = "INVALID_CHAIN".to_string();
+        details = Some(json!({ "reason": e.0 }));
+    } else if let Some(e) = err.find::<ChainAlreadyRegisteredError>() {
+        code = StatusCode::CONFLICT;
+        message = "CHAIN_ALREADY_REGISTERED".to_string();
+        details = Some(json!({ "chainId": e.0 }));
+    } else if let Some(e) = err.find::<HistoryError>() {
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        message = "HISTORY_ERROR".to_string();
+        details = Some(json!({ "error": e.0.to_string() }));
+    } else if let Some(e) = err.find::<AbiStoreError>() {
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        message = "ABI_STORE_ERROR".to_string();
+        details = Some(json!({ "error": e.0.to_string() }));
+    } else if let Some(AbiNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "ABI_NOT_FOUND".to_string();
+    } else if let Some(e) = err.find::<InvalidAbiError>() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_ABI".to_string();
+        details = Some(json!({ "reason": e.0 }));
+    } else if let Some(TransactionNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "TRANSACTION_NOT_FOUND".to_string();
+    } else if let Some(ForksNotAllowedError) = err.find() {
+        code = StatusCode::FORBIDDEN;
+        message = "FORKS_NOT_ALLOWED".to_string();
+    } else if let Some(e) = err.find::<BlockNotFoundError>() {
+        code = StatusCode::NOT_FOUND;
+        message = "BLOCK_NOT_FOUND".to_string();
+        details = Some(json!({ "blockHash": e.0 }));
+    } else if let Some(e) = err.find::<RpcError>() {
+        code = StatusCode::BAD_GATEWAY;
+        message = "RPC_ERROR".to_string();
+        details = Some(json!({ "error": e.0.to_string() }));
+    } else if let Some(BalanceSlotNotFoundError) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "BALANCE_SLOT_NOT_FOUND".to_string();
+    } else if let Some(MissingApiKeyError) = err.find() {
+        code = StatusCode::UNAUTHORIZED;
+        message = "MISSING_API_KEY".to_string();
+    } else if let Some(InvalidApiKeyError) = err.find() {
+        code = StatusCode::FORBIDDEN;
+        message = "INVALID_API_KEY".to_string();
+    } else if let Some(e) = err.find::<RateLimitedError>() {
+        code = StatusCode::TOO_MANY_REQUESTS;
+        message = "RATE_LIMITED".to_string();
+        retry_after = Some(e.retry_after);
+        details = Some(json!({ "retryAfter": e.retry_after }));
+    } else if let Some(e) = err.find::<QueueFullError>() {
+        code = StatusCode::SERVICE_UNAVAILABLE;
+        message = "QUEUE_FULL".to_string();
+        retry_after = Some(e.retry_after);
+        details = Some(json!({ "retryAfter": e.retry_after }));
+    } else if let Some(BundleTooLargeError) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "BUNDLE_TOO_LARGE".to_string();
+    } else if let Some(WatchNotFoundError) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "WATCH_NOT_FOUND".to_string();
+    } else if let Some(TooManyWatchesError) = err.find() {
+        code = StatusCode::TOO_MANY_REQUESTS;
+        message = "TOO_MANY_WATCHES".to_string();
+    } else if let Some(e) = err.find::<InvalidSweepError>() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_SWEEP".to_string();
+        details = Some(json!({ "reason": e.0 }));
+    } else if let Some(e) = err.find::<InvalidRequestError>() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_REQUEST".to_string();
+        details = Some(json!({ "errors": e.0 }));
+    } else if let Some(e) = err.find::<UnsupportedApiVersionError>() {
+        code = StatusCode::BAD_REQUEST;
+        message = "UNSUPPORTED_API_VERSION".to_string();
+        details = Some(json!({ "apiVersion": e.0, "supported": SUPPORTED_API_VERSIONS }));
+    } else if let Some(e) = err.find::<InvalidBodyError>() {
+        // Same as warp's own body errors
+        log::debug!(target: "ts::api", "Invalid body: {}", e.0);
+        code = StatusCode::BAD_REQUEST;
+        message = format!("BAD REQUEST: {}", e.0);
+        details = Some(json!({ "cause": e.0.to_string() }));
+    } else if let Some(e) = err.find::<EvmError>() {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "EVM_ERROR".to_string();
+        details = Some(json!({ "error": e.0.to_string() }));
     } else if let Some(e) = err.find::<BodyDeserializeError>() {
         // This error happens if the body could not be deserialized correctly
         // We can use the cause to analyze the error and customize the error message
-        dbg!(e);
+        log::debug!(target: "ts::api", "Invalid body: {e}");
         message = match e.source() {
             Some(cause) => format!("BAD REQUEST: {cause}"),
             None => "BAD_REQUEST".to_string(),
         };
+        details = e
+            .source()
+            .map(|cause| json!({ "cause": cause.to_string() }));
         code = StatusCode::BAD_REQUEST;
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_QUERY".to_string();
+        details = Some(json!({ "cause": e.to_string() }));
+    } else if let Some(e) = err.find::<warp::reject::InvalidHeader>() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_HEADER".to_string();
+        details = Some(json!({ "header": e.name() }));
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        code = StatusCode::PAYLOAD_TOO_LARGE;
+        message = "PAYLOAD_TOO_LARGE".to_string();
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        code = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+        message = "UNSUPPORTED_MEDIA_TYPE".to_string();
     } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
         // We can handle a specific error, here METHOD_NOT_ALLOWED,
         // and render it however we want
         code = StatusCode::METHOD_NOT_ALLOWED;
         message = "METHOD_NOT_ALLOWED".to_string();
-    } else if err.find::<warp::reject::MissingHeader>().is_some() {
-        code = StatusCode::UNAUTHORIZED;
-        message = "UNAUTHORIZED".to_string();
     } else {
         // We should have expected this... Just log and say its a 500
-        eprintln!("unhandled rejection: {err:?}");
+        log::error!(target: "ts::api", "Unhandled rejection: {err:?}");
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "UNHANDLED_REJECTION".to_string();
     }
 
-    let json = warp::reply::json(&ErrorMessage {
+    let error = ErrorMessage {
         code: code.as_u16(),
         message,
-    });
+        details,
+    };
 
-    Ok(warp::reply::with_status(json, code))
+    (error, retry_after)
 }
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::ExecutionRevertedError;
+use crate::evm::CallOptions;
+use crate::simulation::{
+    apply_state_overrides, call_raw_request, chain_id_to_fork_url, check_gas_limit, with_timeout,
+    SimulationRequest,
+};
+
+use super::config::Config;
+use super::pool::EvmPool;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GasEstimateResponse {
+    /// Lowest gas limit the transaction succeeds with, plus the configured buffer.
+    #[serde(rename = "gasLimit")]
+    pub gas_limit: u64,
+    /// Lowest gas limit the transaction succeeds with.
+    #[serde(rename = "gasEstimate")]
+    pub gas_estimate: u64,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    #[serde(rename = "blockNumber")]
+    pub block_number: u64,
+}
+
+/// Estimates gas like `eth_estimateGas`, using the request's `gasLimit` as the upper bound.
+pub async fn estimate(
+    transaction: SimulationRequest,
+    config: Config,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let request = call_raw_request(&transaction)?;
+    if let Some(state_overrides) = transaction.state_overrides {
+        apply_state_overrides(&mut evm, state_overrides)?;
+    }
+
+    check_gas_limit(&evm, request.gas_limit)?;
+
+    let timeout = evm.timeout();
+    let (result, gas_estimate) = with_timeout(timeout, async {
+        let result = evm.call_raw(&request, CallOptions::default()).await?;
+        if !result.success {
+            return Err(ExecutionRevertedError(result.revert_reason).into());
+        }
+        let gas_estimate = evm.estimate_gas(&request, result.gas_used).await?;
+        Ok::<_, Rejection>((result, gas_estimate))
+    })
+    .await?;
+
+    Ok(warp::reply::json(&GasEstimateResponse {
+        gas_limit: gas_estimate + gas_estimate * config.gas_estimate_buffer / 100,
+        gas_estimate,
+        gas_used: result.gas_used,
+        block_number: result.block_number,
+    }))
+}
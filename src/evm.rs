@@ -1,25 +1,125 @@
-use ethers::abi::{Address, Uint};
-use ethers::types::{Bytes, Log};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ethers::abi::{Address, Event, Hash, Uint};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::transaction::eip2930::{AccessList, AccessListItem};
+use ethers::types::{Block, BlockNumber, Bytes, Log};
+use ethers::utils::hex;
+use foundry_evm::debug::{DebugArena, Instruction};
+use foundry_evm::decode::decode_revert;
 use foundry_evm::executor::{fork::CreateFork, Executor};
-use foundry_evm::executor::{opts::EvmOpts, Backend, ExecutorBuilder};
+use foundry_evm::executor::{opts::EvmOpts, Backend, ExecutorBuilder, RawCallResult};
 use foundry_evm::trace::identifier::{EtherscanIdentifier, SignaturesIdentifier};
 use foundry_evm::trace::node::CallTraceNode;
-use foundry_evm::trace::{CallTraceArena, CallTraceDecoder, CallTraceDecoderBuilder};
-use revm::Env;
+use foundry_evm::trace::{
+    CallTraceArena, CallTraceDecoder, CallTraceDecoderBuilder, RawOrDecodedCall,
+    RawOrDecodedReturnData,
+};
+use foundry_evm::CallKind;
 use revm::Return;
+use revm::{
+    Account, AccountInfo, BlockEnv, Bytecode, CreateScheme, DatabaseCommit, DatabaseRef, Env,
+    SpecId, TransactTo, TxEnv, KECCAK_EMPTY,
+};
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
 
+use crate::access_stats::{access_stats, AccessStats};
+use crate::blob::blob_base_fee;
+use crate::contract_cache::ContractCache;
+use crate::decode::{decode_call, decode_log, decode_return_data};
 use crate::errors::EvmError;
-use crate::simulation::CallTrace;
+use crate::four_byte;
+use crate::pinned_rpc::pinned_url;
+use crate::policy::PolicyEngine;
+use crate::prices::PriceOracle;
+use crate::proxies::ProxyInfo;
+use crate::simulation::{
+    AccountDiff, BlockOverrides, CallTrace, CallTraceTree, CodeChange, CodeChangeKind, DecodedCall,
+    DecodedLog, FrameStorageAccesses, StorageAccess, StructLog, StructLogOptions, ValueDiff,
+};
+use crate::source_trace::{source_stack_trace, SourceFrame};
+
+/// A transaction to execute, `to` being `None` for deployments. Gas is only charged if one of
+/// the fee fields is set.
+#[derive(Debug, Clone, Default)]
+pub struct CallRawRequest {
+    pub from: Address,
+    pub to: Option<Address>,
+    pub value: Option<Uint>,
+    pub data: Option<Bytes>,
+    pub gas_limit: u64,
+    pub gas_price: Option<Uint>,
+    pub max_fee_per_gas: Option<Uint>,
+    pub max_priority_fee_per_gas: Option<Uint>,
+    pub access_list: Option<AccessList>,
+}
+
+/// Upper bound of the struct logs returned for a single call, whatever the requested limit.
+pub const MAX_STRUCT_LOGS: usize = 100_000;
+
+/// Which optional outputs to produce for a call, on top of the trace and logs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallOptions {
+    pub format_trace: bool,
+    pub decode_logs: bool,
+    pub decode_calls: bool,
+    /// Falls back to 4byte.directory for selectors without a verified ABI when decoding calls.
+    pub four_byte_lookup: bool,
+    pub state_diff: bool,
+    pub code_changes: bool,
+    /// Records the storage accesses of every call frame, with the debugger like `struct_logs`.
+    pub storage_accesses: bool,
+    /// Counts cold and warm accesses, with the debugger like `struct_logs`.
+    pub access_stats: bool,
+    pub access_list: bool,
+    /// Fetches the verified ABIs of the contracts in the trace from Etherscan, which formatting
+    /// the trace and decoding do anyway.
+    pub identify_contracts: bool,
+    /// Records every executed opcode, which is considerably slower.
+    pub struct_logs: Option<StructLogOptions>,
+    /// Locates the frames of a revert in the verified source of their contracts, with the
+    /// debugger like `struct_logs`.
+    pub source_stack_trace: bool,
+}
 
 #[derive(Debug, Clone)]
 pub struct CallRawResult {
+    /// Net of `gas_refunded`.
     pub gas_used: u64,
+    pub gas_refunded: u64,
     pub block_number: u64,
     pub success: bool,
     pub trace: Option<CallTraceArena>,
     pub logs: Vec<Log>,
+    pub decoded_logs: Option<Vec<DecodedLog>>,
     pub exit_reason: Return,
+    pub output: Bytes,
+    pub effective_gas_price: Uint,
+    pub decoded_output: Option<Vec<String>>,
+    pub revert_reason: Option<String>,
     pub formatted_trace: Option<String>,
+    /// Address of the contract a deployment created.
+    pub created_address: Option<Address>,
+    /// Size of the runtime bytecode a deployment created.
+    pub deployed_code_size: Option<usize>,
+    pub state_diff: Option<Vec<AccountDiff>>,
+    pub code_changes: Option<Vec<CodeChange>>,
+    pub access_list: Option<AccessList>,
+    pub struct_logs: Option<Vec<StructLog>>,
+    pub storage_accesses: Option<Vec<FrameStorageAccesses>>,
+    pub access_stats: Option<AccessStats>,
+    /// Only set if the call reverted.
+    pub source_stack_trace: Option<Vec<SourceFrame>>,
+    /// Decoded function of every call frame, by index in the trace arena.
+    pub decoded_calls: Option<Vec<Option<DecodedCall>>>,
+    /// Time spent executing, including fetching missing state from the RPC.
+    pub execution_time: Duration,
+    /// Time spent on the outputs of `CallOptions`, like identifying contracts and formatting the
+    /// trace.
+    pub processing_time: Duration,
 }
 
 impl From<CallTraceNode> for CallTrace {
@@ -29,6 +129,67 @@ impl From<CallTraceNode> for CallTrace {
             from: item.trace.caller,
             to: item.trace.address,
             value: item.trace.value,
+            decoded_call: None,
+            proxy: None,
+        }
+    }
+}
+
+impl CallTraceTree {
+    /// Builds the nested call tree from the root of the arena, if anything was traced.
+    /// `decoded_calls` and `proxies` are indexed like the arena and may be empty.
+    pub fn from_arena(
+        arena: &CallTraceArena,
+        decoded_calls: &[Option<DecodedCall>],
+        proxies: &[Option<ProxyInfo>],
+    ) -> Option<Self> {
+        if arena.arena.is_empty() {
+            return None;
+        }
+        Some(Self::from_node(arena, decoded_calls, proxies, 0))
+    }
+
+    fn from_node(
+        arena: &CallTraceArena,
+        decoded_calls: &[Option<DecodedCall>],
+        proxies: &[Option<ProxyInfo>],
+        idx: usize,
+    ) -> Self {
+        let node = &arena.arena[idx];
+        let trace = &node.trace;
+
+        let input = match &trace.data {
+            RawOrDecodedCall::Raw(data) => data.clone().into(),
+            RawOrDecodedCall::Decoded(..) => Bytes::default(),
+        };
+        let output = match &trace.output {
+            RawOrDecodedReturnData::Raw(data) => data.clone().into(),
+            RawOrDecodedReturnData::Decoded(_) => Bytes::default(),
+        };
+        let created_address = match trace.kind {
+            CallKind::Create | CallKind::Create2 if trace.success => Some(trace.address),
+            _ => None,
+        };
+
+        CallTraceTree {
+            call_type: trace.kind,
+            from: trace.caller,
+            to: trace.address,
+            value: trace.value,
+            input,
+            output,
+            gas_used: trace.gas_cost,
+            depth: trace.depth,
+            success: trace.success,
+            exit_reason: trace.status,
+            created_address,
+            decoded_call: decoded_calls.get(idx).cloned().flatten(),
+            proxy: proxies.get(idx).cloned().flatten(),
+            calls: node
+                .children
+                .iter()
+                .map(|child| Self::from_node(arena, decoded_calls, proxies, *child))
+                .collect(),
         }
     }
 }
@@ -37,17 +198,97 @@ pub struct Evm {
     executor: Executor,
     decoder: CallTraceDecoder,
     etherscan_identifier: Option<EtherscanIdentifier>,
+    /// Contracts are only identified with an Etherscan API key, without one none are found.
+    etherscan_key: Option<String>,
+    /// Shared by every `Evm` of a pool so that contracts are fetched from Etherscan once.
+    contract_cache: Option<Arc<ContractCache>>,
+    /// Shared by every `Evm` of a pool so that prices are cached, Chainlink only if not set.
+    price_oracle: Option<Arc<PriceOracle>>,
+    /// Checked against every simulation run with `run`.
+    policies: PolicyEngine,
+    block_number: u64,
+    /// Hash of the forked block, the block environment may have moved on since.
+    block_hash: Option<Hash>,
+    /// RPC the state is fetched from, not set without a fork.
+    fork_url: Option<String>,
+    /// Shared by every `Evm` of a pool to limit how many execute at once.
+    permits: Option<Arc<Semaphore>>,
+    max_gas_limit: Option<u64>,
+    timeout: Option<Duration>,
+    /// Blob fee market of the block, which the EVM itself doesn't know about.
+    excess_blob_gas: u64,
+    blob_base_fee: Option<Uint>,
+    /// Accounts and storage slots loaded by the executions since `record_touched_state`.
+    touched_state: Option<BTreeMap<Address, BTreeSet<Uint>>>,
+    /// Code hash of the contracts committed executions self-destructed, to tell redeploys.
+    destroyed: BTreeMap<Address, Hash>,
+    /// Accounts delegated by EIP-7702 authorizations and their delegate, whose code they got
+    /// rather than a delegation designator.
+    delegated: BTreeMap<Address, Address>,
 }
 
-impl Evm {
-    pub fn new(
-        env: Option<Env>,
-        fork_url: String,
-        fork_block_number: Option<u64>,
-        gas_limit: u64,
-        tracing: bool,
-        etherscan_key: Option<String>,
-    ) -> Self {
+/// A spawned fork and the environment of the block it was forked at. Clones are cheap and share
+/// the state already fetched from the RPC, but not each other's modifications.
+#[derive(Clone)]
+pub struct ForkBackend {
+    backend: Backend,
+    env: Env,
+    /// Not known if the block could not be fetched, forks are then of the RPC's latest block.
+    block_hash: Option<Hash>,
+    fork_url: Option<String>,
+}
+
+/// State of an `Evm` to roll back to: its backend, which shares the state fetched from the RPC
+/// like a `ForkBackend` clone, and its block environment.
+#[derive(Clone)]
+pub struct EvmSnapshot {
+    backend: Backend,
+    env: Env,
+    excess_blob_gas: u64,
+    blob_base_fee: Option<Uint>,
+    destroyed: BTreeMap<Address, Hash>,
+    delegated: BTreeMap<Address, Address>,
+}
+
+/// Fetches the block a fork is created at, blocking.
+fn fetch_block(fork_url: &str, block_number: Option<u64>) -> Option<Block<Hash>> {
+    let provider = Provider::<Http>::try_from(fork_url).ok()?;
+    let block_id = match block_number {
+        Some(block_number) => BlockNumber::Number(block_number.into()),
+        None => BlockNumber::Latest,
+    };
+    match Handle::current().block_on(provider.get_block(block_id)) {
+        Ok(block) => block,
+        Err(err) => {
+            log::warn!(target: "ts::evm", "Failed to fetch the block to fork: {err}");
+            None
+        }
+    }
+}
+
+/// Fetches the number of the latest block, blocking.
+pub(crate) fn latest_block_number(fork_url: &str) -> Option<u64> {
+    let provider = Provider::<Http>::try_from(fork_url).ok()?;
+    match Handle::current().block_on(provider.get_block_number()) {
+        Ok(block_number) => Some(block_number.as_u64()),
+        Err(err) => {
+            log::warn!(target: "ts::evm", "Failed to fetch the latest block number: {err}");
+            None
+        }
+    }
+}
+
+impl ForkBackend {
+    /// Forks `fork_block_number`, or the latest block pinned to its number so that every state
+    /// read of the fork is at the same block. Blocks, so it must run within `block_in_place`.
+    pub fn spawn(fork_url: String, fork_block_number: Option<u64>) -> Self {
+        let block = fetch_block(&fork_url, fork_block_number);
+        let block_hash = block.as_ref().and_then(|block| block.hash);
+        let fork_block_number = block
+            .and_then(|block| block.number)
+            .map(|number| number.as_u64())
+            .or(fork_block_number);
+
         let evm_opts = EvmOpts {
             fork_url: Some(fork_url.clone()),
             fork_block_number,
@@ -55,37 +296,117 @@ impl Evm {
         };
 
         let fork_opts = CreateFork {
-            url: fork_url,
+            url: fork_url.clone(),
             enable_caching: true,
             env: evm_opts.evm_env_blocking().unwrap(),
             evm_opts,
         };
 
-        let db = Backend::spawn(Some(fork_opts.clone()));
+        let env = fork_opts.env.clone();
+        let backend = Backend::spawn(Some(fork_opts));
 
-        let mut builder = ExecutorBuilder::default()
-            .with_gas_limit(gas_limit.into())
-            .set_tracing(tracing);
+        ForkBackend {
+            backend,
+            env,
+            block_hash,
+            fork_url: Some(fork_url),
+        }
+    }
 
-        if let Some(env) = env {
-            builder = builder.with_config(env);
-        } else {
-            builder = builder.with_config(Env {
-                cfg: Default::default(),
-                block: fork_opts.env.block,
-                tx: Default::default(),
-            });
+    /// A backend without a fork, whose accounts are all empty until set. Doesn't block.
+    pub fn offline(env: Env, block_hash: Option<Hash>) -> Self {
+        ForkBackend {
+            backend: Backend::spawn(None),
+            env,
+            block_hash,
+            fork_url: None,
+        }
+    }
+
+    /// Forks the block `block_hash`, which may have been reorged out as long as the RPC still
+    /// serves it, its state then being read by hash through `pinned_url`. `None` if the RPC
+    /// doesn't know the block. Blocks, so it must run within `block_in_place`.
+    pub fn spawn_at_hash(fork_url: String, block_hash: Hash) -> Option<Self> {
+        let provider = Provider::<Http>::try_from(fork_url.as_str()).ok()?;
+        let block = match Handle::current().block_on(provider.get_block(block_hash)) {
+            Ok(block) => block?,
+            Err(err) => {
+                log::warn!(target: "ts::evm", "Failed to fetch the block to fork: {err}");
+                return None;
+            }
+        };
+        let number = block.number?.as_u64();
+
+        let canonical = fetch_block(&fork_url, Some(number)).and_then(|block| block.hash);
+        if canonical == Some(block_hash) {
+            return Some(Self::spawn(fork_url, Some(number)));
         }
+        log::info!(target: "ts::evm", "Forking non-canonical block {block_hash:?} at {number}");
+        Some(Self::spawn(
+            pinned_url(&fork_url, number, block_hash)?,
+            Some(number),
+        ))
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.env.block.number.as_u64()
+    }
 
-        let executor = builder.build(db);
+    pub fn block_hash(&self) -> Option<Hash> {
+        self.block_hash
+    }
+}
+
+impl Evm {
+    pub fn new(
+        env: Option<Env>,
+        fork_url: String,
+        fork_block_number: Option<u64>,
+        gas_limit: u64,
+        tracing: bool,
+        etherscan_key: Option<String>,
+    ) -> Self {
+        Self::from_fork(
+            env,
+            ForkBackend::spawn(fork_url, fork_block_number),
+            gas_limit,
+            tracing,
+            etherscan_key,
+        )
+    }
+
+    pub fn from_fork(
+        env: Option<Env>,
+        fork: ForkBackend,
+        gas_limit: u64,
+        tracing: bool,
+        etherscan_key: Option<String>,
+    ) -> Self {
+        let block_number = fork.block_number();
+        let block_hash = fork.block_hash();
+        let fork_url = fork.fork_url.clone();
+        let chain_id = fork.env.cfg.chain_id;
+
+        let mut env = env.unwrap_or_else(|| Env {
+            cfg: Default::default(),
+            block: fork.env.block,
+            tx: Default::default(),
+        });
+        // Contract senders, like smart wallets, are simulated as if they could sign
+        env.cfg.disable_eip3607 = true;
+
+        let executor = ExecutorBuilder::default()
+            .with_gas_limit(gas_limit.into())
+            .set_tracing(tracing)
+            .with_config(env)
+            .build(fork.backend);
 
         let foundry_config = foundry_config::Config {
-            etherscan_api_key: etherscan_key,
+            etherscan_api_key: etherscan_key.clone(),
             ..Default::default()
         };
 
-        let etherscan_identifier =
-            EtherscanIdentifier::new(&foundry_config, Some(fork_opts.env.cfg.chain_id)).ok();
+        let etherscan_identifier = EtherscanIdentifier::new(&foundry_config, Some(chain_id)).ok();
         let mut decoder = CallTraceDecoderBuilder::new().with_verbosity(5).build();
 
         if let Ok(identifier) =
@@ -98,36 +419,657 @@ impl Evm {
             executor,
             decoder,
             etherscan_identifier,
+            etherscan_key,
+            contract_cache: None,
+            price_oracle: None,
+            policies: PolicyEngine::default(),
+            block_number,
+            block_hash,
+            fork_url,
+            permits: None,
+            max_gas_limit: None,
+            timeout: None,
+            excess_blob_gas: 0,
+            blob_base_fee: None,
+            touched_state: None,
+            destroyed: BTreeMap::new(),
+            delegated: BTreeMap::new(),
+        }
+    }
+
+    /// Makes executions wait for one of `permits` first.
+    pub fn with_concurrency_limit(mut self, permits: Arc<Semaphore>) -> Self {
+        self.permits = Some(permits);
+        self
+    }
+
+    /// Identifies contracts from `contract_cache` before asking Etherscan.
+    pub fn with_contract_cache(mut self, contract_cache: Arc<ContractCache>) -> Self {
+        self.contract_cache = Some(contract_cache);
+        self
+    }
+
+    /// Prices asset changes with `price_oracle`.
+    pub fn with_price_oracle(mut self, price_oracle: Arc<PriceOracle>) -> Self {
+        self.price_oracle = Some(price_oracle);
+        self
+    }
+
+    pub fn price_oracle(&self) -> Option<Arc<PriceOracle>> {
+        self.price_oracle.clone()
+    }
+
+    /// Flags or rejects simulations with `policies`.
+    pub fn with_policies(mut self, policies: PolicyEngine) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    pub fn policies(&self) -> &PolicyEngine {
+        &self.policies
+    }
+
+    /// Decodes logs with `events` too, before the events added earlier and those of the
+    /// contracts identified from Etherscan.
+    pub fn add_events(&mut self, events: impl IntoIterator<Item = Event>) {
+        for event in events {
+            let indexed = event.inputs.iter().filter(|input| input.indexed).count();
+            self.decoder
+                .events
+                .entry((event.signature(), indexed))
+                .or_default()
+                .insert(0, event);
+        }
+    }
+
+    /// Limits the gas limit of the transactions simulated, and how long a simulation may take.
+    pub fn with_limits(mut self, max_gas_limit: u64, timeout: Duration) -> Self {
+        self.max_gas_limit = Some(max_gas_limit);
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_gas_limit(&self) -> Option<u64> {
+        self.max_gas_limit
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Runs the synchronous part of an execution, which also fetches missing fork state from the
+    /// RPC, without stalling the other tasks of the runtime worker. Needs a multi-threaded runtime.
+    /// Yields afterwards so that a timeout around the simulation is noticed between executions.
+    /// `block_in_place` can't be cancelled, so the permit, and the admission slot of the request,
+    /// are only released once the execution has returned, even if the simulation timed out.
+    async fn blocking<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let _permit = match self.permits.clone() {
+            Some(permits) => Some(
+                permits
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed"),
+            ),
+            None => None,
+        };
+        let result = tokio::task::block_in_place(|| f(self));
+        tokio::task::yield_now().await;
+        result
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    /// Hash of the forked block.
+    pub fn block_hash(&self) -> Option<Hash> {
+        self.block_hash
+    }
+
+    pub fn fork_url(&self) -> Option<&str> {
+        self.fork_url.as_deref()
+    }
+
+    /// Timestamp of the block transactions are executed in, with `blockOverrides`.
+    pub fn timestamp(&self) -> u64 {
+        self.executor.env.block.timestamp.as_u64()
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.executor.env.cfg.chain_id.as_u64()
+    }
+
+    /// Block transactions are executed in, with `blockOverrides`.
+    pub fn block_env(&self) -> &BlockEnv {
+        &self.executor.env.block
+    }
+
+    pub fn spec_id(&self) -> SpecId {
+        self.executor.env.cfg.spec_id
+    }
+
+    /// Rules the following transactions are executed with, the newest the EVM implements unless
+    /// set.
+    pub fn set_spec_id(&mut self, spec_id: SpecId) {
+        self.executor.env.cfg.spec_id = spec_id;
+    }
+
+    /// Whether `address` has verified source on Etherscan, as found by the calls which identified
+    /// the contracts in their trace. Not known without Etherscan.
+    pub fn is_verified(&self, address: Address) -> Option<bool> {
+        (self.etherscan_key.is_some() && self.etherscan_identifier.is_some())
+            .then(|| self.decoder.contracts.contains_key(&address))
+    }
+
+    /// Name of the contract at `address`, as identified from Etherscan by the calls which
+    /// identified the contracts in their trace, or labelled by the decoder like precompiles.
+    pub fn contract_name(&self, address: Address) -> Option<&str> {
+        self.decoder
+            .labels
+            .get(&address)
+            .or_else(|| self.decoder.contracts.get(&address))
+            .map(String::as_str)
+    }
+
+    pub fn etherscan_key(&self) -> Option<&str> {
+        self.etherscan_key.as_deref()
+    }
+
+    pub fn block_gas_limit(&self) -> Uint {
+        self.executor.env.block.gas_limit
+    }
+
+    /// Set with `blockOverrides`, or derived from the excess blob gas, 0 unless overridden.
+    pub fn blob_base_fee(&self) -> Uint {
+        self.blob_base_fee
+            .unwrap_or_else(|| blob_base_fee(self.excess_blob_gas))
+    }
+
+    pub fn coinbase(&self) -> Address {
+        self.executor.env.block.coinbase
+    }
+
+    pub fn base_fee(&self) -> Uint {
+        self.executor.env.block.basefee
+    }
+
+    pub fn prevrandao(&self) -> Option<Hash> {
+        self.executor.env.block.prevrandao
+    }
+
+    /// Moves the environment forward to block `number`, advancing the timestamp by 12 seconds per
+    /// block. State stays as left by the previous transactions.
+    pub fn roll_block(&mut self, number: u64) {
+        let block = &mut self.executor.env.block;
+        let blocks = number.saturating_sub(block.number.as_u64());
+        block.timestamp += Uint::from(blocks * 12);
+        block.number = number.into();
+    }
+
+    pub fn snapshot(&self) -> EvmSnapshot {
+        EvmSnapshot {
+            backend: self.executor.backend().clone(),
+            env: self.executor.env.clone(),
+            excess_blob_gas: self.excess_blob_gas,
+            blob_base_fee: self.blob_base_fee,
+            destroyed: self.destroyed.clone(),
+            delegated: self.delegated.clone(),
+        }
+    }
+
+    /// Rolls the state and block environment back to those of the snapshot.
+    pub fn revert(&mut self, snapshot: EvmSnapshot) {
+        *self.executor.backend_mut() = snapshot.backend;
+        self.executor.env = snapshot.env;
+        self.excess_blob_gas = snapshot.excess_blob_gas;
+        self.blob_base_fee = snapshot.blob_base_fee;
+        self.destroyed = snapshot.destroyed;
+        self.delegated = snapshot.delegated;
+    }
+
+    /// Records the accounts and storage slots every following execution loads, read or written,
+    /// until `take_touched_state`.
+    pub fn record_touched_state(&mut self) {
+        self.touched_state = Some(BTreeMap::new());
+    }
+
+    /// The accounts and storage slots loaded since `record_touched_state`, which stops recording.
+    pub fn take_touched_state(&mut self) -> BTreeMap<Address, BTreeSet<Uint>> {
+        self.touched_state.take().unwrap_or_default()
+    }
+
+    pub fn override_block(&mut self, overrides: &BlockOverrides) {
+        let block = &mut self.executor.env.block;
+        if let Some(number) = overrides.number {
+            block.number = number.into();
+        }
+        if let Some(timestamp) = overrides.timestamp {
+            block.timestamp = timestamp.into();
+        }
+        if let Some(base_fee) = overrides.base_fee {
+            block.basefee = base_fee;
+        }
+        if let Some(coinbase) = overrides.coinbase {
+            block.coinbase = coinbase;
+        }
+        if let Some(prevrandao) = overrides.prevrandao {
+            block.prevrandao = Some(prevrandao);
+        }
+        if let Some(excess_blob_gas) = overrides.excess_blob_gas {
+            self.excess_blob_gas = excess_blob_gas;
+        }
+        if let Some(blob_base_fee) = overrides.blob_base_fee {
+            self.blob_base_fee = Some(blob_base_fee);
+        }
+    }
+
+    pub fn basic(&self, address: Address) -> Result<AccountInfo, EvmError> {
+        let info = self
+            .executor
+            .backend()
+            .basic(address)
+            .map_err(|err| EvmError(err.into()))?;
+
+        Ok(info.unwrap_or_default())
+    }
+
+    pub fn account_code(&self, address: Address) -> Result<Bytes, EvmError> {
+        self.code(&self.basic(address)?)
+    }
+
+    pub fn storage(&self, address: Address, slot: Uint) -> Result<Uint, EvmError> {
+        self.executor
+            .backend()
+            .storage(address, slot)
+            .map_err(|err| EvmError(err.into()))
+    }
+
+    pub fn set_balance(&mut self, address: Address, balance: Uint) -> Result<(), EvmError> {
+        self.executor
+            .set_balance(address, balance)
+            .map_err(|err| EvmError(err.into()))?;
+
+        Ok(())
+    }
+
+    pub fn set_nonce(&mut self, address: Address, nonce: u64) -> Result<(), EvmError> {
+        self.executor
+            .set_nonce(address, nonce)
+            .map_err(|err| EvmError(err.into()))?;
+
+        Ok(())
+    }
+
+    pub fn set_code(&mut self, address: Address, code: Bytes) -> Result<(), EvmError> {
+        let mut info = self.basic(address)?;
+        let bytecode = Bytecode::new_raw(code.0).to_checked();
+        info.code_hash = bytecode.hash();
+        info.code = Some(bytecode);
+        self.executor
+            .backend_mut()
+            .insert_account_info(address, info);
+
+        Ok(())
+    }
+
+    /// Records that an EIP-7702 authorization delegated `authority` to `delegate`, the zero
+    /// address clearing the delegation, which the code it got doesn't tell.
+    pub fn set_delegate(&mut self, authority: Address, delegate: Address) {
+        if delegate.is_zero() {
+            self.delegated.remove(&authority);
+        } else {
+            self.delegated.insert(authority, delegate);
         }
     }
 
+    /// The delegate of an account delegated by an authorization executed on this `Evm`.
+    pub fn delegate(&self, address: Address) -> Option<Address> {
+        self.delegated.get(&address).copied()
+    }
+
+    pub fn set_storage(
+        &mut self,
+        address: Address,
+        slot: Uint,
+        value: Uint,
+    ) -> Result<(), EvmError> {
+        self.executor
+            .backend_mut()
+            .insert_account_storage(address, slot, value)
+            .map_err(|err| EvmError(err.into()))?;
+
+        Ok(())
+    }
+
     pub async fn call_raw(
         &mut self,
-        from: Address,
-        to: Address,
-        value: Option<Uint>,
-        data: Option<Bytes>,
-        format_trace: bool,
+        request: &CallRawRequest,
+        options: CallOptions,
     ) -> Result<CallRawResult, EvmError> {
+        self.execute(request, options, false).await
+    }
+
+    pub async fn call_raw_committing(
+        &mut self,
+        request: &CallRawRequest,
+        options: CallOptions,
+    ) -> Result<CallRawResult, EvmError> {
+        self.execute(request, options, true).await
+    }
+
+    async fn execute(
+        &mut self,
+        request: &CallRawRequest,
+        options: CallOptions,
+        commit: bool,
+    ) -> Result<CallRawResult, EvmError> {
+        let env = self.build_env(request, request.gas_limit);
+        let calldata = env.tx.data.clone();
+        let start = Instant::now();
         let res = self
+            .blocking(|evm| {
+                evm.executor.set_debugger(
+                    options.struct_logs.is_some()
+                        || options.storage_accesses
+                        || options.access_stats
+                        || options.source_stack_trace,
+                );
+                let res = evm.executor.call_raw_with_env(env);
+                evm.executor.set_debugger(false);
+                res
+            })
+            .await;
+        let res = res.map_err(|err| {
+            dbg!(&err);
+            EvmError(err)
+        })?;
+        let execution_time = start.elapsed();
+
+        if let (Some(touched), Some(changeset)) = (&mut self.touched_state, &res.state_changeset) {
+            for (address, account) in changeset {
+                touched
+                    .entry(*address)
+                    .or_default()
+                    .extend(account.storage.keys().copied());
+            }
+        }
+
+        // Pre-state is read from the backend, so the diff has to be taken before committing
+        let state_diff = match (&res.state_changeset, options.state_diff) {
+            (Some(changeset), true) => Some(self.state_diff(changeset)?),
+            (None, true) => Some(vec![]),
+            (_, false) => None,
+        };
+        let code_changes = match (&res.state_changeset, options.code_changes) {
+            (Some(changeset), true) => Some(self.code_changes(changeset, res.traces.as_ref())?),
+            (None, true) => Some(vec![]),
+            (_, false) => None,
+        };
+        if commit {
+            for address in self_destructs(res.traces.as_ref()) {
+                let code_hash = self.basic(address)?.code_hash;
+                if code_hash != KECCAK_EMPTY {
+                    self.destroyed.insert(address, code_hash);
+                }
+            }
+            if let Some(changeset) = res.state_changeset.clone() {
+                self.executor.backend_mut().commit(changeset);
+            }
+        }
+
+        let start = Instant::now();
+        let mut result = self.process_result(res, &calldata, options).await;
+        result.state_diff = state_diff;
+        result.code_changes = code_changes;
+        result.execution_time = execution_time;
+        result.processing_time = start.elapsed();
+        Ok(result)
+    }
+
+    /// Compares every account the call touched against the backend. Storage slots which were
+    /// written back with their original value are not included.
+    fn state_diff<'a>(
+        &self,
+        changeset: impl IntoIterator<Item = (&'a Address, &'a Account)>,
+    ) -> Result<Vec<AccountDiff>, EvmError> {
+        let mut diffs = vec![];
+        for (address, account) in changeset {
+            let pre = self.basic(*address)?;
+            let post = &account.info;
+
+            let balance = (pre.balance != post.balance).then_some(ValueDiff {
+                pre: pre.balance,
+                post: post.balance,
+            });
+            let nonce = (pre.nonce != post.nonce).then_some(ValueDiff {
+                pre: pre.nonce,
+                post: post.nonce,
+            });
+            let code = if pre.code_hash != post.code_hash {
+                Some(ValueDiff {
+                    pre: self.code(&pre)?,
+                    post: self.code(post)?,
+                })
+            } else {
+                None
+            };
+            let storage: BTreeMap<Hash, ValueDiff<Hash>> = account
+                .storage
+                .iter()
+                .filter(|(_, slot)| slot.original_value != slot.present_value)
+                .map(|(slot, value)| {
+                    (
+                        uint_to_hash(*slot),
+                        ValueDiff {
+                            pre: uint_to_hash(value.original_value),
+                            post: uint_to_hash(value.present_value),
+                        },
+                    )
+                })
+                .collect();
+
+            if balance.is_some() || nonce.is_some() || code.is_some() || !storage.is_empty() {
+                diffs.push(AccountDiff {
+                    address: *address,
+                    balance,
+                    nonce,
+                    code,
+                    storage,
+                });
+            }
+        }
+        diffs.sort_by_key(|diff| diff.address);
+
+        Ok(diffs)
+    }
+
+    /// Compares the code hash of every account the call touched against the backend, like
+    /// `state_diff`. Deployments are only listed where a contract self-destructed before.
+    fn code_changes<'a>(
+        &self,
+        changeset: impl IntoIterator<Item = (&'a Address, &'a Account)>,
+        trace: Option<&CallTraceArena>,
+    ) -> Result<Vec<CodeChange>, EvmError> {
+        let self_destructs = self_destructs(trace);
+        let code_hash = |hash: Hash| (hash != KECCAK_EMPTY).then_some(hash);
+
+        let mut changes = vec![];
+        for (address, account) in changeset {
+            let pre = self.basic(*address)?.code_hash;
+            let post = account.info.code_hash;
+            let (kind, previous_code_hash) =
+                if self_destructs.contains(address) || (pre != post && post == KECCAK_EMPTY) {
+                    (CodeChangeKind::SelfDestruct, code_hash(pre))
+                } else if pre == post {
+                    continue;
+                } else if pre == KECCAK_EMPTY {
+                    match self.destroyed.get(address) {
+                        Some(destroyed) => (CodeChangeKind::Redeployed, Some(*destroyed)),
+                        None => continue,
+                    }
+                } else {
+                    (CodeChangeKind::Replaced, Some(pre))
+                };
+            changes.push(CodeChange {
+                address: *address,
+                kind,
+                previous_code_hash,
+                code_hash: code_hash(post),
+                delegate: None,
+            });
+        }
+        changes.sort_by_key(|change| change.address);
+
+        Ok(changes)
+    }
+
+    fn code(&self, info: &AccountInfo) -> Result<Bytes, EvmError> {
+        if let Some(code) = &info.code {
+            return Ok(code.original_bytes().into());
+        }
+        if info.code_hash == KECCAK_EMPTY {
+            return Ok(Bytes::default());
+        }
+        let code = self
             .executor
-            .call_raw(
-                from,
-                to,
-                data.unwrap_or_default().0,
-                value.unwrap_or_default(),
-            )
-            .map_err(|err| {
-                dbg!(&err);
-                EvmError(err)
-            })?;
+            .backend()
+            .code_by_hash(info.code_hash)
+            .map_err(|err| EvmError(err.into()))?;
+
+        Ok(code.original_bytes().into())
+    }
+
+    /// Binary searches the lowest gas limit the call succeeds with, between `gas_used` which
+    /// a successful run at the request's gas limit reported and that gas limit itself. Every
+    /// execution is run on its own, so that a timeout is noticed between them.
+    pub async fn estimate_gas(
+        &mut self,
+        request: &CallRawRequest,
+        gas_used: u64,
+    ) -> Result<u64, EvmError> {
+        // Refunds mean a call can need more gas than it ends up using
+        let mut lo = gas_used.saturating_sub(1);
+        let mut hi = request.gas_limit;
+        while lo + 1 < hi {
+            let mid = lo + (hi - lo) / 2;
+            let env = self.build_env(request, mid);
+            let res = self
+                .blocking(|evm| evm.executor.call_raw_with_env(env))
+                .await
+                .map_err(EvmError)?;
+            if res.reverted {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        Ok(hi)
+    }
+
+    /// Builds the environment of a transaction the same way the executor does for its own calls,
+    /// deploying `data` as init code if there is no `to`. Without any fee set, neither the base
+    /// fee nor gas are charged. The gas limit is capped at `max_gas_limit`, which bounds the
+    /// executions the timeout can't interrupt, pending transactions of the mempool included.
+    fn build_env(&self, request: &CallRawRequest, gas_limit: u64) -> Env {
+        let gas_limit = self
+            .max_gas_limit
+            .map_or(gas_limit, |max_gas_limit| gas_limit.min(max_gas_limit));
+        let transact_to = match request.to {
+            Some(to) => TransactTo::Call(to),
+            None => TransactTo::Create(CreateScheme::Create),
+        };
+
+        let block = &self.executor.env.block;
+        let (basefee, gas_price, gas_priority_fee) = match (
+            request.gas_price,
+            request.max_fee_per_gas,
+            request.max_priority_fee_per_gas,
+        ) {
+            (Some(gas_price), _, _) => (block.basefee, gas_price, None),
+            (None, None, None) => (Uint::zero(), Uint::zero(), None),
+            (None, max_fee_per_gas, max_priority_fee_per_gas) => {
+                let max_priority_fee_per_gas = max_priority_fee_per_gas.unwrap_or_default();
+                let max_fee_per_gas =
+                    max_fee_per_gas.unwrap_or(block.basefee + max_priority_fee_per_gas);
+                (
+                    block.basefee,
+                    max_fee_per_gas,
+                    Some(max_priority_fee_per_gas),
+                )
+            }
+        };
+
+        Env {
+            cfg: self.executor.env.cfg.clone(),
+            block: BlockEnv {
+                basefee,
+                gas_limit: gas_limit.into(),
+                ..block.clone()
+            },
+            tx: TxEnv {
+                caller: request.from,
+                transact_to,
+                data: request.data.clone().unwrap_or_default().0,
+                value: request.value.unwrap_or_default(),
+                gas_price,
+                gas_priority_fee,
+                gas_limit,
+                access_list: request
+                    .access_list
+                    .iter()
+                    .flat_map(|list| &list.0)
+                    .map(|item| {
+                        let slots = item
+                            .storage_keys
+                            .iter()
+                            .map(|slot| Uint::from_big_endian(slot.as_bytes()))
+                            .collect();
+                        (item.address, slots)
+                    })
+                    .collect(),
+                ..self.executor.env.tx.clone()
+            },
+        }
+    }
+
+    async fn process_result(
+        &mut self,
+        res: RawCallResult,
+        calldata: &[u8],
+        options: CallOptions,
+    ) -> CallRawResult {
+        let CallOptions {
+            format_trace,
+            decode_logs,
+            decode_calls,
+            four_byte_lookup,
+            access_list,
+            identify_contracts,
+            struct_logs,
+            storage_accesses,
+            access_stats: count_accesses,
+            source_stack_trace: locate_revert,
+            ..
+        } = options;
+
+        // Fetches the ABIs of every contract in the trace from Etherscan, also needed to
+        // resolve custom errors on revert
+        if format_trace || decode_logs || decode_calls || identify_contracts || res.reverted {
+            let chain_id = self.chain_id();
+            if let (Some(trace), Some(identifier)) = (&res.traces, &mut self.etherscan_identifier) {
+                match &self.contract_cache {
+                    Some(cache) => self
+                        .decoder
+                        .identify(trace, &mut cache.identifier(chain_id, identifier)),
+                    None => self.decoder.identify(trace, identifier),
+                }
+            }
+        }
 
         let formatted_trace = if format_trace {
             let mut output = String::new();
             for trace in &mut res.traces.clone() {
-                if let Some(identifier) = &mut self.etherscan_identifier {
-                    self.decoder.identify(trace, identifier);
-                }
                 self.decoder.decode(trace).await;
                 output.push_str(format!("{trace}").as_str());
             }
@@ -136,62 +1078,371 @@ impl Evm {
             None
         };
 
-        Ok(CallRawResult {
-            gas_used: res.gas_used,
-            block_number: res.env.block.number.as_u64(),
-            success: !res.reverted,
-            trace: res.traces,
-            logs: res.logs,
-            exit_reason: res.exit_reason,
-            formatted_trace,
-        })
-    }
-
-    pub async fn call_raw_committing(
-        &mut self,
-        from: Address,
-        to: Address,
-        value: Option<Uint>,
-        data: Option<Bytes>,
-        gas_limit: u64,
-        format_trace: bool,
-    ) -> Result<CallRawResult, EvmError> {
-        self.executor.set_gas_limit(gas_limit.into());
-        let res = self
-            .executor
-            .call_raw_committing(
-                from,
-                to,
-                data.unwrap_or_default().0,
-                value.unwrap_or_default(),
+        let decoded_logs = if decode_logs {
+            Some(
+                res.logs
+                    .iter()
+                    .map(|log| decode_log(&self.decoder.events, log))
+                    .collect(),
             )
-            .map_err(|err| {
-                dbg!(&err);
-                EvmError(err)
-            })?;
+        } else {
+            None
+        };
 
-        let formatted_trace = if format_trace {
-            let mut output = String::new();
-            for trace in &mut res.traces.clone() {
-                if let Some(identifier) = &mut self.etherscan_identifier {
-                    self.decoder.identify(trace, identifier);
+        let decoded_calls = match (&res.traces, decode_calls) {
+            (Some(arena), true) => {
+                let mut decoded_calls = Vec::with_capacity(arena.arena.len());
+                for node in &arena.arena {
+                    decoded_calls.push(self.decode_call(&node.trace.data, four_byte_lookup).await);
                 }
-                self.decoder.decode(trace).await;
-                output.push_str(format!("{trace}").as_str());
+                Some(decoded_calls)
             }
-            Some(output)
+            (None, true) => Some(vec![]),
+            (_, false) => None,
+        };
+
+        let decoded_output = if format_trace && !res.reverted {
+            decode_return_data(&self.decoder.functions, calldata, &res.result)
         } else {
             None
         };
 
-        Ok(CallRawResult {
+        let revert_reason = if res.reverted {
+            decode_revert(
+                &res.result,
+                Some(&self.decoder.errors),
+                Some(res.exit_reason),
+            )
+            .ok()
+        } else {
+            None
+        };
+
+        // Like geth, accounts which are warm anyway are only listed for their storage slots
+        let access_list = match (&res.state_changeset, access_list) {
+            (Some(changeset), true) => {
+                let mut warm = vec![res.env.tx.caller, res.env.block.coinbase];
+                if let TransactTo::Call(to) = res.env.tx.transact_to {
+                    warm.push(to);
+                }
+                Some(build_access_list(changeset, &warm))
+            }
+            (None, true) => Some(AccessList::default()),
+            (_, false) => None,
+        };
+
+        let storage_accesses = match (&res.debug, storage_accesses) {
+            (Some(debug), true) => Some(build_storage_accesses(
+                debug,
+                res.state_changeset.iter().flatten(),
+            )),
+            (None, true) => Some(vec![]),
+            (_, false) => None,
+        };
+
+        let access_stats = match (&res.debug, count_accesses) {
+            (Some(debug), true) => Some(access_stats(debug, &res.env)),
+            (None, true) => Some(AccessStats::default()),
+            (_, false) => None,
+        };
+
+        let struct_logs = match (&res.debug, struct_logs) {
+            (Some(debug), Some(options)) => Some(build_struct_logs(debug, options)),
+            (None, Some(_)) => Some(vec![]),
+            (_, None) => None,
+        };
+
+        let source_stack_trace = match (&res.debug, locate_revert && res.reverted) {
+            (Some(debug), true) => Some(source_stack_trace(self, debug).await),
+            (None, true) => Some(vec![]),
+            (_, false) => None,
+        };
+
+        // The tracer records the runtime bytecode as the output of a create frame
+        let deployment = res
+            .traces
+            .as_ref()
+            .and_then(|arena| arena.arena.first())
+            .filter(|node| {
+                matches!(node.trace.kind, CallKind::Create | CallKind::Create2)
+                    && node.trace.success
+            })
+            .map(|node| {
+                let code_size = match &node.trace.output {
+                    RawOrDecodedReturnData::Raw(code) => code.len(),
+                    RawOrDecodedReturnData::Decoded(_) => 0,
+                };
+                (node.trace.address, code_size)
+            });
+
+        CallRawResult {
             gas_used: res.gas_used,
+            gas_refunded: res.gas_refunded,
             block_number: res.env.block.number.as_u64(),
             success: !res.reverted,
             trace: res.traces,
             logs: res.logs,
+            decoded_logs,
             exit_reason: res.exit_reason,
+            effective_gas_price: res.env.effective_gas_price(),
+            output: res.result.into(),
+            decoded_output,
+            revert_reason,
             formatted_trace,
+            created_address: deployment.map(|(address, _)| address),
+            deployed_code_size: deployment.map(|(_, code_size)| code_size),
+            state_diff: None,
+            code_changes: None,
+            access_list,
+            struct_logs,
+            storage_accesses,
+            access_stats,
+            source_stack_trace,
+            decoded_calls,
+            execution_time: Duration::ZERO,
+            processing_time: Duration::ZERO,
+        }
+    }
+
+    /// Decodes calldata against the verified ABIs of the contracts in the trace, then
+    /// 4byte.directory signatures if enabled.
+    async fn decode_call(
+        &self,
+        data: &RawOrDecodedCall,
+        four_byte_lookup: bool,
+    ) -> Option<DecodedCall> {
+        let RawOrDecodedCall::Raw(input) = data else {
+            return None;
+        };
+        let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+
+        if let Some(functions) = self.decoder.functions.get(&selector) {
+            if let Some(decoded) = decode_call(functions, input) {
+                return Some(decoded);
+            }
+        }
+        if four_byte_lookup {
+            return decode_call(&four_byte::lookup(selector).await, input);
+        }
+        None
+    }
+}
+
+pub(crate) fn uint_to_hash(value: Uint) -> Hash {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    Hash::from(bytes)
+}
+
+/// Lists every account and storage slot the call loaded. Accounts in `warm` and precompiles are
+/// only listed if storage slots of theirs were loaded.
+fn build_access_list<'a>(
+    changeset: impl IntoIterator<Item = (&'a Address, &'a Account)>,
+    warm: &[Address],
+) -> AccessList {
+    let mut items: Vec<AccessListItem> = changeset
+        .into_iter()
+        .filter_map(|(address, account)| {
+            let mut storage_keys: Vec<Hash> = account
+                .storage
+                .keys()
+                .map(|slot| uint_to_hash(*slot))
+                .collect();
+            storage_keys.sort();
+            if storage_keys.is_empty() && (warm.contains(address) || is_precompile(address)) {
+                return None;
+            }
+            Some(AccessListItem {
+                address: *address,
+                storage_keys,
+            })
         })
+        .collect();
+    items.sort_by_key(|item| item.address);
+
+    AccessList(items)
+}
+
+/// Contracts whose frame ended with a `SELFDESTRUCT`.
+fn self_destructs(trace: Option<&CallTraceArena>) -> BTreeSet<Address> {
+    trace
+        .map(|trace| &trace.arena[..])
+        .unwrap_or_default()
+        .iter()
+        .filter(|node| node.trace.status == Return::SelfDestruct)
+        .map(|node| node.trace.address)
+        .collect()
+}
+
+pub(crate) fn is_precompile(address: &Address) -> bool {
+    let bytes = address.as_bytes();
+    bytes[..19].iter().all(|byte| *byte == 0) && (1..=9).contains(&bytes[19])
+}
+
+const SLOAD: u8 = 0x54;
+const SSTORE: u8 = 0x55;
+
+/// Lists the storage accesses of the debugger's call frames. Slots start with the values the
+/// transaction started with, from the state changeset.
+fn build_storage_accesses<'a>(
+    debug: &DebugArena,
+    changeset: impl IntoIterator<Item = (&'a Address, &'a Account)>,
+) -> Vec<FrameStorageAccesses> {
+    let mut values: HashMap<(Address, Uint), Uint> = changeset
+        .into_iter()
+        .flat_map(|(address, account)| {
+            account
+                .storage
+                .iter()
+                .map(|(slot, value)| ((*address, *slot), value.original_value))
+        })
+        .collect();
+    let mut frames = vec![];
+    if let Some(root) = debug.arena.first() {
+        push_storage_accesses(debug, 0, root.address, 0, &mut values, &mut frames);
+    }
+    frames
+}
+
+fn push_storage_accesses(
+    debug: &DebugArena,
+    idx: usize,
+    address: Address,
+    depth: usize,
+    values: &mut HashMap<(Address, Uint), Uint>,
+    frames: &mut Vec<FrameStorageAccesses>,
+) {
+    let node = &debug.arena[idx];
+    // Listed before the frames it calls
+    let position = frames.len();
+    let mut accesses = vec![];
+    for (i, step) in node.steps.iter().enumerate() {
+        match step.instruction {
+            Instruction::OpCode(SLOAD) => {
+                let loaded = step
+                    .stack
+                    .last()
+                    .zip(node.steps.get(i + 1).and_then(|next| next.stack.last()));
+                if let Some((slot, value)) = loaded {
+                    values.insert((address, *slot), *value);
+                    accesses.push(StorageAccess {
+                        slot: uint_to_hash(*slot),
+                        previous_value: uint_to_hash(*value),
+                        new_value: uint_to_hash(*value),
+                        is_write: false,
+                    });
+                }
+            }
+            Instruction::OpCode(SSTORE) => {
+                if let [.., value, slot] = step.stack.as_slice() {
+                    let previous = values.insert((address, *slot), *value).unwrap_or_default();
+                    accesses.push(StorageAccess {
+                        slot: uint_to_hash(*slot),
+                        previous_value: uint_to_hash(previous),
+                        new_value: uint_to_hash(*value),
+                        is_write: true,
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        // Calls made by this opcode start right after it, delegatecalls and callcodes running on
+        // the storage of this frame
+        for child in &node.children {
+            let child_node = &debug.arena[*child];
+            if child_node.location == i + 1 {
+                let storage_address = match child_node.kind {
+                    CallKind::DelegateCall | CallKind::CallCode => address,
+                    _ => child_node.address,
+                };
+                push_storage_accesses(debug, *child, storage_address, depth + 1, values, frames);
+            }
+        }
+    }
+
+    if !accesses.is_empty() {
+        frames.insert(
+            position,
+            FrameStorageAccesses {
+                address,
+                code_address: node.address,
+                depth,
+                accesses,
+            },
+        );
+    }
+}
+
+/// Flattens the debugger's call frames into the executed opcodes, in execution order.
+fn build_struct_logs(debug: &DebugArena, options: StructLogOptions) -> Vec<StructLog> {
+    let limit = options
+        .limit
+        .unwrap_or(MAX_STRUCT_LOGS)
+        .min(MAX_STRUCT_LOGS);
+    let mut logs = vec![];
+    let mut storage = HashMap::new();
+    if !debug.arena.is_empty() {
+        push_struct_logs(debug, 0, 1, options, limit, &mut storage, &mut logs);
+    }
+    logs
+}
+
+fn push_struct_logs(
+    debug: &DebugArena,
+    idx: usize,
+    depth: usize,
+    options: StructLogOptions,
+    limit: usize,
+    storage: &mut HashMap<Address, BTreeMap<Hash, Hash>>,
+    logs: &mut Vec<StructLog>,
+) {
+    let node = &debug.arena[idx];
+    for (i, step) in node.steps.iter().enumerate() {
+        if logs.len() >= limit {
+            return;
+        }
+        let next = node.steps.get(i + 1);
+
+        let slot = match step.instruction {
+            Instruction::OpCode(SLOAD) => step
+                .stack
+                .last()
+                .zip(next.and_then(|next| next.stack.last())),
+            Instruction::OpCode(SSTORE) => match step.stack.as_slice() {
+                [.., value, key] => Some((key, value)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let step_storage = match slot {
+            Some((key, value)) if !options.disable_storage.unwrap_or_default() => {
+                let contract = storage.entry(node.address).or_default();
+                contract.insert(uint_to_hash(*key), uint_to_hash(*value));
+                Some(contract.clone())
+            }
+            _ => None,
+        };
+
+        logs.push(StructLog {
+            pc: step.pc,
+            op: step.instruction.to_string(),
+            gas_used: step.total_gas_used,
+            gas_cost: next.map(|next| next.total_gas_used.saturating_sub(step.total_gas_used)),
+            depth,
+            stack: (!options.disable_stack.unwrap_or_default()).then(|| step.stack.clone()),
+            memory: options
+                .enable_memory
+                .unwrap_or_default()
+                .then(|| step.memory.data().chunks(32).map(hex::encode).collect()),
+            storage: step_storage,
+        });
+
+        // Calls made by this opcode start right after it
+        for child in &node.children {
+            if debug.arena[*child].location == i + 1 {
+                push_struct_logs(debug, *child, depth + 1, options, limit, storage, logs);
+            }
+        }
     }
 }
@@ -0,0 +1,171 @@
+use std::collections::BTreeSet;
+use std::io;
+
+use ethers::abi::Address;
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::http::header::CONTENT_TYPE;
+use warp::http::Response;
+use warp::hyper::Body;
+use warp::Rejection;
+
+use crate::history::{History, SimulationRecord};
+
+/// Simulations read from the store at once while exporting.
+const EXPORT_PAGE_SIZE: usize = 500;
+
+const CSV_HEADER: &str = "id,createdAt,chainId,blockNumber,from,to,success,gasUsed,addresses\n";
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line.
+    #[default]
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<ExportFormat>,
+    /// Unix timestamp in seconds, every simulation is exported if not set.
+    pub since: Option<u64>,
+}
+
+/// A simulation of the history, flattened for analytics.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExportedSimulation {
+    pub id: Uuid,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    #[serde(rename = "blockNumber")]
+    pub block_number: u64,
+    pub from: Address,
+    /// Not set for deployments.
+    pub to: Option<Address>,
+    pub success: bool,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    /// Every account the simulation called, created or got logs from, sender included, sorted.
+    pub addresses: Vec<Address>,
+}
+
+impl From<&SimulationRecord> for ExportedSimulation {
+    fn from(record: &SimulationRecord) -> Self {
+        let response = &record.response;
+        let mut addresses = BTreeSet::from([record.request.from]);
+        addresses.extend(record.request.to);
+        addresses.extend(response.created_address);
+        addresses.extend(response.trace.iter().map(|call| call.to));
+        addresses.extend(response.logs.iter().map(|log| log.address));
+
+        ExportedSimulation {
+            id: record.id,
+            created_at: record.created_at,
+            chain_id: record.request.chain_id,
+            block_number: response.block_number,
+            from: record.request.from,
+            to: record.request.to,
+            success: response.success,
+            gas_used: response.gas_used,
+            addresses: addresses.into_iter().collect(),
+        }
+    }
+}
+
+impl ExportedSimulation {
+    /// None of the fields can hold a comma nor a quote, so nothing is escaped.
+    fn csv_line(&self) -> String {
+        let addresses: Vec<String> = self
+            .addresses
+            .iter()
+            .map(|address| format!("{address:?}"))
+            .collect();
+        format!(
+            "{},{},{},{},{:?},{},{},{},{}\n",
+            self.id,
+            self.created_at,
+            self.chain_id,
+            self.block_number,
+            self.from,
+            self.to.map(|to| format!("{to:?}")).unwrap_or_default(),
+            self.success,
+            self.gas_used,
+            addresses.join(" ")
+        )
+    }
+
+    fn line(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Csv => self.csv_line(),
+            ExportFormat::Jsonl => {
+                let mut line = serde_json::to_string(self).expect("simulations must serialize");
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+fn page_lines(records: &[SimulationRecord], format: ExportFormat) -> String {
+    records
+        .iter()
+        .map(|record| ExportedSimulation::from(record).line(format))
+        .collect()
+}
+
+/// Streams the simulations of the history created at `since` or later, oldest first, as CSV or
+/// JSON lines, a page of the store at a time.
+pub async fn export_simulations(
+    query: ExportQuery,
+    history: History,
+) -> Result<Response<Body>, Rejection> {
+    let format = query.format.unwrap_or_default();
+    let since = query.since.unwrap_or_default();
+
+    // The first page is read upfront for errors to be answered as such
+    let first = history.find_since(since, None, EXPORT_PAGE_SIZE)?;
+    let mut head = match format {
+        ExportFormat::Csv => CSV_HEADER.to_string(),
+        ExportFormat::Jsonl => String::new(),
+    };
+    head.push_str(&page_lines(&first, format));
+    let after = (first.len() == EXPORT_PAGE_SIZE)
+        .then(|| first.last().map(|record| (record.created_at, record.id)))
+        .flatten();
+
+    let pages = stream::unfold(after, move |after| {
+        let history = history.clone();
+        async move {
+            let after = after?;
+            match history.find_since(since, Some(after), EXPORT_PAGE_SIZE) {
+                Ok(records) => {
+                    let next = (records.len() == EXPORT_PAGE_SIZE)
+                        .then(|| records.last().map(|record| (record.created_at, record.id)))
+                        .flatten();
+                    Some((Ok(page_lines(&records, format)), next))
+                }
+                Err(err) => {
+                    log::warn!(target: "ts::history", "Failed to export simulations: {}", err.0);
+                    Some((
+                        Err(io::Error::new(io::ErrorKind::Other, err.0.to_string())),
+                        None,
+                    ))
+                }
+            }
+        }
+    });
+    let body = Body::wrap_stream(stream::once(async { Ok(head) }).chain(pages));
+
+    let content_type = match format {
+        ExportFormat::Csv => "text/csv",
+        ExportFormat::Jsonl => "application/x-ndjson",
+    };
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(body)
+        .expect("export response must build"))
+}
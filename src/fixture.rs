@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ethers::abi::{Address, Hash, Uint};
+use ethers::types::Bytes;
+use revm::{BlockEnv, CfgEnv, Env};
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{ChainIdMismatchError, EvmError};
+use crate::evm::{uint_to_hash, Evm};
+use crate::quantity;
+use crate::simulation::{
+    chain_id_to_fork_url, run, BlockOverrides, SimulationRequest, SimulationResponse,
+};
+
+use super::config::Config;
+use super::pool::EvmPool;
+
+/// Largest fixture accepted by `/simulate-fixture`.
+pub const MAX_FIXTURE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The chain state a simulation read and wrote, as it was before the simulation, and the block
+/// it ran in: enough to run it again without an RPC.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Fixture {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub block: FixtureBlock,
+    /// Every account the simulation loaded, with the storage slots it loaded.
+    pub accounts: BTreeMap<Address, FixtureAccount>,
+}
+
+/// The block environment, before `blockOverrides`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FixtureBlock {
+    pub number: u64,
+    /// Hash of the forked block, if it was known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<Hash>,
+    pub timestamp: u64,
+    #[serde(rename = "baseFee")]
+    pub base_fee: Uint,
+    pub coinbase: Address,
+    #[serde(rename = "gasLimit")]
+    pub gas_limit: Uint,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prevrandao: Option<Hash>,
+    #[serde(rename = "blobBaseFee", default)]
+    pub blob_base_fee: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FixtureAccount {
+    pub balance: Uint,
+    pub nonce: u64,
+    #[serde(default)]
+    pub code: Bytes,
+    /// Non-zero storage slots, every other slot being zero.
+    #[serde(default)]
+    pub storage: BTreeMap<Hash, Hash>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureExport {
+    pub fixture: Fixture,
+    /// The simulation the fixture was taken from.
+    pub simulation: SimulationResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureSimulationRequest {
+    pub fixture: Fixture,
+    pub transaction: SimulationRequest,
+}
+
+impl Fixture {
+    /// Reads the accounts and slots of `touched` from the state of `evm`.
+    pub fn capture(
+        evm: &Evm,
+        chain_id: u64,
+        touched: BTreeMap<Address, BTreeSet<Uint>>,
+    ) -> Result<Self, EvmError> {
+        let mut accounts = BTreeMap::new();
+        for (address, slots) in touched {
+            let info = evm.basic(address)?;
+            let mut storage = BTreeMap::new();
+            for slot in slots {
+                let value = evm.storage(address, slot)?;
+                if !value.is_zero() {
+                    storage.insert(uint_to_hash(slot), uint_to_hash(value));
+                }
+            }
+            accounts.insert(
+                address,
+                FixtureAccount {
+                    balance: info.balance,
+                    nonce: info.nonce,
+                    code: evm.account_code(address)?,
+                    storage,
+                },
+            );
+        }
+
+        Ok(Fixture {
+            chain_id,
+            block: FixtureBlock {
+                number: evm.block_number(),
+                hash: evm.block_hash(),
+                timestamp: evm.timestamp(),
+                base_fee: evm.base_fee(),
+                coinbase: evm.coinbase(),
+                gas_limit: evm.block_gas_limit(),
+                prevrandao: evm.prevrandao(),
+                blob_base_fee: evm.blob_base_fee(),
+            },
+            accounts,
+        })
+    }
+
+    pub fn env(&self) -> Env {
+        Env {
+            cfg: CfgEnv {
+                chain_id: self.chain_id.into(),
+                ..Default::default()
+            },
+            block: BlockEnv {
+                number: self.block.number.into(),
+                coinbase: self.block.coinbase,
+                timestamp: self.block.timestamp.into(),
+                basefee: self.block.base_fee,
+                gas_limit: self.block.gas_limit,
+                prevrandao: self.block.prevrandao,
+                ..Default::default()
+            },
+            tx: Default::default(),
+        }
+    }
+
+    /// Writes the accounts of the fixture to the state of `evm`.
+    pub fn apply(&self, evm: &mut Evm) -> Result<(), EvmError> {
+        evm.override_block(&BlockOverrides {
+            blob_base_fee: Some(self.block.blob_base_fee),
+            ..Default::default()
+        });
+        for (address, account) in &self.accounts {
+            evm.set_balance(*address, account.balance)?;
+            evm.set_nonce(*address, account.nonce)?;
+            if !account.code.is_empty() {
+                evm.set_code(*address, account.code.clone())?;
+            }
+            for (slot, value) in &account.storage {
+                evm.set_storage(
+                    *address,
+                    Uint::from_big_endian(slot.as_bytes()),
+                    Uint::from_big_endian(value.as_bytes()),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Simulates the transaction like `/simulate`, then reads every account and storage slot it
+/// loaded as they were before the simulation and its overrides.
+pub async fn export_fixture(
+    transaction: SimulationRequest,
+    config: Config,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let snapshot = evm.snapshot();
+    evm.record_touched_state();
+    let simulation = run(&mut evm, transaction.clone(), false).await;
+    let touched = evm.take_touched_state();
+    let simulation = simulation?;
+    evm.revert(snapshot);
+
+    // Everything touched was fetched by the simulation, but reads still go through the fork
+    let fixture =
+        tokio::task::block_in_place(|| Fixture::capture(&evm, transaction.chain_id, touched))?;
+
+    // Always hex, for the fixture to be accepted back as is
+    Ok(warp::reply::json(&FixtureExport {
+        fixture,
+        simulation,
+    }))
+}
+
+/// Simulates the transaction against the state of the fixture alone, without an RPC.
+pub async fn simulate_fixture(
+    request: FixtureSimulationRequest,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    let FixtureSimulationRequest {
+        fixture,
+        transaction,
+    } = request;
+    if transaction.chain_id != fixture.chain_id {
+        return Err(ChainIdMismatchError.into());
+    }
+
+    let mut evm = pool.get_offline(fixture.env(), fixture.block.hash, transaction.gas_limit);
+    fixture.apply(&mut evm)?;
+    let response = run(&mut evm, transaction.clone(), false).await?;
+
+    Ok(quantity::json(&response, transaction.quantity_format))
+}
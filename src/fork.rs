@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ethers::abi::{encode, Address, Hash, Token, Uint};
+use ethers::types::Bytes;
+use ethers::utils::{id, keccak256};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+use warp::hyper::StatusCode;
+use warp::reply::Json;
+use warp::{Rejection, Reply};
+
+use crate::assets::{token_info, TokenInfo};
+use crate::errors::{
+    BalanceSlotNotFoundError, ChainIdMismatchError, EvmError, ForkNotFoundError,
+    ForksNotAllowedError, InvalidRequestError, SnapshotNotFoundError,
+};
+use crate::quantity;
+use crate::simulation::{chain_id_to_fork_url, run, SimulationRequest, SimulationResponse};
+use crate::validation::FieldError;
+use crate::webhook;
+
+use super::config::Config;
+use super::evm::{CallOptions, CallRawRequest, Evm, EvmSnapshot};
+use super::history::History;
+use super::pool::EvmPool;
+
+/// Gas limit the fork executor is created with, each committed transaction sets its own.
+pub(crate) const FORK_GAS_LIMIT: u64 = 30_000_000;
+
+/// Storage slots searched for the balances mapping of a token by `deal`.
+const MAX_BALANCE_SLOT: u64 = 100;
+
+/// Body of the callback of a simulation on a fork.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ForkCallback {
+    #[serde(rename = "forkId")]
+    pub fork_id: Uuid,
+    pub result: SimulationResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkRequest {
+    #[serde(rename = "chainId", deserialize_with = "quantity::deserialize_u64")]
+    pub chain_id: u64,
+    #[serde(
+        rename = "blockNumber",
+        default,
+        deserialize_with = "quantity::deserialize_option_u64"
+    )]
+    pub block_number: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ForkResponse {
+    #[serde(rename = "forkId")]
+    pub fork_id: Uuid,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    #[serde(rename = "blockNumber")]
+    pub block_number: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetBalanceRequest {
+    pub address: Address,
+    pub balance: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetStorageRequest {
+    pub address: Address,
+    pub slot: Hash,
+    pub value: Hash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetCodeRequest {
+    pub address: Address,
+    pub code: Bytes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DealRequest {
+    /// ERC-20 token whose balance is set.
+    pub token: Address,
+    pub holder: Address,
+    pub amount: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DealResponse {
+    /// Storage slot of the token holding the balance of the holder.
+    pub slot: Hash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NftQuery {
+    /// ERC-721 token.
+    pub token: Address,
+    #[serde(rename = "tokenId", deserialize_with = "quantity::deserialize_uint")]
+    pub token_id: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenBalancesRequest {
+    pub holder: Address,
+    /// ERC-20 tokens, read with `balanceOf`.
+    #[serde(default)]
+    pub tokens: Vec<Address>,
+    /// ERC-721 tokens, read with `ownerOf`.
+    #[serde(default)]
+    pub nfts: Vec<NftQuery>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenBalance {
+    pub token: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<Uint>,
+    #[serde(rename = "tokenInfo", default, skip_serializing_if = "Option::is_none")]
+    pub token_info: Option<TokenInfo>,
+    /// Why the balance could not be read, e.g. the token not implementing `balanceOf`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NftOwnership {
+    pub token: Address,
+    #[serde(rename = "tokenId")]
+    pub token_id: Uint,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Address>,
+    /// Whether `owner` is the holder.
+    pub owned: bool,
+    /// Why the owner could not be read, e.g. the token not being minted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenBalancesResponse {
+    pub holder: Address,
+    /// Ether balance of the holder.
+    pub balance: Uint,
+    /// In the order of the request.
+    pub tokens: Vec<TokenBalance>,
+    pub nfts: Vec<NftOwnership>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SnapshotResponse {
+    #[serde(rename = "snapshotId")]
+    pub snapshot_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevertRequest {
+    /// Also accepts the hex IDs of `evm_snapshot`.
+    #[serde(rename = "snapshotId", deserialize_with = "quantity::deserialize_u64")]
+    pub snapshot_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountQuery {
+    pub address: Address,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageQuery {
+    pub address: Address,
+    /// A quantity like `0x0` or a 32 byte hex slot.
+    #[serde(deserialize_with = "quantity::deserialize_uint")]
+    pub slot: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BalanceResponse {
+    pub balance: Uint,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CodeResponse {
+    pub code: Bytes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StorageResponse {
+    pub value: Hash,
+}
+
+pub struct Fork {
+    pub chain_id: u64,
+    pub evm: Evm,
+    /// Oldest first, with their IDs.
+    snapshots: Vec<(u64, EvmSnapshot)>,
+    last_snapshot_id: u64,
+}
+
+impl Fork {
+    pub fn new(chain_id: u64, evm: Evm) -> Self {
+        Fork {
+            chain_id,
+            evm,
+            snapshots: Vec::new(),
+            last_snapshot_id: 0,
+        }
+    }
+
+    /// Saves the current state, like `evm_snapshot`. IDs start at 1 and increase.
+    pub fn snapshot(&mut self) -> u64 {
+        self.last_snapshot_id += 1;
+        self.snapshots
+            .push((self.last_snapshot_id, self.evm.snapshot()));
+        self.last_snapshot_id
+    }
+
+    /// Rolls back to a snapshot, like `evm_revert`, which drops it and every later snapshot.
+    /// Returns whether the snapshot existed.
+    pub fn revert(&mut self, snapshot_id: u64) -> bool {
+        let Some(idx) = self.snapshots.iter().position(|(id, _)| *id == snapshot_id) else {
+            return false;
+        };
+        self.snapshots.truncate(idx + 1);
+        let (_, snapshot) = self.snapshots.pop().expect("the snapshot was found");
+        self.evm.revert(snapshot);
+        true
+    }
+}
+
+/// Long-lived forks, keyed by fork ID, which keep their state between requests.
+#[derive(Clone, Default)]
+pub struct ForkStore {
+    forks: Arc<RwLock<HashMap<Uuid, Arc<Mutex<Fork>>>>>,
+}
+
+impl ForkStore {
+    pub async fn insert(&self, fork: Fork) -> Uuid {
+        let fork_id = Uuid::new_v4();
+        self.forks
+            .write()
+            .await
+            .insert(fork_id, Arc::new(Mutex::new(fork)));
+        fork_id
+    }
+
+    pub async fn get(&self, fork_id: Uuid) -> Result<Arc<Mutex<Fork>>, Rejection> {
+        self.forks
+            .read()
+            .await
+            .get(&fork_id)
+            .cloned()
+            .ok_or_else(|| ForkNotFoundError.into())
+    }
+
+    pub async fn remove(&self, fork_id: Uuid) -> Result<(), Rejection> {
+        self.forks
+            .write()
+            .await
+            .remove(&fork_id)
+            .map(|_| ())
+            .ok_or_else(|| ForkNotFoundError.into())
+    }
+}
+
+pub async fn create_fork(
+    request: ForkRequest,
+    config: Config,
+    forks: ForkStore,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    if let Some(policy) = &config.api_key_policy {
+        if !policy.persistent_forks {
+            return Err(warp::reject::custom(ForksNotAllowedError));
+        }
+    }
+    let fork_url = chain_id_to_fork_url(request.chain_id, &config)?;
+    let evm = pool.get(
+        request.chain_id,
+        fork_url,
+        request.block_number,
+        FORK_GAS_LIMIT,
+        config.etherscan_key,
+    );
+    let block_number = evm.block_number();
+
+    let fork_id = forks.insert(Fork::new(request.chain_id, evm)).await;
+
+    Ok(warp::reply::json(&ForkResponse {
+        fork_id,
+        chain_id: request.chain_id,
+        block_number,
+    }))
+}
+
+pub async fn simulate_on_fork(
+    fork_id: Uuid,
+    transaction: SimulationRequest,
+    forks: ForkStore,
+    history: History,
+    config: Config,
+) -> Result<Json, Rejection> {
+    if let Some(url) = &transaction.callback_url {
+        webhook::check_callback_url(url, &config).await?;
+    }
+    let fork = forks.get(fork_id).await?;
+    let mut fork = fork.lock().await;
+
+    if transaction.chain_id != fork.chain_id {
+        return Err(ChainIdMismatchError.into());
+    }
+    // The fork is already at a block, later ones can only be reached by number
+    if transaction.block_hash.is_some() {
+        return Err(InvalidRequestError(vec![FieldError {
+            field: "blockHash".to_string(),
+            message: "forks can't be moved to a block hash".to_string(),
+        }])
+        .into());
+    }
+
+    let response = run(&mut fork.evm, transaction.clone(), true).await?;
+    history.record(&transaction, &response);
+
+    if let Some(url) = transaction.callback_url {
+        let callback = ForkCallback {
+            fork_id,
+            result: response.clone(),
+        };
+        webhook::send(url, &callback, &config);
+    }
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn set_balance(
+    fork_id: Uuid,
+    request: SetBalanceRequest,
+    forks: ForkStore,
+) -> Result<impl Reply, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    fork.lock()
+        .await
+        .evm
+        .set_balance(request.address, request.balance)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn set_storage(
+    fork_id: Uuid,
+    request: SetStorageRequest,
+    forks: ForkStore,
+) -> Result<impl Reply, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    fork.lock().await.evm.set_storage(
+        request.address,
+        Uint::from_big_endian(request.slot.as_bytes()),
+        Uint::from_big_endian(request.value.as_bytes()),
+    )?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn set_code(
+    fork_id: Uuid,
+    request: SetCodeRequest,
+    forks: ForkStore,
+) -> Result<impl Reply, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    fork.lock()
+        .await
+        .evm
+        .set_code(request.address, request.code)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn snapshot(fork_id: Uuid, forks: ForkStore) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let snapshot_id = fork.lock().await.snapshot();
+
+    Ok(warp::reply::json(&SnapshotResponse { snapshot_id }))
+}
+
+pub async fn revert(
+    fork_id: Uuid,
+    request: RevertRequest,
+    forks: ForkStore,
+) -> Result<impl Reply, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    if !fork.lock().await.revert(request.snapshot_id) {
+        return Err(SnapshotNotFoundError.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn balance_of(evm: &mut Evm, token: Address, holder: Address) -> Result<Uint, Rejection> {
+    let mut data = id("balanceOf(address)").to_vec();
+    data.extend(encode(&[Token::Address(holder)]));
+    let request = CallRawRequest {
+        to: Some(token),
+        data: Some(data.into()),
+        gas_limit: FORK_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&request, CallOptions::default()).await?;
+
+    match result.output.get(..32) {
+        Some(output) if result.success => Ok(Uint::from_big_endian(output)),
+        _ => Err(BalanceSlotNotFoundError.into()),
+    }
+}
+
+/// Sets the ERC-20 balance of a holder like forge-std's `deal`: the balances mapping is found by
+/// writing a marker to the slot the holder's balance would have for each candidate mapping slot,
+/// with both Solidity and Vyper layouts, until `balanceOf` returns it.
+pub async fn deal(
+    fork_id: Uuid,
+    request: DealRequest,
+    forks: ForkStore,
+) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let mut fork = fork.lock().await;
+    let evm = &mut fork.evm;
+    let marker = Uint::from(keccak256("ts::deal"));
+
+    for mapping_slot in 0..MAX_BALANCE_SLOT {
+        let holder = Token::Address(request.holder);
+        let mapping_slot = Token::Uint(mapping_slot.into());
+        let layouts = [
+            encode(&[holder.clone(), mapping_slot.clone()]),
+            encode(&[mapping_slot, holder]),
+        ];
+
+        for layout in layouts {
+            let slot = Uint::from(keccak256(layout));
+            let original = evm.storage(request.token, slot)?;
+            evm.set_storage(request.token, slot, marker)?;
+
+            if balance_of(evm, request.token, request.holder).await? == marker {
+                evm.set_storage(request.token, slot, request.amount)?;
+                let mut bytes = [0u8; 32];
+                slot.to_big_endian(&mut bytes);
+                return Ok(warp::reply::json(&DealResponse {
+                    slot: Hash::from(bytes),
+                }));
+            }
+            evm.set_storage(request.token, slot, original)?;
+        }
+    }
+
+    Err(BalanceSlotNotFoundError.into())
+}
+
+/// Calls a view function of a token on the fork, returning its output if it succeeded.
+async fn call_token(
+    evm: &mut Evm,
+    token: Address,
+    data: Vec<u8>,
+) -> Result<Option<Bytes>, EvmError> {
+    let request = CallRawRequest {
+        to: Some(token),
+        data: Some(data.into()),
+        gas_limit: FORK_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&request, CallOptions::default()).await?;
+
+    Ok(result.success.then_some(result.output))
+}
+
+/// Reads the ERC-20 balances and ERC-721 ownerships of a holder on a persistent fork, without
+/// committing anything. A token failing to answer is reported on its entry rather than failing
+/// the whole request.
+pub async fn token_balances(
+    fork_id: Uuid,
+    request: TokenBalancesRequest,
+    forks: ForkStore,
+) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let mut fork = fork.lock().await;
+    let evm = &mut fork.evm;
+    let balance = evm.basic(request.holder)?.balance;
+
+    let mut tokens = Vec::with_capacity(request.tokens.len());
+    for token in request.tokens {
+        let mut data = id("balanceOf(address)").to_vec();
+        data.extend(encode(&[Token::Address(request.holder)]));
+        let output = call_token(evm, token, data).await?;
+        let balance = output
+            .as_deref()
+            .and_then(|output| output.get(..32))
+            .map(Uint::from_big_endian);
+        let token_info = match balance {
+            Some(_) => token_info(evm, token).await.ok(),
+            None => None,
+        };
+        tokens.push(TokenBalance {
+            token,
+            balance,
+            token_info,
+            error: balance
+                .is_none()
+                .then(|| format!("balanceOf failed on {token:?}")),
+        });
+    }
+
+    let mut nfts = Vec::with_capacity(request.nfts.len());
+    for nft in request.nfts {
+        let mut data = id("ownerOf(uint256)").to_vec();
+        data.extend(encode(&[Token::Uint(nft.token_id)]));
+        let output = call_token(evm, nft.token, data).await?;
+        let owner = output
+            .as_deref()
+            .and_then(|output| output.get(12..32))
+            .map(Address::from_slice);
+        nfts.push(NftOwnership {
+            token: nft.token,
+            token_id: nft.token_id,
+            owner,
+            owned: owner == Some(request.holder),
+            error: owner
+                .is_none()
+                .then(|| format!("ownerOf({}) failed on {:?}", nft.token_id, nft.token)),
+        });
+    }
+
+    Ok(warp::reply::json(&TokenBalancesResponse {
+        holder: request.holder,
+        balance,
+        tokens,
+        nfts,
+    }))
+}
+
+pub async fn get_balance(
+    fork_id: Uuid,
+    query: AccountQuery,
+    forks: ForkStore,
+) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let info = fork.lock().await.evm.basic(query.address)?;
+
+    Ok(warp::reply::json(&BalanceResponse {
+        balance: info.balance,
+        nonce: info.nonce,
+    }))
+}
+
+pub async fn get_code(
+    fork_id: Uuid,
+    query: AccountQuery,
+    forks: ForkStore,
+) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let code = fork.lock().await.evm.account_code(query.address)?;
+
+    Ok(warp::reply::json(&CodeResponse { code }))
+}
+
+pub async fn get_storage(
+    fork_id: Uuid,
+    query: StorageQuery,
+    forks: ForkStore,
+) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let value = fork.lock().await.evm.storage(query.address, query.slot)?;
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+
+    Ok(warp::reply::json(&StorageResponse {
+        value: Hash::from(bytes),
+    }))
+}
+
+pub async fn delete_fork(fork_id: Uuid, forks: ForkStore) -> Result<impl Reply, Rejection> {
+    forks.remove(fork_id).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,82 @@
+use std::sync::Mutex;
+
+use eyre::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+
+/// Methods whose result never changes once the block they are asked at is mined, with the
+/// position of the block parameter.
+const CACHEABLE_METHODS: &[(&str, usize)] = &[
+    ("eth_getBalance", 1),
+    ("eth_getTransactionCount", 1),
+    ("eth_getCode", 1),
+    ("eth_getStorageAt", 2),
+    ("eth_getBlockByNumber", 0),
+];
+
+/// Fork RPC responses persisted in SQLite, keyed by chain, method and parameters, which include
+/// the block, address and slot.
+pub struct ForkCache {
+    connection: Mutex<Connection>,
+}
+
+impl ForkCache {
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS rpc_cache (
+                chain_id INTEGER NOT NULL,
+                method TEXT NOT NULL,
+                params TEXT NOT NULL,
+                result TEXT NOT NULL,
+                PRIMARY KEY (chain_id, method, params)
+            );",
+        )?;
+
+        Ok(ForkCache {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub(crate) fn get(&self, chain_id: u64, method: &str, params: &str) -> Result<Option<Value>> {
+        let result: Option<String> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT result FROM rpc_cache WHERE chain_id = ?1 AND method = ?2 AND params = ?3",
+                params![chain_id, method, params],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(result
+            .map(|result| serde_json::from_str(&result))
+            .transpose()?)
+    }
+
+    pub(crate) fn insert(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: &str,
+        result: &Value,
+    ) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO rpc_cache (chain_id, method, params, result)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![chain_id, method, params, serde_json::to_string(result)?],
+        )?;
+        Ok(())
+    }
+}
+
+/// Only requests at a block number can be cached, tags like `latest` move on.
+pub(crate) fn is_cacheable(method: &str, params: &Value) -> bool {
+    CACHEABLE_METHODS
+        .iter()
+        .find(|(cacheable, _)| *cacheable == method)
+        .and_then(|(_, position)| params.get(*position))
+        .and_then(Value::as_str)
+        .map_or(false, |block| block.starts_with("0x"))
+}
@@ -0,0 +1,67 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use ethers::abi::{AbiParser, Function};
+use ethers::utils::hex;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+const FOUR_BYTE_URL: &str = "https://www.4byte.directory/api/v1/signatures/";
+
+/// Signatures looked up so far by selector, including selectors without any.
+static SIGNATURES: Lazy<Mutex<LruCache<[u8; 4], Vec<String>>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(10_000).unwrap())));
+
+#[derive(Deserialize)]
+struct SignaturesResponse {
+    results: Vec<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    text_signature: String,
+}
+
+async fn fetch(selector: [u8; 4]) -> reqwest::Result<Vec<String>> {
+    let response: SignaturesResponse = reqwest::Client::new()
+        .get(FOUR_BYTE_URL)
+        .query(&[("hex_signature", format!("0x{}", hex::encode(selector)))])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(response
+        .results
+        .into_iter()
+        .map(|signature| signature.text_signature)
+        .collect())
+}
+
+/// Functions 4byte.directory knows for `selector`, oldest first. Several unrelated functions
+/// may share a selector, callers pick the one the calldata decodes with.
+pub(crate) async fn lookup(selector: [u8; 4]) -> Vec<Function> {
+    let cached = SIGNATURES.lock().unwrap().get(&selector).cloned();
+    let signatures = match cached {
+        Some(signatures) => signatures,
+        None => match fetch(selector).await {
+            Ok(mut signatures) => {
+                // The API lists the most recently submitted first, collisions are usually newer
+                signatures.reverse();
+                SIGNATURES.lock().unwrap().put(selector, signatures.clone());
+                signatures
+            }
+            Err(err) => {
+                log::warn!(target: "ts::four_byte", "Failed to look selector up: {err}");
+                vec![]
+            }
+        },
+    };
+
+    signatures
+        .iter()
+        .filter_map(|signature| AbiParser::default().parse_function(signature).ok())
+        .collect()
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use ethers::abi::{Address, Uint};
+use ethers::types::Log;
+use foundry_evm::trace::CallTraceArena;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{token_info, transfers, AssetType, TokenInfo};
+use crate::evm::Evm;
+
+/// An account funds moved from or to.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FlowNode {
+    pub address: Address,
+    /// Name of the contract identified from Etherscan, or symbol of the token, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// An asset moved from one node to another, summed over the transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FlowEdge {
+    pub from: Address,
+    pub to: Address,
+    #[serde(rename = "assetType")]
+    pub asset_type: AssetType,
+    /// Token contract, not set for the native asset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Address>,
+    #[serde(rename = "tokenId", default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<Uint>,
+    pub amount: Uint,
+    #[serde(rename = "tokenInfo", default, skip_serializing_if = "Option::is_none")]
+    pub token_info: Option<TokenInfo>,
+}
+
+/// Graph of the native value and tokens the transaction moved, to render as a funds flow.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FundsFlow {
+    /// Every sender and recipient of an edge, ordered by address. The zero address is the source
+    /// of mints and the sink of burns.
+    pub nodes: Vec<FlowNode>,
+    /// Ordered by sender.
+    pub edges: Vec<FlowEdge>,
+}
+
+/// Derives the funds flow from the successful call frames and transfer events, like
+/// `assetChanges`. Nodes are labelled with the contracts identified so far and the symbols of
+/// the tokens, resolved on the state of `evm`.
+pub(crate) async fn funds_flow(evm: &mut Evm, arena: &CallTraceArena, logs: &[Log]) -> FundsFlow {
+    let mut tokens: BTreeMap<Address, Option<TokenInfo>> = BTreeMap::new();
+    let mut edges = vec![];
+    for transfer in transfers(arena, logs) {
+        let token_info = match transfer.token {
+            Some(token) => match tokens.get(&token) {
+                Some(info) => info.clone(),
+                None => {
+                    let info = match token_info(evm, token).await {
+                        Ok(info) => Some(info),
+                        Err(err) => {
+                            log::warn!(
+                                target: "ts::assets",
+                                "Failed to resolve token {token:?}: {}",
+                                err.0
+                            );
+                            None
+                        }
+                    };
+                    tokens.insert(token, info.clone());
+                    info
+                }
+            },
+            None => None,
+        };
+        edges.push(FlowEdge {
+            from: transfer.from,
+            to: transfer.to,
+            asset_type: transfer.asset_type,
+            token: transfer.token,
+            token_id: transfer.token_id,
+            amount: transfer.amount,
+            token_info,
+        });
+    }
+
+    let mut nodes: BTreeMap<Address, Option<String>> = BTreeMap::new();
+    for edge in &edges {
+        for address in [edge.from, edge.to] {
+            nodes.entry(address).or_insert_with(|| {
+                evm.contract_name(address).map(str::to_string).or_else(|| {
+                    tokens
+                        .get(&address)
+                        .cloned()
+                        .flatten()
+                        .and_then(|info| info.symbol)
+                })
+            });
+        }
+    }
+
+    FundsFlow {
+        nodes: nodes
+            .into_iter()
+            .map(|(address, label)| FlowNode { address, label })
+            .collect(),
+        edges,
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use ethers::abi::Address;
+use ethers::types::Bytes;
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall};
+use foundry_evm::CallKind;
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::DecodedCall;
+
+/// Where the gas of a transaction went, each frame counting the gas it used itself, without the
+/// gas of the frames it called.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GasProfile {
+    /// Gas not spent executing code: the intrinsic gas of the transaction, minus refunds.
+    #[serde(rename = "intrinsicGas")]
+    pub intrinsic_gas: u64,
+    /// Most expensive first.
+    #[serde(rename = "byContract")]
+    pub by_contract: Vec<ContractGas>,
+    /// Most expensive first.
+    #[serde(rename = "byFunction")]
+    pub by_function: Vec<FunctionGas>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ContractGas {
+    /// Contract whose code ran, the implementation for delegatecalls.
+    pub address: Address,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    /// Frames which ran its code.
+    pub calls: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FunctionGas {
+    pub address: Address,
+    /// Not set for deployments and calls with less than 4 bytes of calldata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<Bytes>,
+    /// Only set with `decodeCalls`, if the function could be decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    pub calls: usize,
+}
+
+/// Aggregates the gas of every frame of the trace by contract and by function. `decoded_calls`
+/// are indexed like the arena and may be empty.
+pub fn gas_profile(
+    arena: &CallTraceArena,
+    decoded_calls: &[Option<DecodedCall>],
+    gas_used: u64,
+) -> GasProfile {
+    let mut by_contract: HashMap<Address, ContractGas> = HashMap::new();
+    let mut by_function: HashMap<(Address, Option<Bytes>), FunctionGas> = HashMap::new();
+    let mut execution_gas = 0u64;
+
+    for (idx, node) in arena.arena.iter().enumerate() {
+        let children_gas: u64 = node
+            .children
+            .iter()
+            .map(|child| arena.arena[*child].trace.gas_cost)
+            .sum();
+        let self_gas = node.trace.gas_cost.saturating_sub(children_gas);
+        execution_gas += self_gas;
+
+        let address = node.trace.address;
+        let contract = by_contract.entry(address).or_insert(ContractGas {
+            address,
+            gas_used: 0,
+            calls: 0,
+        });
+        contract.gas_used += self_gas;
+        contract.calls += 1;
+
+        let selector = match (&node.trace.kind, &node.trace.data) {
+            (CallKind::Create | CallKind::Create2, _) => None,
+            (_, RawOrDecodedCall::Raw(input)) => input.get(..4).map(|s| s.to_vec().into()),
+            (_, RawOrDecodedCall::Decoded(..)) => None,
+        };
+        let signature = decoded_calls
+            .get(idx)
+            .and_then(Option::as_ref)
+            .map(|call| call.signature.clone());
+        let function = by_function
+            .entry((address, selector.clone()))
+            .or_insert(FunctionGas {
+                address,
+                selector,
+                signature: None,
+                gas_used: 0,
+                calls: 0,
+            });
+        function.gas_used += self_gas;
+        function.calls += 1;
+        if function.signature.is_none() {
+            function.signature = signature;
+        }
+    }
+
+    let mut by_contract: Vec<ContractGas> = by_contract.into_values().collect();
+    by_contract.sort_by(|a, b| {
+        b.gas_used
+            .cmp(&a.gas_used)
+            .then_with(|| a.address.cmp(&b.address))
+    });
+    let mut by_function: Vec<FunctionGas> = by_function.into_values().collect();
+    by_function.sort_by(|a, b| {
+        b.gas_used
+            .cmp(&a.gas_used)
+            .then_with(|| a.address.cmp(&b.address))
+            .then_with(|| a.selector.cmp(&b.selector))
+    });
+
+    GasProfile {
+        intrinsic_gas: gas_used.saturating_sub(execution_gas),
+        by_contract,
+        by_function,
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use ethers::types::Bytes;
+use futures_util::Stream;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::service::interceptor::{InterceptedService, Interceptor};
+use tonic::{Code, Request, Response, Status};
+use warp::Rejection;
+
+use crate::admission::{AdmissionQueue, Admitted};
+use crate::auth::check_api_key;
+use crate::errors::{
+    error_message, BundleTooLargeError, QueueFullError, RateLimitedError, SimulationError,
+};
+use crate::rate_limit::RateLimiter;
+use crate::simulation::{SimulationRequest, SimulationResponse};
+use crate::simulator::Simulator;
+
+pub mod proto {
+    tonic::include_proto!("simulator.v1");
+}
+
+use proto::bundle_event::Event;
+use proto::simulator_server::{self, SimulatorServer};
+
+/// Events of a bundle buffered while the client reads the previous ones.
+const BUNDLE_EVENTS_BUFFER: usize = 16;
+
+/// Fields of the request merged into the JSON object of `options_json`, then deserialized like a
+/// body of `/simulate` so that quantities are parsed the same way.
+impl TryFrom<proto::SimulationRequest> for SimulationRequest {
+    type Error = Status;
+
+    fn try_from(request: proto::SimulationRequest) -> Result<Self, Status> {
+        let mut fields = match request.options_json.as_str() {
+            "" => Map::new(),
+            options => match serde_json::from_str(options) {
+                Ok(Value::Object(fields)) => fields,
+                _ => {
+                    return Err(Status::invalid_argument(
+                        "options_json must be a JSON object",
+                    ))
+                }
+            },
+        };
+
+        fields.insert("chainId".to_string(), request.chain_id.into());
+        fields.insert("from".to_string(), request.from.into());
+        fields.insert("gasLimit".to_string(), request.gas_limit.into());
+        if request.format_trace {
+            fields.insert("formatTrace".to_string(), true.into());
+        }
+        if let Some(data) = request.data {
+            let data = serde_json::to_value(Bytes::from(data)).expect("bytes must serialize");
+            fields.insert("data".to_string(), data);
+        }
+        if let Some(block_number) = request.block_number {
+            fields.insert("blockNumber".to_string(), block_number.into());
+        }
+        let strings = [
+            ("to", request.to),
+            ("value", request.value),
+            ("gasPrice", request.gas_price),
+            ("maxFeePerGas", request.max_fee_per_gas),
+            ("maxPriorityFeePerGas", request.max_priority_fee_per_gas),
+        ];
+        for (name, value) in strings {
+            if let Some(value) = value {
+                fields.insert(name.to_string(), value.into());
+            }
+        }
+
+        serde_json::from_value(Value::Object(fields))
+            .map_err(|err| Status::invalid_argument(err.to_string()))
+    }
+}
+
+impl From<&SimulationResponse> for proto::SimulationResponse {
+    fn from(response: &SimulationResponse) -> Self {
+        proto::SimulationResponse {
+            simulation_id: response.simulation_id.to_string(),
+            gas_used: response.gas_used,
+            gas_refunded: response.gas_refunded,
+            block_number: response.block_number,
+            block_hash: response.block_hash.map(|hash| format!("{hash:?}")),
+            timestamp: response.timestamp,
+            success: response.success,
+            trace: response
+                .trace
+                .iter()
+                .map(|call| proto::CallTrace {
+                    call_type: format!("{:?}", call.call_type).to_uppercase(),
+                    from: format!("{:?}", call.from),
+                    to: format!("{:?}", call.to),
+                    value: format!("0x{:x}", call.value),
+                })
+                .collect(),
+            logs: response
+                .logs
+                .iter()
+                .map(|log| proto::Log {
+                    address: format!("{:?}", log.address),
+                    topics: log
+                        .topics
+                        .iter()
+                        .map(|topic| topic.as_bytes().to_vec())
+                        .collect(),
+                    data: log.data.to_vec(),
+                })
+                .collect(),
+            return_data: response.return_data.to_vec(),
+            effective_gas_price: format!("0x{:x}", response.effective_gas_price),
+            fee_paid: format!("0x{:x}", response.fee_paid),
+            revert_reason: response.revert_reason.clone(),
+            formatted_trace: response.formatted_trace.clone(),
+            created_address: response
+                .created_address
+                .map(|address| format!("{address:?}")),
+            cached: response.cached,
+            json: serde_json::to_string(response).expect("responses must serialize"),
+        }
+    }
+}
+
+/// The error of the HTTP API as a gRPC status, with its JSON `ErrorMessage` as details.
+fn status(err: impl Into<Rejection>) -> Status {
+    let (error, _) = error_message(&err.into());
+    let code = match error.code {
+        400 => Code::InvalidArgument,
+        401 => Code::Unauthenticated,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        429 | 503 => Code::ResourceExhausted,
+        502 => Code::Unavailable,
+        504 => Code::DeadlineExceeded,
+        _ => Code::Internal,
+    };
+    let details = serde_json::to_vec(&error).expect("errors must serialize");
+
+    Status::with_details(code, error.message, details.into())
+}
+
+/// Requires a known key in the `x-api-key` metadata, lets every request through if `api_keys` is
+/// empty, then rate limits it, like the HTTP API. The key is passed on to the service as an
+/// `ApiKey` extension.
+#[derive(Clone)]
+pub struct ApiKeyInterceptor {
+    api_keys: Arc<HashSet<String>>,
+    rate_limiter: RateLimiter,
+}
+
+/// The `x-api-key` of a call, which its policy is applied for.
+#[derive(Clone)]
+struct ApiKey(Option<String>);
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let key = request
+            .metadata()
+            .get("x-api-key")
+            .and_then(|key| key.to_str().ok())
+            .map(str::to_string);
+        check_api_key(&self.api_keys, key.clone()).map_err(status)?;
+        self.rate_limiter
+            .acquire_for(key.as_deref(), request.remote_addr())
+            .map_err(|retry_after| status(RateLimitedError { retry_after }))?;
+        request.extensions_mut().insert(ApiKey(key));
+
+        Ok(request)
+    }
+}
+
+/// Serves the simulator over gRPC, see `proto/simulator.proto`. Simulations aren't recorded in
+/// the history of the HTTP API.
+#[derive(Clone)]
+pub struct GrpcService {
+    simulator: Simulator,
+    admission: Option<AdmissionQueue>,
+    rate_limiter: RateLimiter,
+}
+
+impl GrpcService {
+    /// A service admitting every call, without rate limit.
+    pub fn new(simulator: Simulator) -> Self {
+        GrpcService {
+            simulator,
+            admission: None,
+            rate_limiter: RateLimiter::new(0),
+        }
+    }
+
+    /// Simulates only once `admission` admits the call, sharing the queue of the HTTP API.
+    pub fn with_admission(mut self, admission: AdmissionQueue) -> Self {
+        self.admission = Some(admission);
+        self
+    }
+
+    /// Counts calls in the buckets of `rate_limiter`, sharing them with the HTTP API.
+    pub fn with_rate_limit(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    fn admit(&self) -> Result<Option<Admitted>, Status> {
+        self.admission
+            .as_ref()
+            .map(|admission| admission.admit())
+            .transpose()
+            .map_err(|retry_after| status(QueueFullError { retry_after }))
+    }
+
+    /// The simulator restricted to the policy of the API key of `request`.
+    fn simulator<T>(&self, request: &Request<T>) -> Simulator {
+        let api_key = request
+            .extensions()
+            .get::<ApiKey>()
+            .and_then(|ApiKey(key)| key.as_deref());
+        self.simulator.for_api_key(api_key)
+    }
+
+    /// The service, checking the API keys of `api_keys`.
+    pub fn server(
+        self,
+        api_keys: HashSet<String>,
+    ) -> InterceptedService<SimulatorServer<Self>, ApiKeyInterceptor> {
+        let interceptor = ApiKeyInterceptor {
+            api_keys: Arc::new(api_keys),
+            rate_limiter: self.rate_limiter.clone(),
+        };
+        SimulatorServer::with_interceptor(self, interceptor)
+    }
+}
+
+#[tonic::async_trait]
+impl simulator_server::Simulator for GrpcService {
+    type SimulateBundleStream =
+        Pin<Box<dyn Stream<Item = Result<proto::BundleEvent, Status>> + Send>>;
+
+    async fn simulate(
+        &self,
+        request: Request<proto::SimulationRequest>,
+    ) -> Result<Response<proto::SimulationResponse>, Status> {
+        let simulator = self.simulator(&request);
+        let transaction = SimulationRequest::try_from(request.into_inner())?;
+        let _admitted = self.admit()?;
+        let response = simulator.simulate(transaction).await.map_err(status)?;
+
+        Ok(Response::new((&response).into()))
+    }
+
+    async fn simulate_bundle(
+        &self,
+        request: Request<proto::BundleRequest>,
+    ) -> Result<Response<Self::SimulateBundleStream>, Status> {
+        let simulator = self.simulator(&request);
+        let transactions = request
+            .into_inner()
+            .transactions
+            .into_iter()
+            .map(SimulationRequest::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        if transactions.len() > simulator.config().max_bundle_size {
+            return Err(status(BundleTooLargeError));
+        }
+
+        // Held until the whole bundle has executed
+        let admitted = self.admit()?;
+        let (sender, receiver) = mpsc::channel(BUNDLE_EVENTS_BUFFER);
+        tokio::spawn(async move {
+            let _admitted = admitted;
+            if let Err(err) = stream_bundle(&simulator, transactions, &sender).await {
+                sender.send(Err(status(err))).await.ok();
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+/// Simulates the transactions one after the other on a single fork, sending the result of each
+/// as soon as it has executed, then the summary. Stops early if the client went away.
+async fn stream_bundle(
+    simulator: &Simulator,
+    transactions: Vec<SimulationRequest>,
+    sender: &mpsc::Sender<Result<proto::BundleEvent, Status>>,
+) -> Result<(), SimulationError> {
+    let mut gas_used = 0;
+    let mut success = true;
+
+    if let Some(first) = transactions.first() {
+        let first_chain_id = first.chain_id;
+        let mut evm = simulator.fork(
+            first_chain_id,
+            first.block_number,
+            first.block_hash,
+            first.gas_limit,
+        )?;
+
+        let mut block_number = evm.block_number();
+        for (index, transaction) in transactions.iter().enumerate() {
+            if transaction.chain_id != first_chain_id {
+                return Err(SimulationError::MultipleChainIds);
+            }
+            if let Some(next_block_number) = transaction.block_number {
+                if next_block_number < block_number {
+                    return Err(SimulationError::BlockNumberDecreasing);
+                }
+                evm.roll_block(next_block_number);
+                block_number = next_block_number;
+            }
+
+            let response = simulator
+                .simulate_on(&mut evm, transaction.clone(), true)
+                .await?;
+            gas_used += response.gas_used;
+            success &= response.success;
+
+            let event = Event::Result(proto::TransactionResult {
+                transaction: index as u32,
+                response: Some((&response).into()),
+            });
+            let event = proto::BundleEvent { event: Some(event) };
+            if sender.send(Ok(event)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    let event = Event::Summary(proto::BundleSummary {
+        transactions: transactions.len() as u32,
+        gas_used,
+        success,
+    });
+    sender
+        .send(Ok(proto::BundleEvent { event: Some(event) }))
+        .await
+        .ok();
+
+    Ok(())
+}
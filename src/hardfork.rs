@@ -0,0 +1,45 @@
+use revm::SpecId;
+use serde::{Deserialize, Serialize};
+
+/// Rules a transaction is executed with, by the upgrade which introduced them. Only those the EVM
+/// implements, up to the merge, later upgrades fail to parse.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Hardfork {
+    Frontier,
+    Homestead,
+    Tangerine,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    MuirGlacier,
+    Berlin,
+    London,
+    ArrowGlacier,
+    GrayGlacier,
+    #[serde(alias = "paris")]
+    Merge,
+}
+
+impl Hardfork {
+    pub fn spec_id(self) -> SpecId {
+        match self {
+            Hardfork::Frontier => SpecId::FRONTIER,
+            Hardfork::Homestead => SpecId::HOMESTEAD,
+            Hardfork::Tangerine => SpecId::TANGERINE,
+            Hardfork::SpuriousDragon => SpecId::SPURIOUS_DRAGON,
+            Hardfork::Byzantium => SpecId::BYZANTIUM,
+            Hardfork::Constantinople => SpecId::CONSTANTINOPLE,
+            Hardfork::Petersburg => SpecId::PETERSBURG,
+            Hardfork::Istanbul => SpecId::ISTANBUL,
+            Hardfork::MuirGlacier => SpecId::MUIR_GLACIER,
+            Hardfork::Berlin => SpecId::BERLIN,
+            Hardfork::London => SpecId::LONDON,
+            Hardfork::ArrowGlacier => SpecId::ARROW_GLACIER,
+            Hardfork::GrayGlacier => SpecId::GRAY_GLACIER,
+            Hardfork::Merge => SpecId::MERGE,
+        }
+    }
+}
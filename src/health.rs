@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use warp::hyper::StatusCode;
+use warp::{Rejection, Reply};
+
+use super::config::Config;
+
+const ETHERSCAN_URL: &str = "https://api.etherscan.io/api";
+
+/// How long an upstream may take to answer a readiness check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Check {
+    pub ok: bool,
+    /// Time the upstream took to answer, not set if it didn't.
+    #[serde(rename = "latencyMs", default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReadinessResponse {
+    /// Whether every configured chain and Etherscan can be used.
+    pub ready: bool,
+    /// Keyed by chain ID, ok if any of its RPCs answers with the right chain ID.
+    pub chains: BTreeMap<u64, Check>,
+    /// Not checked without `ETHERSCAN_KEY`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etherscan: Option<Check>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct EtherscanResponse {
+    status: String,
+    result: Value,
+}
+
+impl Check {
+    fn new(start: Instant, result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Check {
+                ok: true,
+                latency_ms: Some(start.elapsed().as_millis() as u64),
+                error: None,
+            },
+            Err(error) => Check {
+                ok: false,
+                latency_ms: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// Asks an RPC which chain it is for with `eth_chainId`.
+pub(crate) async fn rpc_chain_id(client: &reqwest::Client, url: &str) -> Result<u64, String> {
+    let response: RpcResponse = client
+        .post(url)
+        .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": [] }))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| err.without_url().to_string())?
+        .json()
+        .await
+        .map_err(|err| err.without_url().to_string())?;
+
+    if let Some(error) = response.error {
+        return Err(format!("RPC error: {error}"));
+    }
+    response
+        .result
+        .as_deref()
+        .and_then(|result| u64::from_str_radix(result.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| "invalid eth_chainId response".to_string())
+}
+
+async fn check_rpc(client: &reqwest::Client, chain_id: u64, url: &str) -> Result<(), String> {
+    match rpc_chain_id(client, url).await? {
+        answered if answered == chain_id => Ok(()),
+        answered => Err(format!("RPC is for chain {answered}")),
+    }
+}
+
+/// Tries the RPCs of the chain in order, like failover does, and reports the first which works
+/// or the error of the last one. RPC URLs are left out of errors as they hold API keys.
+async fn check_chain(client: &reqwest::Client, chain_id: u64, urls: &[String]) -> Check {
+    let mut error = "no RPC URL".to_string();
+    for url in urls {
+        let start = Instant::now();
+        match check_rpc(client, chain_id, url).await {
+            Ok(()) => return Check::new(start, Ok(())),
+            Err(err) => {
+                log::warn!(target: "ts::health", "RPC of chain {chain_id} not ready: {err}");
+                error = err;
+            }
+        }
+    }
+    Check::new(Instant::now(), Err(error))
+}
+
+async fn check_etherscan(client: &reqwest::Client, key: &str) -> Check {
+    let start = Instant::now();
+    let result = async {
+        let response: EtherscanResponse = client
+            .get(ETHERSCAN_URL)
+            .query(&[
+                ("module", "stats"),
+                ("action", "ethsupply"),
+                ("apikey", key),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.without_url().to_string())?
+            .json()
+            .await
+            .map_err(|err| err.without_url().to_string())?;
+
+        // Invalid keys are reported in the result with a `0` status
+        match response.status.as_str() {
+            "1" => Ok(()),
+            _ => Err(match response.result {
+                Value::String(message) => message,
+                result => result.to_string(),
+            }),
+        }
+    }
+    .await;
+    if let Err(err) = &result {
+        log::warn!(target: "ts::health", "Etherscan not ready: {err}");
+    }
+    Check::new(start, result)
+}
+
+/// Liveness, answers as long as the server runs.
+pub async fn health() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&json!({ "status": "ok" })))
+}
+
+/// Readiness, checks that the RPC of every configured chain answers and that the Etherscan key
+/// is valid. Answers with a `503` if any of them isn't.
+pub async fn ready(config: Config) -> Result<impl Reply, Rejection> {
+    let client = reqwest::Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        .build()
+        .expect("HTTP client must build");
+
+    let chains = join_all(config.chains.iter().map(|(chain_id, urls)| async {
+        (*chain_id, check_chain(&client, *chain_id, urls).await)
+    }));
+    let etherscan = async {
+        match &config.etherscan_key {
+            Some(key) => Some(check_etherscan(&client, key).await),
+            None => None,
+        }
+    };
+    let (chains, etherscan) = tokio::join!(chains, etherscan);
+    let chains: BTreeMap<u64, Check> = chains.into_iter().collect();
+
+    let ready =
+        chains.values().all(|check| check.ok) && etherscan.as_ref().map_or(true, |check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ReadinessResponse {
+            ready,
+            chains,
+            etherscan,
+        }),
+        status,
+    ))
+}
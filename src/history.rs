@@ -0,0 +1,324 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::abi::Address;
+use eyre::Result;
+use lru::LruCache;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{HistoryError, SimulationNotFoundError};
+use crate::simulation::{SimulationRequest, SimulationResponse};
+
+use super::config::Config;
+
+/// Simulations kept by the in-memory store, the oldest are dropped first.
+const MEMORY_HISTORY_SIZE: usize = 10_000;
+
+/// Default and maximum number of simulations returned by `GET /simulations`.
+const DEFAULT_LIST_LIMIT: usize = 50;
+const MAX_LIST_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationRecord {
+    pub id: Uuid,
+    /// Unix timestamp in seconds.
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    pub request: SimulationRequest,
+    pub response: SimulationResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationsQuery {
+    pub from: Address,
+    pub limit: Option<usize>,
+}
+
+/// Where simulations are persisted. Implementations block, they are expected to be fast.
+pub trait SimulationStore: Send + Sync {
+    fn insert(&self, record: &SimulationRecord) -> Result<()>;
+
+    fn get(&self, id: Uuid) -> Result<Option<SimulationRecord>>;
+
+    /// The most recent simulations sent from `from`, newest first.
+    fn find_by_from(&self, from: Address, limit: usize) -> Result<Vec<SimulationRecord>>;
+
+    /// Simulations created at `since` or later, oldest first, ties broken by ID. Only those
+    /// after the `after` creation time and ID if set, to page through them.
+    fn find_since(
+        &self,
+        since: u64,
+        after: Option<(u64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<SimulationRecord>>;
+}
+
+pub struct MemoryStore {
+    records: Mutex<LruCache<Uuid, SimulationRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new(size: usize) -> Self {
+        let size = NonZeroUsize::new(size.max(1)).unwrap();
+        MemoryStore {
+            records: Mutex::new(LruCache::new(size)),
+        }
+    }
+}
+
+impl SimulationStore for MemoryStore {
+    fn insert(&self, record: &SimulationRecord) -> Result<()> {
+        self.records.lock().unwrap().put(record.id, record.clone());
+        Ok(())
+    }
+
+    fn get(&self, id: Uuid) -> Result<Option<SimulationRecord>> {
+        Ok(self.records.lock().unwrap().peek(&id).cloned())
+    }
+
+    fn find_by_from(&self, from: Address, limit: usize) -> Result<Vec<SimulationRecord>> {
+        let records = self.records.lock().unwrap();
+        let mut found: Vec<SimulationRecord> = records
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.request.from == from)
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        found.truncate(limit);
+        Ok(found)
+    }
+
+    fn find_since(
+        &self,
+        since: u64,
+        after: Option<(u64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<SimulationRecord>> {
+        let records = self.records.lock().unwrap();
+        let mut found: Vec<SimulationRecord> = records
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.created_at >= since)
+            .filter(|record| after.map_or(true, |after| (record.created_at, record.id) > after))
+            .cloned()
+            .collect();
+        found.sort_by_key(|record| (record.created_at, record.id));
+        found.truncate(limit);
+        Ok(found)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS simulations (
+                id TEXT PRIMARY KEY,
+                created_at INTEGER NOT NULL,
+                from_address TEXT NOT NULL,
+                request TEXT NOT NULL,
+                response TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS simulations_from_address
+                ON simulations (from_address, created_at);
+            CREATE INDEX IF NOT EXISTS simulations_created_at
+                ON simulations (created_at, id);",
+        )?;
+
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn record(
+        id: String,
+        created_at: u64,
+        request: String,
+        response: String,
+    ) -> Result<SimulationRecord> {
+        Ok(SimulationRecord {
+            id: id.parse()?,
+            created_at,
+            request: serde_json::from_str(&request)?,
+            response: serde_json::from_str(&response)?,
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SimulationStore for SqliteStore {
+    fn insert(&self, record: &SimulationRecord) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO simulations (id, created_at, from_address, request, response)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                record.id.to_string(),
+                record.created_at,
+                format!("{:?}", record.request.from),
+                serde_json::to_string(&record.request)?,
+                serde_json::to_string(&record.response)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, id: Uuid) -> Result<Option<SimulationRecord>> {
+        let row = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id, created_at, request, response FROM simulations WHERE id = ?1",
+                params![id.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+
+        row.map(|(id, created_at, request, response)| {
+            Self::record(id, created_at, request, response)
+        })
+        .transpose()
+    }
+
+    fn find_by_from(&self, from: Address, limit: usize) -> Result<Vec<SimulationRecord>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT id, created_at, request, response FROM simulations
+                WHERE from_address = ?1 ORDER BY created_at DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![format!("{from:?}"), limit], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+
+        rows.map(|row| {
+            let (id, created_at, request, response) = row?;
+            Self::record(id, created_at, request, response)
+        })
+        .collect()
+    }
+
+    fn find_since(
+        &self,
+        since: u64,
+        after: Option<(u64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<SimulationRecord>> {
+        // Hyphenated UUIDs sort like the UUIDs themselves
+        let (after_created_at, after_id) = match after {
+            Some((created_at, id)) => (created_at, id.to_string()),
+            None => (0, String::new()),
+        };
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT id, created_at, request, response FROM simulations
+                WHERE created_at >= ?1 AND (created_at > ?2 OR (created_at = ?2 AND id > ?3))
+                ORDER BY created_at, id LIMIT ?4",
+        )?;
+        let rows = statement
+            .query_map(params![since, after_created_at, after_id, limit], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?;
+
+        rows.map(|row| {
+            let (id, created_at, request, response) = row?;
+            Self::record(id, created_at, request, response)
+        })
+        .collect()
+    }
+}
+
+/// Every simulation served, stored in SQLite if `SIMULATION_DB` is set and in memory otherwise.
+#[derive(Clone)]
+pub struct History {
+    store: Arc<dyn SimulationStore>,
+}
+
+impl History {
+    pub fn new(store: Arc<dyn SimulationStore>) -> Self {
+        History { store }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        match &config.simulation_db {
+            #[cfg(feature = "sqlite")]
+            Some(path) => Self::new(Arc::new(
+                SqliteStore::open(path).expect("SIMULATION_DB must be a valid SQLite database."),
+            )),
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => panic!("SIMULATION_DB requires the sqlite feature."),
+            None => Self::new(Arc::new(MemoryStore::new(MEMORY_HISTORY_SIZE))),
+        }
+    }
+
+    /// Persists a simulation. Failing to do so is logged rather than failing the request.
+    pub fn record(&self, request: &SimulationRequest, response: &SimulationResponse) {
+        let record = SimulationRecord {
+            id: response.simulation_id,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            request: request.clone(),
+            response: response.clone(),
+        };
+
+        if let Err(err) = self.store.insert(&record) {
+            log::warn!(target: "ts::history", "Failed to persist simulation {}: {err}", record.id);
+        }
+    }
+
+    /// A page of the simulations created at `since` or later, see `SimulationStore::find_since`.
+    pub fn find_since(
+        &self,
+        since: u64,
+        after: Option<(u64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<SimulationRecord>, HistoryError> {
+        self.store
+            .find_since(since, after, limit)
+            .map_err(HistoryError)
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<SimulationRecord, Rejection> {
+        Ok(self
+            .store
+            .get(id)
+            .map_err(HistoryError)?
+            .ok_or(SimulationNotFoundError)?)
+    }
+}
+
+pub async fn get_simulation(id: Uuid, history: History) -> Result<Json, Rejection> {
+    let record = history.get(id)?;
+
+    Ok(warp::reply::json(&record))
+}
+
+pub async fn list_simulations(
+    query: SimulationsQuery,
+    history: History,
+) -> Result<Json, Rejection> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .min(MAX_LIST_LIMIT);
+    let records = history
+        .store
+        .find_by_from(query.from, limit)
+        .map_err(HistoryError)?;
+
+    Ok(warp::reply::json(&records))
+}
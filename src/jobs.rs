@@ -0,0 +1,269 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use uuid::Uuid;
+use warp::hyper::StatusCode;
+use warp::reply::Json;
+use warp::{Rejection, Reply};
+
+use crate::bundle::{run_bundle, BundleRequest};
+use crate::errors::{
+    error_message, BundleTooLargeError, ErrorMessage, JobNotFoundError, SimulationError,
+};
+use crate::quantity;
+use crate::simulation::{chain_id_to_fork_url, check_gas_limit, run, SimulationRequest};
+use crate::webhook;
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// Jobs kept for `GET /jobs/{id}`, the oldest are dropped first.
+const MAX_JOBS: usize = 10_000;
+
+/// Jobs queued or running on any queue, which shutting down waits for.
+static PENDING_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Resolves once every job queued so far has finished. Callbacks may still be in flight.
+pub async fn drained() {
+    while PENDING_JOBS.load(Ordering::Acquire) > 0 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Anything `/simulate` or `/simulate-bundle` accepts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JobRequest {
+    Bundle(BundleRequest),
+    Simulation(Box<SimulationRequest>),
+}
+
+impl JobRequest {
+    pub fn callback_url(&self) -> Option<&str> {
+        match self {
+            JobRequest::Bundle(bundle) => bundle.callback_url(),
+            JobRequest::Simulation(transaction) => transaction.callback_url.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    /// The simulation could not run, see `error`. Reverted transactions are completed.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Job {
+    #[serde(rename = "jobId")]
+    pub job_id: Uuid,
+    pub status: JobStatus,
+    /// Unix timestamps in seconds.
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "startedAt", default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    #[serde(
+        rename = "finishedAt",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub finished_at: Option<u64>,
+    /// Response of `/simulate` or `/simulate-bundle`, once completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorMessage>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// A job waiting for a worker, with the config and pool of the API key it was queued with, so
+/// that it runs under the same policy as it would have synchronously.
+struct QueuedJob {
+    job_id: Uuid,
+    request: JobRequest,
+    config: Config,
+    pool: EvmPool,
+}
+
+/// Simulations queued by `/simulate-async` and run in the background by a fixed number of
+/// workers. Cheap to clone, clones share the queue.
+#[derive(Clone)]
+pub struct JobQueue {
+    jobs: Arc<Mutex<LruCache<Uuid, Job>>>,
+    sender: UnboundedSender<QueuedJob>,
+}
+
+impl JobQueue {
+    /// Spawns the workers, so it must be called within a Tokio runtime.
+    pub fn new(config: &Config, history: History) -> Self {
+        let jobs = Arc::new(Mutex::new(LruCache::new(
+            NonZeroUsize::new(MAX_JOBS).unwrap(),
+        )));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+
+        let queue = JobQueue { jobs, sender };
+        for _ in 0..config.async_workers.max(1) {
+            tokio::spawn(queue.clone().work(receiver.clone(), history.clone()));
+        }
+        queue
+    }
+
+    pub fn get(&self, job_id: Uuid) -> Option<Job> {
+        self.jobs.lock().unwrap().peek(&job_id).cloned()
+    }
+
+    /// Queues `request` to run with `config` and `pool`, those of the API key of the request.
+    pub fn push(&self, request: JobRequest, config: Config, pool: EvmPool) -> Job {
+        let job = Job {
+            job_id: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+            result: None,
+            error: None,
+        };
+        self.jobs.lock().unwrap().put(job.job_id, job.clone());
+        PENDING_JOBS.fetch_add(1, Ordering::AcqRel);
+        self.sender
+            .send(QueuedJob {
+                job_id: job.job_id,
+                request,
+                config,
+                pool,
+            })
+            .expect("workers run as long as the queue");
+        job
+    }
+
+    fn update(&self, job_id: Uuid, update: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            update(job);
+        }
+    }
+
+    async fn work(
+        self,
+        receiver: Arc<tokio::sync::Mutex<UnboundedReceiver<QueuedJob>>>,
+        history: History,
+    ) {
+        loop {
+            let Some(QueuedJob {
+                job_id,
+                request,
+                config,
+                pool,
+            }) = receiver.lock().await.recv().await
+            else {
+                return;
+            };
+            let callback_url = request.callback_url().map(str::to_string);
+            self.update(job_id, |job| {
+                job.status = JobStatus::Running;
+                job.started_at = Some(now());
+            });
+
+            // Run on its own task so that a panicking simulation fails the job, not the worker
+            let result = tokio::spawn(run_job(request, config.clone(), pool, history.clone()))
+                .await
+                .unwrap_or_else(|err| Err(SimulationError::Evm(err.into()).into()));
+
+            self.update(job_id, |job| {
+                job.finished_at = Some(now());
+                match result {
+                    Ok(result) => {
+                        job.status = JobStatus::Completed;
+                        job.result = Some(result);
+                    }
+                    Err(err) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(error_message(&err).0);
+                    }
+                }
+            });
+
+            if let (Some(url), Some(job)) = (callback_url, self.get(job_id)) {
+                webhook::send(url, &job, &config);
+            }
+            PENDING_JOBS.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+async fn run_job(
+    request: JobRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Value, Rejection> {
+    match request {
+        JobRequest::Bundle(bundle) => run_bundle(bundle, config, pool, history).await,
+        JobRequest::Simulation(transaction) => {
+            let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+            let mut evm = pool.get_at(
+                transaction.chain_id,
+                fork_url,
+                transaction.block_number,
+                transaction.block_hash,
+                transaction.gas_limit,
+                config.etherscan_key,
+            )?;
+            check_gas_limit(&evm, transaction.gas_limit)?;
+
+            let response = run(&mut evm, (*transaction).clone(), false).await?;
+            history.record(&transaction, &response);
+
+            Ok(quantity::to_value(&response, transaction.quantity_format))
+        }
+    }
+}
+
+/// Queues the simulation and answers right away with a `202` and the queued job.
+pub async fn simulate_async(
+    request: JobRequest,
+    config: Config,
+    pool: EvmPool,
+    jobs: JobQueue,
+) -> Result<impl Reply, Rejection> {
+    if let JobRequest::Bundle(bundle) = &request {
+        if bundle.len() > config.max_bundle_size {
+            return Err(warp::reject::custom(BundleTooLargeError));
+        }
+        bundle.check_not_empty()?;
+    }
+    if let Some(url) = request.callback_url() {
+        webhook::check_callback_url(url, &config).await?;
+    }
+
+    let job = jobs.push(request, config, pool);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&job),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+pub async fn get_job(job_id: Uuid, jobs: JobQueue) -> Result<Json, Rejection> {
+    match jobs.get(job_id) {
+        Some(job) => Ok(warp::reply::json(&job)),
+        None => Err(warp::reject::custom(JobNotFoundError)),
+    }
+}
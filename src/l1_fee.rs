@@ -0,0 +1,132 @@
+use ethers::abi::{encode, Address, Token, Uint};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{BlockNumber, Eip1559TransactionRequest, NameOrAddress, TransactionRequest};
+use ethers::utils::id;
+
+use crate::errors::EvmError;
+use crate::evm::{CallOptions, CallRawRequest, Evm};
+
+/// OP Stack predeploy pricing the L1 data of transactions from the `L1Block` attributes.
+const GAS_PRICE_ORACLE: &str = "0x420000000000000000000000000000000000000F";
+
+/// Arbitrum's virtual contract, only answered by nodes through `eth_call` and so missing from
+/// forks.
+const NODE_INTERFACE: &str = "0x00000000000000000000000000000000000000C8";
+
+/// Gas limit of the `getL1Fee` call.
+const ORACLE_GAS_LIMIT: u64 = 1_000_000;
+
+enum Rollup {
+    Optimism,
+    Arbitrum,
+}
+
+fn rollup(chain_id: u64) -> Option<Rollup> {
+    match chain_id {
+        10 | 420 => Some(Rollup::Optimism),
+        42161 | 421613 => Some(Rollup::Arbitrum),
+        _ => None,
+    }
+}
+
+/// The fee paid for posting the transaction's data to L1 on Optimism and Arbitrum, `None` on
+/// other chains or if it could not be estimated.
+pub(crate) async fn l1_fee(
+    evm: &mut Evm,
+    request: &CallRawRequest,
+    nonce: Option<u64>,
+) -> Option<Uint> {
+    let l1_fee = match rollup(evm.chain_id())? {
+        Rollup::Optimism => optimism_l1_fee(evm, request, nonce).await,
+        Rollup::Arbitrum => arbitrum_l1_fee(evm, request).await,
+    };
+    match l1_fee {
+        Ok(l1_fee) => Some(l1_fee),
+        Err(err) => {
+            log::warn!(target: "ts::l1_fee", "Failed to estimate the L1 fee: {}", err.0);
+            None
+        }
+    }
+}
+
+/// Asks the `GasPriceOracle` to price the unsigned transaction, which accounts for the signature
+/// and compression itself.
+async fn optimism_l1_fee(
+    evm: &mut Evm,
+    request: &CallRawRequest,
+    nonce: Option<u64>,
+) -> Result<Uint, EvmError> {
+    let nonce = match nonce {
+        Some(nonce) => nonce,
+        None => evm.basic(request.from)?.nonce,
+    };
+    let mut transaction = Eip1559TransactionRequest::new()
+        .from(request.from)
+        .value(request.value.unwrap_or_default())
+        .data(request.data.clone().unwrap_or_default())
+        .gas(request.gas_limit)
+        .nonce(nonce)
+        .chain_id(evm.chain_id())
+        .max_fee_per_gas(
+            request
+                .max_fee_per_gas
+                .or(request.gas_price)
+                .unwrap_or_default(),
+        )
+        .max_priority_fee_per_gas(request.max_priority_fee_per_gas.unwrap_or_default())
+        .access_list(request.access_list.clone().unwrap_or_default());
+    transaction.to = request.to.map(NameOrAddress::Address);
+    let serialized = TypedTransaction::Eip1559(transaction).rlp();
+
+    let mut data = id("getL1Fee(bytes)").to_vec();
+    data.extend(encode(&[Token::Bytes(serialized.to_vec())]));
+    let call = CallRawRequest {
+        to: Some(GAS_PRICE_ORACLE.parse::<Address>().unwrap()),
+        data: Some(data.into()),
+        gas_limit: ORACLE_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&call, CallOptions::default()).await?;
+
+    match result.output.get(..32) {
+        Some(output) if result.success => Ok(Uint::from_big_endian(output)),
+        _ => Err(EvmError(eyre::eyre!("getL1Fee failed"))),
+    }
+}
+
+/// Asks the fork's RPC for the L1 gas the transaction is charged on top of its execution, priced
+/// at the L2 base fee like Arbitrum does.
+async fn arbitrum_l1_fee(evm: &Evm, request: &CallRawRequest) -> Result<Uint, EvmError> {
+    let fork_url = evm
+        .fork_url()
+        .ok_or_else(|| EvmError(eyre::eyre!("no RPC to estimate the L1 fee with")))?;
+    let provider = Provider::<Http>::try_from(fork_url).map_err(|err| EvmError(err.into()))?;
+
+    let mut data = id("gasEstimateL1Component(address,bool,bytes)").to_vec();
+    data.extend(encode(&[
+        Token::Address(request.to.unwrap_or_default()),
+        Token::Bool(request.to.is_none()),
+        Token::Bytes(request.data.clone().unwrap_or_default().to_vec()),
+    ]));
+    let call: TypedTransaction = TransactionRequest::new()
+        .from(request.from)
+        .to(NODE_INTERFACE.parse::<Address>().unwrap())
+        .data(data)
+        .into();
+    let block = BlockNumber::Number(evm.block_number().into());
+    let output = provider
+        .call(&call, Some(block.into()))
+        .await
+        .map_err(|err| EvmError(err.into()))?;
+
+    // `gasEstimateForL1`, `baseFee` and `l1BaseFeeEstimate`
+    match (output.get(..32), output.get(32..64)) {
+        (Some(gas), Some(base_fee)) => {
+            Ok(Uint::from_big_endian(gas).saturating_mul(Uint::from_big_endian(base_fee)))
+        }
+        _ => Err(EvmError(eyre::eyre!(
+            "invalid gasEstimateL1Component output"
+        ))),
+    }
+}
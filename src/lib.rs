@@ -1,48 +1,911 @@
+use abis::{AbiRegistration, AbiRegistry, MAX_ABI_SIZE};
+use admission::{with_admission, AdmissionQueue, Admitted};
+use contract_cache::ContractCache;
+use ethers::abi::Event;
+use export::ExportQuery;
+use fixture::{FixtureSimulationRequest, MAX_FIXTURE_SIZE};
+use fork::{AccountQuery, ForkStore, StorageQuery};
+use history::{History, SimulationsQuery};
+use jobs::JobQueue;
+use policy::PolicyEngine;
+use pool::EvmPool;
+use precompiles::PrecompileStubs;
+use prices::PriceOracle;
+use proxy::with_proxy;
 use serde::de::DeserializeOwned;
 use simulation::SimulationRequest;
+use simulation_cache::SimulationCache;
+use uuid::Uuid;
 use warp::{Filter, Rejection, Reply};
+use watch::Watches;
 
+pub mod abis;
+pub mod access_list;
+pub mod access_stats;
+pub mod admission;
+pub mod approvals;
+pub mod assertions;
+pub mod assets;
+pub mod auth;
+pub mod authorization;
+pub mod batch;
+pub mod blob;
+pub mod block_env;
+pub mod bundle;
+pub mod chains;
+pub mod cli;
+pub mod client;
 pub mod config;
+pub mod console;
+pub mod contract_cache;
+pub mod contracts;
+pub mod decode;
+pub mod diff;
 use config::Config;
 
 pub mod errors;
+pub mod estimate;
 pub mod evm;
+pub mod export;
+pub mod fixture;
+pub mod fork;
+#[cfg(feature = "sqlite")]
+pub mod fork_cache;
+pub mod four_byte;
+pub mod funds_flow;
+pub mod gas_profile;
+#[cfg(feature = "server")]
+pub mod grpc;
+pub mod hardfork;
+pub mod health;
+pub mod history;
+pub mod jobs;
+pub mod l1_fee;
+pub mod mempool;
+pub mod metrics;
+pub mod pinned_rpc;
+pub mod policy;
+pub mod pool;
+pub mod precompiles;
+pub mod prefetch;
+pub mod prices;
+pub mod proxies;
+pub mod proxy;
+pub mod public_url;
+pub mod quantity;
+pub mod rate_limit;
+pub mod raw;
+pub mod replay;
+pub mod request_id;
+pub mod rpc;
+#[cfg(feature = "server")]
+pub mod server;
 
+pub mod simulate_v1;
 pub mod simulation;
+pub mod simulation_cache;
+pub mod simulator;
+pub mod source_trace;
+pub mod stream;
+pub mod sweep;
+pub mod tenderly;
+pub mod trace_format;
+pub mod trim;
+pub mod user_operation;
+pub mod validation;
+pub mod warnings;
+pub mod watch;
+pub mod webhook;
 
 pub fn simulate_routes(
     config: Config,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    simulate(config.clone()).or(simulate_bundle(config))
+    let admission = AdmissionQueue::new(config.max_concurrency, config.max_queue_size);
+    simulate_routes_with_admission(config, admission)
+}
+
+/// The routes of the API, those executing the EVM only once `admission` admits them, so that
+/// other servers, e.g. the gRPC one, can share the queue.
+pub fn simulate_routes_with_admission(
+    config: Config,
+    admission: AdmissionQueue,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let config = with_proxy(config);
+    let forks = ForkStore::default();
+    let pool = EvmPool::new(config.pool_size, config.max_concurrency)
+        .with_limits(config.max_gas_limit, config.simulation_timeout)
+        .with_contract_cache(ContractCache::from_config(&config))
+        .with_price_oracle(PriceOracle::from_config(&config))
+        .with_policies(PolicyEngine::from_config(&config))
+        .with_precompile_stubs(PrecompileStubs::from_config(&config))
+        .with_api_key_policies(config.api_key_policies.clone());
+    let history = History::from_config(&config);
+    let cache = SimulationCache::from_config(&config);
+    let abis = AbiRegistry::from_config(&config);
+    let jobs = JobQueue::new(&config, history.clone());
+    let watches = Watches::default();
+
+    let evm_routes = simulate(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+        cache,
+        abis.clone(),
+    )
+    .or(simulate_stream(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ))
+    .or(simulate_bundle_stream(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ))
+    .or(simulate_bundle(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ))
+    .or(simulate_batch(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ))
+    .or(simulate_sweep(config.clone(), pool.clone()))
+    .or(simulate_raw(config.clone(), pool.clone(), history.clone()))
+    .or(simulate_v1(config.clone(), pool.clone(), history.clone()))
+    .or(estimate(config.clone(), pool.clone()))
+    .or(create_access_list(config.clone(), pool.clone()))
+    .or(export_fixture(config.clone(), pool.clone()))
+    .or(simulate_fixture(pool.clone()))
+    .or(replay(config.clone(), pool.clone(), history.clone()))
+    .or(simulate_user_operation(config.clone(), pool.clone()))
+    .or(tenderly_simulate(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ))
+    .or(tenderly_simulate_bundle(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ))
+    .or(simulate_on_fork(
+        config.clone(),
+        forks.clone(),
+        history.clone(),
+    ))
+    .or(deal(forks.clone()))
+    .or(token_balances(forks.clone()))
+    .or(fork_rpc(forks.clone(), history.clone()))
+    .or(diff_simulations(
+        config.clone(),
+        pool.clone(),
+        history.clone(),
+    ));
+
+    // Queued jobs and watches run in the background, without holding a place in the queue
+    let other_routes = simulate_async(config.clone(), pool.clone(), jobs.clone())
+        .or(get_job(jobs))
+        .or(create_watch(
+            config.clone(),
+            pool.clone(),
+            history.clone(),
+            watches.clone(),
+        ))
+        .or(get_watch(watches.clone()))
+        .or(delete_watch(watches))
+        .or(create_fork(config.clone(), forks.clone(), pool))
+        .or(set_balance(forks.clone()))
+        .or(set_storage(forks.clone()))
+        .or(set_code(forks.clone()))
+        .or(snapshot_fork(forks.clone()))
+        .or(revert_fork(forks.clone()))
+        .or(get_fork_balance(forks.clone()))
+        .or(get_fork_code(forks.clone()))
+        .or(get_fork_storage(forks.clone()))
+        .or(delete_fork(forks))
+        .or(register_chain(config.clone()))
+        .or(list_chains(config))
+        .or(register_abi(abis.clone()))
+        .or(list_abis(abis.clone()))
+        .or(delete_abi(abis))
+        .or(export_simulations(history.clone()))
+        .or(get_simulation(history.clone()))
+        .or(list_simulations(history));
+
+    // Other routes are tried first, for them to be served even if the queue is full
+    other_routes.or(with_admission(admission)
+        .and(evm_routes)
+        .map(|_admitted: Admitted, reply| reply))
+}
+
+/// GET /metrics
+pub fn metrics() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and_then(metrics::metrics)
+}
+
+/// GET /health
+pub fn health() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("health")
+        .and(warp::get())
+        .and_then(health::health)
+}
+
+/// GET /ready
+pub fn ready(config: Config) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("ready")
+        .and(warp::get())
+        .and(with_config(config))
+        .and_then(health::ready)
 }
 
 /// POST /simulate
-pub fn simulate(config: Config) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+pub fn simulate(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+    cache: SimulationCache,
+    abis: AbiRegistry,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     warp::path!("simulate")
         .and(warp::post())
-        .and(json_body::<SimulationRequest>())
+        .and(simulation_body())
         .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and(with_simulation_cache(cache))
+        .and(with_registered_events(abis))
         .and_then(simulation::simulate)
 }
 
+/// WS /simulate/stream
+pub fn simulate_stream(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate" / "stream")
+        .and(warp::ws())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .map(
+            |ws: warp::ws::Ws, config: Config, pool: EvmPool, history: History| {
+                ws.on_upgrade(move |socket| stream::simulate_stream(socket, config, pool, history))
+            },
+        )
+}
+
+/// POST /simulate-bundle with `Accept: application/x-ndjson`
+pub fn simulate_bundle_stream(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-bundle")
+        .and(warp::post())
+        .and(accepts_ndjson())
+        .and(simulations_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(bundle::stream_bundle)
+}
+
 /// POST /simulate-bundle
 pub fn simulate_bundle(
     config: Config,
+    pool: EvmPool,
+    history: History,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     warp::path!("simulate-bundle")
+        .and(warp::post())
+        .and(simulations_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(bundle::simulate_bundle)
+}
+
+/// POST /simulate-batch
+pub fn simulate_batch(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-batch")
+        .and(warp::post())
+        .and(simulations_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(batch::simulate_batch)
+}
+
+/// POST /simulate-sweep
+pub fn simulate_sweep(
+    config: Config,
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-sweep")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and_then(sweep::simulate_sweep)
+}
+
+/// POST /simulate-async
+pub fn simulate_async(
+    config: Config,
+    pool: EvmPool,
+    jobs: JobQueue,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-async")
+        .and(warp::post())
+        .and(simulations_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_jobs(jobs))
+        .and_then(jobs::simulate_async)
+}
+
+/// GET /jobs/{id}
+pub fn get_job(jobs: JobQueue) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("jobs" / Uuid)
+        .and(warp::get())
+        .and(with_jobs(jobs))
+        .and_then(jobs::get_job)
+}
+
+/// POST /watch
+pub fn create_watch(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+    watches: Watches,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("watch")
+        .and(warp::post())
+        .and(simulations_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and(with_watches(watches))
+        .and_then(watch::create_watch)
+}
+
+/// GET /watch/{id}
+pub fn get_watch(
+    watches: Watches,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("watch" / Uuid)
+        .and(warp::get())
+        .and(with_watches(watches))
+        .and_then(watch::get_watch)
+}
+
+/// DELETE /watch/{id}
+pub fn delete_watch(
+    watches: Watches,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("watch" / Uuid)
+        .and(warp::delete())
+        .and(with_watches(watches))
+        .and_then(watch::delete_watch)
+}
+
+/// POST /simulate-raw
+pub fn simulate_raw(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-raw")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(raw::simulate_raw)
+}
+
+/// POST /tenderly/account/{account}/project/{project}/simulate
+pub fn tenderly_simulate(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("tenderly" / "account" / String / "project" / String / "simulate")
         .and(warp::post())
         .and(json_body())
         .and(with_config(config))
-        .and_then(simulation::simulate_bundle)
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(tenderly::simulate)
+}
+
+/// POST /tenderly/account/{account}/project/{project}/simulate-bundle
+pub fn tenderly_simulate_bundle(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("tenderly" / "account" / String / "project" / String / "simulate-bundle")
+        .and(warp::post())
+        .and(bundle_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(tenderly::simulate_bundle)
+}
+
+/// POST /simulate-v1
+pub fn simulate_v1(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-v1")
+        .and(warp::post())
+        .and(bundle_body(&config))
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(simulate_v1::simulate_v1)
+}
+
+/// POST /estimate
+pub fn estimate(
+    config: Config,
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("estimate")
+        .and(warp::post())
+        .and(simulation_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and_then(estimate::estimate)
 }
 
-fn with_config(
+/// POST /access-list
+pub fn create_access_list(
     config: Config,
-) -> impl Filter<Extract = (Config,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || config.clone())
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("access-list")
+        .and(warp::post())
+        .and(simulation_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and_then(access_list::create_access_list)
+}
+
+/// POST /fixtures
+pub fn export_fixture(
+    config: Config,
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fixtures")
+        .and(warp::post())
+        .and(simulation_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and_then(fixture::export_fixture)
+}
+
+/// POST /simulate-fixture
+pub fn simulate_fixture(
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-fixture")
+        .and(warp::post())
+        .and(fixture_body())
+        .and(with_pool(pool))
+        .and_then(fixture::simulate_fixture)
+}
+
+/// POST /replay
+pub fn replay(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("replay")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(replay::replay)
+}
+
+/// POST /user-operation
+pub fn simulate_user_operation(
+    config: Config,
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("user-operation")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and_then(user_operation::simulate_user_operation)
+}
+
+/// POST /fork
+pub fn create_fork(
+    config: Config,
+    forks: ForkStore,
+    pool: EvmPool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and(with_forks(forks))
+        .and(with_pool(pool))
+        .and_then(fork::create_fork)
 }
 
+/// POST /fork/{id}/simulate
+pub fn simulate_on_fork(
+    config: Config,
+    forks: ForkStore,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "simulate")
+        .and(warp::post())
+        .and(simulation_body())
+        .and(with_forks(forks))
+        .and(with_history(history))
+        .and(with_config(config))
+        .and_then(fork::simulate_on_fork)
+}
+
+/// POST /fork/{id}/set-balance
+pub fn set_balance(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "set-balance")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and_then(fork::set_balance)
+}
+
+/// POST /fork/{id}/set-storage
+pub fn set_storage(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "set-storage")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and_then(fork::set_storage)
+}
+
+/// POST /fork/{id}/set-code
+pub fn set_code(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "set-code")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and_then(fork::set_code)
+}
+
+/// POST /fork/{id}/deal
+pub fn deal(forks: ForkStore) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "deal")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and_then(fork::deal)
+}
+
+/// POST /fork/{id}/token-balances
+pub fn token_balances(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "token-balances")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and_then(fork::token_balances)
+}
+
+/// POST /fork/{id}/snapshot
+pub fn snapshot_fork(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "snapshot")
+        .and(warp::post())
+        .and(with_forks(forks))
+        .and_then(fork::snapshot)
+}
+
+/// POST /fork/{id}/revert
+pub fn revert_fork(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "revert")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and_then(fork::revert)
+}
+
+/// POST /fork/{id}/rpc
+pub fn fork_rpc(
+    forks: ForkStore,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "rpc")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_forks(forks))
+        .and(with_history(history))
+        .and_then(rpc::rpc)
+}
+
+/// GET /fork/{id}/balance?address={address}
+pub fn get_fork_balance(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "balance")
+        .and(warp::get())
+        .and(warp::query::<AccountQuery>())
+        .and(with_forks(forks))
+        .and_then(fork::get_balance)
+}
+
+/// GET /fork/{id}/code?address={address}
+pub fn get_fork_code(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "code")
+        .and(warp::get())
+        .and(warp::query::<AccountQuery>())
+        .and(with_forks(forks))
+        .and_then(fork::get_code)
+}
+
+/// GET /fork/{id}/storage?address={address}&slot={slot}
+pub fn get_fork_storage(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid / "storage")
+        .and(warp::get())
+        .and(warp::query::<StorageQuery>())
+        .and(with_forks(forks))
+        .and_then(fork::get_storage)
+}
+
+/// DELETE /fork/{id}
+pub fn delete_fork(
+    forks: ForkStore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fork" / Uuid)
+        .and(warp::delete())
+        .and(with_forks(forks))
+        .and_then(fork::delete_fork)
+}
+
+/// POST /abis
+pub fn register_abi(
+    abis: AbiRegistry,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("abis")
+        .and(warp::post())
+        .and(abi_body())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(with_abis(abis))
+        .and_then(abis::register_abi)
+}
+
+/// GET /abis
+pub fn list_abis(
+    abis: AbiRegistry,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("abis")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(with_abis(abis))
+        .and_then(abis::list_abis)
+}
+
+/// DELETE /abis/{id}
+pub fn delete_abi(
+    abis: AbiRegistry,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("abis" / Uuid)
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(with_abis(abis))
+        .and_then(abis::delete_abi)
+}
+
+/// POST /simulations/diff
+pub fn diff_simulations(
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulations" / "diff")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and(with_pool(pool))
+        .and(with_history(history))
+        .and_then(diff::diff)
+}
+
+/// GET /simulations/export?format=csv|jsonl&since={timestamp}
+pub fn export_simulations(
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulations" / "export")
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and(with_history(history))
+        .and_then(export::export_simulations)
+}
+
+/// GET /simulations/{id}
+pub fn get_simulation(
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulations" / Uuid)
+        .and(warp::get())
+        .and(with_history(history))
+        .and_then(history::get_simulation)
+}
+
+/// GET /simulations?from={address}
+pub fn list_simulations(
+    history: History,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulations")
+        .and(warp::get())
+        .and(warp::query::<SimulationsQuery>())
+        .and(with_history(history))
+        .and_then(history::list_simulations)
+}
+
+/// POST /chains
+pub fn register_chain(
+    config: Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("chains")
+        .and(warp::post())
+        .and(json_body())
+        .and(with_config(config))
+        .and_then(chains::register_chain)
+}
+
+/// GET /chains
+pub fn list_chains(
+    config: Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("chains")
+        .and(warp::get())
+        .and(with_config(config))
+        .and_then(chains::list_chains)
+}
+
+/// The config of the API key of the request, lowered to the limits of its policy.
+fn with_config(config: Config) -> impl Filter<Extract = (Config,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .map(move |api_key: Option<String>| config.for_api_key(api_key.as_deref()))
+}
+
+fn with_forks(
+    forks: ForkStore,
+) -> impl Filter<Extract = (ForkStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || forks.clone())
+}
+
+fn with_jobs(
+    jobs: JobQueue,
+) -> impl Filter<Extract = (JobQueue,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || jobs.clone())
+}
+
+fn with_watches(
+    watches: Watches,
+) -> impl Filter<Extract = (Watches,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || watches.clone())
+}
+
+fn with_history(
+    history: History,
+) -> impl Filter<Extract = (History,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || history.clone())
+}
+
+fn with_abis(
+    abis: AbiRegistry,
+) -> impl Filter<Extract = (AbiRegistry,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || abis.clone())
+}
+
+/// The events registered by the API key of the request.
+fn with_registered_events(
+    abis: AbiRegistry,
+) -> impl Filter<Extract = (Vec<Event>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .map(move |api_key: Option<String>| abis.events(api_key.as_deref()))
+}
+
+fn with_simulation_cache(
+    cache: SimulationCache,
+) -> impl Filter<Extract = (SimulationCache,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || cache.clone())
+}
+
+/// The pool of the API key of the request, lowered to the gas limit of its policy.
+fn with_pool(pool: EvmPool) -> impl Filter<Extract = (EvmPool,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .map(move |api_key: Option<String>| pool.for_api_key(api_key.as_deref()))
+}
+
+/// Requests accepting JSON lines, the others being left to the next route.
+fn accepts_ndjson() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept")
+        .and_then(|accept: Option<String>| async move {
+            match accept {
+                Some(accept) if accept.contains("application/x-ndjson") => Ok(()),
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+}
+
+/// The limit of request bodies, but those of several transactions, ABIs and fixtures.
+const MAX_BODY_SIZE: u64 = 1024 * 16;
+
 fn json_body<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
 {
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+    warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::json())
+}
+
+/// Requests of several transactions may each be as large as a single one, up to
+/// `max_bundle_size` of them.
+fn bundle_body<T: DeserializeOwned + Send>(
+    config: &Config,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(MAX_BODY_SIZE * config.max_bundle_size.max(1) as u64)
+        .and(warp::body::json())
+}
+
+/// Like `bundle_body`, strictly validating the simulation requests with an `apiVersion`.
+fn simulations_body<T: DeserializeOwned + Send + 'static>(
+    config: &Config,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(MAX_BODY_SIZE * config.max_bundle_size.max(1) as u64)
+        .and(warp::body::bytes())
+        .and_then(validation::parse_simulation_requests::<T>)
+}
+
+/// Like `json_body`, strictly validating requests with an `apiVersion`.
+fn simulation_body() -> impl Filter<Extract = (SimulationRequest,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(MAX_BODY_SIZE)
+        .and(warp::body::bytes())
+        .and_then(validation::parse_simulation_request)
+}
+
+/// ABIs of whole contracts are over the limit of other bodies.
+fn abi_body() -> impl Filter<Extract = (AbiRegistration,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(MAX_ABI_SIZE).and(warp::body::json())
+}
+
+/// Fixtures hold whole contracts and their storage, well over the limit of other bodies.
+fn fixture_body() -> impl Filter<Extract = (FixtureSimulationRequest,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(MAX_FIXTURE_SIZE).and(warp::body::json())
 }
@@ -1,42 +1,192 @@
 use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::process::ExitCode;
 
-use transaction_simulator::{config::get_config, errors::handle_rejection, simulate_routes};
-use warp::Filter;
+use futures_util::FutureExt;
+use tracing_subscriber::EnvFilter;
+use transaction_simulator::{
+    admission::AdmissionQueue,
+    auth::with_api_key,
+    cli::{self, Command, USAGE},
+    config::get_config,
+    errors::handle_rejection,
+    grpc::GrpcService,
+    health, metrics,
+    rate_limit::{with_rate_limit, RateLimiter},
+    ready,
+    server::{cors, serve, shutdown_signal},
+    simulate_routes_with_admission,
+    simulator::Simulator,
+};
+use warp::{Filter, Reply};
+
+/// Logs to stderr what `RUST_LOG` enables, `default_filter` if not set, as JSON lines if
+/// `LOG_FORMAT=json`. Also picks up the records of the `log` crate.
+fn init_logging(default_filter: &str) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    if env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        builder.json().with_current_span(true).init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Simulates the request of `path`, or of stdin if `None`, without the server. The response is
+/// printed to stdout, errors to stderr, simulation errors in the format of API errors.
+async fn simulate_command(path: Option<&str>) -> ExitCode {
+    let input = match path {
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).map(|_| input)
+        }
+        Some(path) => fs::read_to_string(path),
+    };
+    let input = match input {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Failed to read the request: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match cli::simulate(&Simulator::new(get_config()), &input).await {
+        Ok(response) => {
+            println!("{response:#}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
 #[tokio::main]
-async fn main() {
-    if env::var_os("RUST_LOG").is_none() {
-        // Set `RUST_LOG=ts::api=debug` to see debug logs, this only shows access logs.
-        env::set_var("RUST_LOG", "ts::api=info");
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match cli::parse_args(&args) {
+        Some(Command::Serve) => {}
+        Some(Command::Simulate(path)) => {
+            // Only warnings, to keep the output clean
+            init_logging("warn");
+            return simulate_command(path.as_deref()).await;
+        }
+        Some(Command::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        None => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
     }
-    pretty_env_logger::init();
 
-    let config = get_config();
+    // Set `RUST_LOG=ts=debug` to see debug logs, this only shows access logs and the timings of
+    // simulations.
+    init_logging("ts::api=info,ts::pool=info,ts::simulation=info");
 
-    let port = config.port;
-    let api_key = config.clone().api_key;
+    let config = get_config();
 
-    let api_base = warp::path("api").and(warp::path("v1"));
+    let api_keys = config.api_keys.clone();
 
-    let api_base = if let Some(api_key) = api_key {
+    if !api_keys.is_empty() {
         log::info!(
             target: "ts::api",
-            "Running with API key protection"
+            "Running with API key protection, {} keys",
+            api_keys.len()
         );
-        let api_key_filter = warp::header::exact("X-API-KEY", Box::leak(api_key.into_boxed_str()));
-        api_base.and(api_key_filter).boxed()
-    } else {
-        api_base.boxed()
-    };
+    }
+
+    // Shared with the gRPC server, so that its calls count towards the same limits
+    let rate_limiter = RateLimiter::new(config.rate_limit).with_api_keys(api_keys.clone());
+    let admission = AdmissionQueue::new(config.max_concurrency, config.max_queue_size);
+
+    let api_base = warp::path("api")
+        .and(warp::path("v1"))
+        .and(with_api_key(api_keys))
+        .and(with_rate_limit(rate_limiter.clone()));
 
+    // Metrics and probes are served outside of the API, without authentication, for Prometheus
+    // and orchestrators to scrape
     let routes = api_base
-        .and(simulate_routes(config))
-        .recover(handle_rejection)
-        .with(warp::log("ts::api"));
+        .and(simulate_routes_with_admission(
+            config.clone(),
+            admission.clone(),
+        ))
+        .or(metrics())
+        .or(health())
+        .or(ready(config.clone()))
+        .recover(handle_rejection);
+    // CORS wraps the error responses too, and answers preflight requests before authentication
+    let routes = match cors(&config) {
+        Some(cors) => routes
+            .with(cors)
+            .map(|reply| Box::new(reply) as Box<dyn Reply>)
+            .boxed(),
+        None => routes
+            .map(|reply| Box::new(reply) as Box<dyn Reply>)
+            .boxed(),
+    }
+    .with(warp::log("ts::api"));
+
+    // Both servers stop on the same signal
+    let shutdown = shutdown_signal().boxed().shared();
+
+    // Served next to the HTTP API, with its own pool of forks. Drains its in-flight calls on
+    // shutdown, for at most `SHUTDOWN_TIMEOUT` like the HTTP API
+    let grpc = config.grpc_port.map(|grpc_port| {
+        let address = SocketAddr::new(config.bind_address, grpc_port);
+        let service = GrpcService::new(Simulator::new(config.clone()))
+            .with_admission(admission)
+            .with_rate_limit(rate_limiter)
+            .server(config.api_keys.clone());
+        let server = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_shutdown(address, shutdown.clone());
+        let timeout = config.shutdown_timeout;
+        let deadline = shutdown.clone().then(move |()| tokio::time::sleep(timeout));
+        log::info!(target: "ts::grpc", "Starting gRPC server on {address}");
+        tokio::spawn(async move {
+            tokio::select! {
+                result = server => {
+                    if let Err(err) = result {
+                        log::error!(target: "ts::grpc", "gRPC server failed: {err}");
+                    }
+                }
+                _ = deadline => {
+                    log::warn!(
+                        target: "ts::grpc",
+                        "Calls still running after {}s, shutting down anyway",
+                        timeout.as_secs()
+                    );
+                }
+            }
+        })
+    });
 
     log::info!(
         target: "ts::api",
-        "Starting server on port {port}"
+        "Starting server on {}:{}{}",
+        config.bind_address,
+        config.port,
+        if config.tls_cert.is_some() { " with TLS" } else { "" }
     );
-    warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    if let Err(err) = serve(warp::service(routes), &config, shutdown).await {
+        log::error!(target: "ts::api", "Server failed: {err}");
+        return ExitCode::FAILURE;
+    }
+    // Has been draining since the same signal
+    if let Some(grpc) = grpc {
+        let _ = grpc.await;
+    }
+    // Returning drops the forks and caches, which writes the fork RPC caches to disk and closes
+    // the SQLite databases
+    log::info!(target: "ts::api", "Server stopped");
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,132 @@
+use ethers::abi::Address;
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::{BlockNumber, Bytes, H256};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::SimulationError;
+use crate::evm::{CallOptions, Evm};
+use crate::raw::RawSimulationRequest;
+use crate::replay::replay_request;
+use crate::simulation::{call_raw_request, SimulationRequest};
+
+/// Pending transactions applied when `limit` is not set.
+const DEFAULT_LIMIT: usize = 100;
+
+/// Pending transactions to execute before the simulated one, as if they were all included in the
+/// next block.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MempoolOptions {
+    /// Fetches the transactions of the RPC's pending block, in the order it would include them.
+    #[serde(default)]
+    pub fetch: bool,
+    /// Most fetched transactions applied, 100 if not set.
+    pub limit: Option<usize>,
+    /// Signed raw transactions, applied before the fetched ones.
+    #[serde(default)]
+    pub transactions: Vec<Bytes>,
+}
+
+/// What became of a pending transaction, in the order they were applied.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PendingTransaction {
+    pub hash: H256,
+    /// Not set if the raw transaction could not be decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<Address>,
+    pub success: bool,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    /// Why the transaction could not be executed, it was then skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PendingTransaction {
+    fn skipped(hash: H256, from: Option<Address>, error: String) -> Self {
+        PendingTransaction {
+            hash,
+            from,
+            success: false,
+            gas_used: 0,
+            error: Some(error),
+        }
+    }
+}
+
+/// Moves to the next block and commits the pending transactions of `options` in order. Those
+/// which can't be executed, e.g. because they were mined in the meantime, are skipped.
+pub(crate) async fn apply_pending_transactions(
+    evm: &mut Evm,
+    transaction: &SimulationRequest,
+    options: &MempoolOptions,
+) -> Result<Vec<PendingTransaction>, SimulationError> {
+    let chain_id = transaction.chain_id;
+    let mut pending: Vec<(H256, Result<SimulationRequest, String>)> = options
+        .transactions
+        .iter()
+        .map(|raw| {
+            let request = SimulationRequest::try_from(RawSimulationRequest {
+                chain_id: Some(chain_id),
+                raw_transaction: raw.clone(),
+                block_number: None,
+                format_trace: None,
+                validation: None,
+            })
+            .map_err(|_err| "invalid raw transaction".to_string());
+            (H256::from(keccak256(raw)), request)
+        })
+        .collect();
+
+    if options.fetch {
+        let fork_url = evm.fork_url().ok_or_else(|| {
+            SimulationError::Rpc(eyre::eyre!("no RPC to fetch pending transactions from"))
+        })?;
+        let provider =
+            Provider::<Http>::try_from(fork_url).map_err(|err| SimulationError::Rpc(err.into()))?;
+        let block = provider
+            .get_block_with_txs(BlockNumber::Pending)
+            .await
+            .map_err(|err| SimulationError::Rpc(err.into()))?;
+        pending.extend(
+            block
+                .into_iter()
+                .flat_map(|block| block.transactions)
+                .take(options.limit.unwrap_or(DEFAULT_LIMIT))
+                .map(|pending| (pending.hash, Ok(replay_request(chain_id, &pending)))),
+        );
+    }
+
+    evm.roll_block(evm.block_number() + 1);
+
+    let mut applied = Vec::with_capacity(pending.len());
+    for (hash, request) in pending {
+        let request = match request {
+            Ok(request) => request,
+            Err(error) => {
+                applied.push(PendingTransaction::skipped(hash, None, error));
+                continue;
+            }
+        };
+        let from = Some(request.from);
+        let result = match call_raw_request(&request) {
+            Ok(call) => evm
+                .call_raw_committing(&call, CallOptions::default())
+                .await
+                .map_err(|err| err.0.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        applied.push(match result {
+            Ok(result) => PendingTransaction {
+                hash,
+                from,
+                success: result.success,
+                gas_used: result.gas_used,
+                error: None,
+            },
+            Err(error) => PendingTransaction::skipped(hash, from, error),
+        });
+    }
+
+    Ok(applied)
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use warp::Rejection;
+
+static SIMULATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ts_simulations_total",
+        "Simulated transactions by chain and outcome.",
+        &["chain_id", "status"]
+    )
+    .unwrap()
+});
+
+static SIMULATION_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ts_simulation_duration_seconds",
+        "Time spent executing a transaction, including state fetched from the fork RPC.",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+static FORK_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ts_fork_duration_seconds",
+        "Time spent creating a fork, which fetches the block from the fork RPC.",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+static POOL_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ts_pool_requests_total",
+        "Fork backends requested from the pool, by whether they were pooled.",
+        &["chain_id", "result"]
+    )
+    .unwrap()
+});
+
+static ETHERSCAN_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ts_etherscan_requests_total",
+        "Contracts identified for traces, by whether they were cached or fetched from Etherscan.",
+        &["chain_id", "result"]
+    )
+    .unwrap()
+});
+
+static SIMULATION_CACHE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ts_simulation_cache_requests_total",
+        "Simulations looked up in the simulation cache, by whether they were cached.",
+        &["chain_id", "result"]
+    )
+    .unwrap()
+});
+
+static QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "ts_queue_depth",
+        "Requests admitted beyond MAX_CONCURRENCY, waiting for a simulation to finish."
+    )
+    .unwrap()
+});
+
+static QUEUE_REJECTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "ts_queue_rejections_total",
+        "Requests rejected with a 503 as the queue was full."
+    )
+    .unwrap()
+});
+
+pub(crate) fn record_simulation(chain_id: u64, success: bool, duration: Duration) {
+    let chain_id = chain_id.to_string();
+    let status = if success { "success" } else { "revert" };
+    SIMULATIONS.with_label_values(&[&chain_id, status]).inc();
+    SIMULATION_DURATION
+        .with_label_values(&[&chain_id])
+        .observe(duration.as_secs_f64());
+}
+
+pub(crate) fn record_fork(chain_id: u64, duration: Duration) {
+    FORK_DURATION
+        .with_label_values(&[&chain_id.to_string()])
+        .observe(duration.as_secs_f64());
+}
+
+/// `result` is `hit` or `miss`, or `latest` for forks of the latest block whose number couldn't be
+/// resolved first.
+pub(crate) fn record_pool_request(chain_id: u64, result: &str) {
+    POOL_REQUESTS
+        .with_label_values(&[&chain_id.to_string(), result])
+        .inc();
+}
+
+/// `result` is `hit` for cached contracts or `miss` for those fetched from Etherscan.
+pub(crate) fn record_etherscan_request(chain_id: u64, result: &str) {
+    ETHERSCAN_REQUESTS
+        .with_label_values(&[&chain_id.to_string(), result])
+        .inc();
+}
+
+/// `result` is `hit` for cached simulations or `miss` for those executed.
+pub(crate) fn record_simulation_cache_request(chain_id: u64, result: &str) {
+    SIMULATION_CACHE_REQUESTS
+        .with_label_values(&[&chain_id.to_string(), result])
+        .inc();
+}
+
+pub(crate) fn record_queue_depth(depth: usize) {
+    QUEUE_DEPTH.set(depth as i64);
+}
+
+pub(crate) fn record_queue_rejection() {
+    QUEUE_REJECTIONS.inc();
+}
+
+pub async fn metrics() -> Result<String, Rejection> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("metrics must be encodable");
+
+    Ok(String::from_utf8(buffer).expect("metrics must be UTF-8"))
+}
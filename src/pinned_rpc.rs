@@ -0,0 +1,133 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use ethers::abi::Hash;
+use ethers::providers::{Http, Provider};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use warp::Filter;
+
+use super::proxy::upstream_error;
+
+/// Methods whose last parameter is the block their state is read at.
+const STATE_METHODS: &[&str] = &[
+    "eth_getBalance",
+    "eth_getTransactionCount",
+    "eth_getCode",
+    "eth_getStorageAt",
+    "eth_call",
+    "eth_getProof",
+];
+
+/// A block served by its hash rather than its number, which may point at another block once it
+/// was reorged out.
+struct Pin {
+    fork_url: String,
+    provider: Provider<Http>,
+    number: u64,
+    hash: Hash,
+}
+
+impl Pin {
+    async fn handle(&self, request: Value) -> Value {
+        match request {
+            Value::Array(requests) => {
+                let mut responses = Vec::with_capacity(requests.len());
+                for request in requests {
+                    responses.push(self.handle_one(request).await);
+                }
+                Value::Array(responses)
+            }
+            request => self.handle_one(request).await,
+        }
+    }
+
+    async fn handle_one(&self, request: Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or(json!([]));
+
+        let (method, params) = self.rewrite(method, params);
+        match self.provider.request(&method, params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": upstream_error(err) }),
+        }
+    }
+
+    /// Reads at the pinned block's number are made by its hash instead, with EIP-1898 for state.
+    fn rewrite(&self, method: &str, mut params: Value) -> (String, Value) {
+        let Some(values) = params.as_array_mut() else {
+            return (method.to_string(), params);
+        };
+        if method == "eth_getBlockByNumber" && self.is_pinned(values.first()) {
+            values[0] = json!(self.hash);
+            return ("eth_getBlockByHash".to_string(), params);
+        }
+        if STATE_METHODS.contains(&method) && self.is_pinned(values.last()) {
+            if let Some(block) = values.last_mut() {
+                *block = json!({ "blockHash": self.hash, "requireCanonical": false });
+            }
+        }
+
+        (method.to_string(), params)
+    }
+
+    fn is_pinned(&self, block: Option<&Value>) -> bool {
+        block
+            .and_then(Value::as_str)
+            .and_then(|block| block.strip_prefix("0x"))
+            .and_then(|block| u64::from_str_radix(block, 16).ok())
+            == Some(self.number)
+    }
+}
+
+/// Every block pinned since the server started, few as only non-canonical blocks are.
+static PINS: Lazy<Mutex<Vec<Arc<Pin>>>> = Lazy::new(Default::default);
+
+/// Local JSON-RPC server serving each pin at `/{index}`, started with the first pin.
+static ADDR: Lazy<SocketAddr> = Lazy::new(|| {
+    let route = warp::path!(usize)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(|pin: usize, request: Value| async move {
+            let pin = PINS.lock().unwrap().get(pin).cloned();
+            let response = match pin {
+                Some(pin) => pin.handle(request).await,
+                None => {
+                    let error = json!({ "code": -32601, "message": "unknown block" });
+                    json!({ "jsonrpc": "2.0", "id": null, "error": error })
+                }
+            };
+            Ok::<_, warp::Rejection>(warp::reply::json(&response))
+        });
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+});
+
+/// URL of an RPC forwarding to `fork_url` which serves block `hash` as block `number`, for a
+/// fork to read the state of a block no longer canonical. Must be called within a Tokio runtime.
+pub(crate) fn pinned_url(fork_url: &str, number: u64, hash: Hash) -> Option<String> {
+    let provider = Provider::<Http>::try_from(fork_url).ok()?;
+    let mut pins = PINS.lock().unwrap();
+    let index = match pins
+        .iter()
+        .position(|pin| pin.fork_url == fork_url && pin.hash == hash)
+    {
+        Some(index) => index,
+        None => {
+            pins.push(Arc::new(Pin {
+                fork_url: fork_url.to_string(),
+                provider,
+                number,
+                hash,
+            }));
+            pins.len() - 1
+        }
+    };
+
+    Some(format!("http://{}/{index}", *ADDR))
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use ethers::abi::{Address, Uint};
+use ethers::types::Bytes;
+use ethers::utils::hex;
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall};
+use foundry_evm::CallKind;
+use serde::{Deserialize, Serialize};
+
+use crate::quantity;
+use crate::simulation::{SimulationRequest, SimulationResponse};
+
+use super::config::Config;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PolicyAction {
+    /// Simulated as usual, with the decision listed in `policyDecisions`.
+    Flag,
+    /// Answered with a `POLICY_VIOLATION` error instead of the simulation.
+    Reject,
+}
+
+/// Why a policy flagged or rejected a simulation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PolicyDecision {
+    /// Name of the policy.
+    pub policy: String,
+    pub action: PolicyAction,
+    pub reason: String,
+    /// Account the decision is about, e.g. the sanctioned address touched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<Address>,
+}
+
+/// A simulated transaction, as seen by policies.
+pub struct PolicyContext<'a> {
+    pub transaction: &'a SimulationRequest,
+    pub response: &'a SimulationResponse,
+    /// Every call frame, with its raw input.
+    pub trace: &'a CallTraceArena,
+}
+
+/// Decides whether simulations are flagged or rejected. Implement it to enforce rules the
+/// config can't express, and add it with `Simulator::with_policy` or `EvmPool::with_policy`.
+pub trait Policy: Send + Sync {
+    /// Decisions about the simulation, none if it complies with the policy.
+    fn evaluate(&self, context: &PolicyContext) -> Vec<PolicyDecision>;
+}
+
+/// A policy of `POLICY_FILE`. Each condition set is checked on its own and makes a decision when
+/// it's met.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PolicyRule {
+    pub name: String,
+    pub action: PolicyAction,
+    /// Accounts which must not be touched: sending or receiving the transaction, in a call frame,
+    /// emitting a log or in an asset change.
+    #[serde(default)]
+    pub addresses: Vec<Address>,
+    /// Most native value a call frame, the top level one included, may move.
+    #[serde(
+        rename = "maxValue",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub max_value: Option<Uint>,
+    /// 4 byte function selectors which must not be called, by any call frame.
+    #[serde(default)]
+    pub selectors: Vec<Bytes>,
+}
+
+impl PolicyRule {
+    fn decision(&self, reason: String, address: Option<Address>) -> PolicyDecision {
+        PolicyDecision {
+            policy: self.name.clone(),
+            action: self.action,
+            reason,
+            address,
+        }
+    }
+}
+
+impl Policy for PolicyRule {
+    fn evaluate(&self, context: &PolicyContext) -> Vec<PolicyDecision> {
+        let mut decisions = vec![];
+        let frames = &context.trace.arena;
+
+        if !self.addresses.is_empty() {
+            let response = context.response;
+            let touched: BTreeSet<Address> = [context.transaction.from]
+                .into_iter()
+                .chain(context.transaction.to)
+                .chain(
+                    frames
+                        .iter()
+                        .flat_map(|node| [node.trace.caller, node.trace.address]),
+                )
+                .chain(response.logs.iter().map(|log| log.address))
+                .chain(response.asset_changes.iter().map(|change| change.address))
+                .collect();
+            for address in &self.addresses {
+                if touched.contains(address) {
+                    decisions.push(self.decision(
+                        format!("touches blocked address {address:?}"),
+                        Some(*address),
+                    ));
+                }
+            }
+        }
+
+        if let Some(max_value) = self.max_value {
+            for node in frames {
+                let moves_value = matches!(
+                    node.trace.kind,
+                    CallKind::Call | CallKind::Create | CallKind::Create2
+                );
+                if moves_value && node.trace.value > max_value {
+                    decisions.push(self.decision(
+                        format!(
+                            "{:?} sends {} wei to {:?}, more than {max_value}",
+                            node.trace.caller, node.trace.value, node.trace.address
+                        ),
+                        Some(node.trace.address),
+                    ));
+                }
+            }
+        }
+
+        if !self.selectors.is_empty() {
+            for node in frames {
+                let RawOrDecodedCall::Raw(input) = &node.trace.data else {
+                    continue;
+                };
+                let Some(selector) = input.get(..4) else {
+                    continue;
+                };
+                if self.selectors.iter().any(|blocked| **blocked == *selector) {
+                    decisions.push(self.decision(
+                        format!(
+                            "calls blocked selector 0x{} on {:?}",
+                            hex::encode(selector),
+                            node.trace.address
+                        ),
+                        Some(node.trace.address),
+                    ));
+                }
+            }
+        }
+
+        decisions
+    }
+}
+
+/// The policies every simulation of a pool is checked against. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct PolicyEngine {
+    policies: Vec<Arc<dyn Policy>>,
+}
+
+impl PolicyEngine {
+    /// The rules of `POLICY_FILE`.
+    pub fn from_config(config: &Config) -> Self {
+        config
+            .policy_rules
+            .iter()
+            .cloned()
+            .fold(PolicyEngine::default(), PolicyEngine::with_policy)
+    }
+
+    pub fn with_policy(mut self, policy: impl Policy + 'static) -> Self {
+        self.policies.push(Arc::new(policy));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    /// Decisions of every policy, in the order the policies were added.
+    pub fn evaluate(&self, context: &PolicyContext) -> Vec<PolicyDecision> {
+        self.policies
+            .iter()
+            .flat_map(|policy| policy.evaluate(context))
+            .collect()
+    }
+}
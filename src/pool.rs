@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ethers::abi::Hash;
+use lru::LruCache;
+use revm::Env;
+use tokio::sync::Semaphore;
+
+use super::auth::ApiKeyPolicy;
+use super::contract_cache::ContractCache;
+use super::errors::BlockNotFoundError;
+use super::evm::{latest_block_number, Evm, ForkBackend};
+use super::metrics::{record_fork, record_pool_request};
+use super::policy::{Policy, PolicyEngine};
+use super::precompiles::{PrecompileStub, PrecompileStubs};
+use super::prices::PriceOracle;
+
+/// Fork backends shared across requests, keyed by `(fork_url, block_number)` as API keys may
+/// register the same chain ID with different RPCs. Requests of the latest block are resolved to
+/// its number first, so they share the fork of that block until a new one is mined.
+#[derive(Clone)]
+pub struct EvmPool {
+    forks: Arc<Mutex<LruCache<(String, u64), ForkBackend>>>,
+    /// Forks requested by block hash, keyed by `(fork_url, block_hash)`.
+    hash_forks: Arc<Mutex<LruCache<(String, Hash), ForkBackend>>>,
+    /// Executions of every `Evm` created by the pool, including long-lived forks.
+    permits: Arc<Semaphore>,
+    max_gas_limit: Option<u64>,
+    timeout: Option<Duration>,
+    /// Contracts identified by Etherscan, shared by every `Evm` created by the pool.
+    contract_cache: Option<Arc<ContractCache>>,
+    /// Prices asset changes for every `Evm` created by the pool.
+    price_oracle: Option<Arc<PriceOracle>>,
+    /// Checked against the simulations of every `Evm` created by the pool.
+    policies: PolicyEngine,
+    /// Put on the fork of every `Evm` created by the pool.
+    precompile_stubs: Arc<PrecompileStubs>,
+    /// Limits applied by `for_api_key`.
+    api_key_policies: Arc<HashMap<String, ApiKeyPolicy>>,
+}
+
+impl EvmPool {
+    pub fn new(size: usize, max_concurrency: usize) -> Self {
+        let size = NonZeroUsize::new(size.max(1)).unwrap();
+        EvmPool {
+            forks: Arc::new(Mutex::new(LruCache::new(size))),
+            hash_forks: Arc::new(Mutex::new(LruCache::new(size))),
+            permits: Arc::new(Semaphore::new(max_concurrency.max(1))),
+            max_gas_limit: None,
+            timeout: None,
+            contract_cache: None,
+            price_oracle: None,
+            policies: PolicyEngine::default(),
+            precompile_stubs: Arc::default(),
+            api_key_policies: Arc::default(),
+        }
+    }
+
+    /// Limits the gas and time of the simulations run with the `Evm`s of the pool.
+    pub fn with_limits(mut self, max_gas_limit: u64, timeout: Duration) -> Self {
+        self.max_gas_limit = Some(max_gas_limit);
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Caches the contracts the `Evm`s of the pool identify with Etherscan.
+    pub fn with_contract_cache(mut self, contract_cache: ContractCache) -> Self {
+        self.contract_cache = Some(Arc::new(contract_cache));
+        self
+    }
+
+    /// Prices the asset changes of the `Evm`s of the pool with `price_oracle`.
+    pub fn with_price_oracle(mut self, price_oracle: PriceOracle) -> Self {
+        self.price_oracle = Some(Arc::new(price_oracle));
+        self
+    }
+
+    /// Flags or rejects the simulations of the `Evm`s of the pool with `policies`.
+    pub fn with_policies(mut self, policies: PolicyEngine) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    /// Stubs precompiles and system contracts on the forks of the `Evm`s of the pool.
+    pub fn with_precompile_stubs(mut self, precompile_stubs: PrecompileStubs) -> Self {
+        self.precompile_stubs = Arc::new(precompile_stubs);
+        self
+    }
+
+    /// Adds a stub to those put on the forks of the pool, replacing any at the same address.
+    pub fn with_precompile_stub(mut self, stub: PrecompileStub) -> Self {
+        let precompile_stubs = (*self.precompile_stubs).clone().with_stub(stub);
+        self.precompile_stubs = Arc::new(precompile_stubs);
+        self
+    }
+
+    /// Lowers the gas limit of the `Evm`s of `for_api_key` to that of the policy of their key.
+    pub fn with_api_key_policies(
+        mut self,
+        api_key_policies: HashMap<String, ApiKeyPolicy>,
+    ) -> Self {
+        self.api_key_policies = Arc::new(api_key_policies);
+        self
+    }
+
+    /// The pool, sharing its forks, for a request made with `api_key`.
+    pub fn for_api_key(&self, api_key: Option<&str>) -> EvmPool {
+        let mut pool = self.clone();
+        let max_gas_limit = api_key
+            .and_then(|key| self.api_key_policies.get(key))
+            .and_then(|policy| policy.max_gas_limit);
+        if let Some(max_gas_limit) = max_gas_limit {
+            pool.max_gas_limit = Some(
+                self.max_gas_limit
+                    .map_or(max_gas_limit, |max| max.min(max_gas_limit)),
+            );
+        }
+        pool
+    }
+
+    /// Adds a policy to those the simulations of the pool are checked against.
+    pub fn with_policy(mut self, policy: impl Policy + 'static) -> Self {
+        self.policies = self.policies.with_policy(policy);
+        self
+    }
+
+    /// Creates an `Evm` on top of a pooled backend. Every `Evm` gets its own copy of the backend,
+    /// so state changes made by one request are never seen by another.
+    pub fn get(
+        &self,
+        chain_id: u64,
+        fork_url: String,
+        block_number: Option<u64>,
+        gas_limit: u64,
+        etherscan_key: Option<String>,
+    ) -> Evm {
+        let start = Instant::now();
+        let fork = self.fork(chain_id, fork_url, block_number);
+        tracing::info!(
+            target: "ts::pool",
+            chain_id,
+            block_number = fork.block_number(),
+            fork_ms = start.elapsed().as_millis() as u64,
+            "Fork ready"
+        );
+        self.configure(Evm::from_fork(None, fork, gas_limit, true, etherscan_key))
+    }
+
+    /// Like `get`, at the block `block_hash`, which may have been reorged out as long as the RPC
+    /// still serves it.
+    pub fn get_at_hash(
+        &self,
+        chain_id: u64,
+        fork_url: String,
+        block_hash: Hash,
+        gas_limit: u64,
+        etherscan_key: Option<String>,
+    ) -> Result<Evm, BlockNotFoundError> {
+        let cached = self
+            .hash_forks
+            .lock()
+            .unwrap()
+            .get(&(fork_url.clone(), block_hash))
+            .cloned();
+        let fork = match cached {
+            Some(fork) => {
+                record_pool_request(chain_id, "hit");
+                fork
+            }
+            None => {
+                record_pool_request(chain_id, "miss");
+                let start = Instant::now();
+                let fork = tokio::task::block_in_place(|| {
+                    ForkBackend::spawn_at_hash(fork_url.clone(), block_hash)
+                })
+                .ok_or(BlockNotFoundError(block_hash))?;
+                record_fork(chain_id, start.elapsed());
+                self.hash_forks
+                    .lock()
+                    .unwrap()
+                    .put((fork_url, block_hash), fork.clone());
+                fork
+            }
+        };
+
+        Ok(self.configure(Evm::from_fork(None, fork, gas_limit, true, etherscan_key)))
+    }
+
+    /// Like `get`, at the block `block_hash` if set rather than `block_number`.
+    pub fn get_at(
+        &self,
+        chain_id: u64,
+        fork_url: String,
+        block_number: Option<u64>,
+        block_hash: Option<Hash>,
+        gas_limit: u64,
+        etherscan_key: Option<String>,
+    ) -> Result<Evm, BlockNotFoundError> {
+        match block_hash {
+            Some(block_hash) => {
+                self.get_at_hash(chain_id, fork_url, block_hash, gas_limit, etherscan_key)
+            }
+            None => Ok(self.get(chain_id, fork_url, block_number, gas_limit, etherscan_key)),
+        }
+    }
+
+    /// Creates an `Evm` in `env` without a fork nor Etherscan, every account being empty until
+    /// set. Nothing is pooled.
+    pub fn get_offline(&self, env: Env, block_hash: Option<Hash>, gas_limit: u64) -> Evm {
+        let fork = ForkBackend::offline(env.clone(), block_hash);
+        self.configure(Evm::from_fork(Some(env), fork, gas_limit, true, None))
+    }
+
+    /// Applies the limits and services of the pool.
+    fn configure(&self, evm: Evm) -> Evm {
+        let mut evm = evm.with_concurrency_limit(self.permits.clone());
+        if let (Some(max_gas_limit), Some(timeout)) = (self.max_gas_limit, self.timeout) {
+            evm = evm.with_limits(max_gas_limit, timeout);
+        }
+        if let Some(contract_cache) = &self.contract_cache {
+            evm = evm.with_contract_cache(contract_cache.clone());
+        }
+        if let Some(price_oracle) = &self.price_oracle {
+            evm = evm.with_price_oracle(price_oracle.clone());
+        }
+        let mut evm = evm.with_policies(self.policies.clone());
+        self.precompile_stubs.apply(&mut evm);
+        evm
+    }
+
+    fn fork(&self, chain_id: u64, fork_url: String, block_number: Option<u64>) -> ForkBackend {
+        let block_number =
+            block_number.or_else(|| tokio::task::block_in_place(|| latest_block_number(&fork_url)));
+        let Some(block_number) = block_number else {
+            // The fork fetches the latest block itself, and is pooled at the number it got
+            record_pool_request(chain_id, "latest");
+            let fork = spawn(chain_id, fork_url.clone(), None);
+            self.forks
+                .lock()
+                .unwrap()
+                .put((fork_url, fork.block_number()), fork.clone());
+            return fork;
+        };
+
+        let key = (fork_url, block_number);
+        if let Some(fork) = self.forks.lock().unwrap().get(&key) {
+            record_pool_request(chain_id, "hit");
+            return fork.clone();
+        }
+
+        record_pool_request(chain_id, "miss");
+        let fork = spawn(chain_id, key.0.clone(), Some(block_number));
+        self.forks.lock().unwrap().put(key, fork.clone());
+        fork
+    }
+}
+
+/// Spawning a fork fetches its block from the RPC, which blocks.
+fn spawn(chain_id: u64, fork_url: String, block_number: Option<u64>) -> ForkBackend {
+    let start = Instant::now();
+    let fork = tokio::task::block_in_place(|| ForkBackend::spawn(fork_url, block_number));
+    record_fork(chain_id, start.elapsed());
+    fork
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use ethers::abi::Address;
+use ethers::types::Bytes;
+use ethers::utils::id;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::evm::Evm;
+
+const ARBITRUM_CHAINS: &[u64] = &[42161, 42170, 421613, 421614];
+
+/// Arbitrum's system precompile, native to its nodes and so missing from forks.
+const ARB_SYS: &str = "0x0000000000000000000000000000000000000064";
+
+const JUMPDEST: u8 = 0x5b;
+const PUSH1: u8 = 0x60;
+const PUSH2: u8 = 0x61;
+const PUSH4: u8 = 0x63;
+const DUP1: u8 = 0x80;
+const EQ: u8 = 0x14;
+const SHR: u8 = 0x1c;
+const CALLDATALOAD: u8 = 0x35;
+const CODECOPY: u8 = 0x39;
+const MSTORE: u8 = 0x52;
+const JUMPI: u8 = 0x57;
+const RETURN: u8 = 0xf3;
+const REVERT: u8 = 0xfd;
+
+/// Word of the block environment a stubbed function returns.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EnvironmentValue {
+    BlockNumber,
+    Timestamp,
+    ChainId,
+    BaseFee,
+    Coinbase,
+}
+
+impl EnvironmentValue {
+    fn opcode(self) -> u8 {
+        match self {
+            EnvironmentValue::Coinbase => 0x41,
+            EnvironmentValue::Timestamp => 0x42,
+            EnvironmentValue::BlockNumber => 0x43,
+            EnvironmentValue::ChainId => 0x46,
+            EnvironmentValue::BaseFee => 0x48,
+        }
+    }
+}
+
+/// What a stubbed function returns: a word of the block environment, or fixed ABI encoded data.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum StubValue {
+    Environment(EnvironmentValue),
+    Data(Bytes),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StubFunction {
+    /// E.g. `arbBlockNumber()`, matched by selector.
+    pub signature: String,
+    pub returns: StubValue,
+}
+
+/// Code put at `address` on forks of `chain_id`, for precompiles and system contracts which the
+/// chain's nodes implement natively. Calls to other functions revert.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PrecompileStub {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    pub address: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub functions: Vec<StubFunction>,
+}
+
+impl PrecompileStub {
+    /// Dispatches on the selector, then returns the word of the environment or copies the data
+    /// appended after the code.
+    fn code(&self) -> Bytes {
+        const DISPATCH_LENGTH: usize = 11;
+        const ENVIRONMENT_LENGTH: usize = 10;
+        const DATA_LENGTH: usize = 16;
+
+        let mut code = vec![PUSH1, 0x00, CALLDATALOAD, PUSH1, 0xe0, SHR];
+        let bodies_start = code.len() + DISPATCH_LENGTH * self.functions.len() + 4;
+        let body_length = |function: &StubFunction| match function.returns {
+            StubValue::Environment(_) => ENVIRONMENT_LENGTH,
+            StubValue::Data(_) => DATA_LENGTH,
+        };
+        let data_start = bodies_start + self.functions.iter().map(body_length).sum::<usize>();
+
+        let mut body = bodies_start;
+        for function in &self.functions {
+            code.extend([DUP1, PUSH4]);
+            code.extend(id(&function.signature));
+            code.extend([EQ, PUSH2]);
+            code.extend((body as u16).to_be_bytes());
+            code.push(JUMPI);
+            body += body_length(function);
+        }
+        code.extend([PUSH1, 0x00, DUP1, REVERT]);
+
+        let mut data = vec![];
+        for function in &self.functions {
+            code.push(JUMPDEST);
+            match &function.returns {
+                StubValue::Environment(value) => code.extend([
+                    value.opcode(),
+                    PUSH1,
+                    0x00,
+                    MSTORE,
+                    PUSH1,
+                    0x20,
+                    PUSH1,
+                    0x00,
+                    RETURN,
+                ]),
+                StubValue::Data(returns) => {
+                    let length = (returns.len() as u16).to_be_bytes();
+                    let offset = ((data_start + data.len()) as u16).to_be_bytes();
+                    code.extend([PUSH2, length[0], length[1], PUSH2, offset[0], offset[1]]);
+                    code.extend([PUSH1, 0x00, CODECOPY, PUSH2, length[0], length[1]]);
+                    code.extend([PUSH1, 0x00, RETURN]);
+                    data.extend_from_slice(returns);
+                }
+            }
+        }
+        code.extend(data);
+
+        code.into()
+    }
+}
+
+/// The stubs of Arbitrum's precompiles, `ArbSys` returning the L2 block number and chain ID.
+fn system_contracts() -> Vec<PrecompileStub> {
+    ARBITRUM_CHAINS
+        .iter()
+        .map(|chain_id| PrecompileStub {
+            chain_id: *chain_id,
+            address: ARB_SYS.parse().unwrap(),
+            name: Some("ArbSys".to_string()),
+            functions: vec![
+                StubFunction {
+                    signature: "arbBlockNumber()".to_string(),
+                    returns: StubValue::Environment(EnvironmentValue::BlockNumber),
+                },
+                StubFunction {
+                    signature: "arbChainID()".to_string(),
+                    returns: StubValue::Environment(EnvironmentValue::ChainId),
+                },
+            ],
+        })
+        .collect()
+}
+
+/// Stubs put on every fork of their chain, keyed by `(chain_id, address)`, with their code.
+#[derive(Debug, Clone, Default)]
+pub struct PrecompileStubs(HashMap<(u64, Address), (PrecompileStub, Bytes)>);
+
+impl PrecompileStubs {
+    /// The system contracts unless `SYSTEM_CONTRACTS` is disabled, then the stubs of
+    /// `PRECOMPILE_STUBS_FILE`, replacing those at the same address.
+    pub fn from_config(config: &Config) -> Self {
+        let system_contracts = if config.system_contracts {
+            system_contracts()
+        } else {
+            vec![]
+        };
+        system_contracts
+            .into_iter()
+            .chain(config.precompile_stubs.iter().cloned())
+            .fold(PrecompileStubs::default(), PrecompileStubs::with_stub)
+    }
+
+    /// Replaces the stub at the same address of the same chain, if any.
+    pub fn with_stub(mut self, stub: PrecompileStub) -> Self {
+        let code = stub.code();
+        self.0.insert((stub.chain_id, stub.address), (stub, code));
+        self
+    }
+
+    /// Puts the stubs of the chain of `evm` on its fork.
+    pub(crate) fn apply(&self, evm: &mut Evm) {
+        let chain_id = evm.chain_id();
+        for ((stub_chain_id, address), (stub, code)) in &self.0 {
+            if *stub_chain_id != chain_id {
+                continue;
+            }
+            if let Err(err) = evm.set_code(*address, code.clone()) {
+                log::warn!(
+                    target: "ts::precompiles",
+                    "Failed to stub {}: {}",
+                    stub.name.as_deref().unwrap_or("precompile"),
+                    err.0
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use futures_util::stream::{self, StreamExt};
+use tokio::task::JoinHandle;
+
+use crate::evm::CallOptions;
+use crate::simulation::{call_raw_request, SimulationRequest};
+
+use super::pool::EvmPool;
+
+/// Speculative executions of the transactions of a bundle, aborted once dropped.
+pub(crate) struct Prefetch(Option<JoinHandle<()>>);
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.0 {
+            handle.abort();
+        }
+    }
+}
+
+/// Executes `transactions` speculatively, `concurrency` at a time, each on its own copy of the
+/// fork at `block_number` and so against the state of the forked block rather than that left by
+/// the transactions before it. The copies share the fork's cache, so the accounts, code and slots
+/// the transactions load are fetched from the RPC while the bundle runs, rather than one
+/// round-trip at a time once it reaches them. Results are discarded. Disabled if `concurrency`
+/// is zero.
+pub(crate) fn prefetch(
+    pool: &EvmPool,
+    chain_id: u64,
+    fork_url: &str,
+    block_number: u64,
+    transactions: &[SimulationRequest],
+    concurrency: usize,
+) -> Prefetch {
+    let requests: Vec<_> = transactions
+        .iter()
+        .filter_map(|transaction| call_raw_request(transaction).ok())
+        .collect();
+    if concurrency == 0 || requests.is_empty() {
+        return Prefetch(None);
+    }
+
+    let pool = pool.clone();
+    let fork_url = fork_url.to_string();
+    Prefetch(Some(tokio::spawn(async move {
+        stream::iter(requests)
+            .for_each_concurrent(concurrency, |request| {
+                let mut evm = pool.get(
+                    chain_id,
+                    fork_url.clone(),
+                    Some(block_number),
+                    request.gas_limit,
+                    None,
+                );
+                async move {
+                    if let Err(err) = evm.call_raw(&request, CallOptions::default()).await {
+                        log::debug!(target: "ts::prefetch", "Speculative execution failed: {}", err.0);
+                    }
+                }
+            })
+            .await;
+    })))
+}
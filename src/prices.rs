@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers::abi::{decode, encode, Address, ParamType, Token, Uint};
+use ethers::utils::{format_units, id};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::assets::{AssetChange, AssetType};
+use crate::evm::{CallOptions, CallRawRequest, Evm};
+
+use super::config::Config;
+
+/// Chainlink's Feed Registry, only deployed on Ethereum mainnet.
+const FEED_REGISTRY: &str = "0x47Fb2585D2C56Fe188D0E6ec628a38b74fCeeeDf";
+
+/// Denominations of the Feed Registry: ETH, BTC and USD.
+const ETH: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+const BTC: &str = "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB";
+const USD: &str = "0x0000000000000000000000000000000000000348";
+
+/// Wrapped assets priced like the asset they wrap, as the Feed Registry has no feeds for them.
+const WRAPPED: &[(&str, &str)] = &[
+    // WETH
+    ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", ETH),
+    // WBTC
+    ("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599", BTC),
+];
+
+/// Gas limit of the calls reading a price feed.
+const FEED_GAS_LIMIT: u64 = 200_000;
+
+/// How long prices of the price API are cached.
+const PRICE_TTL: Duration = Duration::from_secs(60);
+
+/// How long the price API may take to answer.
+const PRICE_API_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// USD value an address gained, negative if it lost value, over its priced asset changes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NetValueChange {
+    pub address: Address,
+    #[serde(rename = "valueUsd")]
+    pub value_usd: f64,
+    /// Whether every fungible asset change of the address could be priced, NFTs are never priced.
+    pub complete: bool,
+}
+
+/// Where USD prices come from: the price API of `PRICE_API_URL` if set, Chainlink's Feed Registry
+/// read from the fork otherwise. Shared by every `Evm` of a pool so that API prices are cached.
+pub struct PriceOracle {
+    /// Template with `{chainId}` and `{token}` placeholders.
+    api_url: Option<String>,
+    client: reqwest::Client,
+    /// Prices of the API keyed by `(chain_id, token)`, `None` for the native asset.
+    cache: Mutex<HashMap<(u64, Option<Address>), (Option<f64>, Instant)>>,
+}
+
+impl Default for PriceOracle {
+    fn default() -> Self {
+        PriceOracle::new(None)
+    }
+}
+
+impl PriceOracle {
+    pub fn new(api_url: Option<String>) -> Self {
+        PriceOracle {
+            api_url,
+            client: reqwest::Client::builder()
+                .timeout(PRICE_API_TIMEOUT)
+                .build()
+                .expect("HTTP client must build"),
+            cache: Default::default(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.price_api_url.clone())
+    }
+
+    /// USD price of one whole token, or of the native asset if `token` is `None`.
+    async fn price(&self, evm: &mut Evm, token: Option<Address>) -> Option<f64> {
+        match &self.api_url {
+            Some(api_url) => self.api_price(api_url, evm.chain_id(), token).await,
+            None => feed_registry_price(evm, token).await,
+        }
+    }
+
+    async fn api_price(&self, api_url: &str, chain_id: u64, token: Option<Address>) -> Option<f64> {
+        let key = (chain_id, token);
+        if let Some((price, fetched_at)) = self.cache.lock().unwrap().get(&key) {
+            if fetched_at.elapsed() < PRICE_TTL {
+                return *price;
+            }
+        }
+
+        let token_param = match token {
+            Some(token) => format!("{token:?}"),
+            None => "native".to_string(),
+        };
+        let url = api_url
+            .replace("{chainId}", &chain_id.to_string())
+            .replace("{token}", &token_param);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        let price = match response {
+            Ok(response) => response.json::<Value>().await.ok().and_then(|body| {
+                body.as_f64()
+                    .or_else(|| body.get("usd").and_then(Value::as_f64))
+            }),
+            Err(err) => {
+                log::warn!(target: "ts::prices", "Price API failed: {}", err.without_url());
+                None
+            }
+        };
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, (price, Instant::now()));
+        price
+    }
+}
+
+/// Reads the `base / USD` feed of the Feed Registry on the fork, on Ethereum mainnet only.
+async fn feed_registry_price(evm: &mut Evm, token: Option<Address>) -> Option<f64> {
+    if evm.chain_id() != 1 {
+        return None;
+    }
+    let base = match token {
+        None => ETH.parse().unwrap(),
+        Some(token) => WRAPPED
+            .iter()
+            .find(|(wrapped, _)| wrapped.parse::<Address>().unwrap() == token)
+            .map_or(token, |(_, base)| base.parse().unwrap()),
+    };
+    let args = encode(&[Token::Address(base), Token::Address(USD.parse().unwrap())]);
+
+    let answer = call_feed(evm, "latestRoundData(address,address)", &args).await?;
+    let answer = decode(
+        &[
+            ParamType::Uint(80),
+            ParamType::Int(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(80),
+        ],
+        &answer,
+    )
+    .ok()?
+    .into_iter()
+    .nth(1)?
+    .into_int()?;
+    // Negative answers are two's complement
+    if answer.bit(255) {
+        return None;
+    }
+
+    let decimals = call_feed(evm, "decimals(address,address)", &args).await?;
+    let decimals = Uint::from_big_endian(decimals.get(..32)?);
+    if decimals > Uint::from(u8::MAX) {
+        return None;
+    }
+
+    to_f64(answer, decimals.as_u32())
+}
+
+async fn call_feed(evm: &mut Evm, signature: &str, args: &[u8]) -> Option<Vec<u8>> {
+    let mut data = id(signature).to_vec();
+    data.extend_from_slice(args);
+    let request = CallRawRequest {
+        to: Some(FEED_REGISTRY.parse().unwrap()),
+        data: Some(data.into()),
+        gas_limit: FEED_GAS_LIMIT,
+        ..Default::default()
+    };
+    // The registry reverts for assets without a feed
+    match evm.call_raw(&request, CallOptions::default()).await {
+        Ok(result) if result.success => Some(result.output.to_vec()),
+        _ => None,
+    }
+}
+
+fn to_f64(amount: Uint, decimals: u32) -> Option<f64> {
+    format_units(amount, decimals).ok()?.parse().ok()
+}
+
+/// Sets the USD price and value of the native and ERC-20 asset changes, with the token metadata
+/// already resolved, and sums the value gained or lost by each address.
+pub(crate) async fn price_asset_changes(
+    evm: &mut Evm,
+    oracle: &PriceOracle,
+    changes: &mut [AssetChange],
+) -> Vec<NetValueChange> {
+    let mut prices: HashMap<Option<Address>, Option<f64>> = HashMap::new();
+    let mut net: BTreeMap<Address, NetValueChange> = BTreeMap::new();
+
+    for change in changes {
+        let fungible = matches!(change.asset_type, AssetType::Native | AssetType::Erc20);
+        let decimals = match change.asset_type {
+            AssetType::Native => Some(18),
+            AssetType::Erc20 => change.token_info.as_ref().and_then(|info| info.decimals),
+            AssetType::Erc721 | AssetType::Erc1155 => None,
+        };
+        let price = match decimals {
+            Some(_) => match prices.get(&change.token) {
+                Some(price) => *price,
+                None => {
+                    let price = oracle.price(evm, change.token).await;
+                    prices.insert(change.token, price);
+                    price
+                }
+            },
+            None => None,
+        };
+
+        let value = price.zip(decimals).and_then(|(price, decimals)| {
+            let received = to_f64(change.received, decimals.into())?;
+            let sent = to_f64(change.sent, decimals.into())?;
+            Some((received - sent) * price)
+        });
+        change.price_usd = price;
+        change.value_usd = value;
+
+        let entry = net.entry(change.address).or_insert(NetValueChange {
+            address: change.address,
+            value_usd: 0.0,
+            complete: true,
+        });
+        match value {
+            Some(value) => entry.value_usd += value,
+            None if fungible => entry.complete = false,
+            None => {}
+        }
+    }
+
+    net.into_values().collect()
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use ethers::abi::{Address, Hash, Uint};
+use ethers::utils::id;
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall};
+use foundry_evm::CallKind;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::EvmError;
+use crate::evm::{uint_to_hash, CallOptions, CallRawRequest, Evm};
+use crate::simulation::DecodedCall;
+
+/// `bytes32(uint256(keccak256("eip1967.proxy.implementation")) - 1)`.
+const IMPLEMENTATION_SLOT: &str =
+    "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+
+/// `bytes32(uint256(keccak256("eip1967.proxy.beacon")) - 1)`.
+const BEACON_SLOT: &str = "0xa3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50";
+
+/// `keccak256("PROXIABLE")`, the implementation slot of EIP-1822, which UUPS predates.
+const PROXIABLE_SLOT: &str = "0xc5f16f0fcc639fa48a6947836d9850f504798523bf8c9a3a87d5876cf622bcf7";
+
+/// Gas limit of the `implementation()` and `proxiableUUID()` calls.
+const GETTER_GAS_LIMIT: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProxyKind {
+    /// EIP-1967 implementation slot, e.g. a transparent proxy.
+    Eip1967,
+    /// EIP-1967 implementation slot, upgraded through the implementation (EIP-1822
+    /// `proxiableUUID`).
+    Uups,
+    /// EIP-1967 beacon slot, the implementation being returned by the beacon.
+    Beacon,
+    /// Original EIP-1822 `PROXIABLE` slot.
+    Eip1822,
+}
+
+/// The proxy a call frame called and the implementation it runs.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProxyInfo {
+    pub kind: ProxyKind,
+    /// The contract the frame called.
+    pub proxy: Address,
+    /// The contract the proxy delegated the call to, or whose address its slots hold if it
+    /// didn't delegate, e.g. for an admin call to a transparent proxy.
+    pub implementation: Address,
+    /// Only for beacon proxies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beacon: Option<Address>,
+}
+
+/// Detects the EIP-1967, UUPS, beacon and EIP-1822 proxies called by the frames of `arena` from
+/// their storage slots, indexed like the arena. Proxy frames take the decoded call of the
+/// implementation they delegated to, decoded against its ABI rather than the proxy's.
+pub(crate) async fn resolve_proxies(
+    evm: &mut Evm,
+    arena: &CallTraceArena,
+    decoded_calls: &mut [Option<DecodedCall>],
+) -> Vec<Option<ProxyInfo>> {
+    let mut detected: HashMap<Address, Option<ProxyInfo>> = HashMap::new();
+    let mut proxies = Vec::with_capacity(arena.arena.len());
+
+    for node in &arena.arena {
+        let trace = &node.trace;
+        if !matches!(trace.kind, CallKind::Call | CallKind::StaticCall) {
+            proxies.push(None);
+            continue;
+        }
+        if !detected.contains_key(&trace.address) {
+            let proxy = match detect_proxy(evm, trace.address).await {
+                Ok(proxy) => proxy,
+                Err(err) => {
+                    log::warn!(
+                        target: "ts::proxies",
+                        "Failed to detect proxy {:?}: {}",
+                        trace.address,
+                        err.0
+                    );
+                    None
+                }
+            };
+            detected.insert(trace.address, proxy);
+        }
+        let Some(mut proxy) = detected[&trace.address].clone() else {
+            proxies.push(None);
+            continue;
+        };
+
+        // What actually ran, which an upgrade during the transaction may have changed
+        let delegated = node.children.iter().copied().find(|child| {
+            let child = &arena.arena[*child].trace;
+            child.kind == CallKind::DelegateCall && same_input(&child.data, &trace.data)
+        });
+        if let Some(child) = delegated {
+            proxy.implementation = arena.arena[child].trace.address;
+            if let Some(decoded_call) = decoded_calls.get(child).cloned().flatten() {
+                if let Some(call) = decoded_calls.get_mut(node.idx) {
+                    *call = Some(decoded_call);
+                }
+            }
+        }
+        proxies.push(Some(proxy));
+    }
+
+    proxies
+}
+
+async fn detect_proxy(evm: &mut Evm, address: Address) -> Result<Option<ProxyInfo>, EvmError> {
+    let implementation = slot_address(evm, address, IMPLEMENTATION_SLOT)?;
+    if let Some(implementation) = implementation {
+        let uuid = call_getter(evm, implementation, "proxiableUUID()").await?;
+        let kind = if uuid.as_deref() == Some(slot(IMPLEMENTATION_SLOT).as_bytes()) {
+            ProxyKind::Uups
+        } else {
+            ProxyKind::Eip1967
+        };
+        return Ok(Some(ProxyInfo {
+            kind,
+            proxy: address,
+            implementation,
+            beacon: None,
+        }));
+    }
+
+    if let Some(beacon) = slot_address(evm, address, BEACON_SLOT)? {
+        let implementation = call_getter(evm, beacon, "implementation()")
+            .await?
+            .filter(|output| output.len() == 32)
+            .map(|output| Address::from_slice(&output[12..]));
+        return Ok(implementation.map(|implementation| ProxyInfo {
+            kind: ProxyKind::Beacon,
+            proxy: address,
+            implementation,
+            beacon: Some(beacon),
+        }));
+    }
+
+    Ok(
+        slot_address(evm, address, PROXIABLE_SLOT)?.map(|implementation| ProxyInfo {
+            kind: ProxyKind::Eip1822,
+            proxy: address,
+            implementation,
+            beacon: None,
+        }),
+    )
+}
+
+fn slot(slot: &str) -> Hash {
+    slot.parse().unwrap()
+}
+
+/// The address stored in `slot` of `address`, if any.
+fn slot_address(evm: &Evm, address: Address, slot: &str) -> Result<Option<Address>, EvmError> {
+    let value = evm.storage(address, Uint::from_big_endian(self::slot(slot).as_bytes()))?;
+    Ok((!value.is_zero()).then(|| Address::from_slice(&uint_to_hash(value).as_bytes()[12..])))
+}
+
+/// Calls a getter of `address`, returning its output if it succeeded.
+async fn call_getter(
+    evm: &mut Evm,
+    address: Address,
+    signature: &str,
+) -> Result<Option<Vec<u8>>, EvmError> {
+    let request = CallRawRequest {
+        to: Some(address),
+        data: Some(id(signature).to_vec().into()),
+        gas_limit: GETTER_GAS_LIMIT,
+        ..Default::default()
+    };
+    let result = evm.call_raw(&request, CallOptions::default()).await?;
+
+    Ok(result.success.then(|| result.output.to_vec()))
+}
+
+fn same_input(a: &RawOrDecodedCall, b: &RawOrDecodedCall) -> bool {
+    match (a, b) {
+        (RawOrDecodedCall::Raw(a), RawOrDecodedCall::Raw(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ethers::providers::{Http, Provider, ProviderError, RpcError};
+use serde_json::{json, Value};
+use warp::Filter;
+
+use super::config::Config;
+#[cfg(feature = "sqlite")]
+use super::fork_cache::{is_cacheable, ForkCache};
+
+/// Rounds over all the RPCs of a chain before a request fails.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Wait before the second round, doubled for each following one.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// How long an RPC which failed is skipped for, unless a health check finds it back up earlier.
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+struct Upstream {
+    url: String,
+    provider: Provider<Http>,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn is_healthy(&self) -> bool {
+        self.unhealthy_until
+            .lock()
+            .unwrap()
+            .map_or(true, |until| until <= Instant::now())
+    }
+
+    fn set_healthy(&self, healthy: bool) {
+        let mut unhealthy_until = self.unhealthy_until.lock().unwrap();
+        if !healthy && unhealthy_until.is_none() {
+            log::warn!(target: "ts::proxy", "RPC {} is unhealthy", self.url);
+        } else if healthy && unhealthy_until.is_some() {
+            log::info!(target: "ts::proxy", "RPC {} is healthy again", self.url);
+        }
+        *unhealthy_until = (!healthy).then(|| Instant::now() + UNHEALTHY_COOLDOWN);
+    }
+}
+
+/// Rate limits and transport errors are worth retrying on another RPC, errors of the request
+/// itself like reverts would fail the same way there.
+fn is_retryable(err: &ProviderError) -> bool {
+    match err.as_error_response() {
+        Some(error) => {
+            error.code == 429
+                || error.code == -32005
+                || error.message.to_lowercase().contains("rate limit")
+        }
+        None => true,
+    }
+}
+
+pub(crate) fn upstream_error(err: ProviderError) -> Value {
+    match err.as_error_response() {
+        Some(error) => json!({ "code": error.code, "message": error.message, "data": error.data }),
+        None => json!({ "code": -32603, "message": err.to_string() }),
+    }
+}
+
+/// The RPCs of a chain in order of preference.
+struct Upstreams(Vec<Upstream>);
+
+impl Upstreams {
+    fn new(urls: &[String]) -> Self {
+        Upstreams(
+            urls.iter()
+                .map(|url| Upstream {
+                    url: url.clone(),
+                    provider: Provider::<Http>::try_from(url.as_str())
+                        .expect("RPC URLs must be valid URLs."),
+                    unhealthy_until: Mutex::new(None),
+                })
+                .collect(),
+        )
+    }
+
+    /// Sends the request to the first healthy RPC, failing over to the next ones, and retries
+    /// with exponential backoff once all of them failed. Unhealthy RPCs are only tried if none
+    /// is healthy.
+    async fn request(&self, method: &str, params: &Value) -> Result<Value, Value> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut last_error = json!({ "code": -32603, "message": "no RPC configured" });
+
+        for attempt in 0..MAX_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            let healthy: Vec<&Upstream> = self.0.iter().filter(|u| u.is_healthy()).collect();
+            let upstreams = if healthy.is_empty() {
+                self.0.iter().collect()
+            } else {
+                healthy
+            };
+
+            for upstream in upstreams {
+                match upstream.provider.request(method, params).await {
+                    Ok(result) => {
+                        upstream.set_healthy(true);
+                        return Ok(result);
+                    }
+                    Err(err) if is_retryable(&err) => {
+                        log::debug!(target: "ts::proxy", "{method} failed on {}: {err}", upstream.url);
+                        upstream.set_healthy(false);
+                        last_error = upstream_error(err);
+                    }
+                    Err(err) => return Err(upstream_error(err)),
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Marks every RPC healthy or not depending on whether it answers `eth_chainId`.
+    async fn check_health(&self) {
+        for upstream in &self.0 {
+            let healthy = upstream
+                .provider
+                .request::<_, Value>("eth_chainId", ())
+                .await
+                .is_ok();
+            upstream.set_healthy(healthy);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Proxy {
+    #[cfg(feature = "sqlite")]
+    cache: Option<Arc<ForkCache>>,
+    upstreams: Arc<HashMap<u64, Upstreams>>,
+}
+
+impl Proxy {
+    async fn handle(&self, chain_id: u64, request: Value) -> Value {
+        match request {
+            Value::Array(requests) => {
+                let mut responses = Vec::with_capacity(requests.len());
+                for request in requests {
+                    responses.push(self.handle_one(chain_id, request).await);
+                }
+                Value::Array(responses)
+            }
+            request => self.handle_one(chain_id, request).await,
+        }
+    }
+
+    async fn handle_one(&self, chain_id: u64, request: Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or(json!([]));
+
+        match self.call(chain_id, method, params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        }
+    }
+
+    async fn call(&self, chain_id: u64, method: &str, params: Value) -> Result<Value, Value> {
+        #[cfg(feature = "sqlite")]
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| is_cacheable(method, &params));
+        #[cfg(feature = "sqlite")]
+        let key = params.to_string();
+
+        #[cfg(feature = "sqlite")]
+        if let Some(cache) = cache {
+            match cache.get(chain_id, method, &key) {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => {}
+                Err(err) => log::warn!(target: "ts::fork_cache", "Failed to read cache: {err}"),
+            }
+        }
+
+        let upstreams = self
+            .upstreams
+            .get(&chain_id)
+            .ok_or_else(|| json!({ "code": -32601, "message": "chain not configured" }))?;
+        let result = upstreams.request(method, &params).await?;
+
+        // Unknown blocks and accounts come back as null, they may exist later
+        #[cfg(feature = "sqlite")]
+        if let Some(cache) = cache.filter(|_| !result.is_null()) {
+            if let Err(err) = cache.insert(chain_id, method, &key, &result) {
+                log::warn!(target: "ts::fork_cache", "Failed to write cache: {err}");
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Serves the chains of `config` through a local JSON-RPC proxy if `FORK_CACHE` is set or any
+/// chain has several RPCs, and returns a config pointing at it. Must be called within a Tokio
+/// runtime.
+pub fn with_proxy(config: Config) -> Config {
+    let failover = config.chains.values().any(|urls| urls.len() > 1);
+    if config.fork_cache.is_none() && !failover {
+        return config;
+    }
+
+    #[cfg(feature = "sqlite")]
+    let cache = config.fork_cache.as_ref().map(|path| {
+        log::info!(target: "ts::fork_cache", "Caching fork state in {path}");
+        Arc::new(ForkCache::open(path).expect("FORK_CACHE must be a valid SQLite database."))
+    });
+    #[cfg(not(feature = "sqlite"))]
+    assert!(
+        config.fork_cache.is_none(),
+        "FORK_CACHE requires the sqlite feature."
+    );
+    let upstreams: Arc<HashMap<u64, Upstreams>> = Arc::new(
+        config
+            .chains
+            .iter()
+            .map(|(chain_id, urls)| (*chain_id, Upstreams::new(urls)))
+            .collect(),
+    );
+    let proxy = Proxy {
+        #[cfg(feature = "sqlite")]
+        cache,
+        upstreams: upstreams.clone(),
+    };
+
+    if failover {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                for upstreams in upstreams.values().filter(|upstreams| upstreams.0.len() > 1) {
+                    upstreams.check_health().await;
+                }
+            }
+        });
+    }
+
+    let route = warp::path!(u64)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(move |chain_id: u64, request: Value| {
+            let proxy = proxy.clone();
+            async move {
+                let response = proxy.handle(chain_id, request).await;
+                Ok::<_, warp::Rejection>(warp::reply::json(&response))
+            }
+        });
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let chains = config
+        .chains
+        .keys()
+        .map(|chain_id| (*chain_id, vec![format!("http://{addr}/{chain_id}")]))
+        .collect();
+
+    Config { chains, ..config }
+}
@@ -0,0 +1,66 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use reqwest::Url;
+
+/// Resolves the host of `url`, which must be an HTTP(S) URL, and checks that it only points at
+/// public addresses, so that URLs supplied by clients can't reach the services next to the
+/// simulator, e.g. cloud metadata endpoints. Returns the addresses to connect to, for the request
+/// not to resolve the host again to another address.
+pub(crate) async fn resolve_public(url: &Url) -> Result<Vec<SocketAddr>, String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("must be an HTTP(S) URL".to_string());
+    }
+    let host = url.host_str().ok_or("must have a host")?;
+    let port = url.port_or_known_default().ok_or("must have a port")?;
+    // IPv6 hosts are bracketed in URLs
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| format!("host doesn't resolve: {err}"))?
+        .collect();
+    if addresses.is_empty() {
+        return Err("host doesn't resolve".to_string());
+    }
+    if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+        return Err(format!("{} isn't a public address", address.ip()));
+    }
+
+    Ok(addresses)
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8, "this network"
+        || a == 0
+        // 100.64.0.0/10, carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // fc00::/7, unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10, link-local
+        || (first & 0xffc0) == 0xfe80)
+}
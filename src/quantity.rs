@@ -0,0 +1,207 @@
+use std::str::FromStr;
+
+use ethers::abi::Uint;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use warp::reply::Json;
+
+/// Keys of the response holding quantities, converted by `quantityFormat`. Signed amounts like
+/// `netProfit` are left as decimal strings.
+const QUANTITY_KEYS: &[&str] = &[
+    "gasUsed",
+    "gasCost",
+    "gasLimit",
+    "blockNumber",
+    "transactionIndex",
+    "logIndex",
+    "effectiveGasPrice",
+    "feePaid",
+    "blobGasUsed",
+    "blobGasPrice",
+    "blobFee",
+    "value",
+    "sent",
+    "received",
+    "tokenId",
+    "balance",
+    "nonce",
+    "coinbaseDiff",
+    "gasFees",
+    "totalGasUsed",
+    "bundleGasPrice",
+];
+
+/// Keys of the response whose values are decoded from the ABI, e.g. a `value` param which is an
+/// address or a string, never converted.
+const DECODED_KEYS: &[&str] = &["params", "decodedReturnData"];
+
+/// How quantities are serialized in responses. If not set, 64 bit quantities like `gasUsed` are
+/// JSON numbers and 256 bit ones like `value` are hex strings.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantityFormat {
+    /// Every quantity as a `0x` prefixed hex string, like JSON-RPC.
+    Hex,
+    /// Every quantity as a decimal string, which JavaScript numbers can't lose precision on.
+    Decimal,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(u64),
+    String(String),
+}
+
+/// Parses a `0x` prefixed hex or a decimal quantity.
+pub fn parse_quantity(quantity: &str) -> Result<Uint, String> {
+    let quantity = quantity.trim();
+    match quantity
+        .strip_prefix("0x")
+        .or_else(|| quantity.strip_prefix("0X"))
+    {
+        Some(hex) if !hex.is_empty() => {
+            Uint::from_str(hex).map_err(|_err| format!("invalid hex quantity {quantity}"))
+        }
+        _ => Uint::from_dec_str(quantity).map_err(|_err| format!("invalid quantity {quantity}")),
+    }
+}
+
+impl Quantity {
+    fn into_uint(self) -> Result<Uint, String> {
+        match self {
+            Quantity::Number(number) => Ok(Uint::from(number)),
+            Quantity::String(string) => parse_quantity(&string),
+        }
+    }
+
+    fn into_u64(self) -> Result<u64, String> {
+        match self {
+            Quantity::Number(number) => Ok(number),
+            Quantity::String(string) => {
+                let quantity = parse_quantity(&string)?;
+                if quantity > Uint::from(u64::MAX) {
+                    return Err(format!("quantity {string} doesn't fit in 64 bits"));
+                }
+                Ok(quantity.as_u64())
+            }
+        }
+    }
+}
+
+/// Deserializes a JSON number, a hex string or a decimal string.
+pub fn deserialize_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    Quantity::deserialize(deserializer)?
+        .into_u64()
+        .map_err(D::Error::custom)
+}
+
+pub fn deserialize_option_u64<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    Option::<Quantity>::deserialize(deserializer)?
+        .map(Quantity::into_u64)
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+pub fn deserialize_uint<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uint, D::Error> {
+    Quantity::deserialize(deserializer)?
+        .into_uint()
+        .map_err(D::Error::custom)
+}
+
+pub fn deserialize_uints<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Uint>, D::Error> {
+    Vec::<Quantity>::deserialize(deserializer)?
+        .into_iter()
+        .map(Quantity::into_uint)
+        .collect::<Result<_, _>>()
+        .map_err(D::Error::custom)
+}
+
+pub fn deserialize_option_uint<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Uint>, D::Error> {
+    Option::<Quantity>::deserialize(deserializer)?
+        .map(Quantity::into_uint)
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+/// Keeps a quantity as a string, JSON numbers included, for fields parsed later on like `value`.
+pub fn deserialize_option_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(
+        Option::<Quantity>::deserialize(deserializer)?.map(|quantity| match quantity {
+            Quantity::Number(number) => number.to_string(),
+            Quantity::String(string) => string,
+        }),
+    )
+}
+
+fn format_quantity(value: &mut Value, format: QuantityFormat) {
+    let quantity = match value {
+        Value::Number(number) => match number.as_u64() {
+            Some(number) => Uint::from(number),
+            None => return,
+        },
+        Value::String(string) => match parse_quantity(string) {
+            Ok(quantity) => quantity,
+            Err(_) => return,
+        },
+        // e.g. the `pre` and `post` balances of a state diff
+        Value::Object(object) => {
+            object
+                .values_mut()
+                .for_each(|value| format_quantity(value, format));
+            return;
+        }
+        _ => return,
+    };
+    *value = Value::String(match format {
+        QuantityFormat::Hex => format!("0x{quantity:x}"),
+        QuantityFormat::Decimal => quantity.to_string(),
+    });
+}
+
+/// Converts every quantity of a serialized response to `format`.
+pub fn format_quantities(value: &mut Value, format: QuantityFormat) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if DECODED_KEYS.contains(&key.as_str()) {
+                    continue;
+                }
+                if QUANTITY_KEYS.contains(&key.as_str()) {
+                    format_quantity(value, format);
+                } else {
+                    format_quantities(value, format);
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| format_quantities(value, format)),
+        _ => {}
+    }
+}
+
+/// Serializes `response` with its quantities in `format`, as is if not set.
+pub(crate) fn json<T: Serialize>(response: &T, format: Option<QuantityFormat>) -> Json {
+    match format {
+        Some(format) => warp::reply::json(&to_value(response, Some(format))),
+        None => warp::reply::json(response),
+    }
+}
+
+pub(crate) fn to_value<T: Serialize>(response: &T, format: Option<QuantityFormat>) -> Value {
+    let mut value = serde_json::to_value(response).expect("responses must be serializable");
+    if let Some(format) = format {
+        format_quantities(&mut value, format);
+    }
+    value
+}
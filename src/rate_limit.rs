@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use lru::LruCache;
+use warp::{Filter, Rejection};
+
+use crate::errors::RateLimitedError;
+
+/// Buckets kept before the least recently used ones are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per known API key, or per IP for requests without one. Each bucket holds a
+/// minute of requests and refills continuously.
+#[derive(Clone)]
+pub struct RateLimiter {
+    requests_per_minute: u32,
+    /// Keys which get a bucket of their own, others would let clients pick a new bucket per
+    /// request.
+    api_keys: Arc<HashSet<String>>,
+    buckets: Arc<Mutex<LruCache<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    /// A limit of `0` disables rate limiting.
+    pub fn new(requests_per_minute: u32) -> Self {
+        RateLimiter {
+            requests_per_minute,
+            api_keys: Arc::default(),
+            buckets: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_BUCKETS).unwrap(),
+            ))),
+        }
+    }
+
+    /// Limits requests with one of `api_keys` per key rather than per IP, like `API_KEYS`
+    /// authenticates them.
+    pub fn with_api_keys(mut self, api_keys: HashSet<String>) -> Self {
+        self.api_keys = Arc::new(api_keys);
+        self
+    }
+
+    /// Takes a token for a request with `key` from `addr`, from the bucket of the key if it's
+    /// one of `api_keys` and of the IP otherwise, or returns how many seconds until one is
+    /// available.
+    pub(crate) fn acquire_for(
+        &self,
+        key: Option<&str>,
+        addr: Option<SocketAddr>,
+    ) -> Result<(), u64> {
+        let client = match (key, addr) {
+            (Some(key), _) if self.api_keys.contains(key) => format!("key:{key}"),
+            (_, Some(addr)) => format!("ip:{}", addr.ip()),
+            (_, None) => "unknown".to_string(),
+        };
+        self.acquire(client)
+    }
+
+    /// Takes a token from the bucket of `client`, or returns how many seconds until one is
+    /// available.
+    fn acquire(&self, client: String) -> Result<(), u64> {
+        if self.requests_per_minute == 0 {
+            return Ok(());
+        }
+
+        let capacity = self.requests_per_minute as f64;
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains(&client) {
+            // Drops the least recently used bucket once full
+            buckets.put(
+                client.clone(),
+                Bucket {
+                    tokens: capacity,
+                    updated: now,
+                },
+            );
+        }
+        let bucket = buckets.get_mut(&client).expect("bucket was inserted");
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(((1.0 - bucket.tokens) / per_second).ceil() as u64);
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Rejects requests over the limit of their API key or IP with a `429`. Unknown keys are limited
+/// by IP.
+pub fn with_rate_limit(
+    limiter: RateLimiter,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and(warp::addr::remote())
+        .and_then(move |key: Option<String>, addr: Option<SocketAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                limiter
+                    .acquire_for(key.as_deref(), addr)
+                    .map_err(|retry_after| warp::reject::custom(RateLimitedError { retry_after }))
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,124 @@
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Bytes, NameOrAddress};
+use ethers::utils::rlp::Rlp;
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{ChainIdMismatchError, InvalidRawTransactionError};
+use crate::quantity;
+use crate::simulation::{chain_id_to_fork_url, run, SimulationRequest};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawSimulationRequest {
+    /// Only needed for legacy transactions signed without a chain ID (pre EIP-155).
+    #[serde(
+        rename = "chainId",
+        default,
+        deserialize_with = "quantity::deserialize_option_u64"
+    )]
+    pub chain_id: Option<u64>,
+    #[serde(rename = "rawTransaction")]
+    pub raw_transaction: Bytes,
+    #[serde(
+        rename = "blockNumber",
+        default,
+        deserialize_with = "quantity::deserialize_option_u64"
+    )]
+    pub block_number: Option<u64>,
+    #[serde(rename = "formatTrace")]
+    pub format_trace: Option<bool>,
+    /// Checks the nonce, balance and gas limit of the transaction like `validation` does.
+    pub validation: Option<bool>,
+}
+
+impl TryFrom<RawSimulationRequest> for SimulationRequest {
+    type Error = Rejection;
+
+    fn try_from(request: RawSimulationRequest) -> Result<Self, Self::Error> {
+        let (transaction, signature) =
+            TypedTransaction::decode_signed(&Rlp::new(&request.raw_transaction))
+                .map_err(|_err| InvalidRawTransactionError)?;
+        let from = signature
+            .recover(transaction.sighash())
+            .map_err(|_err| InvalidRawTransactionError)?;
+
+        let chain_id = match (transaction.chain_id(), request.chain_id) {
+            (Some(signed), Some(requested)) if signed.as_u64() != requested => {
+                return Err(ChainIdMismatchError.into())
+            }
+            (Some(signed), _) => signed.as_u64(),
+            (None, Some(requested)) => requested,
+            (None, None) => return Err(InvalidRawTransactionError.into()),
+        };
+
+        let to = match transaction.to() {
+            Some(NameOrAddress::Address(to)) => Some(*to),
+            Some(NameOrAddress::Name(_)) => return Err(InvalidRawTransactionError.into()),
+            None => None,
+        };
+
+        // Transactions signed with a zero gas price are simulated without charging gas
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match &transaction {
+            TypedTransaction::Eip1559(tx) => {
+                (None, tx.max_fee_per_gas, tx.max_priority_fee_per_gas)
+            }
+            _ => (
+                transaction
+                    .gas_price()
+                    .filter(|gas_price| !gas_price.is_zero()),
+                None,
+                None,
+            ),
+        };
+
+        Ok(SimulationRequest {
+            chain_id,
+            from,
+            to,
+            data: transaction.data().cloned(),
+            gas_limit: transaction
+                .gas()
+                .map(|gas| gas.as_u64())
+                .unwrap_or_default(),
+            value: transaction.value().map(|value| value.to_string()),
+            gas_price,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            access_list: transaction.access_list().cloned(),
+            block_number: request.block_number,
+            format_trace: request.format_trace,
+            nonce: transaction.nonce().map(|nonce| nonce.as_u64()),
+            validation: request.validation,
+            ..Default::default()
+        })
+    }
+}
+
+pub async fn simulate_raw(
+    request: RawSimulationRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    let transaction = SimulationRequest::try_from(request)?;
+
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let response = run(&mut evm, transaction.clone(), false).await?;
+    history.record(&transaction, &response);
+
+    Ok(warp::reply::json(&response))
+}
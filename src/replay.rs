@@ -0,0 +1,235 @@
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::{Log, Transaction, TransactionReceipt, H256};
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{RpcError, TransactionNotFoundError};
+use crate::evm::CallOptions;
+use crate::simulation::{
+    call_raw_request, chain_id_to_fork_url, run, BlockOverrides, SimulationRequest,
+    SimulationResponse,
+};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayRequest {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    #[serde(rename = "txHash")]
+    pub tx_hash: H256,
+}
+
+/// A value of the replay next to that of the receipt.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Comparison<T> {
+    pub simulated: T,
+    /// Not set if the receipt doesn't have it, like the status before Byzantium.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<T>,
+    /// Whether both are equal, `true` if `actual` isn't known.
+    pub matches: bool,
+}
+
+impl<T: PartialEq> Comparison<T> {
+    fn new(simulated: T, actual: Option<T>) -> Self {
+        let matches = actual.as_ref().map_or(true, |actual| *actual == simulated);
+        Comparison {
+            simulated,
+            actual,
+            matches,
+        }
+    }
+}
+
+/// The number of logs of the replay and of the receipt, compared by address, topics and data.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LogsComparison {
+    pub simulated: usize,
+    pub actual: usize,
+    pub matches: bool,
+    /// Index of the first log which differs, or is missing from one of them.
+    #[serde(
+        rename = "firstDivergence",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub first_divergence: Option<usize>,
+}
+
+impl LogsComparison {
+    fn new(simulated: &[Log], actual: &[Log]) -> Self {
+        let same = |(simulated, actual): (&Log, &Log)| {
+            simulated.address == actual.address
+                && simulated.topics == actual.topics
+                && simulated.data == actual.data
+        };
+        let first_divergence = simulated
+            .iter()
+            .zip(actual)
+            .position(|logs| !same(logs))
+            .or_else(|| {
+                (simulated.len() != actual.len()).then(|| simulated.len().min(actual.len()))
+            });
+
+        LogsComparison {
+            simulated: simulated.len(),
+            actual: actual.len(),
+            matches: first_divergence.is_none(),
+            first_divergence,
+        }
+    }
+}
+
+/// The replay checked against the receipt of the transaction, which differ if the fork's state
+/// or the EVM diverge from the chain.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplayVerification {
+    /// Whether the status, gas used and logs all match.
+    pub matches: bool,
+    pub success: Comparison<bool>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: Comparison<u64>,
+    pub logs: LogsComparison,
+}
+
+impl ReplayVerification {
+    fn new(response: &SimulationResponse, receipt: &TransactionReceipt) -> Self {
+        let success = Comparison::new(
+            response.success,
+            receipt.status.map(|status| status.as_u64() == 1),
+        );
+        let gas_used = Comparison::new(
+            response.gas_used,
+            receipt.gas_used.map(|gas_used| gas_used.as_u64()),
+        );
+        let logs = LogsComparison::new(&response.logs, &receipt.logs);
+
+        ReplayVerification {
+            matches: success.matches && gas_used.matches && logs.matches,
+            success,
+            gas_used,
+            logs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplayResponse {
+    #[serde(flatten)]
+    pub simulation: SimulationResponse,
+    pub verification: ReplayVerification,
+}
+
+/// Turns a mined transaction back into a request, charging the fees it was sent with.
+pub(crate) fn replay_request(chain_id: u64, transaction: &Transaction) -> SimulationRequest {
+    let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match transaction.max_fee_per_gas {
+        Some(max_fee_per_gas) => (
+            None,
+            Some(max_fee_per_gas),
+            transaction.max_priority_fee_per_gas,
+        ),
+        None => (transaction.gas_price, None, None),
+    };
+
+    SimulationRequest {
+        chain_id,
+        from: transaction.from,
+        to: transaction.to,
+        data: Some(transaction.input.clone()),
+        gas_limit: transaction.gas.as_u64(),
+        value: Some(transaction.value.to_string()),
+        gas_price,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        access_list: transaction.access_list.clone(),
+        block_number: transaction.block_number.map(|number| number.as_u64()),
+        nonce: Some(transaction.nonce.as_u64()),
+        ..Default::default()
+    }
+}
+
+/// Replays a mined transaction on top of the transactions before it in its block, with full
+/// tracing, and compares it with the transaction's receipt.
+pub async fn replay(
+    request: ReplayRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    let fork_url = chain_id_to_fork_url(request.chain_id, &config)?;
+    let provider =
+        Provider::<Http>::try_from(fork_url.as_str()).map_err(|err| RpcError(err.into()))?;
+
+    let transaction = provider
+        .get_transaction(request.tx_hash)
+        .await
+        .map_err(|err| RpcError(err.into()))?
+        .ok_or(TransactionNotFoundError)?;
+    // Pending transactions have no block to replay against
+    let block_number = transaction
+        .block_number
+        .ok_or(TransactionNotFoundError)?
+        .as_u64();
+    let block = provider
+        .get_block_with_txs(block_number)
+        .await
+        .map_err(|err| RpcError(err.into()))?
+        .ok_or(TransactionNotFoundError)?;
+    let receipt = provider
+        .get_transaction_receipt(request.tx_hash)
+        .await
+        .map_err(|err| RpcError(err.into()))?
+        .ok_or(TransactionNotFoundError)?;
+
+    let mut evm = pool.get(
+        request.chain_id,
+        fork_url,
+        Some(block_number.saturating_sub(1)),
+        transaction.gas.as_u64(),
+        config.etherscan_key,
+    );
+    evm.override_block(&BlockOverrides {
+        number: Some(block_number),
+        timestamp: Some(block.timestamp.as_u64()),
+        base_fee: block.base_fee_per_gas,
+        coinbase: block.author,
+        prevrandao: block.mix_hash,
+        ..Default::default()
+    });
+
+    for preceding in block
+        .transactions
+        .iter()
+        .take_while(|preceding| preceding.hash != transaction.hash)
+    {
+        let preceding = call_raw_request(&replay_request(request.chain_id, preceding))?;
+        evm.call_raw_committing(&preceding, CallOptions::default())
+            .await?;
+    }
+
+    let replayed = SimulationRequest {
+        format_trace: Some(true),
+        nest_trace: Some(true),
+        decode_logs: Some(true),
+        ..replay_request(request.chain_id, &transaction)
+    };
+    let response = run(&mut evm, replayed.clone(), false).await?;
+    history.record(&replayed, &response);
+
+    let verification = ReplayVerification::new(&response, &receipt);
+    if !verification.matches {
+        log::warn!(
+            target: "ts::simulation",
+            "Replay of {:?} diverged from its receipt",
+            request.tx_hash
+        );
+    }
+    Ok(warp::reply::json(&ReplayResponse {
+        simulation: response,
+        verification,
+    }))
+}
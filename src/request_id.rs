@@ -0,0 +1,54 @@
+use tracing::Instrument;
+use uuid::Uuid;
+use warp::http::{HeaderValue, Request, Response};
+use warp::hyper::service::Service;
+use warp::hyper::Body;
+
+/// Header a request ID is read from and returned in.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest request ID accepted from a client, longer ones are replaced.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+fn is_valid(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Serves a request in a `request` span carrying its ID, so that every log line it causes can be
+/// told apart. The ID is the `X-Request-Id` of the request if it has a valid one, a new UUID
+/// otherwise, and is returned in the `X-Request-Id` header of the response.
+pub async fn with_request_id<S>(
+    mut service: S,
+    mut request: Request<Body>,
+) -> Result<Response<Body>, S::Error>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+{
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|request_id| is_valid(request_id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let header = HeaderValue::from_str(&request_id).expect("request IDs are valid header values");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header.clone());
+
+    let span = tracing::info_span!(
+        target: "ts::api",
+        "request",
+        id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let mut response = service.call(request).instrument(span).await?;
+    response.headers_mut().insert(REQUEST_ID_HEADER, header);
+
+    Ok(response)
+}
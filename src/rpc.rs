@@ -0,0 +1,332 @@
+use ethers::abi::{Address, Uint};
+use ethers::types::transaction::eip2930::AccessList;
+use ethers::types::{Bytes, H256, U64};
+use ethers::utils::keccak256;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{error_message, EvmError, SimulationError};
+use crate::raw::RawSimulationRequest;
+use crate::simulation::{run, SimulationRequest, StructLog, StructLogOptions};
+
+use super::evm::{CallOptions, CallRawRequest};
+use super::fork::{Fork, ForkStore, FORK_GAS_LIMIT};
+use super::history::History;
+
+/// Standard JSON-RPC error codes, plus the one geth uses for reverts.
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const EXECUTION_REVERTED: i64 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn reverted(revert_reason: Option<String>, output: Bytes) -> Self {
+        let message = match revert_reason {
+            Some(reason) => format!("execution reverted: {reason}"),
+            None => "execution reverted".to_string(),
+        };
+        RpcError {
+            code: EXECUTION_REVERTED,
+            message,
+            data: Some(json!(output)),
+        }
+    }
+}
+
+/// Errors of the simulation keep the message and details they have on the REST endpoints.
+impl From<Rejection> for RpcError {
+    fn from(err: Rejection) -> Self {
+        let (error, _) = error_message(&err);
+        RpcError {
+            code: SERVER_ERROR,
+            message: error.message,
+            data: error.details,
+        }
+    }
+}
+
+impl From<EvmError> for RpcError {
+    fn from(err: EvmError) -> Self {
+        Rejection::from(err).into()
+    }
+}
+
+impl From<SimulationError> for RpcError {
+    fn from(err: SimulationError) -> Self {
+        Rejection::from(err).into()
+    }
+}
+
+/// Call object of `eth_call`, `eth_estimateGas` and `debug_traceCall`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RpcCallRequest {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub gas: Option<Uint>,
+    #[serde(rename = "gasPrice")]
+    pub gas_price: Option<Uint>,
+    #[serde(rename = "maxFeePerGas")]
+    pub max_fee_per_gas: Option<Uint>,
+    #[serde(rename = "maxPriorityFeePerGas")]
+    pub max_priority_fee_per_gas: Option<Uint>,
+    pub value: Option<Uint>,
+    pub data: Option<Bytes>,
+    /// Alias of `data` used by newer clients.
+    pub input: Option<Bytes>,
+    #[serde(rename = "accessList")]
+    pub access_list: Option<AccessList>,
+}
+
+impl From<RpcCallRequest> for CallRawRequest {
+    fn from(request: RpcCallRequest) -> Self {
+        CallRawRequest {
+            from: request.from.unwrap_or_default(),
+            to: request.to,
+            value: request.value,
+            data: request.input.or(request.data),
+            gas_limit: request.gas.map_or(FORK_GAS_LIMIT, |gas| {
+                gas.min(FORK_GAS_LIMIT.into()).as_u64()
+            }),
+            gas_price: request.gas_price,
+            max_fee_per_gas: request.max_fee_per_gas,
+            max_priority_fee_per_gas: request.max_priority_fee_per_gas,
+            access_list: request.access_list,
+        }
+    }
+}
+
+/// Result of `debug_traceCall` with geth's default struct logger.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StructLogTrace {
+    pub gas: u64,
+    pub failed: bool,
+    #[serde(rename = "returnValue")]
+    pub return_value: Bytes,
+    #[serde(rename = "structLogs")]
+    pub struct_logs: Vec<StructLog>,
+}
+
+fn param<T: DeserializeOwned>(params: &Value, position: usize) -> Result<T, RpcError> {
+    let param = params.get(position).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(param)
+        .map_err(|err| RpcError::new(INVALID_PARAMS, format!("invalid param {position}: {err}")))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|err| RpcError::new(SERVER_ERROR, err.to_string()))
+}
+
+/// Answers JSON-RPC requests on the state of a fork, the block parameter of each method is
+/// ignored. Transactions sent with `eth_sendRawTransaction` are simulated and committed to it.
+struct Node<'a> {
+    fork: &'a mut Fork,
+    history: &'a History,
+}
+
+impl Node<'_> {
+    async fn handle(&mut self, request: Value) -> RpcResponse {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let result = match serde_json::from_value::<RpcRequest>(request) {
+            Ok(request) => self.call(&request.method, &request.params).await,
+            Err(err) => Err(RpcError::new(INVALID_REQUEST, err.to_string())),
+        };
+
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        RpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+
+    async fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "eth_chainId" => to_value(U64::from(self.fork.chain_id)),
+            "net_version" => to_value(self.fork.chain_id.to_string()),
+            "eth_blockNumber" => to_value(U64::from(self.fork.evm.block_number())),
+            "eth_getBalance" => {
+                let address: Address = param(params, 0)?;
+                to_value(self.fork.evm.basic(address)?.balance)
+            }
+            "eth_getTransactionCount" => {
+                let address: Address = param(params, 0)?;
+                to_value(U64::from(self.fork.evm.basic(address)?.nonce))
+            }
+            "eth_call" => self.eth_call(param(params, 0)?).await,
+            "eth_estimateGas" => self.eth_estimate_gas(param(params, 0)?).await,
+            "eth_sendRawTransaction" => self.eth_send_raw_transaction(param(params, 0)?).await,
+            "evm_snapshot" => to_value(U64::from(self.fork.snapshot())),
+            "evm_revert" => {
+                let snapshot_id: U64 = param(params, 0)?;
+                to_value(self.fork.revert(snapshot_id.as_u64()))
+            }
+            "debug_traceCall" => {
+                let options: Option<Value> = param(params, 2)?;
+                self.debug_trace_call(param(params, 0)?, options).await
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("the method {method} does not exist/is not available"),
+            )),
+        }
+    }
+
+    async fn eth_call(&mut self, request: RpcCallRequest) -> Result<Value, RpcError> {
+        let result = self
+            .fork
+            .evm
+            .call_raw(&request.into(), CallOptions::default())
+            .await?;
+        if !result.success {
+            return Err(RpcError::reverted(result.revert_reason, result.output));
+        }
+
+        to_value(result.output)
+    }
+
+    async fn eth_estimate_gas(&mut self, request: RpcCallRequest) -> Result<Value, RpcError> {
+        let request = request.into();
+        let result = self
+            .fork
+            .evm
+            .call_raw(&request, CallOptions::default())
+            .await?;
+        if !result.success {
+            return Err(RpcError::reverted(result.revert_reason, result.output));
+        }
+
+        let gas_estimate = self
+            .fork
+            .evm
+            .estimate_gas(&request, result.gas_used)
+            .await?;
+        to_value(U64::from(gas_estimate))
+    }
+
+    /// Returns the transaction hash whether or not the transaction reverted, like a node would.
+    async fn eth_send_raw_transaction(
+        &mut self,
+        raw_transaction: Bytes,
+    ) -> Result<Value, RpcError> {
+        let hash = keccak256(&raw_transaction);
+        let transaction = SimulationRequest::try_from(RawSimulationRequest {
+            chain_id: Some(self.fork.chain_id),
+            raw_transaction,
+            block_number: None,
+            format_trace: None,
+            validation: None,
+        })?;
+
+        let response = run(&mut self.fork.evm, transaction.clone(), true).await?;
+        self.history.record(&transaction, &response);
+
+        to_value(H256::from(hash))
+    }
+
+    /// Only geth's default struct logger is supported, with the same options.
+    async fn debug_trace_call(
+        &mut self,
+        request: RpcCallRequest,
+        options: Option<Value>,
+    ) -> Result<Value, RpcError> {
+        let options = options.unwrap_or_else(|| json!({}));
+        if let Some(tracer) = options.get("tracer") {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("tracer {tracer} is not supported"),
+            ));
+        }
+        let struct_log_options: StructLogOptions = serde_json::from_value(options)
+            .map_err(|err| RpcError::new(INVALID_PARAMS, format!("invalid param 2: {err}")))?;
+
+        let result = self
+            .fork
+            .evm
+            .call_raw(
+                &request.into(),
+                CallOptions {
+                    struct_logs: Some(struct_log_options),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        to_value(StructLogTrace {
+            gas: result.gas_used,
+            failed: !result.success,
+            return_value: result.output,
+            struct_logs: result.struct_logs.unwrap_or_default(),
+        })
+    }
+}
+
+/// Handles a single JSON-RPC request or a batch, which runs in order on the fork.
+pub async fn rpc(
+    fork_id: Uuid,
+    request: Value,
+    forks: ForkStore,
+    history: History,
+) -> Result<Json, Rejection> {
+    let fork = forks.get(fork_id).await?;
+    let mut fork = fork.lock().await;
+    let mut node = Node {
+        fork: &mut fork,
+        history: &history,
+    };
+
+    match request {
+        Value::Array(requests) => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(node.handle(request).await);
+            }
+            Ok(warp::reply::json(&responses))
+        }
+        request => Ok(warp::reply::json(&node.handle(request).await)),
+    }
+}
@@ -0,0 +1,228 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::http::{Request, Response};
+use warp::hyper::server::accept::{self, Accept};
+use warp::hyper::server::Builder;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Server};
+
+use crate::jobs;
+use crate::request_id::{with_request_id, REQUEST_ID_HEADER};
+
+use super::config::Config;
+
+/// How long a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections done with their handshake and waiting to be served.
+const TLS_BACKLOG: usize = 1024;
+
+/// Headers browsers may send on top of the CORS safelisted ones if `CORS_HEADERS` is not set.
+const DEFAULT_CORS_HEADERS: &[&str] = &["content-type", "x-api-key", REQUEST_ID_HEADER];
+
+/// CORS for the origins of `CORS_ORIGINS`, `*` allowing any. `None` if not set, requests from
+/// browsers on other origins are then rejected by the browser.
+pub fn cors(config: &Config) -> Option<warp::cors::Builder> {
+    if config.cors_origins.is_empty() {
+        return None;
+    }
+
+    let cors = warp::cors()
+        .allow_methods(["GET", "POST", "DELETE"])
+        .expose_header(REQUEST_ID_HEADER);
+    let cors = if config.cors_headers.is_empty() {
+        cors.allow_headers(DEFAULT_CORS_HEADERS.iter().copied())
+    } else {
+        cors.allow_headers(config.cors_headers.iter().map(String::as_str))
+    };
+    if config.cors_origins.iter().any(|origin| origin == "*") {
+        Some(cors.allow_any_origin())
+    } else {
+        Some(cors.allow_origins(config.cors_origins.iter().map(String::as_str)))
+    }
+}
+
+/// Resolves on SIGTERM, which orchestrators send before killing the process, or Ctrl-C.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("SIGTERM handler must install")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serves `service` on `BIND_ADDRESS` and `PORT`, over TLS if `TLS_CERT` and `TLS_KEY` are set,
+/// giving every request an ID. Once `shutdown` resolves, stops accepting connections and returns
+/// when the in-flight requests and async jobs are done, or after `SHUTDOWN_TIMEOUT`.
+pub async fn serve<S>(
+    service: S,
+    config: &Config,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send,
+{
+    let addr = SocketAddr::new(config.bind_address, config.port);
+    let timeout = config.shutdown_timeout;
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let incoming = tls_incoming(addr, tls_acceptor(cert, key)?).await?;
+            run(Server::builder(incoming), service, shutdown, timeout).await?
+        }
+        _ => run(Server::try_bind(&addr)?, service, shutdown, timeout).await?,
+    }
+    Ok(())
+}
+
+async fn run<I, S>(
+    builder: Builder<I>,
+    service: S,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    timeout: Duration,
+) -> Result<(), warp::hyper::Error>
+where
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn Error + Send + Sync>>,
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send,
+{
+    let make_service = make_service_fn(move |_| {
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                with_request_id(service.clone(), request)
+            }))
+        }
+    });
+    let (shutting_down, on_shutdown) = oneshot::channel();
+    let server = builder
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            log::info!(target: "ts::api", "Shutting down, draining in-flight requests");
+            let _ = shutting_down.send(());
+        });
+
+    let drained = async {
+        server.await?;
+        jobs::drained().await;
+        Ok(())
+    };
+    // The deadline only runs once shutting down, the server stopping on its own is an error
+    let deadline = async {
+        match on_shutdown.await {
+            Ok(()) => tokio::time::sleep(timeout).await,
+            Err(_) => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = drained => result,
+        _ = deadline => {
+            log::warn!(
+                target: "ts::api",
+                "Requests or jobs still running after {}s, shutting down anyway",
+                timeout.as_secs()
+            );
+            Ok(())
+        }
+    }
+}
+
+fn invalid_data(error: impl Into<Box<dyn Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Reads the PEM certificate chain and private key, PKCS#8, RSA or EC.
+fn tls_acceptor(cert_path: &str, key_path: &str) -> io::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid_data("TLS_KEY holds no private key"))?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts TCP connections and completes their TLS handshake each on its own task, so that a
+/// slow client doesn't hold up the others.
+async fn tls_incoming(
+    addr: SocketAddr,
+    acceptor: TlsAcceptor,
+) -> io::Result<impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    let (sender, receiver) = mpsc::channel::<io::Result<TlsStream<TcpStream>>>(TLS_BACKLOG);
+
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    // e.g. too many open files, which only waiting can fix
+                    log::warn!(target: "ts::api", "Failed to accept a connection: {err}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream)).await;
+                    }
+                    Ok(Err(err)) => {
+                        log::debug!(target: "ts::api", "TLS handshake failed: {err}");
+                    }
+                    Err(_elapsed) => {
+                        log::debug!(target: "ts::api", "TLS handshake timed out");
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(accept::from_stream(futures_util::stream::unfold(
+        receiver,
+        |mut receiver| async move { receiver.recv().await.map(|stream| (stream, receiver)) },
+    )))
+}
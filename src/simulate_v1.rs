@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use ethers::abi::{Address, Hash, Uint};
+use ethers::types::transaction::eip2930::AccessList;
+use ethers::types::{Bytes, Log, U64};
+use ethers::utils::keccak256;
+use revm::Return;
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{BundleTooLargeError, SimulationError};
+use crate::evm::Evm;
+use crate::quantity;
+use crate::simulation::{
+    apply_state_overrides, chain_id_to_fork_url, run, BlockOverrides, SimulationRequest,
+    SimulationResponse, StateOverride,
+};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// Address `traceTransfers` logs native transfers from, as specified by `eth_simulateV1`.
+const NATIVE_TRANSFER_ADDRESS: &str = "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+
+/// `keccak256("Transfer(address,address,uint256)")`, the topic of ERC-20 transfers.
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// Error codes of `eth_simulateV1` for calls which failed.
+const EXECUTION_REVERTED: i64 = 3;
+const VM_ERROR: i64 = -32015;
+
+/// The parameters of `eth_simulateV1`, with the chain and the block to build on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulateV1Request {
+    #[serde(rename = "chainId", deserialize_with = "quantity::deserialize_u64")]
+    pub chain_id: u64,
+    /// Block the first simulated block builds on, the latest one if not set.
+    #[serde(
+        rename = "blockNumber",
+        default,
+        deserialize_with = "quantity::deserialize_option_u64"
+    )]
+    pub block_number: Option<u64>,
+    #[serde(rename = "blockStateCalls")]
+    pub block_state_calls: Vec<BlockStateCall>,
+    /// Adds a log for every native transfer, like an ERC-20 `Transfer` from
+    /// `0xeeee...eeee`.
+    #[serde(rename = "traceTransfers", default)]
+    pub trace_transfers: bool,
+    /// Checks nonces, balances and fees like a node would include the calls.
+    #[serde(default)]
+    pub validation: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockStateCall {
+    #[serde(rename = "blockOverrides")]
+    pub block_overrides: Option<SimulatedBlockOverrides>,
+    #[serde(rename = "stateOverrides")]
+    pub state_overrides: Option<HashMap<Address, SimulatedStateOverride>>,
+    #[serde(default)]
+    pub calls: Vec<SimulatedCall>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimulatedBlockOverrides {
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub number: Option<u64>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub time: Option<u64>,
+    #[serde(rename = "feeRecipient")]
+    pub fee_recipient: Option<Address>,
+    #[serde(rename = "prevRandao")]
+    pub prev_randao: Option<Hash>,
+    #[serde(
+        rename = "baseFeePerGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub base_fee_per_gas: Option<Uint>,
+    #[serde(
+        rename = "blobBaseFee",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub blob_base_fee: Option<Uint>,
+}
+
+/// `state` and `stateDiff` both set the given slots, `state` doesn't clear the others.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimulatedStateOverride {
+    #[serde(default, deserialize_with = "quantity::deserialize_option_uint")]
+    pub balance: Option<Uint>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    pub state: Option<HashMap<Hash, Hash>>,
+    #[serde(rename = "stateDiff")]
+    pub state_diff: Option<HashMap<Hash, Hash>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimulatedCall {
+    #[serde(default)]
+    pub from: Address,
+    pub to: Option<Address>,
+    /// Defaults to the block gas limit.
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub gas: Option<u64>,
+    #[serde(
+        rename = "gasPrice",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub gas_price: Option<Uint>,
+    #[serde(
+        rename = "maxFeePerGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub max_fee_per_gas: Option<Uint>,
+    #[serde(
+        rename = "maxPriorityFeePerGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub max_priority_fee_per_gas: Option<Uint>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_string")]
+    pub value: Option<String>,
+    pub data: Option<Bytes>,
+    /// Takes precedence over `data`.
+    pub input: Option<Bytes>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub nonce: Option<u64>,
+    #[serde(rename = "accessList")]
+    pub access_list: Option<AccessList>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimulatedBlock {
+    pub number: U64,
+    /// Derived from the parent hash and the number, simulated blocks aren't sealed.
+    pub hash: Hash,
+    #[serde(rename = "parentHash")]
+    pub parent_hash: Hash,
+    pub timestamp: U64,
+    #[serde(rename = "gasLimit")]
+    pub gas_limit: U64,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: U64,
+    #[serde(rename = "baseFeePerGas")]
+    pub base_fee_per_gas: Uint,
+    pub miner: Address,
+    pub calls: Vec<SimulatedCallResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimulatedCallResult {
+    /// `0x1` on success, `0x0` on failure.
+    pub status: U64,
+    #[serde(rename = "returnData")]
+    pub return_data: Bytes,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: U64,
+    pub logs: Vec<Log>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<SimulatedCallError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimulatedCallError {
+    pub code: i64,
+    pub message: String,
+    /// Revert data of reverted calls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Bytes>,
+}
+
+impl From<SimulatedStateOverride> for StateOverride {
+    fn from(state_override: SimulatedStateOverride) -> Self {
+        let storage = match (state_override.state, state_override.state_diff) {
+            (None, None) => None,
+            (state, state_diff) => Some(
+                state
+                    .into_iter()
+                    .flatten()
+                    .chain(state_diff.into_iter().flatten())
+                    .collect(),
+            ),
+        };
+        StateOverride {
+            balance: state_override.balance,
+            nonce: state_override.nonce,
+            code: state_override.code,
+            storage,
+        }
+    }
+}
+
+impl SimulatedCall {
+    fn into_simulation_request(
+        self,
+        chain_id: u64,
+        gas_limit: u64,
+        validation: bool,
+    ) -> SimulationRequest {
+        SimulationRequest {
+            chain_id,
+            from: self.from,
+            to: self.to,
+            data: self.input.or(self.data),
+            gas_limit: self.gas.unwrap_or(gas_limit),
+            value: self.value,
+            gas_price: self.gas_price,
+            max_fee_per_gas: self.max_fee_per_gas,
+            max_priority_fee_per_gas: self.max_priority_fee_per_gas,
+            access_list: self.access_list,
+            nonce: self.nonce,
+            validation: Some(validation),
+            ..Default::default()
+        }
+    }
+}
+
+/// Native transfers of a call as ERC-20 `Transfer` logs, the top level one first.
+fn transfer_logs(transaction: &SimulationRequest, response: &SimulationResponse) -> Vec<Log> {
+    let value = transaction
+        .value
+        .as_deref()
+        .and_then(|value| quantity::parse_quantity(value).ok())
+        .unwrap_or_default();
+    let to = transaction.to.or(response.created_address);
+    let top_level = to
+        .filter(|_| !value.is_zero())
+        .map(|to| (transaction.from, to, value));
+    let internal = response
+        .internal_transfers
+        .iter()
+        .map(|transfer| (transfer.from, transfer.to, transfer.value));
+
+    top_level
+        .into_iter()
+        .chain(internal)
+        .map(|(from, to, value)| {
+            let mut amount = [0u8; 32];
+            value.to_big_endian(&mut amount);
+            Log {
+                address: NATIVE_TRANSFER_ADDRESS.parse().unwrap(),
+                topics: vec![TRANSFER_TOPIC.parse().unwrap(), from.into(), to.into()],
+                data: amount.to_vec().into(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn call_result(
+    transaction: &SimulationRequest,
+    response: SimulationResponse,
+    trace_transfers: bool,
+) -> SimulatedCallResult {
+    let mut logs = if trace_transfers && response.success {
+        transfer_logs(transaction, &response)
+    } else {
+        vec![]
+    };
+    // Logs of failed calls are discarded
+    if response.success {
+        logs.extend(response.logs);
+    }
+    let error = match (response.success, response.exit_reason) {
+        (true, _) => None,
+        (false, Return::Revert) => Some(SimulatedCallError {
+            code: EXECUTION_REVERTED,
+            message: match response.revert_reason {
+                Some(reason) => format!("execution reverted: {reason}"),
+                None => "execution reverted".to_string(),
+            },
+            data: Some(response.return_data.clone()),
+        }),
+        (false, exit_reason) => Some(SimulatedCallError {
+            code: VM_ERROR,
+            message: format!("{exit_reason:?}"),
+            data: None,
+        }),
+    };
+
+    SimulatedCallResult {
+        status: U64::from(response.success as u64),
+        return_data: response.return_data,
+        gas_used: U64::from(response.gas_used),
+        logs,
+        error,
+    }
+}
+
+/// Moves the fork to the next block, or to the overridden one, and applies the block overrides.
+fn start_block(
+    evm: &mut Evm,
+    number: u64,
+    overrides: Option<SimulatedBlockOverrides>,
+) -> Result<u64, SimulationError> {
+    let overrides = overrides.unwrap_or_default();
+    let next_number = overrides.number.unwrap_or(number + 1);
+    if next_number <= number {
+        return Err(SimulationError::BlockNumberDecreasing);
+    }
+    evm.roll_block(next_number);
+    evm.override_block(&BlockOverrides {
+        timestamp: overrides.time,
+        base_fee: overrides.base_fee_per_gas,
+        coinbase: overrides.fee_recipient,
+        prevrandao: overrides.prev_randao,
+        blob_base_fee: overrides.blob_base_fee,
+        ..Default::default()
+    });
+    Ok(next_number)
+}
+
+/// Simulates blocks of calls one after the other on a single fork, like `eth_simulateV1`. Every
+/// block builds on the state left by the previous ones, calls included.
+pub async fn simulate_v1(
+    request: SimulateV1Request,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    let calls: usize = request
+        .block_state_calls
+        .iter()
+        .map(|block| block.calls.len())
+        .sum();
+    if calls > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+
+    let chain_id = request.chain_id;
+    let fork_url = chain_id_to_fork_url(chain_id, &config)?;
+    let mut evm = pool.get(
+        chain_id,
+        fork_url,
+        request.block_number,
+        config.max_gas_limit,
+        config.etherscan_key,
+    );
+
+    let mut number = evm.block_number();
+    let mut parent_hash = evm.block_hash().unwrap_or_default();
+    let mut blocks = Vec::with_capacity(request.block_state_calls.len());
+    for block in request.block_state_calls {
+        number = start_block(&mut evm, number, block.block_overrides)?;
+        let hash = Hash::from(keccak256(
+            [parent_hash.as_bytes(), &number.to_be_bytes()].concat(),
+        ));
+        if let Some(state_overrides) = block.state_overrides {
+            let state_overrides = state_overrides
+                .into_iter()
+                .map(|(address, state_override)| (address, state_override.into()))
+                .collect();
+            apply_state_overrides(&mut evm, state_overrides)?;
+        }
+
+        let gas_limit = evm
+            .block_gas_limit()
+            .min(config.max_gas_limit.into())
+            .as_u64();
+        let mut gas_used = 0;
+        let mut log_index = 0u64;
+        let mut results = Vec::with_capacity(block.calls.len());
+        for (index, call) in block.calls.into_iter().enumerate() {
+            let transaction = call.into_simulation_request(chain_id, gas_limit, request.validation);
+            let response = run(&mut evm, transaction.clone(), true).await?;
+            history.record(&transaction, &response);
+            gas_used += response.gas_used;
+
+            // Simulated transactions aren't signed, their hash is derived from their position
+            let transaction_hash = Hash::from(keccak256(
+                [hash.as_bytes(), &(index as u64).to_be_bytes()].concat(),
+            ));
+            let mut result = call_result(&transaction, response, request.trace_transfers);
+            for log in &mut result.logs {
+                log.block_hash = Some(hash);
+                log.block_number = Some(number.into());
+                log.transaction_hash = Some(transaction_hash);
+                log.transaction_index = Some((index as u64).into());
+                log.log_index = Some(log_index.into());
+                log.removed = Some(false);
+                log_index += 1;
+            }
+            results.push(result);
+        }
+
+        blocks.push(SimulatedBlock {
+            number: number.into(),
+            hash,
+            parent_hash,
+            timestamp: evm.timestamp().into(),
+            gas_limit: evm.block_gas_limit().as_u64().into(),
+            gas_used: gas_used.into(),
+            base_fee_per_gas: evm.base_fee(),
+            miner: evm.coinbase(),
+            calls: results,
+        });
+        parent_hash = hash;
+    }
+
+    Ok(warp::reply::json(&blocks))
+}
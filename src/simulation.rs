@@ -1,53 +1,583 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use ethers::abi::{Address, Uint};
+use ethers::abi::{Abi, Address, Event, Hash, Uint};
+use ethers::types::transaction::eip2930::AccessList;
 use ethers::types::{Bytes, Log};
 use foundry_evm::CallKind;
 use revm::Return;
 use serde::{Deserialize, Serialize};
-use warp::reject::custom;
+use uuid::Uuid;
 use warp::reply::Json;
 use warp::Rejection;
 
-use crate::errors::{
-    FromDecStrError, FromHexError, MultipleBlockNumbersError, MultipleChainIdsError,
-    NoURLForChainIdError,
+use crate::access_stats::AccessStats;
+use crate::approvals::{assume_approvals, AssumedApproval};
+use crate::assertions::Assertion;
+use crate::assets::{
+    asset_changes, internal_transfers, resolve_token_info, AssetChange, InternalTransfer,
 };
+use crate::authorization::{
+    apply_authorizations, Authorization, Delegation, DELEGATION_DESIGNATOR,
+};
+use crate::blob::blob_gas_used;
+use crate::block_env::{block_environment, BlockEnvironment};
+use crate::console::console_logs;
+use crate::contracts::{created_contracts, CreatedContract};
+use crate::errors::SimulationError;
+use crate::funds_flow::{funds_flow, FundsFlow};
+use crate::gas_profile::{gas_profile, GasProfile};
+use crate::hardfork::Hardfork;
+use crate::l1_fee::l1_fee;
+use crate::mempool::{apply_pending_transactions, MempoolOptions, PendingTransaction};
+use crate::policy::{PolicyAction, PolicyContext, PolicyDecision};
+use crate::prices::{price_asset_changes, NetValueChange};
+use crate::proxies::{resolve_proxies, ProxyInfo};
+use crate::quantity::{self, QuantityFormat};
+use crate::source_trace::SourceFrame;
+use crate::trace_format::{call_tracer, parity_traces, CallTracerFrame, ParityTrace, TraceFormat};
+use crate::trim::{kept_frames, trim_trace, LogFilter, Trimmed};
+use crate::warnings::{warnings, Warning};
 
 use super::config::Config;
-use super::evm::Evm;
+use super::evm::{CallOptions, CallRawRequest, Evm};
+use super::history::History;
+use super::metrics::record_simulation;
+use super::pool::EvmPool;
+use super::simulation_cache::SimulationCache;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SimulationRequest {
-    #[serde(rename = "chainId")]
+    /// Version of the request schema, validated strictly if set, see `SUPPORTED_API_VERSIONS`.
+    #[serde(rename = "apiVersion")]
+    pub api_version: Option<u64>,
+    #[serde(rename = "chainId", deserialize_with = "quantity::deserialize_u64")]
     pub chain_id: u64,
     pub from: Address,
-    pub to: Address,
+    /// Left out to deploy `data` as init code.
+    pub to: Option<Address>,
     pub data: Option<Bytes>,
-    #[serde(rename = "gasLimit")]
+    #[serde(rename = "gasLimit", deserialize_with = "quantity::deserialize_u64")]
     pub gas_limit: u64,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_string")]
     pub value: Option<String>,
-    #[serde(rename = "blockNumber")]
+    /// Legacy gas price. Without any of the fee fields, no gas is charged.
+    #[serde(
+        rename = "gasPrice",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub gas_price: Option<Uint>,
+    #[serde(
+        rename = "maxFeePerGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub max_fee_per_gas: Option<Uint>,
+    #[serde(
+        rename = "maxPriorityFeePerGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub max_priority_fee_per_gas: Option<Uint>,
+    #[serde(rename = "accessList")]
+    pub access_list: Option<AccessList>,
+    /// Without it, the blob gas of an EIP-4844 transaction isn't charged.
+    #[serde(
+        rename = "maxFeePerBlobGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub max_fee_per_blob_gas: Option<Uint>,
+    /// Makes this an EIP-4844 transaction carrying one blob per hash.
+    #[serde(rename = "blobVersionedHashes")]
+    pub blob_versioned_hashes: Option<Vec<Hash>>,
+    /// Makes this an EIP-7702 transaction delegating the code of the authorities.
+    #[serde(rename = "authorizationList")]
+    pub authorization_list: Option<Vec<Authorization>>,
+    #[serde(
+        rename = "blockNumber",
+        default,
+        deserialize_with = "quantity::deserialize_option_u64"
+    )]
     pub block_number: Option<u64>,
+    /// Forks this block rather than `blockNumber`, even if it was reorged out.
+    #[serde(rename = "blockHash", default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<Hash>,
     #[serde(rename = "formatTrace")]
     pub format_trace: Option<bool>,
+    #[serde(rename = "nestTrace")]
+    pub nest_trace: Option<bool>,
+    #[serde(rename = "decodeLogs")]
+    pub decode_logs: Option<bool>,
+    /// Decodes the function and arguments of every call frame.
+    #[serde(rename = "decodeCalls")]
+    pub decode_calls: Option<bool>,
+    /// Looks selectors without a verified ABI up on 4byte.directory when decoding calls.
+    #[serde(rename = "fourByteLookup")]
+    pub four_byte_lookup: Option<bool>,
+    #[serde(rename = "stateDiff")]
+    pub state_diff: Option<bool>,
+    /// Lists the contracts which self-destructed or got new code in `codeChanges`.
+    #[serde(rename = "codeChanges")]
+    pub code_changes: Option<bool>,
+    /// Lists the `SLOAD`s and `SSTORE`s of every call frame in `storageAccesses`.
+    #[serde(rename = "storageAccesses")]
+    pub storage_accesses: Option<bool>,
+    /// Counts the cold and warm account and storage accesses in `accessStats`.
+    #[serde(rename = "accessStats")]
+    pub access_stats: Option<bool>,
+    /// Locates the frames of a revert in the verified source of their contracts in
+    /// `sourceStackTrace`.
+    #[serde(rename = "sourceStackTrace")]
+    pub source_stack_trace: Option<bool>,
+    /// Breaks `gasUsed` down by contract and function in `gasProfile`.
+    #[serde(rename = "gasProfile")]
+    pub gas_profile: Option<bool>,
+    #[serde(rename = "traceMode")]
+    pub trace_mode: Option<TraceMode>,
+    /// Also returns the call frames in the format of geth's `callTracer` or Parity's traces.
+    #[serde(rename = "traceFormat")]
+    pub trace_format: Option<TraceFormat>,
+    #[serde(rename = "structLogOptions")]
+    pub struct_log_options: Option<StructLogOptions>,
+    /// Applied just before the transaction executes, on top of the state left by the previous
+    /// transactions of a bundle or fork.
+    #[serde(rename = "stateOverrides")]
+    pub state_overrides: Option<HashMap<Address, StateOverride>>,
+    #[serde(rename = "blockOverrides")]
+    pub block_overrides: Option<BlockOverrides>,
+    /// Rules the transaction is executed with, the newest the EVM implements if not set.
+    pub hardfork: Option<Hardfork>,
+    /// Flags risky patterns like unlimited approvals in `warnings`.
+    pub warnings: Option<bool>,
+    /// Values asset changes in USD and sums them per address in `netValueChanges`.
+    pub prices: Option<bool>,
+    /// Sums the assets moved between each pair of addresses in `fundsFlow`, a graph to render.
+    #[serde(rename = "fundsFlow")]
+    pub funds_flow: Option<bool>,
+    /// Sets the ERC-20 allowances `transferFrom`s fail without, reported in `assumedApprovals`.
+    #[serde(rename = "autoApprove")]
+    pub auto_approve: Option<bool>,
+    /// Pending transactions to execute first, reported in `pendingTransactions`.
+    pub mempool: Option<MempoolOptions>,
+    /// Annotates the frames calling proxies with their implementation in `proxy`.
+    #[serde(rename = "resolveProxies")]
+    pub resolve_proxies: Option<bool>,
+    /// Leaves the frames deeper than this out of the returned traces, 0 keeping the top level
+    /// call only.
+    #[serde(rename = "maxTraceDepth")]
+    pub max_trace_depth: Option<usize>,
+    /// Keeps only the first frames of the returned traces, in execution order.
+    #[serde(rename = "maxTraceFrames")]
+    pub max_trace_frames: Option<usize>,
+    /// Keeps only the returned logs matching the filter.
+    #[serde(rename = "logFilter")]
+    pub log_filter: Option<LogFilter>,
+    /// Checked against the sender's nonce with `validation`, not checked if not set.
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub nonce: Option<u64>,
+    /// Rejects transactions which would not be included on chain, instead of simulating them as
+    /// if the sender could pay for anything.
+    pub validation: Option<bool>,
+    /// Lets `validation` accept senders with code, like smart wallets, which EIP-3607 forbids on
+    /// chain.
+    #[serde(rename = "allowContractSender")]
+    pub allow_contract_sender: Option<bool>,
+    /// How quantities are serialized in the response.
+    #[serde(rename = "quantityFormat")]
+    pub quantity_format: Option<QuantityFormat>,
+    /// Where the result is POSTed once an async or fork simulation finished.
+    #[serde(rename = "callbackUrl")]
+    pub callback_url: Option<String>,
+    /// Checked once the transaction executed, only in bundle objects.
+    pub assertions: Option<Vec<Assertion>>,
+    /// JSON ABI whose events decode the logs, before the registered ones and Etherscan's.
+    pub abi: Option<Abi>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceMode {
+    /// One entry per call frame.
+    #[default]
+    Call,
+    /// One entry per executed opcode, in `structLogs`.
+    Opcode,
+}
+
+/// Same options as geth's struct logger, the stack and storage are included unless disabled.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StructLogOptions {
+    #[serde(rename = "enableMemory")]
+    pub enable_memory: Option<bool>,
+    #[serde(rename = "disableStack")]
+    pub disable_stack: Option<bool>,
+    #[serde(rename = "disableStorage")]
+    pub disable_storage: Option<bool>,
+    /// Maximum number of opcodes returned, capped at `MAX_STRUCT_LOGS`.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateOverride {
+    #[serde(default, deserialize_with = "quantity::deserialize_option_uint")]
+    pub balance: Option<Uint>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    pub storage: Option<HashMap<Hash, Hash>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockOverrides {
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub number: Option<u64>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub timestamp: Option<u64>,
+    #[serde(
+        rename = "baseFee",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub base_fee: Option<Uint>,
+    pub coinbase: Option<Address>,
+    pub prevrandao: Option<Hash>,
+    /// Sets the blob base fee through the EIP-4844 formula, forks start with 0.
+    #[serde(
+        rename = "excessBlobGas",
+        default,
+        deserialize_with = "quantity::deserialize_option_u64"
+    )]
+    pub excess_blob_gas: Option<u64>,
+    /// Takes precedence over `excessBlobGas`.
+    #[serde(
+        rename = "blobBaseFee",
+        default,
+        deserialize_with = "quantity::deserialize_option_uint"
+    )]
+    pub blob_base_fee: Option<Uint>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SimulationResponse {
     #[serde(rename = "simulationId")]
-    pub simulation_id: u64,
+    pub simulation_id: Uuid,
+    /// Gas charged, net of `gas_refunded`.
     #[serde(rename = "gasUsed")]
     pub gas_used: u64,
+    /// Refunded for clearing storage slots, capped at a fifth of the gas used since London.
+    #[serde(rename = "gasRefunded", default)]
+    pub gas_refunded: u64,
+    /// `gasUsed + gasRefunded`, the gas needed for the transaction to execute.
+    #[serde(rename = "gasUsedBeforeRefund", default)]
+    pub gas_used_before_refund: u64,
     #[serde(rename = "blockNumber")]
     pub block_number: u64,
+    /// Hash of the forked block, whose state the transaction was executed against. Not set if it
+    /// could not be fetched.
+    #[serde(rename = "blockHash", default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<Hash>,
+    /// Timestamp of the block the transaction was executed in, with `blockOverrides`.
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(rename = "blockEnv", default, skip_serializing_if = "Option::is_none")]
+    pub block_env: Option<BlockEnvironment>,
     pub success: bool,
     pub trace: Vec<CallTrace>,
     #[serde(rename = "formattedTrace")]
     pub formatted_trace: Option<String>,
+    #[serde(
+        rename = "nestedTrace",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub nested_trace: Option<CallTraceTree>,
+    /// Only with `traceFormat` set to `callTracer`.
+    #[serde(
+        rename = "callTracer",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub call_tracer: Option<CallTracerFrame>,
+    /// Only with `traceFormat` set to `parity`.
+    #[serde(
+        rename = "parityTrace",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub parity_trace: Option<Vec<ParityTrace>>,
     pub logs: Vec<Log>,
+    #[serde(
+        rename = "decodedLogs",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub decoded_logs: Option<Vec<DecodedLog>>,
+    #[serde(rename = "assetChanges", default)]
+    pub asset_changes: Vec<AssetChange>,
+    /// Only set with `prices`, ordered by address.
+    #[serde(
+        rename = "netValueChanges",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub net_value_changes: Option<Vec<NetValueChange>>,
+    /// Only with `fundsFlow`.
+    #[serde(rename = "fundsFlow", default, skip_serializing_if = "Option::is_none")]
+    pub funds_flow: Option<FundsFlow>,
+    #[serde(rename = "internalTransfers", default)]
+    pub internal_transfers: Vec<InternalTransfer>,
+    #[serde(rename = "createdContracts", default)]
+    pub created_contracts: Vec<CreatedContract>,
+    /// Messages printed with `console.log` of Hardhat and Foundry.
+    #[serde(rename = "consoleLogs", default)]
+    pub console_logs: Vec<String>,
+    /// Whether the sender had code, other than an EIP-7702 delegation, before the transaction.
+    #[serde(rename = "senderIsContract", default)]
+    pub sender_is_contract: bool,
     #[serde(rename = "exitReason")]
     pub exit_reason: Return,
+    #[serde(rename = "returnData", default)]
+    pub return_data: Bytes,
+    #[serde(rename = "effectiveGasPrice", default)]
+    pub effective_gas_price: Uint,
+    /// `gasUsed * effectiveGasPrice`, charged to the sender.
+    #[serde(rename = "feePaid", default)]
+    pub fee_paid: Uint,
+    /// Only set for blob transactions.
+    #[serde(
+        rename = "blobGasUsed",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub blob_gas_used: Option<u64>,
+    #[serde(
+        rename = "blobGasPrice",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub blob_gas_price: Option<Uint>,
+    /// `blobGasUsed * blobGasPrice`, charged to the sender on top of `feePaid` if
+    /// `maxFeePerBlobGas` is set.
+    #[serde(rename = "blobFee", default, skip_serializing_if = "Option::is_none")]
+    pub blob_fee: Option<Uint>,
+    /// Fee for posting the transaction's data to L1, only set on Optimism and Arbitrum. Not
+    /// charged to the sender.
+    #[serde(rename = "l1Fee", default, skip_serializing_if = "Option::is_none")]
+    pub l1_fee: Option<Uint>,
+    /// `feePaid`, the execution fee, only set with `l1Fee`.
+    #[serde(rename = "l2Fee", default, skip_serializing_if = "Option::is_none")]
+    pub l2_fee: Option<Uint>,
+    /// `l1Fee + l2Fee`, what the transaction costs on the rollup.
+    #[serde(rename = "totalFee", default, skip_serializing_if = "Option::is_none")]
+    pub total_fee: Option<Uint>,
+    /// Only set for transactions with an `authorizationList`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegations: Option<Vec<Delegation>>,
+    #[serde(
+        rename = "decodedReturnData",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub decoded_return_data: Option<Vec<String>>,
+    #[serde(
+        rename = "revertReason",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub revert_reason: Option<String>,
+    #[serde(
+        rename = "rawRevertData",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub raw_revert_data: Option<Bytes>,
+    #[serde(
+        rename = "createdAddress",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_address: Option<Address>,
+    #[serde(
+        rename = "deployedCodeSize",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub deployed_code_size: Option<usize>,
+    #[serde(rename = "stateDiff", default, skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<Vec<AccountDiff>>,
+    #[serde(
+        rename = "codeChanges",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub code_changes: Option<Vec<CodeChange>>,
+    #[serde(
+        rename = "gasProfile",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gas_profile: Option<GasProfile>,
+    /// Only the frames which accessed storage, in the order they were entered.
+    #[serde(
+        rename = "storageAccesses",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub storage_accesses: Option<Vec<FrameStorageAccesses>>,
+    #[serde(
+        rename = "accessStats",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub access_stats: Option<AccessStats>,
+    #[serde(
+        rename = "structLogs",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub struct_logs: Option<Vec<StructLog>>,
+    /// Frames the revert went through, innermost first, only if the transaction reverted.
+    #[serde(
+        rename = "sourceStackTrace",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub source_stack_trace: Option<Vec<SourceFrame>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<Vec<Warning>>,
+    /// Only with `autoApprove`.
+    #[serde(
+        rename = "assumedApprovals",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub assumed_approvals: Option<Vec<AssumedApproval>>,
+    /// Only with `mempool`.
+    #[serde(
+        rename = "pendingTransactions",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pending_transactions: Option<Vec<PendingTransaction>>,
+    /// Only with `maxTraceDepth`, `maxTraceFrames` or `logFilter`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trimmed: Option<Trimmed>,
+    /// Flags raised by the policies of the server, only set if it has any.
+    #[serde(
+        rename = "policyDecisions",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub policy_decisions: Option<Vec<PolicyDecision>>,
+    /// Whether the response was served from the simulation cache, see `SIMULATION_CACHE_TTL`.
+    #[serde(default)]
+    pub cached: bool,
+}
+
+/// An executed opcode, like in geth's `debug_traceCall`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StructLog {
+    pub pc: usize,
+    pub op: String,
+    /// Gas used by the call frame before the opcode.
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    /// Not known for the last opcode of a call frame.
+    #[serde(rename = "gasCost", default, skip_serializing_if = "Option::is_none")]
+    pub gas_cost: Option<u64>,
+    /// Starts at 1 for the top level call.
+    pub depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<Uint>>,
+    /// Memory in 32 byte words.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    /// Storage of the executing contract accessed so far, only set for `SLOAD` and `SSTORE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<Hash, Hash>>,
+}
+
+/// Storage reads and writes of a call frame, in the order they were made.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FrameStorageAccesses {
+    /// Account whose storage was accessed, the caller's for delegatecalls.
+    pub address: Address,
+    /// Contract whose code ran.
+    #[serde(rename = "codeAddress")]
+    pub code_address: Address,
+    /// Starts at 0 for the top level call.
+    pub depth: usize,
+    pub accesses: Vec<StorageAccess>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StorageAccess {
+    pub slot: Hash,
+    /// Value before the access, the same as `newValue` for reads.
+    #[serde(rename = "previousValue")]
+    pub previous_value: Hash,
+    #[serde(rename = "newValue")]
+    pub new_value: Hash,
+    #[serde(rename = "isWrite")]
+    pub is_write: bool,
+}
+
+/// Everything a transaction changed on one account. Only the fields which changed are set.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccountDiff {
+    pub address: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<ValueDiff<Uint>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<ValueDiff<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ValueDiff<Bytes>>,
+    #[serde(default)]
+    pub storage: BTreeMap<Hash, ValueDiff<Hash>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CodeChangeKind {
+    SelfDestruct,
+    /// Code was deployed where a contract self-destructed earlier on the same `Evm`, e.g. by a
+    /// previous transaction of the bundle, like metamorphic contracts are redeployed with CREATE2.
+    Redeployed,
+    /// The code changed without the contract self-destructing.
+    Replaced,
+    /// An EIP-7702 authorization delegated the account to `delegate`, the zero address clearing
+    /// its delegation.
+    Delegated,
+}
+
+/// A contract which lost or changed its code. Plain deployments aren't code changes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CodeChange {
+    pub address: Address,
+    pub kind: CodeChangeKind,
+    /// Not set if the account had no code.
+    #[serde(
+        rename = "previousCodeHash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub previous_code_hash: Option<Hash>,
+    /// Not set if the account has no code left.
+    #[serde(rename = "codeHash", default, skip_serializing_if = "Option::is_none")]
+    pub code_hash: Option<Hash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegate: Option<Address>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValueDiff<T> {
+    pub pre: T,
+    pub post: T,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -57,143 +587,618 @@ pub struct CallTrace {
     pub from: Address,
     pub to: Address,
     pub value: Uint,
+    #[serde(
+        rename = "decodedCall",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub decoded_call: Option<DecodedCall>,
+    /// Only with `resolveProxies`, if `to` is a proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyInfo>,
+}
+
+/// Function called by a call frame, with its arguments.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DecodedCall {
+    pub name: String,
+    /// e.g. `transfer(address,uint256)`.
+    pub signature: String,
+    pub params: Vec<DecodedCallParam>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DecodedCallParam {
+    /// Empty if the ABI doesn't name it.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DecodedLog {
+    /// Event name, not set if no ABI could be found for the log.
+    pub name: Option<String>,
+    pub params: Vec<DecodedLogParam>,
+    pub raw: Log,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DecodedLogParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+    pub indexed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CallTraceTree {
+    #[serde(rename = "callType")]
+    pub call_type: CallKind,
+    pub from: Address,
+    pub to: Address,
+    pub value: Uint,
+    pub input: Bytes,
+    pub output: Bytes,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    pub depth: usize,
+    pub success: bool,
+    #[serde(rename = "exitReason")]
+    pub exit_reason: Return,
+    #[serde(rename = "createdAddress")]
+    pub created_address: Option<Address>,
+    #[serde(
+        rename = "decodedCall",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub decoded_call: Option<DecodedCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyInfo>,
+    pub calls: Vec<CallTraceTree>,
+}
+
+pub(crate) fn chain_id_to_fork_url(
+    chain_id: u64,
+    config: &Config,
+) -> Result<String, SimulationError> {
+    if let Some(policy) = &config.api_key_policy {
+        if !policy.allows_chain(chain_id) {
+            return Err(SimulationError::ChainNotAllowed(chain_id));
+        }
+    }
+    config
+        .chains
+        .get(&chain_id)
+        .and_then(|urls| urls.first())
+        .cloned()
+        .or_else(|| {
+            config
+                .registry
+                .fork_url(config.api_key.as_deref(), chain_id)
+        })
+        .ok_or(SimulationError::ChainIdNotSupported(chain_id))
+}
+
+/// Accepts value in hex or decimal formats
+fn parse_value(value: Option<String>) -> Result<Option<Uint>, SimulationError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    if value.starts_with("0x") {
+        Ok(Some(
+            Uint::from_str(value.as_str()).map_err(|_err| SimulationError::FromHex)?,
+        ))
+    } else {
+        Ok(Some(
+            Uint::from_dec_str(value.as_str()).map_err(|_err| SimulationError::FromDecStr)?,
+        ))
+    }
+}
+
+pub(crate) fn call_raw_request(
+    transaction: &SimulationRequest,
+) -> Result<CallRawRequest, SimulationError> {
+    Ok(CallRawRequest {
+        from: transaction.from,
+        to: transaction.to,
+        value: parse_value(transaction.value.clone())?,
+        data: transaction.data.clone(),
+        gas_limit: transaction.gas_limit,
+        gas_price: transaction.gas_price,
+        max_fee_per_gas: transaction.max_fee_per_gas,
+        max_priority_fee_per_gas: transaction.max_priority_fee_per_gas,
+        access_list: transaction.access_list.clone(),
+    })
+}
+
+pub(crate) fn apply_state_overrides(
+    evm: &mut Evm,
+    state_overrides: HashMap<Address, StateOverride>,
+) -> Result<(), SimulationError> {
+    for (address, state_override) in state_overrides {
+        if let Some(balance) = state_override.balance {
+            evm.set_balance(address, balance)?;
+        }
+        if let Some(nonce) = state_override.nonce {
+            evm.set_nonce(address, nonce)?;
+        }
+        if let Some(code) = state_override.code {
+            evm.set_code(address, code)?;
+        }
+        for (slot, value) in state_override.storage.unwrap_or_default() {
+            evm.set_storage(
+                address,
+                Uint::from_big_endian(slot.as_bytes()),
+                Uint::from_big_endian(value.as_bytes()),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+const BLOB_FEE_OVERFLOW: &str = "blob fee overflows";
+
+/// Whether the account has code other than an EIP-7702 delegation designator, the senders
+/// EIP-3607 rejects on chain. Accounts delegated earlier in the simulation have the code of their
+/// delegate instead of a designator, and aren't contracts either.
+fn is_contract(evm: &Evm, address: Address) -> Result<bool, SimulationError> {
+    if evm.delegate(address).is_some() {
+        return Ok(false);
+    }
+    let code = evm.account_code(address)?;
+    Ok(!code.is_empty() && !code.starts_with(&DELEGATION_DESIGNATOR))
 }
 
-fn chain_id_to_fork_url(chain_id: u64, alchemy_key: String) -> Result<String, Rejection> {
-    match chain_id {
-        // ethereum
-        1 => Ok(format!(
-            "https://eth-mainnet.g.alchemy.com/v2/{alchemy_key}"
-        )),
-        5 => Ok(format!("https://eth-goerli.g.alchemy.com/v2/{alchemy_key}")),
-        // polygon
-        137 => Ok(format!(
-            "https://polygon-mainnet.g.alchemy.com/v2/{alchemy_key}"
-        )),
-        80001 => Ok(format!(
-            "https://polygon-mumbai.g.alchemy.com/v2/{alchemy_key}"
-        )),
-        // avalanche
-        43114 => Ok("https://api.avax.network/ext/bc/C/rpc".to_string()),
-        43113 => Ok("https://api.avax-test.network/ext/bc/C/rpc".to_string()),
-        // fantom
-        250 => Ok("https://rpcapi.fantom.network/".to_string()),
-        4002 => Ok("https://rpc.testnet.fantom.network/".to_string()),
-        // xdai
-        100 => Ok("https://rpc.xdaichain.com/".to_string()),
-        // bsc
-        56 => Ok("https://bsc-dataseed.binance.org/".to_string()),
-        97 => Ok("https://data-seed-prebsc-1-s1.binance.org:8545/".to_string()),
-        // arbitrum
-        42161 => Ok("https://arb1.arbitrum.io/rpc".to_string()),
-        421613 => Ok("https://goerli-rollup.arbitrum.io/rpc".to_string()),
-        // optimism
-        10 => Ok("https://mainnet.optimism.io/".to_string()),
-        420 => Ok("https://goerli.optimism.io/".to_string()),
-        _ => Err(NoURLForChainIdError.into()),
-    }
-}
-
-async fn run(
+/// Checks the transaction could be included on chain as is: its gas limit fits in the block, its
+/// nonce, if set, is the sender's and the sender can pay for its value, gas and `max_blob_fee`.
+fn validate(
+    evm: &Evm,
+    request: &CallRawRequest,
+    nonce: Option<u64>,
+    max_blob_fee: Uint,
+) -> Result<(), SimulationError> {
+    let block_gas_limit = evm.block_gas_limit();
+    if Uint::from(request.gas_limit) > block_gas_limit {
+        return Err(SimulationError::GasLimitExceeded {
+            gas_limit: request.gas_limit,
+            block_gas_limit,
+        });
+    }
+
+    let sender = evm.basic(request.from)?;
+    if let Some(nonce) = nonce {
+        let expected = sender.nonce;
+        if nonce < expected {
+            return Err(SimulationError::NonceTooLow { nonce, expected });
+        }
+        if nonce > expected {
+            return Err(SimulationError::NonceTooHigh { nonce, expected });
+        }
+    }
+
+    let gas_price = request
+        .max_fee_per_gas
+        .or(request.gas_price)
+        .unwrap_or_default();
+    let cost = gas_price
+        .saturating_mul(request.gas_limit.into())
+        .saturating_add(request.value.unwrap_or_default())
+        .saturating_add(max_blob_fee);
+    if cost > sender.balance {
+        return Err(SimulationError::InsufficientFunds {
+            balance: sender.balance,
+            cost,
+        });
+    }
+
+    Ok(())
+}
+
+/// Buys the blob gas up front, like the gas, so that the transaction sees the sender's balance
+/// without it.
+fn charge_blob_fee(evm: &mut Evm, from: Address, blob_fee: Uint) -> Result<(), SimulationError> {
+    let balance = evm.basic(from)?.balance;
+    if blob_fee > balance {
+        return Err(SimulationError::InsufficientFunds {
+            balance,
+            cost: blob_fee,
+        });
+    }
+    evm.set_balance(from, balance - blob_fee)?;
+
+    Ok(())
+}
+
+/// Rejects gas limits above the maximum of the `Evm`, which bounds how long its execution takes.
+pub(crate) fn check_gas_limit(evm: &Evm, gas_limit: u64) -> Result<(), SimulationError> {
+    match evm.max_gas_limit() {
+        Some(max_gas_limit) if gas_limit > max_gas_limit => Err(SimulationError::GasLimitTooHigh {
+            gas_limit,
+            max_gas_limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Fails `future` if it takes longer than `timeout`. The deadline is checked whenever the future
+/// yields, a running EVM execution can't be interrupted and is bounded by `MAX_GAS_LIMIT`
+/// instead, the `504` is then answered once it has returned.
+pub(crate) async fn with_timeout<T, E: From<SimulationError>>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_elapsed| SimulationError::Timeout(timeout))?,
+        None => future.await,
+    }
+}
+
+#[tracing::instrument(
+    name = "simulation",
+    target = "ts::simulation",
+    skip_all,
+    fields(
+        chain_id = transaction.chain_id,
+        block_number = evm.block_number(),
+        from = ?transaction.from,
+        to = ?transaction.to,
+        gas_limit = transaction.gas_limit,
+        commit,
+    )
+)]
+pub(crate) async fn run(
     evm: &mut Evm,
     transaction: SimulationRequest,
     commit: bool,
-) -> Result<SimulationResponse, Rejection> {
-    // Accept value in hex or decimal formats
-    let value = if let Some(value) = transaction.value {
-        if value.starts_with("0x") {
-            Some(Uint::from_str(value.as_str()).map_err(|_err| custom(FromHexError))?)
-        } else {
-            Some(Uint::from_dec_str(value.as_str()).map_err(|_err| custom(FromDecStrError))?)
+) -> Result<SimulationResponse, SimulationError> {
+    let timeout = evm.timeout();
+    with_timeout(timeout, async {
+        // Pending transactions are committed, which must not outlive a simulation that isn't
+        let snapshot = (transaction.mempool.is_some() && !commit).then(|| evm.snapshot());
+        let response = async {
+            let pending_transactions = match &transaction.mempool {
+                Some(mempool) => {
+                    Some(apply_pending_transactions(evm, &transaction, mempool).await?)
+                }
+                None => None,
+            };
+            let assumed_approvals = if transaction.auto_approve.unwrap_or_default() {
+                Some(assume_approvals(evm, &transaction).await?)
+            } else {
+                None
+            };
+            let mut response = run_transaction(evm, transaction, commit).await?;
+            response.assumed_approvals = assumed_approvals;
+            response.pending_transactions = pending_transactions;
+            Ok::<_, SimulationError>(response)
         }
-    } else {
-        None
+        .await;
+        if let Some(snapshot) = snapshot {
+            evm.revert(snapshot);
+        }
+        response
+    })
+    .await
+}
+
+async fn run_transaction(
+    evm: &mut Evm,
+    transaction: SimulationRequest,
+    commit: bool,
+) -> Result<SimulationResponse, SimulationError> {
+    let request = call_raw_request(&transaction)?;
+    let blob_gas_used = blob_gas_used(&transaction)?;
+    if let Some(abi) = &transaction.abi {
+        evm.add_events(abi.events().cloned());
+    }
+
+    if let Some(state_overrides) = transaction.state_overrides.clone() {
+        apply_state_overrides(evm, state_overrides)?;
+    }
+    if let Some(block_overrides) = &transaction.block_overrides {
+        evm.override_block(block_overrides);
+    }
+    if let Some(hardfork) = transaction.hardfork {
+        evm.set_spec_id(hardfork.spec_id());
+    }
+    let blob_gas_price = blob_gas_used.map(|_| evm.blob_base_fee());
+    let max_fee_per_blob_gas = transaction
+        .max_fee_per_blob_gas
+        .filter(|_| blob_gas_used.is_some());
+    let sender_is_contract = is_contract(evm, request.from)?;
+    if transaction.validation.unwrap_or_default() {
+        if sender_is_contract && !transaction.allow_contract_sender.unwrap_or_default() {
+            return Err(SimulationError::SenderNotEoa(request.from));
+        }
+        let max_blob_fee = match max_fee_per_blob_gas.zip(blob_gas_used) {
+            Some((max_fee, gas)) => max_fee
+                .checked_mul(gas.into())
+                .ok_or(SimulationError::InvalidBlobTransaction(BLOB_FEE_OVERFLOW))?,
+            None => Uint::zero(),
+        };
+        validate(evm, &request, transaction.nonce, max_blob_fee)?;
+    }
+    check_gas_limit(evm, request.gas_limit)?;
+    let blob_fee = match (max_fee_per_blob_gas, blob_gas_used, blob_gas_price) {
+        (Some(max_fee_per_blob_gas), Some(gas), Some(blob_base_fee)) => {
+            if max_fee_per_blob_gas < blob_base_fee {
+                return Err(SimulationError::BlobFeeTooLow {
+                    max_fee_per_blob_gas,
+                    blob_base_fee,
+                });
+            }
+            let blob_fee = blob_base_fee
+                .checked_mul(gas.into())
+                .ok_or(SimulationError::InvalidBlobTransaction(BLOB_FEE_OVERFLOW))?;
+            charge_blob_fee(evm, request.from, blob_fee)?;
+            Some(blob_fee)
+        }
+        (None, Some(_), _) => Some(Uint::zero()),
+        _ => None,
     };
+    // Before the authorizations, which may bump the sender's nonce
+    let l1_fee = l1_fee(evm, &request, transaction.nonce).await;
+    let delegations = apply_authorizations(evm, &transaction)?;
 
+    let options = CallOptions {
+        format_trace: transaction.format_trace.unwrap_or_default(),
+        decode_logs: transaction.decode_logs.unwrap_or_default(),
+        decode_calls: transaction.decode_calls.unwrap_or_default(),
+        four_byte_lookup: transaction.four_byte_lookup.unwrap_or_default(),
+        state_diff: transaction.state_diff.unwrap_or_default(),
+        code_changes: transaction.code_changes.unwrap_or_default(),
+        storage_accesses: transaction.storage_accesses.unwrap_or_default(),
+        access_stats: transaction.access_stats.unwrap_or_default(),
+        access_list: false,
+        identify_contracts: transaction.warnings.unwrap_or_default()
+            || transaction.funds_flow.unwrap_or_default(),
+        struct_logs: (transaction.trace_mode == Some(TraceMode::Opcode))
+            .then(|| transaction.struct_log_options.unwrap_or_default()),
+        source_stack_trace: transaction.source_stack_trace.unwrap_or_default(),
+    };
+    let timestamp = evm.timestamp();
+    let block_env = block_environment(evm);
+    let start = Instant::now();
     let result = if commit {
-        evm.call_raw_committing(
-            transaction.from,
-            transaction.to,
-            value,
-            transaction.data,
-            transaction.gas_limit,
-            transaction.format_trace.unwrap_or_default(),
-        )
-        .await?
+        evm.call_raw_committing(&request, options).await?
+    } else {
+        evm.call_raw(&request, options).await?
+    };
+    record_simulation(transaction.chain_id, result.success, start.elapsed());
+    tracing::info!(
+        target: "ts::simulation",
+        success = result.success,
+        gas_used = result.gas_used,
+        execution_ms = result.execution_time.as_millis() as u64,
+        processing_ms = result.processing_time.as_millis() as u64,
+        "Simulated"
+    );
+
+    let trace = result.trace.unwrap_or_default();
+    let mut decoded_calls = result.decoded_calls.unwrap_or_default();
+    let proxies = if transaction.resolve_proxies.unwrap_or_default() {
+        resolve_proxies(evm, &trace, &mut decoded_calls).await
+    } else {
+        vec![]
+    };
+    // Only the returned traces are trimmed, everything else is derived from the whole trace
+    let trimmed_trace = trim_trace(
+        &trace,
+        transaction.max_trace_depth,
+        transaction.max_trace_frames,
+    );
+    let (shown_trace, shown_calls, shown_proxies) = match &trimmed_trace {
+        Some((arena, kept)) => (
+            arena,
+            Cow::Owned(kept_frames(&decoded_calls, kept)),
+            Cow::Owned(kept_frames(&proxies, kept)),
+        ),
+        None => (
+            &trace,
+            Cow::Borrowed(&decoded_calls[..]),
+            Cow::Borrowed(&proxies[..]),
+        ),
+    };
+    let nested_trace = if transaction.nest_trace.unwrap_or_default() {
+        CallTraceTree::from_arena(shown_trace, &shown_calls, &shown_proxies)
     } else {
-        evm.call_raw(
-            transaction.from,
-            transaction.to,
-            value,
-            transaction.data,
-            transaction.format_trace.unwrap_or_default(),
-        )
-        .await?
+        None
+    };
+    let warnings = transaction
+        .warnings
+        .unwrap_or_default()
+        .then(|| warnings(evm, &trace, &result.logs));
+    let gas_profile = transaction
+        .gas_profile
+        .unwrap_or_default()
+        .then(|| gas_profile(&trace, &decoded_calls, result.gas_used));
+    let trace_format = transaction.trace_format.unwrap_or_default();
+    let call_tracer = (trace_format == TraceFormat::CallTracer)
+        .then(|| call_tracer(shown_trace))
+        .flatten();
+    let parity_trace = (trace_format == TraceFormat::Parity).then(|| parity_traces(shown_trace));
+    // Authorizations are applied before the execution, which can't see them
+    let code_changes = result.code_changes.map(|mut changes| {
+        changes.extend(
+            delegations
+                .iter()
+                .flatten()
+                .filter(|delegation| delegation.applied)
+                .filter_map(|delegation| {
+                    Some(CodeChange {
+                        address: delegation.authority?,
+                        kind: CodeChangeKind::Delegated,
+                        previous_code_hash: None,
+                        code_hash: None,
+                        delegate: Some(delegation.address),
+                    })
+                }),
+        );
+        changes
+    });
+    let internal_transfers = internal_transfers(&trace);
+    let created_contracts = created_contracts(&trace);
+    let console_logs = console_logs(&trace);
+    let mut asset_changes = asset_changes(&trace, &result.logs);
+    resolve_token_info(evm, &mut asset_changes).await;
+    let net_value_changes = if transaction.prices.unwrap_or_default() {
+        let oracle = evm.price_oracle().unwrap_or_default();
+        Some(price_asset_changes(evm, &oracle, &mut asset_changes).await)
+    } else {
+        None
+    };
+    let funds_flow = if transaction.funds_flow.unwrap_or_default() {
+        Some(funds_flow(evm, &trace, &result.logs).await)
+    } else {
+        None
     };
 
-    Ok(SimulationResponse {
-        simulation_id: 1,
+    let fee_paid = result.effective_gas_price * result.gas_used;
+    let mut response = SimulationResponse {
+        simulation_id: Uuid::new_v4(),
         gas_used: result.gas_used,
+        gas_refunded: result.gas_refunded,
+        gas_used_before_refund: result.gas_used + result.gas_refunded,
         block_number: result.block_number,
+        block_hash: evm.block_hash(),
+        timestamp,
+        block_env: Some(block_env),
         success: result.success,
-        trace: result
-            .trace
-            .unwrap_or_default()
+        trace: shown_trace
             .arena
-            .into_iter()
-            .map(CallTrace::from)
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(idx, node)| CallTrace {
+                decoded_call: shown_calls.get(idx).cloned().flatten(),
+                proxy: shown_proxies.get(idx).cloned().flatten(),
+                ..CallTrace::from(node)
+            })
             .collect(),
+        nested_trace,
+        call_tracer,
+        parity_trace,
         logs: result.logs,
+        decoded_logs: result.decoded_logs,
+        asset_changes,
+        net_value_changes,
+        funds_flow,
+        internal_transfers,
+        created_contracts,
+        console_logs,
+        sender_is_contract,
         exit_reason: result.exit_reason,
+        return_data: result.output.clone(),
+        effective_gas_price: result.effective_gas_price,
+        fee_paid,
+        l1_fee,
+        l2_fee: l1_fee.map(|_| fee_paid),
+        total_fee: l1_fee.map(|l1_fee| l1_fee.saturating_add(fee_paid)),
+        blob_gas_used,
+        blob_gas_price,
+        blob_fee,
+        delegations,
+        decoded_return_data: result.decoded_output,
+        revert_reason: result.revert_reason,
+        raw_revert_data: (!result.success).then_some(result.output),
         formatted_trace: result.formatted_trace,
-    })
-}
+        created_address: result.created_address,
+        deployed_code_size: result.deployed_code_size,
+        state_diff: result.state_diff,
+        code_changes,
+        gas_profile,
+        storage_accesses: result.storage_accesses,
+        access_stats: result.access_stats,
+        struct_logs: result.struct_logs,
+        source_stack_trace: result.source_stack_trace,
+        warnings,
+        assumed_approvals: None,
+        pending_transactions: None,
+        trimmed: None,
+        policy_decisions: None,
+        cached: false,
+    };
 
-pub async fn simulate(transaction: SimulationRequest, config: Config) -> Result<Json, Rejection> {
-    let alchemy_key = config.alchemy_key.clone();
-    let fork_url = chain_id_to_fork_url(transaction.chain_id, alchemy_key)?;
-    let mut evm = Evm::new(
-        None,
-        fork_url,
-        transaction.block_number,
-        transaction.gas_limit,
-        true,
-        config.etherscan_key,
-    );
+    let policies = evm.policies();
+    if !policies.is_empty() {
+        let decisions = policies.evaluate(&PolicyContext {
+            transaction: &transaction,
+            response: &response,
+            trace: &trace,
+        });
+        if decisions
+            .iter()
+            .any(|decision| decision.action == PolicyAction::Reject)
+        {
+            return Err(SimulationError::PolicyViolation(decisions));
+        }
+        response.policy_decisions = Some(decisions);
+    }
 
-    let response = run(&mut evm, transaction, false).await?;
+    // After the policies, which see every log
+    if trimmed_trace.is_some() || transaction.log_filter.is_some() {
+        let logs = response.logs.len();
+        if let Some(filter) = &transaction.log_filter {
+            response.logs.retain(|log| filter.matches(log));
+            if let Some(decoded_logs) = &mut response.decoded_logs {
+                decoded_logs.retain(|log| filter.matches(&log.raw));
+            }
+        }
+        response.trimmed = Some(Trimmed {
+            trace_frames: trace.arena.len() - shown_trace.arena.len(),
+            logs: logs - response.logs.len(),
+        });
+    }
 
-    Ok(warp::reply::json(&response))
+    Ok(response)
 }
 
-pub async fn simulate_bundle(
-    transactions: Vec<SimulationRequest>,
+pub async fn simulate(
+    transaction: SimulationRequest,
     config: Config,
+    pool: EvmPool,
+    history: History,
+    cache: SimulationCache,
+    events: Vec<Event>,
 ) -> Result<Json, Rejection> {
-    let first_chain_id = transactions[0].chain_id;
-    let first_block_number = transactions[0].block_number;
-
-    let alchemy_key = config.alchemy_key.clone();
-    let fork_url = chain_id_to_fork_url(first_chain_id, alchemy_key)?;
-    let mut evm = Evm::new(
-        None,
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get_at(
+        transaction.chain_id,
         fork_url,
-        first_block_number,
-        transactions[0].gas_limit,
-        true,
+        transaction.block_number,
+        transaction.block_hash,
+        transaction.gas_limit,
         config.etherscan_key,
-    );
+    )?;
 
-    let mut response = Vec::with_capacity(transactions.len());
-    for transaction in transactions {
-        if transaction.chain_id != first_chain_id {
-            return Err(warp::reject::custom(MultipleChainIdsError()));
-        }
-        if transaction.block_number != first_block_number {
-            return Err(warp::reject::custom(MultipleBlockNumbersError()));
-        }
-        response.push(run(&mut evm, transaction, true).await?);
+    // The cache is shared by every API key, so their gas limits are checked first
+    check_gas_limit(&evm, transaction.gas_limit)?;
+
+    // Cached responses are already in the history. The same request sent again while executed
+    // waits for it to be cached
+    let key = cache.key(&transaction, evm.block_number(), &events);
+    let _flight = match key {
+        Some(key) => Some(cache.lock(key).await),
+        None => None,
+    };
+    if let Some(response) = key.and_then(|key| cache.get(transaction.chain_id, &key)) {
+        return Ok(quantity::json(&response, transaction.quantity_format));
+    }
+
+    evm.add_events(events);
+    let response = run(&mut evm, transaction.clone(), false).await?;
+    history.record(&transaction, &response);
+    if let Some(key) = key {
+        cache.insert(key, &response);
     }
 
-    Ok(warp::reply::json(&response))
+    Ok(quantity::json(&response, transaction.quantity_format))
 }
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ethers::abi::{Event, Hash};
+use ethers::utils::keccak256;
+use lru::LruCache;
+use tokio::sync::OwnedMutexGuard;
+
+use super::config::Config;
+use super::metrics::record_simulation_cache_request;
+use super::simulation::{SimulationRequest, SimulationResponse};
+
+/// Responses of `/simulate` keyed by the hash of their request and the block it was resolved to,
+/// so that a transaction simulated again at the same block, e.g. by a wallet polling a pending
+/// transaction, isn't executed again. Disabled if the TTL is zero.
+#[derive(Clone)]
+pub struct SimulationCache {
+    entries: Option<Arc<Mutex<LruCache<Hash, (Instant, SimulationResponse)>>>>,
+    flights: Flights,
+    ttl: Duration,
+}
+
+type Flights = Arc<Mutex<HashMap<Hash, Arc<tokio::sync::Mutex<()>>>>>;
+
+/// Held while a request is executed, so that the same request arriving meanwhile waits for its
+/// response instead of being executed too.
+pub struct Flight {
+    key: Hash,
+    flights: Flights,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        let mut flights = self.flights.lock().unwrap();
+        // Only the map and this guard hold the lock if nobody is waiting for it
+        if flights
+            .get(&self.key)
+            .map_or(false, |lock| Arc::strong_count(lock) == 2)
+        {
+            flights.remove(&self.key);
+        }
+    }
+}
+
+impl SimulationCache {
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        let size = NonZeroUsize::new(size.max(1)).unwrap();
+        SimulationCache {
+            entries: (!ttl.is_zero()).then(|| Arc::new(Mutex::new(LruCache::new(size)))),
+            flights: Default::default(),
+            ttl,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.simulation_cache_size, config.simulation_cache_ttl)
+    }
+
+    /// Hash of the request with its block set to `block_number`, the block it was resolved to, so
+    /// that a request for the latest block and one pinned to the same block share their entry.
+    /// Objects are serialized with sorted keys, the order of the fields of the request body
+    /// doesn't matter. The `events` registered by the API key decode the logs, so they are
+    /// hashed too. `None` if the cache is disabled.
+    pub fn key(
+        &self,
+        request: &SimulationRequest,
+        block_number: u64,
+        events: &[Event],
+    ) -> Option<Hash> {
+        self.entries.as_ref()?;
+        let request = SimulationRequest {
+            block_number: Some(block_number),
+            ..request.clone()
+        };
+        let canonical = serde_json::to_value((&request, events)).ok()?;
+        let bytes = serde_json::to_vec(&canonical).ok()?;
+
+        Some(Hash::from(keccak256(bytes)))
+    }
+
+    /// Waits for the request of `key` being executed, if any, then holds it until the returned
+    /// `Flight` is dropped. Look the response up once holding it, and insert it before dropping it.
+    pub async fn lock(&self, key: Hash) -> Flight {
+        let lock = self.flights.lock().unwrap().entry(key).or_default().clone();
+        Flight {
+            key,
+            flights: self.flights.clone(),
+            _guard: lock.lock_owned().await,
+        }
+    }
+
+    /// The cached response, with `cached` set, if it's younger than the TTL.
+    pub fn get(&self, chain_id: u64, key: &Hash) -> Option<SimulationResponse> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+        let response = match entries.get(key) {
+            Some((cached_at, response)) if cached_at.elapsed() < self.ttl => Some(response.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        };
+        record_simulation_cache_request(chain_id, if response.is_some() { "hit" } else { "miss" });
+
+        response.map(|response| SimulationResponse {
+            cached: true,
+            ..response
+        })
+    }
+
+    pub fn insert(&self, key: Hash, response: &SimulationResponse) {
+        if let Some(entries) = &self.entries {
+            entries
+                .lock()
+                .unwrap()
+                .put(key, (Instant::now(), response.clone()));
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use ethers::abi::Hash;
+
+use crate::bundle::{execute_bundle, BundleOptions, BundleResponse};
+use crate::errors::SimulationError;
+use crate::evm::Evm;
+use crate::simulation::{
+    chain_id_to_fork_url, check_gas_limit, run, SimulationRequest, SimulationResponse,
+};
+
+use super::config::Config;
+use super::contract_cache::ContractCache;
+use super::policy::{Policy, PolicyEngine};
+use super::pool::EvmPool;
+use super::precompiles::{PrecompileStub, PrecompileStubs};
+use super::prices::PriceOracle;
+use super::proxy::with_proxy;
+
+/// Simulates transactions without the HTTP server, for embedding the simulator in another Rust
+/// service. Cheap to clone, clones share their pool of forks.
+#[derive(Clone)]
+pub struct Simulator {
+    config: Config,
+    pool: EvmPool,
+}
+
+impl Simulator {
+    /// Like the server, starts the JSON-RPC proxy if `config` caches forks or fails over between
+    /// RPCs, so it must be called within a Tokio runtime.
+    pub fn new(config: Config) -> Self {
+        let config = with_proxy(config);
+        let pool = EvmPool::new(config.pool_size, config.max_concurrency)
+            .with_limits(config.max_gas_limit, config.simulation_timeout)
+            .with_contract_cache(ContractCache::from_config(&config))
+            .with_price_oracle(PriceOracle::from_config(&config))
+            .with_policies(PolicyEngine::from_config(&config))
+            .with_precompile_stubs(PrecompileStubs::from_config(&config))
+            .with_api_key_policies(config.api_key_policies.clone());
+        Simulator { config, pool }
+    }
+
+    /// The simulator, sharing its forks, for a caller using `api_key`, restricted to the chains
+    /// and limits of its policy in `API_KEY_POLICIES_FILE`.
+    pub fn for_api_key(&self, api_key: Option<&str>) -> Simulator {
+        Simulator {
+            config: self.config.for_api_key(api_key),
+            pool: self.pool.for_api_key(api_key),
+        }
+    }
+
+    /// Adds a policy to the rules of `POLICY_FILE`, which every simulation is checked against.
+    pub fn with_policy(mut self, policy: impl Policy + 'static) -> Self {
+        self.pool = self.pool.with_policy(policy);
+        self
+    }
+
+    /// Adds a stub to the system contracts and those of `PRECOMPILE_STUBS_FILE`, replacing any
+    /// at the same address of the same chain.
+    pub fn with_precompile_stub(mut self, stub: PrecompileStub) -> Self {
+        self.pool = self.pool.with_precompile_stub(stub);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Forks a chain at a block, by hash if `block_hash` is set, otherwise by number, the latest
+    /// one if `None`, for lower level access to the EVM.
+    pub fn fork(
+        &self,
+        chain_id: u64,
+        block_number: Option<u64>,
+        block_hash: Option<Hash>,
+        gas_limit: u64,
+    ) -> Result<Evm, SimulationError> {
+        let fork_url = chain_id_to_fork_url(chain_id, &self.config)?;
+        Ok(self.pool.get_at(
+            chain_id,
+            fork_url,
+            block_number,
+            block_hash,
+            gas_limit,
+            self.config.etherscan_key.clone(),
+        )?)
+    }
+
+    /// Simulates a transaction on its own fork, like `POST /simulate`.
+    pub async fn simulate(
+        &self,
+        transaction: SimulationRequest,
+    ) -> Result<SimulationResponse, SimulationError> {
+        let mut evm = self.fork(
+            transaction.chain_id,
+            transaction.block_number,
+            transaction.block_hash,
+            transaction.gas_limit,
+        )?;
+        check_gas_limit(&evm, transaction.gas_limit)?;
+        run(&mut evm, transaction, false).await
+    }
+
+    /// Simulates transactions one after the other on a single fork, like `POST /simulate-bundle`
+    /// with a bundle object: transactions which fail are reported in their status, skipped or
+    /// rolled back as `options` ask, and the response sums the bundle up.
+    pub async fn simulate_bundle(
+        &self,
+        transactions: Vec<SimulationRequest>,
+        options: BundleOptions,
+    ) -> Result<BundleResponse, SimulationError> {
+        execute_bundle(
+            transactions,
+            options,
+            self.config.clone(),
+            self.pool.clone(),
+            None,
+            None,
+        )
+        .await
+    }
+
+    /// Simulates a transaction on a fork from [`Simulator::fork`], keeping its state changes if
+    /// `commit`.
+    pub async fn simulate_on(
+        &self,
+        evm: &mut Evm,
+        transaction: SimulationRequest,
+        commit: bool,
+    ) -> Result<SimulationResponse, SimulationError> {
+        check_gas_limit(evm, transaction.gas_limit)?;
+        run(evm, transaction, commit).await
+    }
+}
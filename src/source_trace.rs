@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use ethers::abi::Address;
+use ethers::etherscan::errors::EtherscanError;
+use ethers::etherscan::Client;
+use ethers::solc::artifacts::{CompilerInput, Source};
+use ethers::solc::Solc;
+use ethers::types::Chain;
+use foundry_evm::debug::{DebugArena, Instruction};
+use foundry_evm::CallKind;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::evm::Evm;
+use crate::metrics::record_etherscan_request;
+
+const PUSH1: u8 = 0x60;
+const PUSH32: u8 = 0x7f;
+const REVERT: u8 = 0xfd;
+const INVALID: u8 = 0xfe;
+
+/// Contracts compiled so far by chain and address, including those without verified source.
+static COMPILED: Lazy<Mutex<LruCache<(u64, Address), Option<Arc<CompiledContract>>>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(1_000).unwrap())));
+
+/// A call frame the revert went through, located in the verified source of its contract.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SourceFrame {
+    /// The contract whose code ran, the implementation for delegatecalls.
+    pub address: Address,
+    /// Of the opcode the frame stopped at.
+    pub pc: usize,
+    /// Not set if the contract isn't verified or could not be compiled.
+    #[serde(default, skip_serializing_if = "Option::is_none", flatten)]
+    pub location: Option<SourceLocation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SourceLocation {
+    pub contract: String,
+    pub file: String,
+    /// Starts at 1.
+    pub line: usize,
+    /// In bytes, starts at 1.
+    pub column: usize,
+    /// The line, trimmed.
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SourceElement {
+    offset: usize,
+    /// Not set for code the compiler generated.
+    index: Option<u32>,
+}
+
+/// The runtime source map of a verified contract and the sources it points into.
+struct CompiledContract {
+    name: String,
+    /// By instruction index.
+    source_map: Vec<SourceElement>,
+    /// Path and content of the sources, by source index.
+    sources: HashMap<u32, (String, String)>,
+}
+
+impl CompiledContract {
+    fn locate(&self, code: &[u8], pc: usize) -> Option<SourceLocation> {
+        let element = self.source_map.get(instruction_index(code, pc)?)?;
+        let (file, content) = self.sources.get(&element.index?)?;
+        let prefix = content.get(..element.offset)?;
+        let line_start = prefix.rfind('\n').map_or(0, |newline| newline + 1);
+        let source = content[line_start..].lines().next().unwrap_or_default();
+
+        Some(SourceLocation {
+            contract: self.name.clone(),
+            file: file.clone(),
+            line: prefix.matches('\n').count() + 1,
+            column: element.offset - line_start + 1,
+            source: source.trim().to_string(),
+        })
+    }
+}
+
+/// Index of the instruction at `pc`, which source maps are indexed by, pushes taking their data.
+fn instruction_index(code: &[u8], pc: usize) -> Option<usize> {
+    let mut position = 0;
+    let mut index = 0;
+    while position < code.len() {
+        if position == pc {
+            return Some(index);
+        }
+        let op = code[position];
+        position += 1;
+        if (PUSH1..=PUSH32).contains(&op) {
+            position += usize::from(op - PUSH1 + 1);
+        }
+        index += 1;
+    }
+
+    None
+}
+
+/// Parses a compressed source map, `offset:length:index:jump:modifierDepth` per instruction with
+/// fields left empty being those of the previous instruction.
+fn parse_source_map(source_map: &str) -> Vec<SourceElement> {
+    let mut element = SourceElement {
+        offset: 0,
+        index: None,
+    };
+    source_map
+        .split(';')
+        .map(|entry| {
+            let mut fields = entry.split(':');
+            if let Some(offset) = fields.next().and_then(|field| field.parse().ok()) {
+                element.offset = offset;
+            }
+            // The length is not needed to locate the start of the range
+            fields.next();
+            if let Some(index) = fields.next().and_then(|field| field.parse::<i64>().ok()) {
+                element.index = u32::try_from(index).ok();
+            }
+            element
+        })
+        .collect()
+}
+
+/// Fetches the verified source of `address` from Etherscan and compiles it with the settings it
+/// was verified with, `None` if it isn't verified.
+async fn compile(
+    chain_id: u64,
+    etherscan_key: &str,
+    address: Address,
+) -> eyre::Result<Option<CompiledContract>> {
+    let client = Client::new(Chain::try_from(chain_id)?, etherscan_key)?;
+    let metadata = match client.contract_source_code(address).await {
+        Ok(metadata) => metadata,
+        Err(EtherscanError::ContractCodeNotVerified(_)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let Some(metadata) = metadata.items.into_iter().next() else {
+        return Ok(None);
+    };
+
+    let version = metadata.compiler_version()?;
+    let mut settings = metadata.settings()?;
+    settings.output_selection =
+        serde_json::from_value(json!({ "*": { "*": ["evm.deployedBytecode"] } }))?;
+    let sources: BTreeMap<String, String> = metadata
+        .sources()
+        .into_iter()
+        .map(|(path, entry)| (path, entry.content))
+        .collect();
+    let input = CompilerInput {
+        language: "Solidity".to_string(),
+        sources: sources
+            .iter()
+            .map(|(path, content)| (PathBuf::from(path), Source::new(content.as_str())))
+            .collect(),
+        settings,
+    };
+    // Installing the compiler and compiling both block
+    let output = tokio::task::spawn_blocking(move || {
+        let version = format!("{}.{}.{}", version.major, version.minor, version.patch);
+        Solc::find_or_install_svm_version(version)?.compile_exact(&input)
+    })
+    .await??;
+
+    let source_map = output
+        .contracts
+        .values()
+        .find_map(|contracts| contracts.get(&metadata.contract_name))
+        .and_then(|contract| contract.evm.as_ref()?.deployed_bytecode.as_ref())
+        .and_then(|deployed| deployed.bytecode.as_ref()?.source_map.clone());
+    let Some(source_map) = source_map else {
+        return Ok(None);
+    };
+
+    Ok(Some(CompiledContract {
+        name: metadata.contract_name,
+        source_map: parse_source_map(&source_map),
+        sources: output
+            .sources
+            .iter()
+            .filter_map(|(path, file)| {
+                let content = sources.get(path)?;
+                Some((file.id, (path.clone(), content.clone())))
+            })
+            .collect(),
+    }))
+}
+
+async fn compiled(
+    chain_id: u64,
+    etherscan_key: &str,
+    address: Address,
+) -> Option<Arc<CompiledContract>> {
+    let cached = COMPILED.lock().unwrap().get(&(chain_id, address)).cloned();
+    if let Some(contract) = cached {
+        record_etherscan_request(chain_id, "hit");
+        return contract;
+    }
+
+    record_etherscan_request(chain_id, "miss");
+    match compile(chain_id, etherscan_key, address).await {
+        Ok(contract) => {
+            let contract = contract.map(Arc::new);
+            COMPILED
+                .lock()
+                .unwrap()
+                .put((chain_id, address), contract.clone());
+            contract
+        }
+        Err(err) => {
+            log::warn!(target: "ts::source_trace", "Failed to compile {address:?}: {err}");
+            None
+        }
+    }
+}
+
+fn reverted(debug: &DebugArena, idx: usize) -> bool {
+    matches!(
+        debug.arena[idx].steps.last(),
+        Some(step) if matches!(step.instruction, Instruction::OpCode(REVERT | INVALID))
+    )
+}
+
+/// The code address and last pc of the frames a revert went through, from the top level call
+/// down to the frame which reverted first. A frame reverted because of a call if its last call
+/// reverted too, deployments are left out as their code isn't the runtime code.
+fn revert_path(debug: &DebugArena) -> Vec<(Address, usize)> {
+    let mut path = vec![];
+    let mut idx = 0;
+    while let Some(node) = debug.arena.get(idx) {
+        let Some(step) = node.steps.last() else {
+            break;
+        };
+        if !matches!(node.kind, CallKind::Create | CallKind::Create2) {
+            path.push((node.address, step.pc));
+        }
+        match node.children.last() {
+            Some(child) if reverted(debug, *child) => idx = *child,
+            _ => break,
+        }
+    }
+
+    path
+}
+
+/// Frames the revert of a transaction went through, innermost first, located in the verified
+/// source of their contracts. Sources are fetched from Etherscan and compiled once per contract,
+/// frames are only located with an Etherscan API key. The code of the frames is read upfront, so
+/// that the `Evm` isn't borrowed while compiling.
+pub(crate) fn source_stack_trace(
+    evm: &Evm,
+    debug: &DebugArena,
+) -> impl Future<Output = Vec<SourceFrame>> {
+    let chain_id = evm.chain_id();
+    let etherscan_key = evm.etherscan_key().map(str::to_string);
+    let path: Vec<_> = revert_path(debug)
+        .into_iter()
+        .rev()
+        .map(|(address, pc)| (address, pc, evm.account_code(address).ok()))
+        .collect();
+
+    async move {
+        let mut frames = vec![];
+        for (address, pc, code) in path {
+            let location = match (&etherscan_key, code) {
+                (Some(etherscan_key), Some(code)) => compiled(chain_id, etherscan_key, address)
+                    .await
+                    .and_then(|contract| contract.locate(&code, pc)),
+                _ => None,
+            };
+            frames.push(SourceFrame {
+                address,
+                pc,
+                location,
+            });
+        }
+
+        frames
+    }
+}
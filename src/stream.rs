@@ -0,0 +1,179 @@
+use ethers::types::Log;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use warp::ws::{Message, WebSocket};
+use warp::Rejection;
+
+use crate::errors::{error_message, BundleTooLargeError, ErrorMessage, SimulationError};
+use crate::simulation::{
+    chain_id_to_fork_url, run, CallTrace, SimulationRequest, SimulationResponse,
+};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// First message sent over the socket, a single transaction or a bundle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StreamRequest {
+    Transaction(SimulationRequest),
+    Bundle(Vec<SimulationRequest>),
+}
+
+/// Messages sent back, `transaction` is the index of the transaction in the bundle. The call
+/// frames and logs of a transaction are sent as soon as it has executed, followed by its result.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StreamEvent {
+    Call {
+        transaction: usize,
+        call: CallTrace,
+    },
+    Log {
+        transaction: usize,
+        log: Log,
+    },
+    Result {
+        transaction: usize,
+        result: Box<SimulationResponse>,
+    },
+    Summary {
+        transactions: usize,
+        #[serde(rename = "gasUsed")]
+        gas_used: u64,
+        success: bool,
+    },
+    Error {
+        error: ErrorMessage,
+    },
+}
+
+async fn send(socket: &mut WebSocket, event: &StreamEvent) -> bool {
+    let text = serde_json::to_string(event).expect("events must serialize");
+    socket.send(Message::text(text)).await.is_ok()
+}
+
+/// Simulates the transactions of the first message received, streaming events for each, then
+/// closes the socket.
+pub async fn simulate_stream(
+    mut socket: WebSocket,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) {
+    let request = match socket.next().await {
+        Some(Ok(message)) => message
+            .to_str()
+            .ok()
+            .and_then(|text| serde_json::from_str::<StreamRequest>(text).ok()),
+        _ => return,
+    };
+
+    let event = match request {
+        Some(request) => {
+            match stream_transactions(&mut socket, request, config, pool, history).await {
+                Ok(Some(summary)) => summary,
+                // The client went away
+                Ok(None) => return,
+                Err(err) => StreamEvent::Error {
+                    error: error_message(&err).0,
+                },
+            }
+        }
+        None => StreamEvent::Error {
+            error: ErrorMessage {
+                code: 400,
+                message: "BAD_REQUEST".to_string(),
+                details: None,
+            },
+        },
+    };
+
+    send(&mut socket, &event).await;
+    socket.close().await.ok();
+}
+
+async fn stream_transactions(
+    socket: &mut WebSocket,
+    request: StreamRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Option<StreamEvent>, Rejection> {
+    let transactions = match request {
+        StreamRequest::Transaction(transaction) => vec![transaction],
+        StreamRequest::Bundle(transactions) => transactions,
+    };
+    if transactions.len() > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+    let Some(first) = transactions.first() else {
+        return Ok(Some(StreamEvent::Summary {
+            transactions: 0,
+            gas_used: 0,
+            success: true,
+        }));
+    };
+    let first_chain_id = first.chain_id;
+
+    let fork_url = chain_id_to_fork_url(first_chain_id, &config)?;
+    let mut evm = pool.get_at(
+        first_chain_id,
+        fork_url,
+        first.block_number,
+        first.block_hash,
+        first.gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let mut block_number = evm.block_number();
+    let mut gas_used = 0;
+    let mut success = true;
+    for (index, transaction) in transactions.iter().enumerate() {
+        if transaction.chain_id != first_chain_id {
+            return Err(warp::reject::custom(SimulationError::MultipleChainIds));
+        }
+        if let Some(next_block_number) = transaction.block_number {
+            if next_block_number < block_number {
+                return Err(warp::reject::custom(SimulationError::BlockNumberDecreasing));
+            }
+            evm.roll_block(next_block_number);
+            block_number = next_block_number;
+        }
+
+        let result = run(&mut evm, transaction.clone(), true).await?;
+        history.record(transaction, &result);
+        gas_used += result.gas_used;
+        success &= result.success;
+
+        let calls = result.trace.iter().map(|call| StreamEvent::Call {
+            transaction: index,
+            call: call.clone(),
+        });
+        let logs = result.logs.iter().map(|log| StreamEvent::Log {
+            transaction: index,
+            log: log.clone(),
+        });
+        let events: Vec<StreamEvent> = calls.chain(logs).collect();
+        for event in &events {
+            if !send(socket, event).await {
+                return Ok(None);
+            }
+        }
+
+        let event = StreamEvent::Result {
+            transaction: index,
+            result: Box::new(result),
+        };
+        if !send(socket, &event).await {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(StreamEvent::Summary {
+        transactions: transactions.len(),
+        gas_used,
+        success,
+    }))
+}
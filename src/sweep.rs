@@ -0,0 +1,228 @@
+use ethers::abi::Uint;
+use ethers::types::Bytes;
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{error_message, BundleTooLargeError, ErrorMessage, InvalidSweepError};
+use crate::quantity;
+use crate::simulation::{chain_id_to_fork_url, run, SimulationRequest};
+
+use super::config::Config;
+use super::pool::EvmPool;
+
+/// What a parameter of a sweep varies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "target", rename_all = "camelCase")]
+pub enum SweepTarget {
+    Value,
+    GasLimit,
+    /// The 32 byte word of the calldata starting `offset` bytes in, selector included.
+    CalldataWord {
+        offset: usize,
+    },
+}
+
+/// Values from `from` to `to` included, `step` apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SweepRange {
+    #[serde(deserialize_with = "quantity::deserialize_uint")]
+    pub from: Uint,
+    #[serde(deserialize_with = "quantity::deserialize_uint")]
+    pub to: Uint,
+    #[serde(deserialize_with = "quantity::deserialize_uint")]
+    pub step: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SweepParameter {
+    #[serde(flatten)]
+    pub target: SweepTarget,
+    /// Taken before the values of `range`.
+    #[serde(default, deserialize_with = "quantity::deserialize_uints")]
+    pub values: Vec<Uint>,
+    pub range: Option<SweepRange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SweepRequest {
+    pub transaction: SimulationRequest,
+    /// Every combination of their values is simulated, the last parameter varying fastest.
+    pub parameters: Vec<SweepParameter>,
+}
+
+/// Outcome of one combination of values.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SweepVariant {
+    /// Value of each parameter, in the order of `parameters`.
+    pub values: Vec<Uint>,
+    pub success: bool,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    #[serde(rename = "returnData")]
+    pub return_data: Bytes,
+    #[serde(
+        rename = "revertReason",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub revert_reason: Option<String>,
+    /// Why the variant could not be simulated, e.g. its gas limit being too high.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SweepResponse {
+    pub variants: Vec<SweepVariant>,
+}
+
+impl SweepParameter {
+    fn values(&self, max_values: usize) -> Result<Vec<Uint>, InvalidSweepError> {
+        let mut values = self.values.clone();
+        if let Some(range) = &self.range {
+            if range.step.is_zero() || range.from > range.to {
+                return Err(InvalidSweepError("empty range".to_string()));
+            }
+            let mut value = range.from;
+            while value <= range.to {
+                if values.len() >= max_values {
+                    break;
+                }
+                values.push(value);
+                match value.checked_add(range.step) {
+                    Some(next) => value = next,
+                    None => break,
+                }
+            }
+        }
+        if values.is_empty() {
+            return Err(InvalidSweepError("parameter without values".to_string()));
+        }
+
+        Ok(values)
+    }
+
+    fn apply(&self, request: &mut SimulationRequest, value: Uint) -> Result<(), InvalidSweepError> {
+        match self.target {
+            SweepTarget::Value => request.value = Some(format!("0x{value:x}")),
+            SweepTarget::GasLimit => {
+                if value > Uint::from(u64::MAX) {
+                    return Err(InvalidSweepError(format!("gas limit {value} too high")));
+                }
+                request.gas_limit = value.as_u64();
+            }
+            SweepTarget::CalldataWord { offset } => {
+                let mut data = request.data.clone().unwrap_or_default().to_vec();
+                let word = offset
+                    .checked_add(32)
+                    .and_then(|end| data.get_mut(offset..end))
+                    .ok_or_else(|| {
+                        InvalidSweepError(format!("no calldata word at offset {offset}"))
+                    })?;
+                value.to_big_endian(word);
+                request.data = Some(data.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Every combination of the values of `parameters`, the last one varying fastest.
+fn combinations(values: &[Vec<Uint>]) -> Vec<Vec<Uint>> {
+    values.iter().fold(vec![vec![]], |combinations, values| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push(*value);
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+/// Simulates the transaction with every combination of the values of the parameters, on a
+/// single fork reverted after each variant, and answers with the outcome of each.
+pub async fn simulate_sweep(
+    request: SweepRequest,
+    config: Config,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    let SweepRequest {
+        transaction,
+        parameters,
+    } = request;
+
+    let max_variants = config.max_bundle_size;
+    let values = parameters
+        .iter()
+        .map(|parameter| parameter.values(max_variants + 1))
+        .collect::<Result<Vec<_>, _>>()?;
+    let variants = values.iter().try_fold(1usize, |variants, values| {
+        variants
+            .checked_mul(values.len())
+            .filter(|variants| *variants <= max_variants)
+    });
+    if variants.is_none() {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+
+    // Parameters are checked before anything is simulated
+    let mut requests = vec![];
+    for combination in combinations(&values) {
+        let mut variant = transaction.clone();
+        for (parameter, value) in parameters.iter().zip(&combination) {
+            parameter.apply(&mut variant, *value)?;
+        }
+        requests.push((combination, variant));
+    }
+
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let gas_limit = requests
+        .iter()
+        .map(|(_, variant)| variant.gas_limit)
+        .max()
+        .unwrap_or(transaction.gas_limit);
+    let mut evm = pool.get_at(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.block_hash,
+        gas_limit,
+        config.etherscan_key,
+    )?;
+
+    let snapshot = evm.snapshot();
+    let mut results = Vec::with_capacity(requests.len());
+    for (values, variant) in requests {
+        let result = run(&mut evm, variant, false).await;
+        evm.revert(snapshot.clone());
+        results.push(match result {
+            Ok(response) => SweepVariant {
+                values,
+                success: response.success,
+                gas_used: response.gas_used,
+                return_data: response.return_data,
+                revert_reason: response.revert_reason,
+                error: None,
+            },
+            Err(err) => SweepVariant {
+                values,
+                success: false,
+                gas_used: 0,
+                return_data: Bytes::default(),
+                revert_reason: None,
+                error: Some(error_message(&err.into()).0),
+            },
+        });
+    }
+
+    Ok(quantity::json(
+        &SweepResponse { variants: results },
+        transaction.quantity_format,
+    ))
+}
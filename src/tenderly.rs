@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+
+use ethers::abi::{Address, Hash, Uint};
+use ethers::types::transaction::eip2930::AccessList;
+use ethers::types::Bytes;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::{BundleTooLargeError, SimulationError};
+use crate::quantity;
+use crate::simulation::{
+    chain_id_to_fork_url, run, BlockOverrides, CallTraceTree, DecodedLog, SimulationRequest,
+    SimulationResponse, StateOverride,
+};
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// A simulation in the schema of Tenderly's simulate API. Fields this simulator has no use for,
+/// like `save` or `estimate_gas`, are accepted and ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenderlySimulationRequest {
+    #[serde(deserialize_with = "quantity::deserialize_u64")]
+    pub network_id: u64,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub input: Option<Bytes>,
+    /// Defaults to `MAX_GAS_LIMIT`.
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub gas: Option<u64>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_uint")]
+    pub gas_price: Option<Uint>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_string")]
+    pub value: Option<String>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub block_number: Option<u64>,
+    pub access_list: Option<AccessList>,
+    pub state_objects: Option<HashMap<Address, TenderlyStateObject>>,
+    pub block_header: Option<TenderlyBlockHeader>,
+    /// `full` by default, which decodes calls and logs. `quick` and `abi` skip decoding.
+    pub simulation_type: Option<TenderlySimulationType>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TenderlySimulationType {
+    #[default]
+    Full,
+    Quick,
+    Abi,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenderlyStateObject {
+    #[serde(default, deserialize_with = "quantity::deserialize_option_uint")]
+    pub balance: Option<Uint>,
+    pub code: Option<Bytes>,
+    pub storage: Option<HashMap<Hash, Hash>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenderlyBlockHeader {
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub number: Option<u64>,
+    #[serde(default, deserialize_with = "quantity::deserialize_option_u64")]
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenderlyBundleRequest {
+    pub simulations: Vec<TenderlySimulationRequest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlySimulationResponse {
+    pub transaction: TenderlyTransaction,
+    pub simulation: TenderlySimulation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyBundleResponse {
+    pub simulation_results: Vec<TenderlySimulationResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyTransaction {
+    pub network_id: String,
+    pub block_number: u64,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub gas: u64,
+    pub gas_price: String,
+    pub gas_used: u64,
+    pub input: Bytes,
+    pub value: String,
+    pub status: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    pub transaction_info: TenderlyTransactionInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyTransactionInfo {
+    /// Zero address unless the transaction deployed a contract, like Tenderly.
+    pub contract_address: Address,
+    pub block_number: u64,
+    pub gas_used: u64,
+    /// Not set if the transaction didn't execute, e.g. as it ran out of gas upfront.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_trace: Option<TenderlyCallTrace>,
+    pub logs: Vec<TenderlyLog>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyCallTrace {
+    /// e.g. `CALL` or `DELEGATECALL`.
+    pub call_type: String,
+    pub from: Address,
+    pub to: Address,
+    pub value: String,
+    pub gas_used: u64,
+    pub input: Bytes,
+    pub output: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub calls: Vec<TenderlyCallTrace>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyLog {
+    /// Not set if the event couldn't be decoded.
+    pub name: Option<String>,
+    pub inputs: Vec<TenderlyLogInput>,
+    pub raw: TenderlyRawLog,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyLogInput {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+    pub indexed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlyRawLog {
+    pub address: Address,
+    pub topics: Vec<Hash>,
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TenderlySimulation {
+    /// ID of the simulation in this simulator's history.
+    pub id: Uuid,
+    pub network_id: String,
+    pub block_number: u64,
+    pub status: bool,
+    pub gas: u64,
+    pub gas_used: u64,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub input: Bytes,
+    pub value: String,
+}
+
+impl TenderlySimulationRequest {
+    fn into_simulation_request(self, config: &Config) -> SimulationRequest {
+        let decode = self.simulation_type.unwrap_or_default() == TenderlySimulationType::Full;
+        let state_overrides = self.state_objects.map(|state_objects| {
+            state_objects
+                .into_iter()
+                .map(|(address, state)| {
+                    let state_override = StateOverride {
+                        balance: state.balance,
+                        nonce: None,
+                        code: state.code,
+                        storage: state.storage,
+                    };
+                    (address, state_override)
+                })
+                .collect()
+        });
+
+        SimulationRequest {
+            chain_id: self.network_id,
+            from: self.from,
+            to: self.to,
+            data: self.input,
+            gas_limit: self.gas.unwrap_or(config.max_gas_limit),
+            value: self.value,
+            gas_price: self.gas_price.filter(|gas_price| !gas_price.is_zero()),
+            access_list: self.access_list,
+            block_number: self.block_number,
+            nest_trace: Some(true),
+            decode_logs: Some(decode),
+            decode_calls: Some(decode),
+            state_overrides,
+            block_overrides: self.block_header.map(|header| BlockOverrides {
+                number: header.number,
+                timestamp: header.timestamp,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+fn call_trace(call: CallTraceTree) -> TenderlyCallTrace {
+    TenderlyCallTrace {
+        call_type: format!("{:?}", call.call_type).to_uppercase(),
+        from: call.from,
+        to: call.created_address.unwrap_or(call.to),
+        value: call.value.to_string(),
+        gas_used: call.gas_used,
+        input: call.input,
+        output: call.output,
+        function_name: call.decoded_call.map(|decoded| decoded.name),
+        error: (!call.success).then(|| format!("{:?}", call.exit_reason)),
+        calls: call.calls.into_iter().map(call_trace).collect(),
+    }
+}
+
+fn log(log: DecodedLog) -> TenderlyLog {
+    TenderlyLog {
+        name: log.name,
+        inputs: log
+            .params
+            .into_iter()
+            .map(|param| TenderlyLogInput {
+                name: param.name,
+                kind: param.kind,
+                value: param.value,
+                indexed: param.indexed,
+            })
+            .collect(),
+        raw: TenderlyRawLog {
+            address: log.raw.address,
+            topics: log.raw.topics,
+            data: log.raw.data,
+        },
+    }
+}
+
+fn into_response(
+    transaction: &SimulationRequest,
+    response: SimulationResponse,
+) -> TenderlySimulationResponse {
+    let network_id = transaction.chain_id.to_string();
+    let input = transaction.data.clone().unwrap_or_default();
+    let value = transaction
+        .value
+        .as_deref()
+        .and_then(|value| quantity::parse_quantity(value).ok())
+        .unwrap_or_default()
+        .to_string();
+
+    // Logs are only decoded for `full` simulations
+    let logs = match response.decoded_logs {
+        Some(logs) => logs.into_iter().map(log).collect(),
+        None => response
+            .logs
+            .into_iter()
+            .map(|raw| {
+                log(DecodedLog {
+                    name: None,
+                    params: vec![],
+                    raw,
+                })
+            })
+            .collect(),
+    };
+    let call_trace = response.nested_trace.map(call_trace);
+
+    TenderlySimulationResponse {
+        transaction: TenderlyTransaction {
+            network_id: network_id.clone(),
+            block_number: response.block_number,
+            from: transaction.from,
+            to: transaction.to,
+            gas: transaction.gas_limit,
+            gas_price: response.effective_gas_price.to_string(),
+            gas_used: response.gas_used,
+            input: input.clone(),
+            value: value.clone(),
+            status: response.success,
+            error_message: (!response.success).then(|| {
+                response
+                    .revert_reason
+                    .clone()
+                    .unwrap_or_else(|| format!("{:?}", response.exit_reason))
+            }),
+            transaction_info: TenderlyTransactionInfo {
+                contract_address: response.created_address.unwrap_or_default(),
+                block_number: response.block_number,
+                gas_used: response.gas_used,
+                call_trace,
+                logs,
+            },
+        },
+        simulation: TenderlySimulation {
+            id: response.simulation_id,
+            network_id,
+            block_number: response.block_number,
+            status: response.success,
+            gas: transaction.gas_limit,
+            gas_used: response.gas_used,
+            from: transaction.from,
+            to: transaction.to,
+            input,
+            value,
+        },
+    }
+}
+
+/// Simulates a Tenderly simulation like `/simulate`, the account and project of the path are
+/// ignored.
+pub async fn simulate(
+    _account: String,
+    _project: String,
+    request: TenderlySimulationRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    let transaction = request.into_simulation_request(&config);
+
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.gas_limit,
+        config.etherscan_key,
+    );
+
+    let response = run(&mut evm, transaction.clone(), false).await?;
+    history.record(&transaction, &response);
+
+    Ok(warp::reply::json(&into_response(&transaction, response)))
+}
+
+/// Simulates Tenderly simulations one after the other on a single fork, like
+/// `/simulate-bundle` with a list of transactions.
+pub async fn simulate_bundle(
+    _account: String,
+    _project: String,
+    request: TenderlyBundleRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<Json, Rejection> {
+    if request.simulations.len() > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+    let transactions: Vec<SimulationRequest> = request
+        .simulations
+        .into_iter()
+        .map(|simulation| simulation.into_simulation_request(&config))
+        .collect();
+    let Some(first) = transactions.first() else {
+        return Ok(warp::reply::json(&TenderlyBundleResponse {
+            simulation_results: vec![],
+        }));
+    };
+    let first_chain_id = first.chain_id;
+
+    let fork_url = chain_id_to_fork_url(first_chain_id, &config)?;
+    let mut evm = pool.get(
+        first_chain_id,
+        fork_url,
+        first.block_number,
+        first.gas_limit,
+        config.etherscan_key,
+    );
+
+    let mut block_number = evm.block_number();
+    let mut simulation_results = Vec::with_capacity(transactions.len());
+    for transaction in transactions {
+        if transaction.chain_id != first_chain_id {
+            return Err(warp::reject::custom(SimulationError::MultipleChainIds));
+        }
+        if let Some(next_block_number) = transaction.block_number {
+            if next_block_number < block_number {
+                return Err(warp::reject::custom(SimulationError::BlockNumberDecreasing));
+            }
+            evm.roll_block(next_block_number);
+            block_number = next_block_number;
+        }
+
+        let response = run(&mut evm, transaction.clone(), true).await?;
+        history.record(&transaction, &response);
+        simulation_results.push(into_response(&transaction, response));
+    }
+
+    Ok(warp::reply::json(&TenderlyBundleResponse {
+        simulation_results,
+    }))
+}
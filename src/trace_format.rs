@@ -0,0 +1,222 @@
+use ethers::abi::{Address, Uint};
+use ethers::types::Bytes;
+use foundry_evm::decode::decode_revert;
+use foundry_evm::trace::{CallTraceArena, RawOrDecodedCall, RawOrDecodedReturnData};
+use foundry_evm::CallKind;
+use revm::Return;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceFormat {
+    /// `trace`, and `nestedTrace` with `nestTrace`.
+    #[default]
+    Native,
+    /// Also in `callTracer`, like geth's `debug_traceCall` with the `callTracer`.
+    CallTracer,
+    /// Also in `parityTrace`, like `trace_call` of OpenEthereum, Erigon and Nethermind.
+    Parity,
+}
+
+/// A call frame as returned by geth's `callTracer`, except for `gas` which isn't traced.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CallTracerFrame {
+    /// `CALL`, `STATICCALL`, `DELEGATECALL`, `CALLCODE`, `CREATE` or `CREATE2`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub from: Address,
+    /// The created contract for creates.
+    pub to: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Uint>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    pub input: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(
+        rename = "revertReason",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub revert_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallTracerFrame>,
+}
+
+/// A call frame as listed by Parity's `trace_call`, in execution order.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ParityTrace {
+    pub action: ParityAction,
+    /// Not set if the frame failed, see `error`.
+    pub result: Option<ParityResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Number of frames called by this one.
+    pub subtraces: usize,
+    /// Index of the frame among its siblings at every depth, empty for the top level call.
+    #[serde(rename = "traceAddress")]
+    pub trace_address: Vec<usize>,
+    /// `call` or `create`.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ParityAction {
+    pub from: Address,
+    /// `call`, `staticcall`, `delegatecall` or `callcode`, not set for creates.
+    #[serde(rename = "callType", default, skip_serializing_if = "Option::is_none")]
+    pub call_type: Option<String>,
+    /// Not set for creates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<Address>,
+    pub value: Uint,
+    /// Calldata, not set for creates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<Bytes>,
+    /// Init code, only set for creates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init: Option<Bytes>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ParityResult {
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    /// Return data, not set for creates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Bytes>,
+    /// Only set for creates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<Address>,
+    /// Deployed code, only set for creates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+}
+
+fn input(data: &RawOrDecodedCall) -> Bytes {
+    match data {
+        RawOrDecodedCall::Raw(data) => data.clone().into(),
+        RawOrDecodedCall::Decoded(..) => Bytes::default(),
+    }
+}
+
+fn output(data: &RawOrDecodedReturnData) -> Bytes {
+    match data {
+        RawOrDecodedReturnData::Raw(data) => data.clone().into(),
+        RawOrDecodedReturnData::Decoded(_) => Bytes::default(),
+    }
+}
+
+fn is_create(kind: CallKind) -> bool {
+    matches!(kind, CallKind::Create | CallKind::Create2)
+}
+
+/// Error of a failed frame, worded like geth.
+fn error(status: Return) -> String {
+    match status {
+        Return::Revert => "execution reverted".to_string(),
+        Return::OutOfGas => "out of gas".to_string(),
+        status => format!("{status:?}"),
+    }
+}
+
+/// Nests the arena in geth's `callTracer` format, if anything was traced.
+pub fn call_tracer(arena: &CallTraceArena) -> Option<CallTracerFrame> {
+    if arena.arena.is_empty() {
+        return None;
+    }
+    Some(call_tracer_frame(arena, 0))
+}
+
+fn call_tracer_frame(arena: &CallTraceArena, idx: usize) -> CallTracerFrame {
+    let node = &arena.arena[idx];
+    let trace = &node.trace;
+    let output = output(&trace.output);
+    let revert_reason = (trace.status == Return::Revert)
+        .then(|| decode_revert(&output, None, Some(trace.status)).ok())
+        .flatten();
+
+    CallTracerFrame {
+        kind: format!("{:?}", trace.kind).to_uppercase(),
+        from: trace.caller,
+        to: trace.address,
+        // Like geth, delegate and static calls have no value of their own
+        value: (!matches!(trace.kind, CallKind::DelegateCall | CallKind::StaticCall))
+            .then_some(trace.value),
+        gas_used: trace.gas_cost,
+        input: input(&trace.data),
+        output: (!output.is_empty()).then_some(output),
+        error: (!trace.success).then(|| error(trace.status)),
+        revert_reason,
+        calls: node
+            .children
+            .iter()
+            .map(|child| call_tracer_frame(arena, *child))
+            .collect(),
+    }
+}
+
+/// Flattens the arena in Parity's `trace_call` format, in execution order.
+pub fn parity_traces(arena: &CallTraceArena) -> Vec<ParityTrace> {
+    let mut traces = vec![];
+    if !arena.arena.is_empty() {
+        collect_parity_traces(arena, 0, vec![], &mut traces);
+    }
+    traces
+}
+
+fn collect_parity_traces(
+    arena: &CallTraceArena,
+    idx: usize,
+    trace_address: Vec<usize>,
+    traces: &mut Vec<ParityTrace>,
+) {
+    let node = &arena.arena[idx];
+    let trace = &node.trace;
+    let create = is_create(trace.kind);
+    let output = output(&trace.output);
+
+    let action = if create {
+        ParityAction {
+            from: trace.caller,
+            call_type: None,
+            to: None,
+            value: trace.value,
+            input: None,
+            init: Some(input(&trace.data)),
+        }
+    } else {
+        ParityAction {
+            from: trace.caller,
+            call_type: Some(format!("{:?}", trace.kind).to_lowercase()),
+            to: Some(trace.address),
+            value: trace.value,
+            input: Some(input(&trace.data)),
+            init: None,
+        }
+    };
+    let result = trace.success.then(|| ParityResult {
+        gas_used: trace.gas_cost,
+        output: (!create).then(|| output.clone()),
+        address: create.then_some(trace.address),
+        code: create.then(|| output.clone()),
+    });
+
+    traces.push(ParityTrace {
+        action,
+        result,
+        error: (!trace.success).then(|| error(trace.status)),
+        subtraces: node.children.len(),
+        trace_address: trace_address.clone(),
+        kind: if create { "create" } else { "call" }.to_string(),
+    });
+    for (position, child) in node.children.iter().enumerate() {
+        let mut child_address = trace_address.clone();
+        child_address.push(position);
+        collect_parity_traces(arena, *child, child_address, traces);
+    }
+}
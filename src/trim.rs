@@ -0,0 +1,109 @@
+use ethers::abi::Address;
+use ethers::types::{Log, H256};
+use foundry_evm::trace::node::CallTraceNode;
+use foundry_evm::trace::{CallTraceArena, LogCallOrder};
+use serde::{Deserialize, Serialize};
+
+/// Logs kept in the response. A log must match both lists, an empty list matching any log.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogFilter {
+    /// Contracts emitting the logs.
+    #[serde(default)]
+    pub addresses: Vec<Address>,
+    /// Event signatures, matched against the first topic.
+    #[serde(default)]
+    pub topics: Vec<H256>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log: &Log) -> bool {
+        (self.addresses.is_empty() || self.addresses.contains(&log.address))
+            && (self.topics.is_empty()
+                || log
+                    .topics
+                    .first()
+                    .map_or(false, |topic| self.topics.contains(topic)))
+    }
+}
+
+/// How much `maxTraceDepth`, `maxTraceFrames` and `logFilter` left out of the response.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Trimmed {
+    /// Call frames left out of `trace`, `nestedTrace`, `callTracer` and `parityTrace`.
+    #[serde(rename = "traceFrames")]
+    pub trace_frames: usize,
+    /// Logs left out of `logs` and `decodedLogs`.
+    pub logs: usize,
+}
+
+/// The frames of `arena` at most `max_depth` calls deep, the top level call being at depth 0,
+/// and of those the first `max_frames` in execution order, with their indexes in `arena`. `None`
+/// if neither limit is set.
+pub fn trim_trace(
+    arena: &CallTraceArena,
+    max_depth: Option<usize>,
+    max_frames: Option<usize>,
+) -> Option<(CallTraceArena, Vec<usize>)> {
+    if max_depth.is_none() && max_frames.is_none() {
+        return None;
+    }
+
+    // Parents come before their children in the arena, so a frame's parent is decided first
+    let mut depths = Vec::with_capacity(arena.arena.len());
+    let mut new_indexes: Vec<Option<usize>> = Vec::with_capacity(arena.arena.len());
+    let mut kept = vec![];
+    for (idx, node) in arena.arena.iter().enumerate() {
+        let depth = node.parent.map_or(0, |parent| depths[parent] + 1);
+        depths.push(depth);
+        let parent_kept = node
+            .parent
+            .map_or(true, |parent| new_indexes[parent].is_some());
+        if parent_kept
+            && max_depth.map_or(true, |max_depth| depth <= max_depth)
+            && max_frames.map_or(true, |max_frames| kept.len() < max_frames)
+        {
+            new_indexes.push(Some(kept.len()));
+            kept.push(idx);
+        } else {
+            new_indexes.push(None);
+        }
+    }
+
+    let nodes = kept
+        .iter()
+        .map(|idx| {
+            let node = &arena.arena[*idx];
+            // Frames are cut off in execution order, so the children kept come first
+            let children: Vec<usize> = node
+                .children
+                .iter()
+                .filter_map(|child| new_indexes[*child])
+                .collect();
+            let ordering = node
+                .ordering
+                .iter()
+                .filter(|order| match order {
+                    LogCallOrder::Call(child) => *child < children.len(),
+                    LogCallOrder::Log(_) => true,
+                })
+                .cloned()
+                .collect();
+            CallTraceNode {
+                parent: node.parent.and_then(|parent| new_indexes[parent]),
+                idx: new_indexes[*idx].expect("kept frames have an index"),
+                children,
+                ordering,
+                ..node.clone()
+            }
+        })
+        .collect();
+
+    Some((CallTraceArena { arena: nodes }, kept))
+}
+
+/// What is known of the frames kept by `trim_trace`, from what is known of every frame.
+pub fn kept_frames<T: Clone>(frames: &[Option<T>], kept: &[usize]) -> Vec<Option<T>> {
+    kept.iter()
+        .map(|idx| frames.get(*idx).cloned().flatten())
+        .collect()
+}
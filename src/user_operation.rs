@@ -0,0 +1,397 @@
+use ethers::abi::{decode, encode, Address, ParamType, Token, Uint};
+use ethers::types::{Bytes, Log, H256};
+use ethers::utils::{id, keccak256};
+use serde::{Deserialize, Serialize};
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::errors::EvmError;
+use crate::evm::{CallOptions, CallRawRequest, Evm};
+use crate::simulation::{chain_id_to_fork_url, run, SimulationRequest, SimulationResponse};
+
+use super::config::Config;
+use super::pool::EvmPool;
+
+/// Gas limit the EntryPoint is called with, high enough for any single operation.
+const ENTRY_POINT_GAS_LIMIT: u64 = 30_000_000;
+
+/// `UserOperation` struct of EntryPoint v0.6, as a Solidity tuple.
+const USER_OPERATION_TUPLE: &str =
+    "(address,uint256,bytes,bytes,uint256,uint256,uint256,uint256,uint256,bytes,bytes)";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: Uint,
+    #[serde(rename = "initCode")]
+    pub init_code: Bytes,
+    #[serde(rename = "callData")]
+    pub call_data: Bytes,
+    #[serde(rename = "callGasLimit")]
+    pub call_gas_limit: Uint,
+    #[serde(rename = "verificationGasLimit")]
+    pub verification_gas_limit: Uint,
+    #[serde(rename = "preVerificationGas")]
+    pub pre_verification_gas: Uint,
+    #[serde(rename = "maxFeePerGas")]
+    pub max_fee_per_gas: Uint,
+    #[serde(rename = "maxPriorityFeePerGas")]
+    pub max_priority_fee_per_gas: Uint,
+    #[serde(rename = "paymasterAndData")]
+    pub paymaster_and_data: Bytes,
+    pub signature: Bytes,
+}
+
+impl UserOperation {
+    fn token(&self) -> Token {
+        Token::Tuple(vec![
+            Token::Address(self.sender),
+            Token::Uint(self.nonce),
+            Token::Bytes(self.init_code.to_vec()),
+            Token::Bytes(self.call_data.to_vec()),
+            Token::Uint(self.call_gas_limit),
+            Token::Uint(self.verification_gas_limit),
+            Token::Uint(self.pre_verification_gas),
+            Token::Uint(self.max_fee_per_gas),
+            Token::Uint(self.max_priority_fee_per_gas),
+            Token::Bytes(self.paymaster_and_data.to_vec()),
+            Token::Bytes(self.signature.to_vec()),
+        ])
+    }
+
+    fn paymaster(&self) -> Option<Address> {
+        self.paymaster_and_data.get(..20).map(Address::from_slice)
+    }
+
+    /// Calldata cost of the operation in a bundle of one, with the defaults of the reference
+    /// bundler: 21000 per bundle, 18300 per operation and 4 per word on top of the calldata.
+    fn pre_verification_gas(&self) -> u64 {
+        let packed = encode(&[self.token()]);
+        let calldata: u64 = packed
+            .iter()
+            .map(|byte| if *byte == 0 { 4 } else { 16 })
+            .sum();
+        let words = (packed.len() as u64 + 31) / 32;
+        21000 + 18300 + calldata + words * 4
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserOperationRequest {
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    /// EntryPoint v0.6 the operation is sent to.
+    #[serde(rename = "entryPoint")]
+    pub entry_point: Address,
+    #[serde(rename = "userOperation")]
+    pub user_operation: UserOperation,
+    /// Calls `handleOps` and receives its compensation, the zero address if not set.
+    pub beneficiary: Option<Address>,
+    #[serde(rename = "blockNumber")]
+    pub block_number: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidationResult {
+    pub valid: bool,
+    /// Gas used by validation, including `preVerificationGas`.
+    #[serde(rename = "preOpGas", default, skip_serializing_if = "Option::is_none")]
+    pub pre_op_gas: Option<Uint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefund: Option<Uint>,
+    #[serde(rename = "sigFailed", default, skip_serializing_if = "Option::is_none")]
+    pub sig_failed: Option<bool>,
+    #[serde(
+        rename = "validAfter",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub valid_after: Option<u64>,
+    #[serde(
+        rename = "validUntil",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub valid_until: Option<u64>,
+    /// Why validation failed, e.g. the `FailedOp` reason.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserOperationGasEstimates {
+    #[serde(rename = "preVerificationGas")]
+    pub pre_verification_gas: u64,
+    #[serde(rename = "verificationGasLimit")]
+    pub verification_gas_limit: Option<Uint>,
+    #[serde(rename = "callGasLimit")]
+    pub call_gas_limit: Option<Uint>,
+}
+
+/// Decoded `UserOperationEvent` emitted by `handleOps`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserOperationEvent {
+    #[serde(rename = "userOpHash")]
+    pub user_op_hash: Bytes,
+    pub success: bool,
+    #[serde(rename = "actualGasCost")]
+    pub actual_gas_cost: Uint,
+    #[serde(rename = "actualGasUsed")]
+    pub actual_gas_used: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserOperationResponse {
+    pub validation: ValidationResult,
+    #[serde(rename = "gasEstimates")]
+    pub gas_estimates: UserOperationGasEstimates,
+    /// Only set if validation succeeded.
+    #[serde(rename = "userOperationEvent")]
+    pub user_operation_event: Option<UserOperationEvent>,
+    pub paymaster: Option<Address>,
+    /// Decrease of the paymaster's EntryPoint deposit.
+    #[serde(rename = "paymasterDeduction")]
+    pub paymaster_deduction: Option<Uint>,
+    /// The `handleOps` call, only set if validation succeeded.
+    pub execution: Option<SimulationResponse>,
+}
+
+fn entry_point_call(
+    from: Address,
+    entry_point: Address,
+    signature: &str,
+    tokens: &[Token],
+) -> CallRawRequest {
+    let mut data = id(signature).to_vec();
+    data.extend(encode(tokens));
+
+    CallRawRequest {
+        from,
+        to: Some(entry_point),
+        data: Some(data.into()),
+        gas_limit: ENTRY_POINT_GAS_LIMIT,
+        ..Default::default()
+    }
+}
+
+/// Decodes the error `simulateValidation` always reverts with.
+fn validation_result(output: &[u8], revert_reason: Option<String>) -> ValidationResult {
+    let failed = |reason| ValidationResult {
+        valid: false,
+        pre_op_gas: None,
+        prefund: None,
+        sig_failed: None,
+        valid_after: None,
+        valid_until: None,
+        reason,
+    };
+    let (Some(selector), Some(data)) = (output.get(..4), output.get(4..)) else {
+        return failed(revert_reason);
+    };
+
+    let return_info = ParamType::Tuple(vec![
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Bool,
+        ParamType::Uint(48),
+        ParamType::Uint(48),
+        ParamType::Bytes,
+    ]);
+    let stake_info = ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Uint(256)]);
+    let validation_result = id(
+        "ValidationResult((uint256,uint256,bool,uint48,uint48,bytes),(uint256,uint256),(uint256,uint256),(uint256,uint256))",
+    );
+    let validation_result_with_aggregation = id(
+        "ValidationResultWithAggregation((uint256,uint256,bool,uint48,uint48,bytes),(uint256,uint256),(uint256,uint256),(uint256,uint256),(address,(uint256,uint256)))",
+    );
+
+    if selector == validation_result || selector == validation_result_with_aggregation {
+        let decoded = decode(
+            &[
+                return_info,
+                stake_info.clone(),
+                stake_info.clone(),
+                stake_info,
+            ],
+            data,
+        );
+        if let Ok(Some(Token::Tuple(info))) = decoded.map(|tokens| tokens.into_iter().next()) {
+            if let [Token::Uint(pre_op_gas), Token::Uint(prefund), Token::Bool(sig_failed), Token::Uint(valid_after), Token::Uint(valid_until), ..] =
+                info.as_slice()
+            {
+                return ValidationResult {
+                    valid: !sig_failed,
+                    pre_op_gas: Some(*pre_op_gas),
+                    prefund: Some(*prefund),
+                    sig_failed: Some(*sig_failed),
+                    valid_after: Some(valid_after.as_u64()),
+                    valid_until: Some(valid_until.as_u64()),
+                    reason: sig_failed.then(|| "signature validation failed".to_string()),
+                };
+            }
+        }
+    } else if selector == id("FailedOp(uint256,string)") {
+        if let Ok(tokens) = decode(&[ParamType::Uint(256), ParamType::String], data) {
+            if let Some(Token::String(reason)) = tokens.into_iter().nth(1) {
+                return failed(Some(reason));
+            }
+        }
+    }
+
+    failed(revert_reason)
+}
+
+fn user_operation_event(logs: &[Log], entry_point: Address) -> Option<UserOperationEvent> {
+    let topic =
+        keccak256("UserOperationEvent(bytes32,address,address,uint256,bool,uint256,uint256)");
+    let log = logs
+        .iter()
+        .find(|log| log.address == entry_point && log.topics.first() == Some(&H256::from(topic)))?;
+    let tokens = decode(
+        &[
+            ParamType::Uint(256),
+            ParamType::Bool,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ],
+        &log.data,
+    )
+    .ok()?;
+
+    match (tokens.as_slice(), log.topics.get(1)) {
+        (
+            [_, Token::Bool(success), Token::Uint(actual_gas_cost), Token::Uint(actual_gas_used)],
+            Some(user_op_hash),
+        ) => Some(UserOperationEvent {
+            user_op_hash: user_op_hash.as_bytes().to_vec().into(),
+            success: *success,
+            actual_gas_cost: *actual_gas_cost,
+            actual_gas_used: *actual_gas_used,
+        }),
+        _ => None,
+    }
+}
+
+/// EntryPoint deposit of `account`, from `balanceOf`.
+async fn deposit(
+    evm: &mut Evm,
+    from: Address,
+    entry_point: Address,
+    account: Address,
+) -> Result<Uint, EvmError> {
+    let call = entry_point_call(
+        from,
+        entry_point,
+        "balanceOf(address)",
+        &[Token::Address(account)],
+    );
+    let result = evm.call_raw(&call, CallOptions::default()).await?;
+
+    Ok(Uint::from_big_endian(
+        result.output.get(..32).unwrap_or_default(),
+    ))
+}
+
+/// Validates a user operation with `simulateValidation`, then executes it with `handleOps` like a
+/// bundler would.
+pub async fn simulate_user_operation(
+    request: UserOperationRequest,
+    config: Config,
+    pool: EvmPool,
+) -> Result<Json, Rejection> {
+    let fork_url = chain_id_to_fork_url(request.chain_id, &config)?;
+    let mut evm = pool.get(
+        request.chain_id,
+        fork_url,
+        request.block_number,
+        ENTRY_POINT_GAS_LIMIT,
+        config.etherscan_key,
+    );
+
+    let operation = &request.user_operation;
+    let beneficiary = request.beneficiary.unwrap_or_default();
+    let pre_verification_gas = operation.pre_verification_gas();
+
+    let simulate_validation = entry_point_call(
+        beneficiary,
+        request.entry_point,
+        &format!("simulateValidation({USER_OPERATION_TUPLE})"),
+        &[operation.token()],
+    );
+    let result = evm
+        .call_raw(&simulate_validation, CallOptions::default())
+        .await?;
+    let validation = validation_result(&result.output, result.revert_reason);
+
+    let verification_gas_limit = validation
+        .pre_op_gas
+        .map(|pre_op_gas| pre_op_gas.saturating_sub(operation.pre_verification_gas));
+    let paymaster = operation.paymaster();
+
+    if !validation.valid {
+        return Ok(warp::reply::json(&UserOperationResponse {
+            validation,
+            gas_estimates: UserOperationGasEstimates {
+                pre_verification_gas,
+                verification_gas_limit,
+                call_gas_limit: None,
+            },
+            user_operation_event: None,
+            paymaster,
+            paymaster_deduction: None,
+            execution: None,
+        }));
+    }
+
+    let deposit_before = match paymaster {
+        Some(paymaster) => {
+            Some(deposit(&mut evm, beneficiary, request.entry_point, paymaster).await?)
+        }
+        None => None,
+    };
+
+    let mut data = id(&format!("handleOps({USER_OPERATION_TUPLE}[],address)")).to_vec();
+    data.extend(encode(&[
+        Token::Array(vec![operation.token()]),
+        Token::Address(beneficiary),
+    ]));
+    let handle_ops = SimulationRequest {
+        chain_id: request.chain_id,
+        from: beneficiary,
+        to: Some(request.entry_point),
+        data: Some(data.into()),
+        gas_limit: ENTRY_POINT_GAS_LIMIT,
+        decode_logs: Some(true),
+        ..Default::default()
+    };
+    let execution = run(&mut evm, handle_ops, true).await?;
+
+    let event = user_operation_event(&execution.logs, request.entry_point);
+    // Execution gas is everything used after validation, including the paymaster's postOp
+    let call_gas_limit = match (&event, validation.pre_op_gas) {
+        (Some(event), Some(pre_op_gas)) => Some(event.actual_gas_used.saturating_sub(pre_op_gas)),
+        _ => None,
+    };
+
+    let paymaster_deduction = match (paymaster, deposit_before) {
+        (Some(paymaster), Some(deposit_before)) => {
+            let deposit_after =
+                deposit(&mut evm, beneficiary, request.entry_point, paymaster).await?;
+            Some(deposit_before.saturating_sub(deposit_after))
+        }
+        _ => None,
+    };
+
+    Ok(warp::reply::json(&UserOperationResponse {
+        validation,
+        gas_estimates: UserOperationGasEstimates {
+            pre_verification_gas,
+            verification_gas_limit,
+            call_gas_limit,
+        },
+        user_operation_event: event,
+        paymaster,
+        paymaster_deduction,
+        execution: Some(execution),
+    }))
+}
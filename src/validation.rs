@@ -0,0 +1,244 @@
+use bytes::Bytes;
+use ethers::abi::Address;
+use ethers::utils::to_checksum;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use warp::Rejection;
+
+use crate::errors::{InvalidBodyError, InvalidRequestError, UnsupportedApiVersionError};
+use crate::simulation::SimulationRequest;
+
+/// Versions of the request schema accepted in `apiVersion`.
+pub const SUPPORTED_API_VERSIONS: &[u64] = &[1];
+
+/// Largest init code of a deployment, as limited on chain by EIP-3860.
+const MAX_INITCODE_SIZE: usize = 2 * 24576;
+
+/// A field of a versioned request which failed validation, with its path in the request, e.g.
+/// `stateOverrides.0x….balance`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Parses a simulation request. Requests without `apiVersion` are parsed as they always were,
+/// unknown fields being ignored. Versioned requests are validated strictly: unknown fields,
+/// mixed-case addresses with a wrong EIP-55 checksum and oversized init code are rejected,
+/// every failing field being listed.
+pub(crate) async fn parse_simulation_request(body: Bytes) -> Result<SimulationRequest, Rejection> {
+    let value: Value = serde_json::from_slice(&body).map_err(InvalidBodyError)?;
+    let mut errors = vec![];
+    let request = validate(&value, "", &mut errors)?;
+
+    match request {
+        _ if !errors.is_empty() => Err(InvalidRequestError(errors).into()),
+        Some(request) => Ok(request),
+        None => Ok(serde_json::from_slice(&body).map_err(InvalidBodyError)?),
+    }
+}
+
+/// Parses a body holding simulation requests, like a bundle or a batch. Each request with an
+/// `apiVersion` is validated like the body of `/simulate`, its errors listing the field with the
+/// path of the request, e.g. `transactions[1].gaslimit`.
+pub(crate) async fn parse_simulation_requests<T: DeserializeOwned>(
+    body: Bytes,
+) -> Result<T, Rejection> {
+    let value: Value = serde_json::from_slice(&body).map_err(InvalidBodyError)?;
+    let mut errors = vec![];
+    for (path, request) in simulation_requests(&value) {
+        validate(request, &path, &mut errors)?;
+    }
+
+    if !errors.is_empty() {
+        return Err(InvalidRequestError(errors).into());
+    }
+    Ok(serde_json::from_slice(&body).map_err(InvalidBodyError)?)
+}
+
+/// The simulation requests of a body with their path: the items of a list, the `transaction` and
+/// `transactions` of a bundle or watch, those of each of its `chains`, or else the body itself.
+fn simulation_requests(value: &Value) -> Vec<(String, &Value)> {
+    let Value::Object(fields) = value else {
+        return items(String::new(), Some(value));
+    };
+    if let Some(Value::Array(chains)) = fields.get("chains") {
+        return chains
+            .iter()
+            .enumerate()
+            .flat_map(|(index, chain)| {
+                items(
+                    format!("chains[{index}].transactions"),
+                    chain.get("transactions"),
+                )
+            })
+            .collect();
+    }
+    if fields.contains_key("transactions") || fields.contains_key("transaction") {
+        let mut requests = items("transactions".to_string(), fields.get("transactions"));
+        requests.extend(
+            fields
+                .get("transaction")
+                .map(|transaction| ("transaction".to_string(), transaction)),
+        );
+        return requests;
+    }
+    vec![(String::new(), value)]
+}
+
+/// The items of `value` with their path, if it's a list.
+fn items(path: String, value: Option<&Value>) -> Vec<(String, &Value)> {
+    match value {
+        Some(Value::Array(items)) => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (format!("{path}[{index}]"), item))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Validates the request `value` at `path` strictly if it has an `apiVersion`, adding the fields
+/// which failed to `errors`. `None` if it isn't versioned, or failed.
+fn validate(
+    value: &Value,
+    path: &str,
+    errors: &mut Vec<FieldError>,
+) -> Result<Option<SimulationRequest>, Rejection> {
+    let api_version = match value.get("apiVersion") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(api_version) => api_version.as_u64(),
+    };
+    match api_version {
+        Some(api_version) if SUPPORTED_API_VERSIONS.contains(&api_version) => {}
+        _ => {
+            return Err(UnsupportedApiVersionError(value["apiVersion"].clone()).into());
+        }
+    }
+
+    check_checksums(value, path, errors);
+
+    let mut unknown = vec![];
+    let deserializer = serde_ignored::Deserializer::new(value, |field| {
+        unknown.push(field_path(&field));
+    });
+    let request: Result<SimulationRequest, _> = serde_path_to_error::deserialize(deserializer);
+    let known = known_fields();
+    for field in unknown {
+        let message = match suggestion(&field, &known) {
+            Some(known) => format!("unknown field, did you mean `{known}`?"),
+            None => "unknown field".to_string(),
+        };
+        errors.push(FieldError::new(join(path, &field), message));
+    }
+
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => {
+            errors.push(FieldError::new(
+                join(path, &err.path().to_string()),
+                err.into_inner().to_string(),
+            ));
+            return Ok(None);
+        }
+    };
+    let data_size = request.data.as_ref().map_or(0, |data| data.len());
+    if request.to.is_none() && data_size > MAX_INITCODE_SIZE {
+        errors.push(FieldError::new(
+            join(path, "data"),
+            format!("init code of {data_size} bytes exceeds {MAX_INITCODE_SIZE}"),
+        ));
+    }
+
+    Ok(Some(request))
+}
+
+/// The path of `key` within the request at `path`.
+fn join(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        path => format!("{path}.{key}"),
+    }
+}
+
+/// Top level fields of a simulation request.
+fn known_fields() -> Vec<String> {
+    match serde_json::to_value(SimulationRequest::default()) {
+        Ok(Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+/// The top level field an unknown one was likely meant to be, e.g. `gasLimit` for `gaslimit` or
+/// `gas_limit`.
+fn suggestion<'a>(field: &str, known: &'a [String]) -> Option<&'a str> {
+    let normalize = |field: &str| field.replace(['_', '-'], "").to_lowercase();
+    let field = normalize(field);
+    known
+        .iter()
+        .find(|known| normalize(known) == field)
+        .map(String::as_str)
+}
+
+fn field_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{index}]", field_path(parent)),
+        serde_ignored::Path::Map { parent, key } => match field_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => field_path(parent),
+    }
+}
+
+/// Mixed-case addresses must match their EIP-55 checksum, all lowercase or uppercase ones aren't
+/// checksummed. Object keys, like those of `stateOverrides`, are checked too.
+fn check_checksums(value: &Value, path: &str, errors: &mut Vec<FieldError>) {
+    match value {
+        Value::String(string) => {
+            if let Some(error) = checksum_error(string) {
+                errors.push(FieldError::new(path, error));
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                check_checksums(item, &format!("{path}[{index}]"), errors);
+            }
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                if let Some(error) = checksum_error(key) {
+                    errors.push(FieldError::new(join(path, key), error));
+                }
+                check_checksums(field, &join(path, key), errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn checksum_error(string: &str) -> Option<String> {
+    let hex = string.strip_prefix("0x")?;
+    if hex.len() != 40
+        || hex.to_lowercase() == hex
+        || hex.to_uppercase() == hex
+        || !hex.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    let checksummed = to_checksum(&string.parse::<Address>().ok()?, None);
+    (checksummed != string).then(|| format!("invalid checksum, expected {checksummed}"))
+}
@@ -0,0 +1,155 @@
+use ethers::abi::{Address, Hash, Uint};
+use ethers::types::Log;
+use ethers::utils::keccak256;
+use foundry_evm::trace::CallTraceArena;
+use foundry_evm::CallKind;
+use revm::Return;
+use serde::{Deserialize, Serialize};
+
+use crate::evm::Evm;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WarningKind {
+    /// ERC-20 approval of at least `type(uint160).max`, which covers both `type(uint256).max`
+    /// and the largest Permit2 allowance.
+    UnlimitedApproval,
+    /// ERC-721 or ERC-1155 approval of every token of the owner.
+    ApprovalForAll,
+    OwnershipTransfer,
+    ProxyAdminChange,
+    ProxyUpgrade,
+    /// Delegatecall to a contract without verified source on Etherscan.
+    UnverifiedDelegatecall,
+    Selfdestruct,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// Contract the warning is about, e.g. the token approved or the proxy upgraded.
+    pub address: Address,
+    pub message: String,
+}
+
+fn event_signature(signature: &str) -> Hash {
+    Hash::from(keccak256(signature))
+}
+
+fn topic_address(topic: &Hash) -> Address {
+    Address::from_slice(&topic.as_bytes()[12..])
+}
+
+fn log_warning(log: &Log) -> Option<Warning> {
+    let signature = log.topics.first()?;
+    let address = log.address;
+
+    let (kind, message) = if *signature == event_signature("Approval(address,address,uint256)") {
+        // ERC-721 approvals index the token ID and have no data
+        if log.topics.len() != 3 || log.data.len() < 32 {
+            return None;
+        }
+        let amount = Uint::from_big_endian(&log.data[..32]);
+        let unlimited = (Uint::one() << 160) - 1;
+        if amount < unlimited {
+            return None;
+        }
+        (
+            WarningKind::UnlimitedApproval,
+            format!(
+                "{:?} approves {:?} to spend all its tokens",
+                topic_address(&log.topics[1]),
+                topic_address(&log.topics[2])
+            ),
+        )
+    } else if *signature == event_signature("ApprovalForAll(address,address,bool)") {
+        if log.topics.len() != 3 || log.data.len() < 32 || log.data[31] == 0 {
+            return None;
+        }
+        (
+            WarningKind::ApprovalForAll,
+            format!(
+                "{:?} approves {:?} to transfer all its tokens",
+                topic_address(&log.topics[1]),
+                topic_address(&log.topics[2])
+            ),
+        )
+    } else if *signature == event_signature("OwnershipTransferred(address,address)") {
+        if log.topics.len() != 3 {
+            return None;
+        }
+        (
+            WarningKind::OwnershipTransfer,
+            format!(
+                "Ownership transferred from {:?} to {:?}",
+                topic_address(&log.topics[1]),
+                topic_address(&log.topics[2])
+            ),
+        )
+    } else if *signature == event_signature("AdminChanged(address,address)") {
+        // EIP-1967 doesn't index the admins
+        if log.data.len() < 64 {
+            return None;
+        }
+        (
+            WarningKind::ProxyAdminChange,
+            format!(
+                "Proxy admin changed from {:?} to {:?}",
+                Address::from_slice(&log.data[12..32]),
+                Address::from_slice(&log.data[44..64])
+            ),
+        )
+    } else if *signature == event_signature("Upgraded(address)") {
+        if log.topics.len() != 2 {
+            return None;
+        }
+        (
+            WarningKind::ProxyUpgrade,
+            format!(
+                "Proxy upgraded to implementation {:?}",
+                topic_address(&log.topics[1])
+            ),
+        )
+    } else {
+        return None;
+    };
+
+    Some(Warning {
+        kind,
+        address,
+        message,
+    })
+}
+
+/// Flags risky patterns in an executed transaction: unlimited and approve-all approvals,
+/// ownership and proxy admin transfers, proxy upgrades, delegatecalls to unverified contracts and
+/// selfdestructs. Delegatecalls can only be checked with Etherscan, after the contracts of the
+/// trace were identified.
+pub(crate) fn warnings(evm: &Evm, arena: &CallTraceArena, logs: &[Log]) -> Vec<Warning> {
+    let mut warnings = vec![];
+
+    // Frames of a reverted subcall left no changes behind
+    for node in arena.arena.iter().filter(|node| node.trace.success) {
+        let trace = &node.trace;
+        if trace.kind == CallKind::DelegateCall && evm.is_verified(trace.address) == Some(false) {
+            warnings.push(Warning {
+                kind: WarningKind::UnverifiedDelegatecall,
+                address: trace.address,
+                message: format!(
+                    "{:?} delegatecalls unverified contract {:?}",
+                    trace.caller, trace.address
+                ),
+            });
+        }
+        if trace.status == Return::SelfDestruct {
+            warnings.push(Warning {
+                kind: WarningKind::Selfdestruct,
+                address: trace.address,
+                message: format!("{:?} selfdestructs", trace.address),
+            });
+        }
+    }
+    warnings.extend(logs.iter().filter_map(log_warning));
+
+    warnings
+}
@@ -0,0 +1,464 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::providers::{Http, Middleware, Provider};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use uuid::Uuid;
+use warp::hyper::StatusCode;
+use warp::reply::Json;
+use warp::{Rejection, Reply};
+
+use crate::bundle::{execute_bundle, BundleOptions, TransactionStatus};
+use crate::errors::{
+    error_message, BundleTooLargeError, ErrorMessage, InvalidRequestError, SimulationError,
+    TooManyWatchesError, WatchNotFoundError,
+};
+use crate::simulation::{chain_id_to_fork_url, run, SimulationRequest};
+use crate::validation::FieldError;
+use crate::webhook;
+
+use super::config::Config;
+use super::history::History;
+use super::pool::EvmPool;
+
+/// How often the RPC is asked for a new block, for watches without an `interval`.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Results kept per watch, the oldest are dropped first.
+const MAX_RESULTS: usize = 100;
+
+/// Relative change of the gas used, in percent, which counts as a change of the outcome.
+const DEFAULT_GAS_CHANGE_PERCENT: f64 = 10.0;
+
+/// A transaction, or the transactions of a bundle, to re-simulate at the latest block until the
+/// watch is deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<Box<SimulationRequest>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<SimulationRequest>,
+    #[serde(
+        rename = "bundleOptions",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bundle_options: Option<BundleOptions>,
+    /// Seconds between re-simulations, on every new block if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    /// Relative change of the gas used, in percent, which counts as a change, 10 if not set.
+    #[serde(
+        rename = "gasChangePercent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gas_change_percent: Option<f64>,
+    /// Where a `WatchChange` is POSTed whenever the outcome changes.
+    #[serde(
+        rename = "callbackUrl",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub callback_url: Option<String>,
+}
+
+/// Outcome of a re-simulation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WatchResult {
+    /// Not set if the simulation failed before forking a block.
+    #[serde(
+        rename = "blockNumber",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub block_number: Option<u64>,
+    /// Unix timestamp in seconds.
+    #[serde(rename = "checkedAt")]
+    pub checked_at: u64,
+    /// Whether every transaction succeeded.
+    pub success: bool,
+    /// Of every executed transaction together.
+    #[serde(rename = "gasUsed")]
+    pub gas_used: u64,
+    /// Why the simulation, or the first transaction of the bundle which failed, could not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorMessage>,
+    /// Of the executed transactions in the history.
+    #[serde(rename = "simulationIds")]
+    pub simulation_ids: Vec<Uuid>,
+    /// Whether the outcome differs from the previous result.
+    pub changed: bool,
+}
+
+impl WatchResult {
+    fn failed(block_number: Option<u64>, err: &Rejection) -> Self {
+        WatchResult {
+            block_number,
+            checked_at: now(),
+            success: false,
+            gas_used: 0,
+            error: Some(error_message(err).0),
+            simulation_ids: vec![],
+            changed: false,
+        }
+    }
+
+    /// A transaction started or stopped succeeding, failed for another reason, or its gas used
+    /// changed by more than `gas_change_percent`.
+    fn differs_from(&self, previous: &WatchResult, gas_change_percent: f64) -> bool {
+        let gas_change = previous.gas_used.abs_diff(self.gas_used) as f64 * 100.0
+            / previous.gas_used.max(1) as f64;
+        let message = |result: &WatchResult| result.error.as_ref().map(|err| err.message.clone());
+
+        self.success != previous.success
+            || message(self) != message(previous)
+            || gas_change > gas_change_percent
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Watch {
+    #[serde(rename = "watchId")]
+    pub watch_id: Uuid,
+    #[serde(rename = "chainId")]
+    pub chain_id: u64,
+    /// Unix timestamp in seconds.
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    /// Re-simulations run so far.
+    pub runs: u64,
+    /// The latest results, oldest first.
+    pub results: VecDeque<WatchResult>,
+}
+
+/// Body of the callbacks of a watch.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WatchChange {
+    #[serde(rename = "watchId")]
+    pub watch_id: Uuid,
+    pub previous: WatchResult,
+    pub current: WatchResult,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+struct WatchEntry {
+    watch: Watch,
+    task: JoinHandle<()>,
+}
+
+/// Watches re-simulating in the background until deleted. Cheap to clone, clones share the
+/// watches.
+#[derive(Clone, Default)]
+pub struct Watches(Arc<Mutex<HashMap<Uuid, WatchEntry>>>);
+
+impl Watches {
+    pub fn get(&self, watch_id: Uuid) -> Option<Watch> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&watch_id)
+            .map(|entry| entry.watch.clone())
+    }
+
+    /// Stops the watch, `false` if there was none.
+    pub fn remove(&self, watch_id: Uuid) -> bool {
+        match self.0.lock().unwrap().remove(&watch_id) {
+            Some(entry) => {
+                entry.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn record(&self, watch_id: Uuid, result: WatchResult) {
+        if let Some(entry) = self.0.lock().unwrap().get_mut(&watch_id) {
+            let watch = &mut entry.watch;
+            watch.runs += 1;
+            if watch.results.len() == MAX_RESULTS {
+                watch.results.pop_front();
+            }
+            watch.results.push_back(result);
+        }
+    }
+}
+
+/// Re-simulates at every new block of `provider`, or every `interval` seconds at the latest
+/// block, and sends a callback whenever the outcome changes.
+async fn run_watch(
+    watch_id: Uuid,
+    request: WatchRequest,
+    provider: Option<Provider<Http>>,
+    watches: Watches,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) {
+    let period = request.interval.map_or(BLOCK_POLL_INTERVAL, |interval| {
+        Duration::from_secs(interval.max(1))
+    });
+    let gas_change_percent = request
+        .gas_change_percent
+        .unwrap_or(DEFAULT_GAS_CHANGE_PERCENT);
+    let mut ticks = tokio::time::interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut last_block = None;
+    let mut previous: Option<WatchResult> = None;
+    loop {
+        ticks.tick().await;
+        let block_number = match &provider {
+            Some(provider) => match provider.get_block_number().await {
+                Ok(block_number) if Some(block_number.as_u64()) == last_block => continue,
+                Ok(block_number) => Some(block_number.as_u64()),
+                Err(err) => {
+                    log::warn!(
+                        target: "ts::watch",
+                        "Failed to poll the block of watch {watch_id}: {err}"
+                    );
+                    continue;
+                }
+            },
+            None => None,
+        };
+        last_block = block_number;
+
+        // Run on its own task so that a panicking simulation fails the run, not the watch
+        let mut result = tokio::spawn(check(
+            request.clone(),
+            block_number,
+            config.clone(),
+            pool.clone(),
+            history.clone(),
+        ))
+        .await
+        .unwrap_or_else(|err| {
+            let err = warp::reject::custom(SimulationError::Evm(err.into()));
+            WatchResult::failed(block_number, &err)
+        });
+
+        if let Some(previous) = &previous {
+            result.changed = result.differs_from(previous, gas_change_percent);
+            if let (true, Some(url)) = (result.changed, &request.callback_url) {
+                let change = WatchChange {
+                    watch_id,
+                    previous: previous.clone(),
+                    current: result.clone(),
+                };
+                webhook::send(url.clone(), &change, &config);
+            }
+        }
+        watches.record(watch_id, result.clone());
+        previous = Some(result);
+    }
+}
+
+/// Simulates the watched transactions at `block_number`, the latest block if not set.
+async fn check(
+    request: WatchRequest,
+    block_number: Option<u64>,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> WatchResult {
+    let result = match request.transaction {
+        Some(transaction) => {
+            check_transaction(*transaction, block_number, config, pool, history).await
+        }
+        None => {
+            check_bundle(
+                request.transactions,
+                request.bundle_options.unwrap_or_default(),
+                block_number,
+                config,
+                pool,
+                history,
+            )
+            .await
+        }
+    };
+
+    result.unwrap_or_else(|err| WatchResult::failed(block_number, &err))
+}
+
+async fn check_transaction(
+    transaction: SimulationRequest,
+    block_number: Option<u64>,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<WatchResult, Rejection> {
+    let transaction = SimulationRequest {
+        block_number,
+        block_hash: None,
+        ..transaction
+    };
+    let fork_url = chain_id_to_fork_url(transaction.chain_id, &config)?;
+    let mut evm = pool.get(
+        transaction.chain_id,
+        fork_url,
+        transaction.block_number,
+        transaction.gas_limit,
+        config.etherscan_key,
+    );
+
+    let response = run(&mut evm, transaction.clone(), false).await?;
+    history.record(&transaction, &response);
+
+    Ok(WatchResult {
+        block_number: Some(response.block_number),
+        checked_at: now(),
+        success: response.success,
+        gas_used: response.gas_used,
+        error: None,
+        simulation_ids: vec![response.simulation_id],
+        changed: false,
+    })
+}
+
+async fn check_bundle(
+    mut transactions: Vec<SimulationRequest>,
+    options: BundleOptions,
+    block_number: Option<u64>,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+) -> Result<WatchResult, Rejection> {
+    // The whole bundle runs on the block, not on the blocks it was first simulated on
+    for (index, transaction) in transactions.iter_mut().enumerate() {
+        transaction.block_number = if index == 0 { block_number } else { None };
+        transaction.block_hash = None;
+    }
+    let response = execute_bundle(transactions, options, config, pool, Some(history), None).await?;
+
+    Ok(WatchResult {
+        block_number: response
+            .results
+            .first()
+            .map(|result| result.block_number)
+            .or(block_number),
+        checked_at: now(),
+        success: response
+            .statuses
+            .iter()
+            .all(|status| status.status == TransactionStatus::Success),
+        gas_used: response.bundle_summary.total_gas_used,
+        error: response
+            .statuses
+            .iter()
+            .find_map(|status| status.error.clone()),
+        simulation_ids: response
+            .results
+            .iter()
+            .map(|result| result.simulation_id)
+            .collect(),
+        changed: false,
+    })
+}
+
+/// Starts re-simulating the transaction or bundle, the first time right away, and answers with a
+/// `201` and the watch.
+pub async fn create_watch(
+    request: WatchRequest,
+    config: Config,
+    pool: EvmPool,
+    history: History,
+    watches: Watches,
+) -> Result<impl Reply, Rejection> {
+    let chain_id = match (&request.transaction, request.transactions.first()) {
+        (Some(transaction), None) => transaction.chain_id,
+        (None, Some(transaction)) => transaction.chain_id,
+        _ => {
+            return Err(warp::reject::custom(InvalidRequestError(vec![
+                FieldError {
+                    field: "transaction".to_string(),
+                    message: "exactly one of transaction and transactions must be set".to_string(),
+                },
+            ])))
+        }
+    };
+    if request
+        .transactions
+        .iter()
+        .any(|transaction| transaction.chain_id != chain_id)
+    {
+        return Err(warp::reject::custom(SimulationError::MultipleChainIds));
+    }
+    if request.transactions.len() > config.max_bundle_size {
+        return Err(warp::reject::custom(BundleTooLargeError));
+    }
+    if let Some(url) = &request.callback_url {
+        webhook::check_callback_url(url, &config).await?;
+    }
+    let fork_url = chain_id_to_fork_url(chain_id, &config)?;
+    let provider = match request.interval {
+        Some(_) => None,
+        None => Some(
+            Provider::<Http>::try_from(fork_url).map_err(|err| SimulationError::Rpc(err.into()))?,
+        ),
+    };
+
+    let mut entries = watches.0.lock().unwrap();
+    if entries.len() >= config.max_watches {
+        return Err(warp::reject::custom(TooManyWatchesError));
+    }
+    let watch = Watch {
+        watch_id: Uuid::new_v4(),
+        chain_id,
+        created_at: now(),
+        interval: request.interval,
+        runs: 0,
+        results: VecDeque::new(),
+    };
+    let task = tokio::spawn(run_watch(
+        watch.watch_id,
+        request,
+        provider,
+        watches.clone(),
+        config,
+        pool,
+        history,
+    ));
+    entries.insert(
+        watch.watch_id,
+        WatchEntry {
+            watch: watch.clone(),
+            task,
+        },
+    );
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&watch),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn get_watch(watch_id: Uuid, watches: Watches) -> Result<Json, Rejection> {
+    match watches.get(watch_id) {
+        Some(watch) => Ok(warp::reply::json(&watch)),
+        None => Err(warp::reject::custom(WatchNotFoundError)),
+    }
+}
+
+pub async fn delete_watch(watch_id: Uuid, watches: Watches) -> Result<impl Reply, Rejection> {
+    if !watches.remove(watch_id) {
+        return Err(warp::reject::custom(WatchNotFoundError));
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        StatusCode::NO_CONTENT,
+    ))
+}
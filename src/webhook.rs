@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use ethers::utils::hex;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use warp::Rejection;
+
+use crate::errors::InvalidCallbackUrlError;
+use crate::public_url::resolve_public;
+
+use super::config::Config;
+
+/// Header carrying `sha256=<hex HMAC of the body>` if `WEBHOOK_SECRET` is set.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Attempts at delivering a callback, waiting twice as long after each failure.
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a callback URL may take to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checks that a callback URL is an HTTP(S) URL, whose host only resolves to public addresses
+/// unless `ALLOW_PRIVATE_URLS` is set, before the simulation runs.
+pub async fn check_callback_url(url: &str, config: &Config) -> Result<(), Rejection> {
+    let invalid = |reason: &str| warp::reject::custom(InvalidCallbackUrlError(reason.to_string()));
+    let url = reqwest::Url::parse(url).map_err(|_| invalid("must be an HTTP(S) URL"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("must be an HTTP(S) URL"));
+    }
+    if !config.allow_private_urls {
+        resolve_public(&url)
+            .await
+            .map_err(|reason| invalid(&reason))?;
+    }
+
+    Ok(())
+}
+
+/// A client posting to `url` without following redirects, connecting to the public address its
+/// host resolves to now, so that neither a redirect nor the host resolving again to another
+/// address lead to a private one.
+async fn client(url: &str, allow_private_urls: bool) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    if allow_private_urls {
+        return builder.build().map_err(|err| err.to_string());
+    }
+
+    let url = reqwest::Url::parse(url).map_err(|err| err.to_string())?;
+    let addresses = resolve_public(&url).await?;
+    let builder = match url.domain() {
+        Some(domain) => builder.resolve(domain, addresses[0]),
+        None => builder,
+    };
+    builder.build().map_err(|err| err.to_string())
+}
+
+/// HMAC-SHA256 of the body, hex encoded, for receivers to check a callback came from here.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs `payload` to `url` in the background, signed with `WEBHOOK_SECRET`, retrying on network
+/// errors, `429`s and `5xx`s.
+pub fn send<T: Serialize>(url: String, payload: &T, config: &Config) {
+    let body = serde_json::to_vec(payload).expect("callbacks must be serializable");
+    let secret = config.webhook_secret.clone();
+    let allow_private_urls = config.allow_private_urls;
+    tokio::spawn(async move {
+        let client = match client(&url, allow_private_urls).await {
+            Ok(client) => client,
+            Err(err) => {
+                log::warn!(target: "ts::webhook", "Callback not sent: {err}");
+                return;
+            }
+        };
+
+        let mut delay = FIRST_RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut request = client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .body(body.clone());
+            if let Some(secret) = &secret {
+                request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response)
+                    if !response.status().is_server_error()
+                        && response.status().as_u16() != 429 =>
+                {
+                    log::warn!(
+                        target: "ts::webhook",
+                        "Callback rejected with {}, not retrying",
+                        response.status()
+                    );
+                    return;
+                }
+                Ok(response) => response.status().to_string(),
+                Err(err) => err.without_url().to_string(),
+            };
+            log::warn!(
+                target: "ts::webhook",
+                "Callback attempt {attempt}/{MAX_ATTEMPTS} failed: {error}"
+            );
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    });
+}
@@ -1,11 +1,69 @@
 use std::fs::File;
+use std::time::Duration;
 
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{transaction::eip2718::TypedTransaction, Address, TransactionRequest, H256},
+};
+use foundry_evm::CallKind;
 use revm::Return;
 use transaction_simulator::{
+    abis::RegisteredAbi,
+    access_list::AccessListResponse,
+    admission::AdmissionQueue,
+    assets::AssetType,
+    auth::{with_api_key, ApiKeyPolicy},
+    batch::BatchResult,
+    bundle::{
+        BundleEvent, BundleOptions, BundleResponse, BundleResult, MultiChainBundleResponse,
+        TransactionStatus,
+    },
+    chains::ChainInfo,
+    cli::{self, Command, CommandError},
     config::get_config,
-    errors::{handle_rejection, ErrorMessage},
-    simulate_routes,
-    simulation::{SimulationRequest, SimulationResponse},
+    diff::SimulationDiff,
+    errors::{handle_rejection, ErrorMessage, SimulationError},
+    estimate::GasEstimateResponse,
+    export::ExportedSimulation,
+    fixture::FixtureExport,
+    fork::{
+        BalanceResponse, CodeResponse, DealResponse, ForkResponse, SnapshotResponse,
+        StorageResponse, TokenBalancesResponse,
+    },
+    grpc::{
+        proto::{
+            bundle_event::Event, simulator_client::SimulatorClient, BundleRequest,
+            SimulationRequest as GrpcSimulationRequest,
+        },
+        GrpcService,
+    },
+    health,
+    health::ReadinessResponse,
+    history::SimulationRecord,
+    jobs::{Job, JobStatus},
+    metrics,
+    policy::{PolicyAction, PolicyRule},
+    pool::EvmPool,
+    precompiles::{EnvironmentValue, PrecompileStub, StubFunction, StubValue},
+    proxies::ProxyKind,
+    rate_limit::{with_rate_limit, RateLimiter},
+    ready,
+    replay::ReplayResponse,
+    request_id::{with_request_id, REQUEST_ID_HEADER},
+    rpc::{RpcResponse, StructLogTrace},
+    server::{cors, serve},
+    simulate_routes, simulate_routes_with_admission,
+    simulate_v1::SimulatedBlock,
+    simulation::{AccountDiff, CodeChangeKind, SimulationRequest, SimulationResponse},
+    simulator::Simulator,
+    stream::StreamEvent,
+    sweep::SweepResponse,
+    tenderly::{TenderlyBundleResponse, TenderlySimulationResponse},
+    user_operation::UserOperationResponse,
+    validation::FieldError,
+    warnings::WarningKind,
+    watch::Watch,
+    webhook,
 };
 use warp::Filter;
 
@@ -39,7 +97,282 @@ async fn post_simulate_file() {
     let expected: SimulationResponse =
         serde_json::from_reader(file).expect("file should be proper JSON");
 
-    assert_eq!(body, expected);
+    assert!(body.block_hash.is_some());
+    assert!(body.timestamp > 0);
+    assert!(body.block_env.is_some());
+
+    // IDs are random and the block is checked above, everything else must match
+    assert_eq!(
+        SimulationResponse {
+            simulation_id: expected.simulation_id,
+            block_hash: expected.block_hash,
+            timestamp: expected.timestamp,
+            block_env: expected.block_env.clone(),
+            ..body
+        },
+        expected
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_return_data() {
+    let filter = filter();
+
+    // decimals() of WETH
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+      "data": "0x313ce567",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(
+        ethers::utils::hex::encode(&body.return_data),
+        format!("{:064x}", 18)
+    );
+    assert_eq!(body.decoded_return_data, None);
+
+    json["formatTrace"] = serde_json::json!(true);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(
+        ethers::utils::hex::encode(&body.return_data),
+        format!("{:064x}", 18)
+    );
+    assert_eq!(body.decoded_return_data, Some(vec!["18".to_string()]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_latest_block_is_pinned() {
+    let filter = filter();
+
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let latest: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(latest.block_number > 16784600);
+    assert!(latest.block_hash.is_some());
+    assert!(latest.timestamp > 0);
+
+    // Simulating at the reported block runs against the same block
+    json["blockNumber"] = latest.block_number.into();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let pinned: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(pinned.block_number, latest.block_number);
+    assert_eq!(pinned.block_hash, latest.block_hash);
+    assert_eq!(pinned.timestamp, latest.timestamp);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_cached() {
+    let mut config = get_config();
+    config.simulation_cache_ttl = Duration::from_secs(60);
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+          "gasLimit": 21000,
+          "value": "100000",
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let first: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(first.cached, false);
+
+    // Same request with its fields in another order
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&serde_json::json!({
+          "blockNumber": 16784600,
+          "value": "100000",
+          "gasLimit": 21000,
+          "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "chainId": 1
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let second: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(second.cached, true);
+    assert_eq!(
+        SimulationResponse {
+            cached: false,
+            ..second
+        },
+        first
+    );
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+          "gasLimit": 21000,
+          "value": "200000",
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    let other: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(other.cached, false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_cache_expired() {
+    let mut config = get_config();
+    config.simulation_cache_ttl = Duration::from_secs(1);
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "300000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let first: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(first.cached, false);
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let second: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(second.cached, false);
+    assert_ne!(second.simulation_id, first.simulation_id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_cached_concurrently() {
+    let mut config = get_config();
+    config.simulation_cache_ttl = Duration::from_secs(60);
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "400000",
+      "blockNumber": 16784600
+    });
+
+    let (first, second) = tokio::join!(
+        warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&json)
+            .reply(&filter),
+        warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&json)
+            .reply(&filter)
+    );
+
+    assert_eq!(first.status(), 200);
+    assert_eq!(second.status(), 200);
+
+    let first: SimulationResponse = serde_json::from_slice(&first.body()).unwrap();
+    let second: SimulationResponse = serde_json::from_slice(&second.body()).unwrap();
+
+    // Executed once, the other waited for its response
+    assert_ne!(first.cached, second.cached);
+    assert_eq!(first.simulation_id, second.simulation_id);
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -199,6 +532,57 @@ async fn post_simulate_bundle_second_reverts() {
     assert_eq!(body[1].success, false);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_batch() {
+    let filter = filter();
+
+    let json = serde_json::json!([{
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    }, {
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16976359
+    }, {
+      "chainId": 12345,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000"
+    }]);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-batch")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<BatchResult> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.len(), 3);
+    let first = body[0].result.as_ref().unwrap();
+    assert_eq!(first.success, true);
+    assert_eq!(first.block_number, 16784600);
+    let second = body[1].result.as_ref().unwrap();
+    assert_eq!(second.success, true);
+    assert_eq!(second.block_number, 16976359);
+    assert!(body[2].result.is_none());
+    assert_eq!(
+        body[2].error.as_ref().unwrap().message,
+        "CHAIN_ID_NOT_SUPPORTED"
+    );
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_no_data() {
     let filter = filter();
@@ -311,16 +695,17 @@ async fn post_simulate_invalid_to() {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn post_simulate_invalid_data() {
+async fn post_simulate_strict_validation() {
     let filter = filter();
 
+    // A typo'd gas limit and a wrong checksum, ignored without apiVersion
     let json = serde_json::json!({
+      "apiVersion": 1,
       "chainId": 1,
       "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
-      "to": "0x66fc62c1748e45435b06cf8dd105b73e9855f93e",
-      "data": "0xffa2ca3b44eea7c8e659973cbdf476546e9e6adfd1c580700537e52ba7124933a97904ea000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000001d0e30db00300ffffffffffffc02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000186a",
-      "gasLimit": 500000,
-      "value": "100000",
+      "to": "0x66Fc62c1748e45435b06cf8dd105b73e9855f93e",
+      "gasLimit": 21000,
+      "gaslimit": 21000,
       "blockNumber": 16784600
     });
 
@@ -334,37 +719,63 @@ async fn post_simulate_invalid_data() {
     assert_eq!(res.status(), 400);
 
     let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
-
+    assert_eq!(body.message, "INVALID_REQUEST");
+    let errors: Vec<FieldError> =
+        serde_json::from_value(body.details.unwrap()["errors"].clone()).unwrap();
     assert_eq!(
-        body.message,
-        "BAD REQUEST: Odd number of digits at line 1 column 709".to_string()
+        errors,
+        vec![
+            FieldError {
+                field: "to".to_string(),
+                message: "invalid checksum, expected 0x66fc62c1748E45435b06cF8dD105B73E9855F93E"
+                    .to_string(),
+            },
+            FieldError {
+                field: "gaslimit".to_string(),
+                message: "unknown field, did you mean `gasLimit`?".to_string(),
+            },
+        ]
     );
-}
-
-#[tokio::test(flavor = "multi_thread")]
-async fn post_simulate_bundle_multiple_block_numbers() {
-    let filter = filter();
 
-    let json = serde_json::json!([{
+    // Field level errors rather than a position in the body
+    let json = serde_json::json!({
+      "apiVersion": 1,
       "chainId": 1,
-      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
-      "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
-      "data": "0x095ea7b300000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000989680",
-      "gasLimit": 5000000,
-      "blockNumber": 16968595,
-    }, {
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": 21000,
+      "stateOverrides": {
+        "0xd8da6bf26964af9d7eed9e03e53415d37aa96045": { "balance": "0xzz" }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
 
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    let errors: Vec<FieldError> =
+        serde_json::from_value(body.details.unwrap()["errors"].clone()).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].field,
+        "stateOverrides.0xd8da6bf26964af9d7eed9e03e53415d37aa96045.balance"
+    );
+
+    let json = serde_json::json!({
+      "apiVersion": 2,
       "chainId": 1,
-      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
-      "to": "0x60f727bdead2ce49b00f2a2133fc707b931d130b",
-      "data": "0x8fd8d1bbffc9011b73f477fef5d4ebdd0903025283464275113654045eef0ce1ba22ccea000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001400000000000000000000000000000000000000000000000000000000000000006f8b2cb4f0100ffffffffff057e7d64d987cab6eed08a191c4c2459daf2f8ed0b095ea7b3010102ffffffffffdac17f958d2ee523a2206206994597c13d831ec719198595a183ffffffffffffdef1c0ded9bec7f1a1670819833240f027b25efff8b2cb4f0100ffffffffff007e7d64d987cab6eed08a191c4c2459daf2f8ed0bb67d77c5010005ffffffff00742f2c5d96c0858d00860039c22d2805bed420e8a1903eab030004ffffffffffae7ab96520de3a18e5e111b5eaab095312d7fe84000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000c000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000140000000000000000000000000000000000000000000000000000000000000018000000000000000000000000000000000000000000000000000000000000002e00000000000000000000000000000000000000000000000000000000000000320000000000000000000000000000000000000000000000000000000000000002000000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000def1c0ded9bec7f1a1670819833240f027b25eff000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000009896800000000000000000000000000000000000000000000000000000000000000128d9627aa40000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000098968000000000000000000000000000000000000000000000000000130e53e715e8a500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000dac17f958d2ee523a2206206994597c13d831ec7000000000000000000000000eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee869584cd00000000000000000000000010000000000000000000000000000000000000110000000000000000000000000000000000000000000000cef2d4383a642acb0d000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
-      "gasLimit": 5000000,
-      "blockNumber": 16968596,
-    }]);
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": 21000
+    });
 
     let res = warp::test::request()
         .method("POST")
-        .path("/simulate-bundle")
+        .path("/simulate")
         .json(&json)
         .reply(&filter)
         .await;
@@ -372,6 +783,6044 @@ async fn post_simulate_bundle_multiple_block_numbers() {
     assert_eq!(res.status(), 400);
 
     let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "UNSUPPORTED_API_VERSION");
+    assert_eq!(
+        body.details,
+        Some(serde_json::json!({ "apiVersion": 2, "supported": [1] }))
+    );
+
+    let json = serde_json::json!({
+      "apiVersion": 1,
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fc62c1748e45435b06cf8dd105b73e9855f93e",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_strict_validation() {
+    let filter = filter();
+
+    let transfer = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fc62c1748e45435b06cf8dd105b73e9855f93e",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    // Only the versioned transaction is validated strictly
+    let mut typo = transfer.clone();
+    typo["apiVersion"] = serde_json::json!(1);
+    typo["gaslimit"] = serde_json::json!(21000);
+    let mut ignored = transfer.clone();
+    ignored["gaslimit"] = serde_json::json!(21000);
+
+    let bodies = [
+        (
+            "/simulate-bundle",
+            serde_json::json!([ignored.clone(), typo.clone()]),
+            "[1].gaslimit",
+        ),
+        (
+            "/simulate-bundle",
+            serde_json::json!({ "transactions": [ignored.clone(), typo.clone()] }),
+            "transactions[1].gaslimit",
+        ),
+        (
+            "/simulate-batch",
+            serde_json::json!([ignored.clone(), typo.clone()]),
+            "[1].gaslimit",
+        ),
+        (
+            "/simulate-async",
+            serde_json::json!({
+              "chains": [{ "chainId": 1, "transactions": [ignored.clone(), typo.clone()] }]
+            }),
+            "chains[0].transactions[1].gaslimit",
+        ),
+        ("/simulate-async", typo.clone(), "gaslimit"),
+        ("/estimate", typo.clone(), "gaslimit"),
+    ];
+    for (path, json, field) in bodies {
+        let res = warp::test::request()
+            .method("POST")
+            .path(path)
+            .json(&json)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 400, "{path}");
+
+        let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+        assert_eq!(body.message, "INVALID_REQUEST");
+        let errors: Vec<FieldError> =
+            serde_json::from_value(body.details.unwrap()["errors"].clone()).unwrap();
+        assert_eq!(
+            errors,
+            vec![FieldError {
+                field: field.to_string(),
+                message: "unknown field, did you mean `gasLimit`?".to_string(),
+            }]
+        );
+    }
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&serde_json::json!([ignored, transfer]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_invalid_data() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fc62c1748e45435b06cf8dd105b73e9855f93e",
+      "data": "0xffa2ca3b44eea7c8e659973cbdf476546e9e6adfd1c580700537e52ba7124933a97904ea000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000001d0e30db00300ffffffffffffc02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000186a",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(
+        body.message,
+        "BAD REQUEST: Odd number of digits at line 1 column 709".to_string()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_multiple_blocks() {
+    let filter = filter();
+
+    let json = serde_json::json!([{
+      "chainId": 1,
+      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+      "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
+      "data": "0x095ea7b300000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000989680",
+      "gasLimit": 5000000,
+      "blockNumber": 16968595,
+    }, {
+
+      "chainId": 1,
+      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+      "to": "0x60f727bdead2ce49b00f2a2133fc707b931d130b",
+      "data": "0x8fd8d1bbffc9011b73f477fef5d4ebdd0903025283464275113654045eef0ce1ba22ccea000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001400000000000000000000000000000000000000000000000000000000000000006f8b2cb4f0100ffffffffff057e7d64d987cab6eed08a191c4c2459daf2f8ed0b095ea7b3010102ffffffffffdac17f958d2ee523a2206206994597c13d831ec719198595a183ffffffffffffdef1c0ded9bec7f1a1670819833240f027b25efff8b2cb4f0100ffffffffff007e7d64d987cab6eed08a191c4c2459daf2f8ed0bb67d77c5010005ffffffff00742f2c5d96c0858d00860039c22d2805bed420e8a1903eab030004ffffffffffae7ab96520de3a18e5e111b5eaab095312d7fe84000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000c000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000140000000000000000000000000000000000000000000000000000000000000018000000000000000000000000000000000000000000000000000000000000002e00000000000000000000000000000000000000000000000000000000000000320000000000000000000000000000000000000000000000000000000000000002000000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000def1c0ded9bec7f1a1670819833240f027b25eff000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000009896800000000000000000000000000000000000000000000000000000000000000128d9627aa40000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000098968000000000000000000000000000000000000000000000000000130e53e715e8a500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000dac17f958d2ee523a2206206994597c13d831ec7000000000000000000000000eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee869584cd00000000000000000000000010000000000000000000000000000000000000110000000000000000000000000000000000000000000000cef2d4383a642acb0d000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+      "gasLimit": 5000000,
+      "blockNumber": 16968596,
+    }]);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body[0].block_number, 16968595);
+    assert_eq!(body[1].block_number, 16968596);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_decreasing_block_numbers() {
+    let filter = filter();
+
+    let json = serde_json::json!([{
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    }, {
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784599
+    }]);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "BLOCK_NUMBER_DECREASING".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_empty() {
+    let filter = filter();
+
+    let transaction = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    let bundles = [
+        (serde_json::json!([]), "transactions"),
+        (serde_json::json!({ "transactions": [] }), "transactions"),
+        (serde_json::json!({ "chains": [] }), "chains"),
+        (
+            serde_json::json!({ "chains": [
+                { "chainId": 1, "transactions": [transaction] },
+                { "chainId": 1, "transactions": [] }
+            ] }),
+            "chains.1.transactions",
+        ),
+    ];
+
+    for (json, field) in bundles {
+        for accept in ["application/json", "application/x-ndjson"] {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/simulate-bundle")
+                .header("accept", accept)
+                .json(&json)
+                .reply(&filter)
+                .await;
+
+            assert_eq!(res.status(), 400);
+
+            let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+            assert_eq!(body.message, "INVALID_REQUEST");
+            let errors: Vec<FieldError> =
+                serde_json::from_value(body.details.unwrap()["errors"].clone()).unwrap();
+            assert_eq!(errors[0].field, field);
+        }
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate-async")
+            .json(&json)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 400);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_partial_failure() {
+    let filter = filter();
+
+    let transfer = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    let mut invalid = transfer.clone();
+    invalid["validation"] = true.into();
+    invalid["nonce"] = 1000000.into();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&serde_json::json!([transfer, invalid, transfer]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<BundleResult> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.len(), 3);
+    assert!(matches!(&body[0], BundleResult::Result(result) if result.success));
+    assert!(matches!(
+        &body[1],
+        BundleResult::Error { error } if error.message == "NONCE_TOO_HIGH"
+    ));
+    assert!(matches!(&body[2], BundleResult::Result(result) if result.success));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_state_overrides() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x000000000000000000000000000000000000dEaD",
+      "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": 21000,
+      "value": "100000000000000000000",
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0x000000000000000000000000000000000000dEaD": {
+          "balance": "0x56bc75e2d631000000"
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_quantity_formats() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": "0x1",
+      "from": "0x000000000000000000000000000000000000dEaD",
+      "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": "21000",
+      "value": 100000,
+      "blockNumber": "0x1001cd8",
+      "stateOverrides": {
+        "0x000000000000000000000000000000000000dEaD": {
+          "balance": "100000000000000000000",
+          "nonce": "0x1"
+        }
+      },
+      "quantityFormat": "decimal"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: serde_json::Value = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body["success"], true);
+    assert_eq!(body["gasUsed"], "21000");
+    assert_eq!(body["blockNumber"], "16784600");
+    assert_eq!(body["trace"][0]["value"], "100000");
+    assert_eq!(body["effectiveGasPrice"], "0");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_fork_simulate_and_delete() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(fork.block_number, 16784600);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/simulate", fork.fork_id))
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+
+    let res = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/fork/{}", fork.fork_id))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/simulate", fork.fork_id))
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "FORK_NOT_FOUND".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_fork_state() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+    let sender = "0x0000000000000000000000000000000000001234";
+    let recipient = "0x0000000000000000000000000000000000005678";
+
+    for (address, balance) in [(sender, "0xde0b6b3a7640000"), (recipient, "0x0")] {
+        let res = warp::test::request()
+            .method("POST")
+            .path(&format!("/fork/{}/set-balance", fork.fork_id))
+            .json(&serde_json::json!({ "address": address, "balance": balance }))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 204);
+    }
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/set-storage", fork.fork_id))
+        .json(&serde_json::json!({
+          "address": recipient,
+          "slot": "0x0000000000000000000000000000000000000000000000000000000000000009",
+          "value": "0x0000000000000000000000000000000000000000000000000000000000000001"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/simulate", fork.fork_id))
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": sender,
+          "to": recipient,
+          "gasLimit": 21000,
+          "value": "100000"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!(
+            "/fork/{}/balance?address={recipient}",
+            fork.fork_id
+        ))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BalanceResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.balance, 100000.into());
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!("/fork/{}/balance?address={sender}", fork.fork_id))
+        .reply(&filter)
+        .await;
+
+    let body: BalanceResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.nonce, 1);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!(
+            "/fork/{}/storage?address={recipient}&slot=9",
+            fork.fork_id
+        ))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StorageResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.value, H256::from_low_u64_be(1));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!(
+            "/fork/{}/code?address=0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            fork.fork_id
+        ))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: CodeResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(!body.code.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_nested_trace() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let mut json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+    json["nestTrace"] = serde_json::json!(true);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let nested_trace = body.nested_trace.expect("nested trace should be returned");
+
+    assert_eq!(nested_trace.depth, 0);
+    assert_eq!(nested_trace.calls.len(), 1);
+    assert_eq!(nested_trace.calls[0].calls.len(), 2);
+    assert_eq!(
+        nested_trace.calls[0].calls[0].created_address,
+        Some(body.trace[2].to)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_gas_profile() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let mut json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+    json["gasProfile"] = serde_json::json!(true);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let profile = body.gas_profile.expect("gas profile should be returned");
+
+    // Every frame is counted once, so the breakdowns add up to the gas used
+    let by_contract: u64 = profile.by_contract.iter().map(|c| c.gas_used).sum();
+    let by_function: u64 = profile.by_function.iter().map(|f| f.gas_used).sum();
+    assert_eq!(by_contract + profile.intrinsic_gas, body.gas_used);
+    assert_eq!(by_function + profile.intrinsic_gas, body.gas_used);
+    assert_eq!(
+        profile.by_contract.iter().map(|c| c.calls).sum::<usize>(),
+        body.trace.len()
+    );
+    assert!(profile
+        .by_contract
+        .windows(2)
+        .all(|pair| pair[0].gas_used >= pair[1].gas_used));
+    assert!(profile
+        .by_function
+        .iter()
+        .any(|f| f.address == body.trace[0].to
+            && f.selector.as_deref() == Some(&[0xff, 0xa2, 0xca, 0x3b][..])));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_storage_accesses() {
+    let filter = filter();
+
+    // Transfer of 1 USDC from Binance 14 to vitalik.eth, through the USDC proxy
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "storageAccesses": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.success);
+
+    let frames = body
+        .storage_accesses
+        .expect("storage accesses should be returned");
+    let usdc: Address = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"
+        .parse()
+        .unwrap();
+
+    // The implementation runs on the storage of the proxy
+    let implementation = frames
+        .iter()
+        .find(|frame| frame.address == usdc && frame.code_address != usdc)
+        .expect("the delegatecall should access the proxy's storage");
+    assert_eq!(implementation.depth, 1);
+
+    // Both balances are read then written
+    let writes: Vec<_> = implementation
+        .accesses
+        .iter()
+        .filter(|access| access.is_write)
+        .collect();
+    assert_eq!(writes.len(), 2);
+    for write in writes {
+        assert_ne!(write.previous_value, write.new_value);
+        assert!(implementation.accesses.iter().any(|access| !access.is_write
+            && access.slot == write.slot
+            && access.new_value == write.previous_value));
+    }
+    assert!(implementation
+        .accesses
+        .iter()
+        .filter(|access| !access.is_write)
+        .all(|access| access.previous_value == access.new_value));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_access_stats() {
+    let filter = filter();
+
+    // Transfer of 1 USDC from Binance 14 to vitalik.eth, through the USDC proxy
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "accessStats": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.success);
+    assert!(body.struct_logs.is_none());
+
+    let stats = body.access_stats.expect("access stats should be returned");
+
+    // The implementation is cold, the proxy being the recipient
+    assert_eq!(stats.accounts.cold, 1);
+    assert_eq!(stats.accounts.cold_gas, 2600);
+
+    // Both balances are read cold then written warm
+    assert!(stats.storage.cold >= 2);
+    assert_eq!(stats.storage.cold_gas, stats.storage.cold * 2100);
+    assert!(stats.storage.warm >= 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_l1_fee() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 10,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "data": "0xdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeef",
+      "gasLimit": 50000,
+      "value": "100000",
+      "maxFeePerGas": "1000000"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.success);
+
+    let l1_fee = body.l1_fee.expect("the L1 fee should be estimated");
+    assert!(!l1_fee.is_zero());
+    assert_eq!(body.l2_fee, Some(body.fee_paid));
+    assert_eq!(body.total_fee, Some(l1_fee + body.fee_paid));
+
+    // Not a rollup
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.l1_fee, None);
+    assert_eq!(body.total_fee, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_tenderly_simulate() {
+    let filter = filter();
+
+    // Transfer of 1 USDC from Binance 14 to vitalik.eth, as Tenderly would be called
+    let json = serde_json::json!({
+      "network_id": "1",
+      "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "input": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gas": 100000,
+      "gas_price": "0",
+      "value": "0",
+      "block_number": 16784600,
+      "save": true,
+      "simulation_type": "quick"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/tenderly/account/me/project/project/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: TenderlySimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.transaction.status);
+    assert_eq!(body.transaction.network_id, "1");
+    assert_eq!(body.transaction.block_number, 16784600);
+    assert_eq!(body.simulation.gas_used, body.transaction.gas_used);
+
+    let info = body.transaction.transaction_info;
+    assert_eq!(info.contract_address, Address::zero());
+    let call_trace = info.call_trace.expect("call trace should be returned");
+    assert_eq!(call_trace.call_type, "CALL");
+    assert_eq!(call_trace.calls[0].call_type, "DELEGATECALL");
+    assert_eq!(info.logs.len(), 1);
+    assert!(info.logs[0].name.is_none());
+
+    // The simulation is recorded like any other
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!("/simulations/{}", body.simulation.id))
+        .reply(&filter)
+        .await;
+    assert_eq!(res.status(), 200);
+
+    let json = serde_json::json!({
+      "simulations": [
+        {
+          "network_id": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+          "value": "1000000000000000000",
+          "gas": 21000,
+          "block_number": 16784600,
+          "state_objects": {
+            "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045": { "balance": "10000000000000000000" }
+          }
+        },
+        {
+          "network_id": 1,
+          "from": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+          "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+          "input": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa960458000000000000000000000000000000000000000000000000000000000000000",
+          "gas": 100000
+        }
+      ]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/tenderly/account/me/project/project/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    // The second transaction transfers more USDC than the sender has
+    let body: TenderlyBundleResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.simulation_results.len(), 2);
+    assert!(body.simulation_results[0].transaction.status);
+    assert!(!body.simulation_results[1].transaction.status);
+    assert!(body.simulation_results[1]
+        .transaction
+        .error_message
+        .is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_v1() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "blockNumber": 16784600,
+      "blockStateCalls": [
+        {
+          "stateOverrides": {
+            "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045": { "balance": "0xde0b6b3a7640000" }
+          },
+          "calls": [
+            {
+              "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+              "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+              "value": "0xde0b6b3a7640000"
+            }
+          ]
+        },
+        {
+          "blockOverrides": { "number": "0x1001cdc", "time": "0x64000000" },
+          "calls": [
+            {
+              "from": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+              "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+              "value": "0x1"
+            },
+            {
+              "from": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+              "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+              "input": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa960458000000000000000000000000000000000000000000000000000000000000000"
+            }
+          ]
+        }
+      ],
+      "traceTransfers": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-v1")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let blocks: Vec<SimulatedBlock> = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].number.as_u64(), 16784601);
+    assert_eq!(blocks[1].number.as_u64(), 16784604);
+    assert_eq!(blocks[1].timestamp.as_u64(), 0x64000000);
+    assert_eq!(blocks[1].parent_hash, blocks[0].hash);
+
+    let transfer = &blocks[0].calls[0];
+    assert_eq!(transfer.status.as_u64(), 1);
+    assert_eq!(transfer.logs.len(), 1);
+    assert_eq!(
+        transfer.logs[0].address,
+        "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee"
+            .parse::<Address>()
+            .unwrap()
+    );
+    assert_eq!(transfer.logs[0].block_hash, Some(blocks[0].hash));
+    assert_eq!(
+        blocks[0].gas_used.as_u64(),
+        blocks[0]
+            .calls
+            .iter()
+            .map(|c| c.gas_used.as_u64())
+            .sum::<u64>()
+    );
+
+    // Transfers more USDC than the sender has
+    assert_eq!(blocks[1].calls[0].status.as_u64(), 1);
+    let reverted = &blocks[1].calls[1];
+    assert_eq!(reverted.status.as_u64(), 0);
+    assert!(reverted.logs.is_empty());
+    let error = reverted.error.as_ref().expect("the call should fail");
+    assert_eq!(error.code, 3);
+    assert!(error.message.starts_with("execution reverted"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_async() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-async")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 202);
+
+    let job: Job = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(job.status, JobStatus::Queued);
+    assert!(job.result.is_none());
+
+    let mut job_status = job.status;
+    for _ in 0..60 {
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/jobs/{}", job.job_id))
+            .reply(&filter)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let job: Job = serde_json::from_slice(&res.body()).unwrap();
+        job_status = job.status;
+        if job_status == JobStatus::Completed {
+            let result: SimulationResponse = serde_json::from_value(job.result.unwrap()).unwrap();
+            assert!(result.success);
+            assert_eq!(result.gas_used, 21000);
+            assert!(job.finished_at.is_some());
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+    assert_eq!(job_status, JobStatus::Completed);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/jobs/00000000-0000-0000-0000-000000000000")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "JOB_NOT_FOUND");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn error_shape() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&serde_json::json!({
+          "chainId": 999999,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+          "gasLimit": 21000,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+    assert_eq!(res.headers()["content-type"], "application/json");
+
+    let body: serde_json::Value = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+          "code": 400,
+          "message": "CHAIN_ID_NOT_SUPPORTED",
+          "details": { "chainId": 999999 }
+        })
+    );
+
+    // Errors without context have no details
+    let res = warp::test::request()
+        .method("GET")
+        .path("/jobs/00000000-0000-0000-0000-000000000000")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: serde_json::Value = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({ "code": 404, "message": "JOB_NOT_FOUND" })
+    );
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork/00000000-0000-0000-0000-000000000000/snapshot")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: serde_json::Value = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({ "code": 404, "message": "FORK_NOT_FOUND" })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_drains_jobs_on_shutdown() {
+    let mut config = get_config();
+    config.port = 0;
+    config.shutdown_timeout = Duration::from_secs(120);
+    let filter = warp::any()
+        .and(simulate_routes(config.clone()))
+        .recover(handle_rejection);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-async")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 202);
+    let job: Job = serde_json::from_slice(&res.body()).unwrap();
+
+    // Shuts down right away, while the job is running
+    serve(warp::service(filter.clone()), &config, async {})
+        .await
+        .unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!("/jobs/{}", job.job_id))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let job: Job = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(job.status, JobStatus::Completed);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_async_callback() {
+    let mut config = get_config();
+    config.webhook_secret = Some("secret".to_string());
+    let private = warp::any()
+        .and(simulate_routes(config.clone()))
+        .recover(handle_rejection);
+    // The callback is received on localhost
+    config.allow_private_urls = true;
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    // Receives the callback
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let callback = warp::post()
+        .and(warp::header::<String>(webhook::SIGNATURE_HEADER))
+        .and(warp::body::bytes())
+        .map(move |signature: String, body: bytes::Bytes| {
+            sender.send((signature, body)).unwrap();
+            warp::reply()
+        });
+    let (address, server) = warp::serve(callback).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16784600,
+      "callbackUrl": format!("http://{address}/callback")
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-async")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 202);
+    let job: Job = serde_json::from_slice(&res.body()).unwrap();
+
+    let (signature, body) =
+        tokio::time::timeout(std::time::Duration::from_secs(60), receiver.recv())
+            .await
+            .expect("the callback should be sent")
+            .unwrap();
+    assert_eq!(signature, webhook::signature("secret", &body));
+
+    let callback: Job = serde_json::from_slice(&body).unwrap();
+    assert_eq!(callback.job_id, job.job_id);
+    assert_eq!(callback.status, JobStatus::Completed);
+
+    let mut json = json;
+    json["callbackUrl"] = serde_json::json!("ftp://example.com");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-async")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "INVALID_CALLBACK_URL");
+
+    // Only public addresses are called back without ALLOW_PRIVATE_URLS
+    for private_url in [
+        "http://127.0.0.1:8080/callback",
+        "http://169.254.169.254/latest/meta-data",
+        "http://192.168.1.1",
+        "http://localhost/callback",
+    ] {
+        json["callbackUrl"] = serde_json::json!(private_url);
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate-async")
+            .json(&json)
+            .reply(&private)
+            .await;
+
+        assert_eq!(res.status(), 400);
+        let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+        assert_eq!(body.message, "INVALID_CALLBACK_URL");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_watch() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "transaction": {
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+        "gasLimit": 21000,
+        "value": "100000"
+      },
+      "interval": 1
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/watch")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 201);
+
+    let watch: Watch = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(watch.chain_id, 1);
+    assert_eq!(watch.interval, Some(1));
+
+    let mut results = vec![];
+    for _ in 0..60 {
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/watch/{}", watch.watch_id))
+            .reply(&filter)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let watch: Watch = serde_json::from_slice(&res.body()).unwrap();
+        if watch.runs >= 2 {
+            results = watch.results.into_iter().collect();
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+    assert!(results.len() >= 2);
+    for result in &results {
+        assert!(result.success);
+        assert_eq!(result.gas_used, 21000);
+        assert_eq!(result.simulation_ids.len(), 1);
+        assert!(!result.changed);
+    }
+
+    let res = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/watch/{}", watch.watch_id))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!("/watch/{}", watch.watch_id))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "WATCH_NOT_FOUND");
+
+    // Neither a transaction nor a bundle
+    let res = warp::test::request()
+        .method("POST")
+        .path("/watch")
+        .json(&serde_json::json!({ "interval": 1 }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "INVALID_REQUEST");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_watch_too_many() {
+    let mut config = get_config();
+    config.max_watches = 1;
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let json = serde_json::json!({
+      "transaction": {
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+        "gasLimit": 21000,
+        "value": "100000"
+      },
+      "interval": 60
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/watch")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 201);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/watch")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 429);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "TOO_MANY_WATCHES");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_chains() {
+    let mut config = get_config();
+    let rpc_url = config.chains[&1][0].clone();
+    let configured = warp::any()
+        .and(simulate_routes(config.clone()))
+        .recover(handle_rejection);
+    config.chains.remove(&1);
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    // Configured chains can't be replaced
+    let json = serde_json::json!({ "rpcUrl": rpc_url });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/chains")
+        .json(&json)
+        .reply(&configured)
+        .await;
+
+    assert_eq!(res.status(), 409);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "CHAIN_ALREADY_REGISTERED");
+
+    // The chain ID doesn't match the RPC's
+    let json = serde_json::json!({
+      "chainId": 424242,
+      "rpcUrl": rpc_url,
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/chains")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "INVALID_CHAIN");
+
+    // RPCs on the network of the server aren't reachable
+    for private_url in [
+        "http://127.0.0.1:8545",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.1",
+        "http://[::1]:8545",
+    ] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/chains")
+            .json(&serde_json::json!({ "rpcUrl": private_url }))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 400);
+        let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+        assert_eq!(body.message, "INVALID_CHAIN");
+    }
+
+    // Explorer APIs aren't used
+    let mut json = serde_json::json!({
+      "rpcUrl": rpc_url,
+      "name": "Ethereum",
+      "nativeCurrency": { "name": "Ether", "symbol": "ETH", "decimals": 18 },
+      "explorerApi": { "url": "https://api.etherscan.io/api" }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/chains")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    // The chain ID is detected
+    json.as_object_mut().unwrap().remove("explorerApi");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/chains")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 201);
+    let chain: ChainInfo = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(chain.chain_id, 1);
+    assert!(chain.registered);
+
+    // Registered chains can't be replaced either
+    let res = warp::test::request()
+        .method("POST")
+        .path("/chains")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 409);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/chains")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let chains: Vec<ChainInfo> = serde_json::from_slice(&res.body()).unwrap();
+    let chain = chains.iter().find(|chain| chain.chain_id == 1).unwrap();
+    assert_eq!(chain.name.as_deref(), Some("Ethereum"));
+    assert!(chain.registered);
+
+    // Other API keys neither see nor use the chain
+    let res = warp::test::request()
+        .method("GET")
+        .path("/chains")
+        .header("X-API-KEY", "other-key")
+        .reply(&filter)
+        .await;
+
+    let chains: Vec<ChainInfo> = serde_json::from_slice(&res.body()).unwrap();
+    assert!(chains.iter().all(|chain| chain.chain_id != 1));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .header("X-API-KEY", "other-key")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+          "gasLimit": 21000,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "CHAIN_ID_NOT_SUPPORTED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_contract_sender() {
+    let filter = filter();
+
+    // WETH sending 1 ETH to vitalik.eth
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+      "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.success);
+    assert!(body.sender_is_contract);
+
+    json["validation"] = true.into();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "SENDER_NOT_EOA");
+
+    json["allowContractSender"] = true.into();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_prices() {
+    let filter = filter();
+
+    // vitalik.eth sending 1 ETH, priced with Chainlink's ETH / USD feed
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16784600,
+      "prices": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let sent = body
+        .asset_changes
+        .iter()
+        .find(|change| change.sent > 0.into())
+        .unwrap();
+    let price = sent.price_usd.unwrap();
+    assert!(price > 1000.0 && price < 3000.0);
+    assert_eq!(sent.value_usd, Some(-price));
+
+    let net_value_changes = body.net_value_changes.unwrap();
+    assert_eq!(net_value_changes.len(), 2);
+    assert!(net_value_changes.iter().all(|change| change.complete));
+    assert_eq!(
+        net_value_changes
+            .iter()
+            .map(|change| change.value_usd)
+            .sum::<f64>(),
+        0.0
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn request_id_header() {
+    let service = warp::service(health());
+
+    // The ID of the request is kept
+    let request = warp::http::Request::get("/health")
+        .header(REQUEST_ID_HEADER, "abc-123")
+        .body(warp::hyper::Body::empty())
+        .unwrap();
+    let response = with_request_id(service.clone(), request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+
+    // Missing and invalid IDs are replaced
+    for request_id in [None, Some("not valid")] {
+        let mut request = warp::http::Request::get("/health");
+        if let Some(request_id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, request_id);
+        }
+        let request = request.body(warp::hyper::Body::empty()).unwrap();
+        let response = with_request_id(service.clone(), request).await.unwrap();
+
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(request_id).is_ok());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_gas_refund() {
+    let filter = filter();
+
+    // Sending the whole WETH balance clears the balance slot of the sender, which is refunded
+    let holder = "0x0000000000000000000000000000000000001234";
+    let slot = format!(
+        "{:?}",
+        H256::from(ethers::utils::keccak256(ethers::abi::encode(&[
+            ethers::abi::Token::Address(holder.parse().unwrap()),
+            ethers::abi::Token::Uint(3.into()),
+        ])))
+    );
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": holder,
+      "to": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa960450000000000000000000000000000000000000000000000000de0b6b3a7640000",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2": {
+          "storage": {
+            slot: "0x0000000000000000000000000000000000000000000000000de0b6b3a7640000"
+          }
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.success);
+    assert!(body.gas_refunded > 0);
+    assert_eq!(
+        body.gas_used_before_refund,
+        body.gas_used + body.gas_refunded
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_state_diffs() {
+    let filter = filter();
+
+    let sender = "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e";
+    let weth: ethers::types::Address = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"
+        .parse()
+        .unwrap();
+    // WETH balances are in the mapping at slot 3
+    let balance_slot = ethers::types::H256::from(ethers::utils::keccak256(ethers::abi::encode(&[
+        ethers::abi::Token::Address(sender.parse().unwrap()),
+        ethers::abi::Token::Uint(3.into()),
+    ])));
+
+    let deposit = serde_json::json!({
+      "chainId": 1,
+      "from": sender,
+      "to": weth,
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    let json = serde_json::json!({
+      "transactions": [deposit, deposit],
+      "bundleOptions": { "stateDiffs": true }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let balance_diff = |diff: &[AccountDiff]| {
+        diff.iter()
+            .find(|account| account.address == weth)
+            .and_then(|account| account.storage.get(&balance_slot))
+            .cloned()
+            .unwrap()
+    };
+    let first = balance_diff(body.results[0].state_diff.as_ref().unwrap());
+    let second = balance_diff(body.results[1].state_diff.as_ref().unwrap());
+    let bundle = balance_diff(body.state_diff.as_ref().unwrap());
+
+    // Each transaction only reports its own deposit
+    assert_eq!(first.post, second.pre);
+    assert_eq!(bundle.pre, first.pre);
+    assert_eq!(bundle.post, second.post);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_policies() {
+    let blocked = "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3";
+    let mut config = get_config();
+    config.policy_rules = vec![
+        PolicyRule {
+            name: "sanctions".to_string(),
+            action: PolicyAction::Reject,
+            addresses: vec![blocked.parse().unwrap()],
+            max_value: None,
+            selectors: vec![],
+        },
+        PolicyRule {
+            name: "large-transfers".to_string(),
+            action: PolicyAction::Flag,
+            addresses: vec![],
+            max_value: Some(ethers::types::U256::exp10(17)),
+            selectors: vec![],
+        },
+    ];
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let transfer = |to: &str| {
+        serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": to,
+          "gasLimit": 21000,
+          "value": "1000000000000000000",
+          "blockNumber": 16784600
+        })
+    };
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&transfer("0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let decisions = body.policy_decisions.unwrap();
+    assert_eq!(decisions.len(), 1);
+    assert_eq!(decisions[0].policy, "large-transfers");
+    assert_eq!(decisions[0].action, PolicyAction::Flag);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&transfer(blocked))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 403);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "POLICY_VIOLATION");
+    assert_eq!(
+        body.details.unwrap()["policyDecisions"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_precompile_stub() {
+    let stub = "0x0000000000000000000000000000000000000100";
+    let answer = format!("{:064x}", 42);
+    let mut config = get_config();
+    config.precompile_stubs = vec![PrecompileStub {
+        chain_id: 1,
+        address: stub.parse().unwrap(),
+        name: Some("Oracle".to_string()),
+        functions: vec![
+            StubFunction {
+                signature: "answer()".to_string(),
+                returns: StubValue::Data(ethers::utils::hex::decode(&answer).unwrap().into()),
+            },
+            StubFunction {
+                signature: "blockNumber()".to_string(),
+                returns: StubValue::Environment(EnvironmentValue::BlockNumber),
+            },
+        ],
+    }];
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let call = |signature: &str| {
+        serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": stub,
+          "data": ethers::types::Bytes::from(ethers::utils::id(signature).to_vec()),
+          "gasLimit": 100000,
+          "blockNumber": 16784600
+        })
+    };
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&call("answer()"))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(ethers::utils::hex::encode(&body.return_data), answer);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&call("blockNumber()"))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(
+        ethers::utils::hex::encode(&body.return_data),
+        format!("{:064x}", body.block_number)
+    );
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&call("latestAnswer()"))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(!body.success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_multi_chain() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chains": [
+        {
+          "chainId": 1,
+          "transactions": [{
+            "chainId": 1,
+            "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+            "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+            "gasLimit": 21000,
+            "value": "100000",
+            "blockNumber": 16784600
+          }]
+        },
+        {
+          "chainId": 137,
+          "transactions": [{
+            "chainId": 137,
+            "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+            "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+            "gasLimit": 21000,
+            "value": "100000"
+          }]
+        }
+      ]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: MultiChainBundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(
+        body.chains
+            .iter()
+            .map(|chain| chain.chain_id)
+            .collect::<Vec<_>>(),
+        vec![1, 137]
+    );
+    assert!(body
+        .chains
+        .iter()
+        .all(|chain| chain.bundle.results[0].success));
+    assert_eq!(body.chains[0].bundle.results[0].block_number, 16784600);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_trace_format() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let mut json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    json["traceFormat"] = serde_json::json!("callTracer");
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let call_tracer = body.call_tracer.expect("callTracer should be returned");
+    assert_eq!(call_tracer.kind, "CALL");
+    assert_eq!(call_tracer.calls.len(), 1);
+    assert_eq!(call_tracer.calls[0].calls.len(), 2);
+    assert_eq!(call_tracer.calls[0].calls[0].kind, "CREATE");
+    assert!(body.parity_trace.is_none());
+
+    json["traceFormat"] = serde_json::json!("parity");
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let parity_trace = body.parity_trace.expect("parity trace should be returned");
+    assert_eq!(parity_trace.len(), body.trace.len());
+    assert_eq!(parity_trace[0].trace_address, Vec::<usize>::new());
+    assert_eq!(parity_trace[0].subtraces, 1);
+    assert_eq!(parity_trace[2].trace_address, vec![0, 0]);
+    assert_eq!(parity_trace[2].kind, "create");
+    assert!(body.call_tracer.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_auto_approve() {
+    let filter = filter();
+
+    let spender = "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045";
+    let usdc = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    // transferFrom of 1 USDC from Binance 14, which never approved the spender
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": spender,
+      "to": usdc,
+      "data": "0x23b872dd00000000000000000000000028c6c06298d514db089934071355e5743bf21d60000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 200000,
+      "blockNumber": 16784600,
+      "autoApprove": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    let approvals = body.assumed_approvals.unwrap();
+    assert_eq!(approvals.len(), 1);
+    assert_eq!(approvals[0].token, usdc.parse().unwrap());
+    assert_eq!(
+        approvals[0].owner,
+        "0x28c6c06298d514db089934071355e5743bf21d60"
+            .parse()
+            .unwrap()
+    );
+    assert_eq!(approvals[0].spender, spender.parse().unwrap());
+    assert_eq!(approvals[0].amount, 1_000_000.into());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cors_preflight() {
+    let mut config = get_config();
+    config.cors_origins = vec!["https://app.example.com".to_string()];
+    let filter = warp::any()
+        .and(simulate_routes(config.clone()))
+        .recover(handle_rejection)
+        .with(cors(&config).unwrap());
+
+    let res = warp::test::request()
+        .method("OPTIONS")
+        .path("/simulate")
+        .header("Origin", "https://app.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.headers()["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+
+    let res = warp::test::request()
+        .method("OPTIONS")
+        .path("/simulate")
+        .header("Origin", "https://evil.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 403);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_fixture() {
+    let filter = filter();
+
+    let weth: Address = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"
+        .parse()
+        .unwrap();
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": weth,
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "200000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fixtures")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let export: FixtureExport = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(export.fixture.chain_id, 1);
+    assert_eq!(export.fixture.block.number, 16784600);
+    assert!(!export.fixture.accounts[&weth].code.is_empty());
+    assert!(!export.fixture.accounts[&weth].storage.is_empty());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-fixture")
+        .json(&serde_json::json!({ "fixture": export.fixture, "transaction": json }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.block_number, 16784600);
+    assert_eq!(body.gas_used, export.simulation.gas_used);
+    assert_eq!(body.logs, export.simulation.logs);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-fixture")
+        .json(&serde_json::json!({
+            "fixture": export.fixture,
+            "transaction": { "chainId": 137, "from": json["from"], "to": weth, "gasLimit": 500000 }
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_code_changes() {
+    let filter = filter();
+
+    // CALLER SELFDESTRUCT
+    let destructible: Address = "0x0000000000000000000000000000000000001234"
+        .parse()
+        .unwrap();
+    let delegator: Address = "0x0000000000000000000000000000000000005678"
+        .parse()
+        .unwrap();
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": destructible,
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0x0000000000000000000000000000000000001234": { "code": "0x33ff" }
+      },
+      "authorizationList": [
+        {
+          "chainId": "0x1",
+          "address": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+          "nonce": 0,
+          "authority": delegator
+        }
+      ],
+      "codeChanges": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    let changes = body.code_changes.unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].address, destructible);
+    assert_eq!(changes[0].kind, CodeChangeKind::SelfDestruct);
+    assert_eq!(
+        changes[0].previous_code_hash,
+        Some(ethers::utils::keccak256([0x33, 0xff]).into())
+    );
+    assert_eq!(changes[1].address, delegator);
+    assert_eq!(changes[1].kind, CodeChangeKind::Delegated);
+    assert_eq!(
+        changes[1].delegate,
+        Some(
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+                .parse()
+                .unwrap()
+        )
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_mempool() {
+    let filter = filter();
+
+    let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse()
+        .unwrap();
+    let wallet = wallet.with_chain_id(1u64);
+    let pending: TypedTransaction = TransactionRequest::new()
+        .to("0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+            .parse::<Address>()
+            .unwrap())
+        .value(0)
+        .gas(21000)
+        .gas_price(0)
+        .nonce(0)
+        .chain_id(1)
+        .into();
+    let signature = wallet.sign_transaction_sync(&pending).unwrap();
+    let raw = pending.rlp_signed(&signature);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "200000",
+      "blockNumber": 16784600,
+      "mempool": { "transactions": [raw, "0x1234"] }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.block_number, 16784601);
+    let pending = body.pending_transactions.unwrap();
+    assert_eq!(pending.len(), 2);
+    assert_eq!(pending[0].hash, H256::from(ethers::utils::keccak256(&raw)));
+    assert_eq!(pending[0].from, Some(wallet.address()));
+    assert_eq!(pending[0].success, true);
+    assert_eq!(pending[0].gas_used, 21000);
+    assert_eq!(pending[1].success, false);
+    assert!(pending[1].error.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_trimmed() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let mut json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+    json["maxTraceDepth"] = serde_json::json!(0);
+    json["nestTrace"] = serde_json::json!(true);
+    json["decodeLogs"] = serde_json::json!(true);
+    // Only USDC logs, of which the WETH deposit emits none
+    json["logFilter"] = serde_json::json!({
+      "addresses": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.trace.len(), 1);
+    assert!(body.nested_trace.unwrap().calls.is_empty());
+    assert!(body.logs.is_empty());
+    assert!(body.decoded_logs.unwrap().is_empty());
+    // Asset changes are still derived from the whole execution
+    assert!(!body.asset_changes.is_empty());
+    let trimmed = body.trimmed.expect("trimmed should be reported");
+    assert!(trimmed.trace_frames > 0);
+    assert!(trimmed.logs > 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_resolve_proxies() {
+    let filter = filter();
+
+    // getReservesList() on the Aave V2 lending pool, an EIP-1967 proxy
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9",
+      "data": "0xd1946dbc",
+      "gasLimit": 500000,
+      "value": "0",
+      "blockNumber": 16784600,
+      "resolveProxies": true,
+      "nestTrace": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.trace[1].call_type, CallKind::DelegateCall);
+    let proxy = body.trace[0]
+        .proxy
+        .clone()
+        .expect("proxy should be resolved");
+    assert_eq!(proxy.kind, ProxyKind::Eip1967);
+    assert_eq!(proxy.proxy, body.trace[0].to);
+    assert_eq!(proxy.implementation, body.trace[1].to);
+    assert_eq!(body.trace[1].proxy, None);
+    assert_eq!(body.nested_trace.unwrap().proxy, Some(proxy));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_sweep() {
+    let filter = filter();
+
+    // Transfers of USDC from Binance 14 to vitalik.eth, up to more than it holds
+    let json = serde_json::json!({
+      "transaction": {
+        "chainId": 1,
+        "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+        "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+        "gasLimit": 100000,
+        "blockNumber": 16784600
+      },
+      "parameters": [
+        { "target": "calldataWord", "offset": 36, "values": ["1000000", "0xffffffffffffffffffff"] },
+        { "target": "gasLimit", "range": { "from": 25000, "to": 100000, "step": 75000 } }
+      ]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-sweep")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SweepResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let outcomes: Vec<(Vec<u64>, bool)> = body
+        .variants
+        .iter()
+        .map(|variant| {
+            (
+                vec![variant.values[0].low_u64(), variant.values[1].low_u64()],
+                variant.success,
+            )
+        })
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            (vec![1000000, 25000], false),
+            (vec![1000000, 100000], true),
+            (vec![u64::MAX, 25000], false),
+            (vec![u64::MAX, 100000], false),
+        ]
+    );
+    assert!(body.variants[3].revert_reason.is_some());
+
+    // Beyond the calldata
+    let json = serde_json::json!({
+      "transaction": {
+        "chainId": 1,
+        "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+        "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        "data": "0xa9059cbb",
+        "gasLimit": 100000
+      },
+      "parameters": [{ "target": "calldataWord", "offset": 4, "values": [1] }]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-sweep")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_SWEEP");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_assertions() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "transactions": [{
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+        "data": "0xd0e30db0",
+        "gasLimit": 500000,
+        "value": "100000",
+        "blockNumber": 16784600,
+        "assertions": [
+          { "type": "success" },
+          {
+            "type": "balance",
+            "address": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+            "token": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "min": "100000"
+          },
+          { "type": "gasUsed", "max": 21000 }
+        ]
+      }, {
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+        "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+        "gasLimit": 100000,
+        "assertions": [
+          { "type": "returnValue", "min": "100000" },
+          { "type": "returnValue", "word": 1, "equals": 0 }
+        ]
+      }]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let passed = |transaction: usize| -> Vec<bool> {
+        body.statuses[transaction]
+            .assertions
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|assertion| assertion.passed)
+            .collect()
+    };
+    // The deposit uses more than 21000 gas, and the balance has a single word
+    assert_eq!(passed(0), vec![true, true, false]);
+    assert_eq!(passed(1), vec![true, false]);
+    assert!(body.statuses[1].assertions.as_ref().unwrap()[1]
+        .error
+        .is_some());
+    assert_eq!(body.assertions_passed, Some(false));
+    assert_eq!(body.statuses[0].status, TransactionStatus::Success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_erc20_asset_changes() {
+    let filter = filter();
+
+    // Transfer of 1 USDC from Binance 14 to vitalik.eth
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.asset_changes.len(), 2);
+    assert!(body
+        .asset_changes
+        .iter()
+        .all(|change| change.asset_type == AssetType::Erc20));
+    assert_eq!(body.asset_changes[0].sent, 1000000.into());
+    assert_eq!(body.asset_changes[1].received, 1000000.into());
+
+    let token_info = body.asset_changes[0].token_info.clone().unwrap();
+    assert_eq!(token_info.name, Some("USD Coin".to_string()));
+    assert_eq!(token_info.symbol, Some("USDC".to_string()));
+    assert_eq!(token_info.decimals, Some(6));
+    assert_eq!(body.asset_changes[1].token_info, Some(token_info));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bytes32_token_info() {
+    let filter = filter();
+
+    // Transfer of 1 MKR, whose name and symbol are bytes32, from the Maker chief to vitalik.eth
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x0a3f6849f78076aefadf113f5bed87720274ddc0",
+      "to": "0x9f8f72aa9304c8b593d555f12ef6589cc3a579a2",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa960450000000000000000000000000000000000000000000000000de0b6b3a7640000",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(body.asset_changes.len(), 2);
+
+    let token_info = body.asset_changes[0].token_info.clone().unwrap();
+    assert_eq!(token_info.name, Some("Maker".to_string()));
+    assert_eq!(token_info.symbol, Some("MKR".to_string()));
+    assert_eq!(token_info.decimals, Some(18));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_funds_flow() {
+    let filter = filter();
+
+    // Transfer of 1 USDC from Binance 14 to vitalik.eth
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "fundsFlow": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let binance: Address = "0x28c6c06298d514db089934071355e5743bf21d60"
+        .parse()
+        .unwrap();
+    let vitalik: Address = "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+        .parse()
+        .unwrap();
+
+    let funds_flow = body.funds_flow.unwrap();
+    assert_eq!(funds_flow.edges.len(), 1);
+    let edge = &funds_flow.edges[0];
+    assert_eq!(edge.from, binance);
+    assert_eq!(edge.to, vitalik);
+    assert_eq!(edge.asset_type, AssetType::Erc20);
+    assert_eq!(edge.amount, 1000000.into());
+    assert_eq!(
+        edge.token_info.clone().unwrap().symbol,
+        Some("USDC".to_string())
+    );
+
+    let addresses: Vec<Address> = funds_flow.nodes.iter().map(|node| node.address).collect();
+    assert_eq!(addresses.len(), 2);
+    assert!(addresses.contains(&binance));
+    assert!(addresses.contains(&vitalik));
+
+    let mut json = json;
+    json["fundsFlow"] = serde_json::json!(false);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert!(body.funds_flow.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_decode_logs() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let mut json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+    json["decodeLogs"] = serde_json::json!(true);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let decoded_logs = body.decoded_logs.expect("decoded logs should be returned");
+
+    assert_eq!(decoded_logs.len(), body.logs.len());
+
+    let deposit = decoded_logs
+        .iter()
+        .find(|log| log.name.as_deref() == Some("Deposit"))
+        .expect("WETH deposit should be decoded");
+
+    assert_eq!(deposit.params[0].name, "dst".to_string());
+    assert_eq!(deposit.params[1].value, "100000".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_abis() {
+    let filter = filter();
+
+    // WETH's Deposit event with other parameter names
+    let deposit = |param: &str| {
+        serde_json::json!([{
+          "type": "event",
+          "name": "Deposit",
+          "anonymous": false,
+          "inputs": [
+            { "name": param, "type": "address", "indexed": true },
+            { "name": "amount", "type": "uint256", "indexed": false }
+          ]
+        }])
+    };
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/abis")
+        .header("x-api-key", "abis")
+        .json(&serde_json::json!({ "name": "WETH", "abi": deposit("account") }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let registered: RegisteredAbi = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(registered.name.as_deref(), Some("WETH"));
+    assert_eq!(registered.events.len(), 1);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/abis")
+        .header("x-api-key", "abis")
+        .reply(&filter)
+        .await;
+
+    let abis: Vec<RegisteredAbi> = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(abis, vec![registered.clone()]);
+
+    // Each key only sees its own
+    let res = warp::test::request()
+        .method("GET")
+        .path("/abis")
+        .header("x-api-key", "other")
+        .reply(&filter)
+        .await;
+
+    let abis: Vec<RegisteredAbi> = serde_json::from_slice(&res.body()).unwrap();
+    assert!(abis.is_empty());
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let mut json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+    json["decodeLogs"] = serde_json::json!(true);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .header("x-api-key", "abis")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let decoded = body
+        .decoded_logs
+        .expect("decoded logs should be returned")
+        .into_iter()
+        .find(|log| log.name.as_deref() == Some("Deposit"))
+        .expect("WETH deposit should be decoded");
+    assert_eq!(decoded.params[0].name, "account");
+
+    // The request's ABI comes first
+    json["abi"] = deposit("owner");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .header("x-api-key", "abis")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let decoded = body
+        .decoded_logs
+        .expect("decoded logs should be returned")
+        .into_iter()
+        .find(|log| log.name.as_deref() == Some("Deposit"))
+        .expect("WETH deposit should be decoded");
+    assert_eq!(decoded.params[0].name, "owner");
+
+    let res = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/abis/{}", registered.id))
+        .header("x-api-key", "abis")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    let res = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/abis/{}", registered.id))
+        .header("x-api-key", "abis")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "ABI_NOT_FOUND");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_revert_reason() {
+    let filter = filter();
+
+    // Transfer of more USDC than vitalik.eth holds
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb00000000000000000000000028c6c06298d514db089934071355e5743bf21d608000000000000000000000000000000000000000000000000000000000000000",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, false);
+    assert_eq!(body.exit_reason, Return::Revert);
+    assert_eq!(
+        body.revert_reason,
+        Some("ERC20: transfer amount exceeds balance".to_string())
+    );
+    assert!(body
+        .raw_revert_data
+        .unwrap()
+        .starts_with(&[0x08, 0xc3, 0x79, 0xa0]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_source_stack_trace() {
+    let filter = filter();
+
+    // Transfer of more USDC than vitalik.eth holds, reverting in the implementation
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb00000000000000000000000028c6c06298d514db089934071355e5743bf21d608000000000000000000000000000000000000000000000000000000000000000",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "sourceStackTrace": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, false);
+    let frames = body.source_stack_trace.unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(
+        frames[0].address,
+        "0xa2327a938febf5fec13bacfb16ae10ecbc4cbdcf"
+            .parse::<Address>()
+            .unwrap()
+    );
+    assert_eq!(
+        frames[1].address,
+        "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"
+            .parse::<Address>()
+            .unwrap()
+    );
+
+    let location = frames[0].location.as_ref().unwrap();
+    assert_eq!(location.contract, "FiatTokenV2_1");
+    assert!(location.file.ends_with(".sol"));
+    assert!(location.line > 0);
+    assert!(location.source.starts_with("require("));
+
+    // Successful transactions have no stack trace
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb00000000000000000000000028c6c06298d514db089934071355e5743bf21d600000000000000000000000000000000000000000000000000000000000000000",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "sourceStackTrace": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert!(body.source_stack_trace.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_pooled_fork_is_isolated() {
+    let filter = filter();
+
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x000000000000000000000000000000000000bEEF",
+      "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": 21000,
+      "value": "100000000000000000000",
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0x000000000000000000000000000000000000bEEF": {
+          "balance": "0x56bc75e2d63100000"
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+
+    // Same block so the pooled fork is reused, the override must not carry over
+    json.as_object_mut().unwrap().remove("stateOverrides");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_estimate() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/estimate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: GasEstimateResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.gas_estimate >= body.gas_used);
+    assert!(body.gas_estimate < 500000);
+    assert_eq!(body.gas_limit, body.gas_estimate + body.gas_estimate / 10);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_estimate_reverts() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb00000000000000000000000028c6c06298d514db089934071355e5743bf21d608000000000000000000000000000000000000000000000000000000000000000",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/estimate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(
+        body.message,
+        "EXECUTION_REVERTED: ERC20: transfer amount exceeds balance".to_string()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_unsupported_chain() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 999999999,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "CHAIN_ID_NOT_SUPPORTED".to_string());
+    assert_eq!(
+        body.details,
+        Some(serde_json::json!({ "chainId": 999999999 }))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_raw() {
+    let filter = filter();
+
+    let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse()
+        .unwrap();
+    let wallet = wallet.with_chain_id(1u64);
+    let transaction: TypedTransaction = TransactionRequest::new()
+        .to("0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+            .parse::<Address>()
+            .unwrap())
+        .value(0)
+        .gas(21000)
+        .gas_price(0)
+        .nonce(0)
+        .chain_id(1)
+        .into();
+    let signature = wallet.sign_transaction_sync(&transaction).unwrap();
+
+    let json = serde_json::json!({
+      "rawTransaction": transaction.rlp_signed(&signature),
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-raw")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.trace[0].from, wallet.address());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_validation() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x0000000000000000000000000000000000001234",
+      "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16784600,
+      "validation": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INSUFFICIENT_FUNDS".to_string());
+    assert_eq!(
+        body.details.unwrap()["cost"],
+        serde_json::json!("0xde0b6b3a7640000")
+    );
+
+    let mut json = json;
+    json["value"] = serde_json::json!("0");
+    json["gasLimit"] = serde_json::json!(100_000_000);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "GAS_LIMIT_EXCEEDS_BLOCK".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_blob_transaction() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 21000,
+      "blockNumber": 16784600,
+      "maxFeePerBlobGas": "0x64",
+      "blobVersionedHashes": [
+        "0x0100000000000000000000000000000000000000000000000000000000000001",
+        "0x0100000000000000000000000000000000000000000000000000000000000002"
+      ],
+      "blockOverrides": { "blobBaseFee": "0xa" }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(body.blob_gas_used, Some(262144));
+    assert_eq!(body.blob_gas_price, Some(10.into()));
+    assert_eq!(body.blob_fee, Some(2621440.into()));
+
+    let mut json = json;
+    json["maxFeePerBlobGas"] = serde_json::json!("0x9");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "MAX_FEE_PER_BLOB_GAS_TOO_LOW".to_string());
+
+    json["blobVersionedHashes"] =
+        serde_json::json!(["0x0200000000000000000000000000000000000000000000000000000000000001"]);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_BLOB_TRANSACTION".to_string());
+
+    // The blob fee overflows
+    let max = "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    json["blobVersionedHashes"] =
+        serde_json::json!(["0x0100000000000000000000000000000000000000000000000000000000000001"]);
+    json["maxFeePerBlobGas"] = serde_json::json!(max);
+    json["blockOverrides"] = serde_json::json!({ "blobBaseFee": max });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_BLOB_TRANSACTION".to_string());
+
+    // The blob base fee saturates without computing every term of the exponential
+    json.as_object_mut().unwrap().remove("maxFeePerBlobGas");
+    json["blockOverrides"] = serde_json::json!({ "excessBlobGas": u64::MAX.to_string() });
+
+    let res = tokio::time::timeout(
+        Duration::from_secs(30),
+        warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&json)
+            .reply(&filter),
+    )
+    .await
+    .expect("the blob base fee should be computed promptly");
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.blob_gas_price.unwrap() > 0.into());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_authorization_list() {
+    let filter = filter();
+
+    // WETH's totalSupply() returns the balance of the account running its code
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x0000000000000000000000000000000000001234",
+      "data": "0x18160ddd",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "authorizationList": [
+        {
+          "chainId": "0x1",
+          "address": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+          "nonce": 0,
+          "authority": "0x0000000000000000000000000000000000001234"
+        },
+        {
+          "chainId": "0x1",
+          "address": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+          "nonce": 7,
+          "authority": "0x0000000000000000000000000000000000005678"
+        }
+      ]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(body.return_data.len(), 32);
+
+    let delegations = body.delegations.unwrap();
+
+    assert!(delegations[0].applied);
+    assert_eq!(
+        delegations[0].designator,
+        Some(
+            "0xef0100c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"
+                .parse()
+                .unwrap()
+        )
+    );
+    assert!(!delegations[1].applied);
+    assert_eq!(delegations[1].reason, Some("nonce mismatch".to_string()));
+    assert_eq!(delegations[1].designator, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_delegated_sender() {
+    let filter = filter();
+
+    // The sender delegates itself, then sends a transaction validated like an EOA's
+    let json = serde_json::json!([
+      {
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "gasLimit": 100000,
+        "blockNumber": 16784600,
+        "stateOverrides": {
+          "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045": { "nonce": 0 }
+        },
+        "authorizationList": [
+          {
+            "chainId": "0x1",
+            "address": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            "nonce": 1,
+            "authority": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+          }
+        ]
+      },
+      {
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+        "gasLimit": 21000,
+        "value": "1",
+        "blockNumber": 16784600,
+        "validation": true
+      }
+    ]);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body[0].delegations.as_ref().unwrap()[0].applied);
+    assert!(!body[1].sender_is_contract);
+    assert!(body[1].success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_console_logs() {
+    let filter = filter();
+
+    // Forwards its calldata to the console address
+    let logger = "0x36600060003760006000366000600073000000000000000000636f6e736f6c652e6c6f675af100";
+    let mut data = ethers::utils::id("log(string,uint256)").to_vec();
+    data.extend(ethers::abi::encode(&[
+        ethers::abi::Token::String("x is %s".to_string()),
+        ethers::abi::Token::Uint(42.into()),
+    ]));
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x0000000000000000000000000000000000001234",
+      "data": ethers::types::Bytes::from(data),
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0x0000000000000000000000000000000000001234": { "code": logger }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(body.console_logs, vec!["x is 42".to_string()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_limits() {
+    let mut config = get_config();
+    config.max_gas_limit = 1_000_000;
+    config.simulation_timeout = std::time::Duration::from_millis(1);
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 5000000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "GAS_LIMIT_TOO_HIGH".to_string());
+
+    let mut json = json;
+    json["gasLimit"] = serde_json::json!(21000);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 504);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "SIMULATION_TIMEOUT".to_string());
+
+    // Estimating runs several executions, the timeout is checked between them
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/estimate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 504);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "SIMULATION_TIMEOUT".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_raw_invalid() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "rawTransaction": "0xdeadbeef",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-raw")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_RAW_TRANSACTION".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_block_overrides() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600,
+      "blockOverrides": {
+        "number": 16784700,
+        "timestamp": 1678900000
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.block_number, 16784700);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_block_hash() {
+    let filter = filter();
+
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let by_number: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    json["blockNumber"] = serde_json::Value::Null;
+    json["blockHash"] = serde_json::json!(by_number.block_hash.unwrap());
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let by_hash: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(by_hash.block_number, 16784600);
+    assert_eq!(by_hash.block_hash, by_number.block_hash);
+    assert_eq!(by_hash.gas_used, by_number.gas_used);
+
+    json["blockHash"] = serde_json::json!(H256::repeat_byte(0x11));
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "BLOCK_NOT_FOUND".to_string());
+
+    // Batches fork each transaction by its hash
+    let unknown = json.clone();
+    json["blockHash"] = serde_json::json!(by_number.block_hash.unwrap());
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-batch")
+        .json(&serde_json::json!([json, unknown]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<BatchResult> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body[0].result.as_ref().unwrap().block_number, 16784600);
+    assert_eq!(body[1].error.as_ref().unwrap().message, "BLOCK_NOT_FOUND");
+
+    // So does the library
+    let simulator = Simulator::new(get_config());
+    let response = simulator
+        .simulate(serde_json::from_value(json.clone()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.block_hash, by_number.block_hash);
+
+    let err = simulator
+        .simulate(serde_json::from_value(unknown).unwrap())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, SimulationError::BlockNotFound(_)));
+
+    // Forks are already at a block
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({ "chainId": 1, "blockNumber": 16784600 }))
+        .reply(&filter)
+        .await;
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/simulate", fork.fork_id))
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_REQUEST".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_hardfork() {
+    let filter = filter();
+
+    // Init code returning the base fee, BASEFEE only exists since London
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "data": "0x4860005260206000f3",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "hardfork": "berlin"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, false);
+    assert_eq!(body.block_env.unwrap().hardfork, "berlin");
+
+    json["hardfork"] = serde_json::json!("london");
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.block_env.unwrap().hardfork, "london");
+
+    // The EVM doesn't implement the upgrades after the merge
+    json["hardfork"] = serde_json::json!("cancun");
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body
+        .message
+        .starts_with("BAD REQUEST: unknown variant `cancun`"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_block_env() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600,
+      "blockOverrides": {
+        "timestamp": 1678900000,
+        "coinbase": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let block_env = body.block_env.unwrap();
+
+    assert_eq!(block_env.number, 16784600);
+    assert_eq!(block_env.timestamp, 1678900000);
+    assert_eq!(
+        block_env.coinbase,
+        "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"
+            .parse::<Address>()
+            .unwrap()
+    );
+    assert_eq!(block_env.chain_id, 1);
+    assert!(!block_env.base_fee.is_zero());
+    assert!(!block_env.gas_limit.is_zero());
+    assert!(!block_env.hardfork.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_deployment() {
+    let filter = filter();
+
+    // Init code returning a single STOP opcode as the runtime bytecode
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "data": "0x600060005360016000f3",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert!(body.created_address.is_some());
+    assert_eq!(body.trace[0].to, body.created_address.unwrap());
+    assert_eq!(body.deployed_code_size, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_created_contracts() {
+    let filter = filter();
+
+    // Factory creating the init code of post_simulate_deployment
+    let factory: Address = "0x0000000000000000000000000000000000001234"
+        .parse()
+        .unwrap();
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": factory,
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0x0000000000000000000000000000000000001234": {
+          "code": "0x69600060005360016000f3600052600a60166000f000"
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.created_contracts.len(), 1);
+
+    let created = &body.created_contracts[0];
+
+    assert_eq!(created.creator, factory);
+    assert_eq!(created.call_type, CallKind::Create);
+    assert_eq!(
+        created.init_code_hash,
+        ethers::types::H256::from(ethers::utils::keccak256(
+            ethers::utils::hex::decode("600060005360016000f3").unwrap()
+        ))
+    );
+    assert_eq!(created.code_size, 1);
+    assert!(created.success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_state_diff() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600,
+      "stateDiff": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+
+    let state_diff = body.state_diff.unwrap();
+    let receiver = state_diff
+        .iter()
+        .find(|diff| {
+            diff.address
+                == "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"
+                    .parse::<Address>()
+                    .unwrap()
+        })
+        .unwrap();
+    let balance = receiver.balance.as_ref().unwrap();
+
+    assert_eq!(balance.post - balance.pre, 100000.into());
+    assert!(receiver.storage.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_summary() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "transactions": [{
+        "chainId": 1,
+        "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+        "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+        "gasLimit": 21000,
+        "value": "100000",
+        "blockNumber": 16784600
+      }]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.results.len(), 1);
+    assert_eq!(body.bundle_summary.transactions.len(), 1);
+    assert_eq!(body.bundle_summary.gas_fees, 0.into());
+    assert_eq!(body.bundle_summary.net_profit, "-100000".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_eip1559_fees() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "maxFeePerGas": "0x174876e800",
+      "maxPriorityFeePerGas": "0x3b9aca00",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert!(body.effective_gas_price > 1_000_000_000u64.into());
+    assert_eq!(body.fee_paid, body.effective_gas_price * body.gas_used);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_access_list() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: serde_json::Value =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/access-list")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: AccessListResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(!body.access_list.0.is_empty());
+    assert_eq!(
+        body.gas_saved,
+        body.gas_used as i64 - body.gas_used_with_access_list as i64
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_opcode_trace() {
+    let filter = filter();
+
+    // balanceOf on the USDC proxy, which delegates to the implementation
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "traceMode": "opcode"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let struct_logs = body.struct_logs.unwrap();
+
+    assert_eq!(struct_logs[0].pc, 0);
+    assert_eq!(struct_logs[0].depth, 1);
+    assert!(struct_logs.iter().any(|log| log.depth == 2));
+    assert!(struct_logs
+        .iter()
+        .any(|log| log.op == "SLOAD" && log.storage.is_some()));
+
+    json["structLogOptions"] = serde_json::json!({ "limit": 10, "disableStack": true });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    let struct_logs = body.struct_logs.unwrap();
+
+    assert_eq!(struct_logs.len(), 10);
+    assert!(struct_logs[0].stack.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulation_history() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&format!("/simulations/{}", body.simulation_id))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let record: SimulationRecord = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(record.id, body.simulation_id);
+    assert_eq!(record.response, body);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations?from=0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let records: Vec<SimulationRecord> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(records.iter().any(|record| record.id == body.simulation_id));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/00000000-0000-0000-0000-000000000000")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulations_export() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/export")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["content-type"], "application/x-ndjson");
+
+    let exported: Vec<ExportedSimulation> = std::str::from_utf8(res.body())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let simulation = exported
+        .iter()
+        .find(|simulation| simulation.id == body.simulation_id)
+        .expect("simulation should be exported");
+
+    assert_eq!(simulation.chain_id, 1);
+    assert_eq!(simulation.gas_used, 21000);
+    assert_eq!(simulation.success, true);
+    assert!(simulation.addresses.contains(
+        &"0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"
+            .parse()
+            .unwrap()
+    ));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/export?format=csv&since=0")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let csv = std::str::from_utf8(res.body()).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("id,createdAt,chainId,blockNumber,from,to,success,gasUsed,addresses")
+    );
+    assert!(lines.any(|line| line.starts_with(&body.simulation_id.to_string())));
+
+    // Nothing was simulated in the future
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/export?since=99999999999")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    assert!(res.body().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulations_diff() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16784600,
+      "stateDiff": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    let base: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    // Compares the stored deposit with a larger one simulated for the diff
+    let mut compare = json.clone();
+    compare["value"] = serde_json::json!("200000");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulations/diff")
+        .json(&serde_json::json!({
+          "base": base.simulation_id,
+          "compare": compare
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationDiff = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.simulation_ids.base, base.simulation_id);
+    assert_eq!(body.success.base, body.success.compare);
+    assert_eq!(body.logs.removed.len(), 1);
+    assert_eq!(body.logs.added.len(), 1);
+    assert!(body.calls.removed.len() == body.calls.added.len());
+    assert!(!body.state_changes.unwrap().is_empty());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulations/diff")
+        .json(&serde_json::json!({
+          "base": "00000000-0000-0000-0000-000000000000",
+          "compare": base.simulation_id
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_replay() {
+    let filter = filter();
+
+    // The first value transfer on mainnet, in block 46147
+    let json = serde_json::json!({
+      "chainId": 1,
+      "txHash": "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/replay")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.block_number, 46147);
+    assert!(body.nested_trace.is_some());
+
+    let body: ReplayResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert!(body.verification.matches);
+    assert_eq!(body.verification.gas_used.simulated, 21000);
+    assert_eq!(body.verification.gas_used.actual, Some(21000));
+    assert_eq!(body.verification.logs.actual, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_replay_diverged() {
+    let json = serde_json::json!({
+      "chainId": 1,
+      "txHash": "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/replay")
+        .json(&json)
+        .reply(&filter())
+        .await;
+
+    let body: ReplayResponse = serde_json::from_slice(&res.body()).unwrap();
+    let recipient = body.simulation.nested_trace.unwrap().to;
+
+    // Code at the recipient makes the transfer fail, unlike on chain
+    let mut config = get_config();
+    config.precompile_stubs = vec![PrecompileStub {
+        chain_id: 1,
+        address: recipient,
+        name: None,
+        functions: vec![],
+    }];
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/replay")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: ReplayResponse = serde_json::from_slice(&res.body()).unwrap();
+    let verification = body.verification;
+
+    assert!(!verification.matches);
+    assert_eq!(verification.success.simulated, false);
+    assert_eq!(verification.success.actual, Some(true));
+    assert!(!verification.success.matches);
+    assert_ne!(verification.gas_used.simulated, 21000);
+    assert_eq!(verification.gas_used.actual, Some(21000));
+    assert!(!verification.gas_used.matches);
+    // Neither has logs, so those still match
+    assert!(verification.logs.matches);
+    assert_eq!(verification.logs.first_divergence, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_replay_unknown_transaction() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "txHash": "0x0000000000000000000000000000000000000000000000000000000000000000"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/replay")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "TRANSACTION_NOT_FOUND".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_user_operation_account_not_deployed() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "entryPoint": "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789",
+      "userOperation": {
+        "sender": "0x1111111111111111111111111111111111111111",
+        "nonce": "0x0",
+        "initCode": "0x",
+        "callData": "0x",
+        "callGasLimit": "0x30d40",
+        "verificationGasLimit": "0x30d40",
+        "preVerificationGas": "0xc350",
+        "maxFeePerGas": "0x0",
+        "maxPriorityFeePerGas": "0x0",
+        "paymasterAndData": "0x",
+        "signature": "0x"
+      },
+      "blockNumber": 17500000
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/user-operation")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: UserOperationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.validation.valid, false);
+    assert_eq!(
+        body.validation.reason,
+        Some("AA20 account not deployed".to_string())
+    );
+    assert!(body.gas_estimates.pre_verification_gas > 21000 + 18300);
+    assert!(body.execution.is_none());
+}
+
+fn authenticated_filter(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone {
+    let mut config = get_config();
+    config.api_keys = ["test-key".to_string()].into_iter().collect();
+
+    warp::any()
+        .and(with_api_key(config.api_keys.clone()))
+        .and(simulate_routes(config))
+        .recover(handle_rejection)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulation_missing_api_key() {
+    let filter = authenticated_filter();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/00000000-0000-0000-0000-000000000000")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 401);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "MISSING_API_KEY".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulation_invalid_api_key() {
+    let filter = authenticated_filter();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/00000000-0000-0000-0000-000000000000")
+        .header("X-API-KEY", "wrong-key")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 403);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_API_KEY".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulation_valid_api_key() {
+    let filter = authenticated_filter();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/00000000-0000-0000-0000-000000000000")
+        .header("X-API-KEY", "test-key")
+        .reply(&filter)
+        .await;
+
+    // Authenticated, the simulation just doesn't exist
+    assert_eq!(res.status(), 404);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_api_key_policy() {
+    let mut config = get_config();
+    config.api_key_policies.insert(
+        "tenant-key".to_string(),
+        ApiKeyPolicy {
+            chains: vec![1],
+            max_gas_limit: Some(100000),
+            max_bundle_size: Some(1),
+            persistent_forks: false,
+        },
+    );
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let transaction = serde_json::json!({
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    let request = |path: &str, json: &serde_json::Value| {
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("X-API-KEY", "tenant-key")
+            .json(json)
+    };
+
+    // Keys without a policy keep the limits of the server
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&transaction)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = request("/simulate", &transaction).reply(&filter).await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "GAS_LIMIT_TOO_HIGH".to_string());
+
+    let mut on_polygon = transaction.clone();
+    on_polygon["chainId"] = serde_json::json!(137);
+    let res = request("/simulate", &on_polygon).reply(&filter).await;
+
+    assert_eq!(res.status(), 403);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "CHAIN_NOT_ALLOWED".to_string());
+
+    let res = request(
+        "/simulate-bundle",
+        &serde_json::json!([transaction, transaction]),
+    )
+    .reply(&filter)
+    .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "BUNDLE_TOO_LARGE".to_string());
+
+    let res = request(
+        "/fork",
+        &serde_json::json!({ "chainId": 1, "blockNumber": 16784600 }),
+    )
+    .reply(&filter)
+    .await;
+
+    assert_eq!(res.status(), 403);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "FORKS_NOT_ALLOWED".to_string());
+
+    // Async jobs run under the policy of the key they were queued with
+    let res = request("/simulate-async", &on_polygon).reply(&filter).await;
+
+    assert_eq!(res.status(), 202);
+
+    let job: Job = serde_json::from_slice(&res.body()).unwrap();
+    let mut error = None;
+    for _ in 0..60 {
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/jobs/{}", job.job_id))
+            .reply(&filter)
+            .await;
+        let job: Job = serde_json::from_slice(&res.body()).unwrap();
+        if job.status == JobStatus::Failed {
+            error = job.error;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    assert_eq!(error.unwrap().message, "CHAIN_NOT_ALLOWED".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grpc_api_key_policy() {
+    let mut config = get_config();
+    config.api_key_policies.insert(
+        "tenant-key".to_string(),
+        ApiKeyPolicy {
+            chains: vec![1],
+            max_gas_limit: Some(100000),
+            ..Default::default()
+        },
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let service = GrpcService::new(Simulator::new(config)).server(Default::default());
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+    );
+
+    let mut client = SimulatorClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let transfer = GrpcSimulationRequest {
+        chain_id: 137,
+        from: "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045".to_string(),
+        to: Some("0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3".to_string()),
+        gas_limit: 21000,
+        ..Default::default()
+    };
+    let with_key = |transfer: GrpcSimulationRequest| {
+        let mut request = tonic::Request::new(transfer);
+        request
+            .metadata_mut()
+            .insert("x-api-key", "tenant-key".parse().unwrap());
+        request
+    };
+
+    let status = client
+        .simulate(with_key(transfer.clone()))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    assert_eq!(status.message(), "CHAIN_NOT_ALLOWED");
+
+    let status = client
+        .simulate(with_key(GrpcSimulationRequest {
+            chain_id: 1,
+            gas_limit: 500000,
+            block_number: Some(16968595),
+            ..transfer
+        }))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(status.message(), "GAS_LIMIT_TOO_HIGH");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulation_rate_limited() {
+    let filter = warp::any()
+        .and(with_rate_limit(RateLimiter::new(1)))
+        .and(simulate_routes(get_config()))
+        .recover(handle_rejection);
+
+    let request = || {
+        warp::test::request()
+            .method("GET")
+            .path("/simulations/00000000-0000-0000-0000-000000000000")
+            .header("X-API-KEY", "test-key")
+    };
+
+    let res = request().reply(&filter).await;
+    assert_eq!(res.status(), 404);
+
+    let res = request().reply(&filter).await;
+    assert_eq!(res.status(), 429);
+    assert_eq!(res.headers()["retry-after"], "60");
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "RATE_LIMITED".to_string());
+
+    // Unknown keys don't get a bucket of their own
+    let res = request()
+        .header("X-API-KEY", "other-key")
+        .reply(&filter)
+        .await;
+    assert_eq!(res.status(), 429);
+
+    let filter = warp::any()
+        .and(with_rate_limit(
+            RateLimiter::new(1).with_api_keys(["test-key".to_string()].into()),
+        ))
+        .and(simulate_routes(get_config()))
+        .recover(handle_rejection);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulations/00000000-0000-0000-0000-000000000000")
+        .reply(&filter)
+        .await;
+    assert_eq!(res.status(), 404);
+
+    let res = request().reply(&filter).await;
+    assert_eq!(res.status(), 404);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_queue_full() {
+    let queue = AdmissionQueue::new(1, 1);
+    let filter = warp::any()
+        .and(simulate_routes_with_admission(get_config(), queue.clone()))
+        .recover(handle_rejection);
+
+    let simulate = || {
+        warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&serde_json::json!({
+              "chainId": 1,
+              "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+              "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+              "gasLimit": 21000,
+              "value": "1000000000000000000",
+              "blockNumber": 16968595
+            }))
+    };
+    let get_simulation = || {
+        warp::test::request()
+            .method("GET")
+            .path("/simulations/00000000-0000-0000-0000-000000000000")
+    };
+
+    // One request running and one waiting fill the queue
+    let running = queue.admit().unwrap();
+    let waiting = queue.admit().unwrap();
+
+    let res = simulate().reply(&filter).await;
+    assert_eq!(res.status(), 503);
+    assert_eq!(res.headers()["retry-after"], "1");
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "QUEUE_FULL".to_string());
+
+    // Requests which don't execute the EVM don't wait for it
+    let res = get_simulation().reply(&filter).await;
+    assert_eq!(res.status(), 404);
+
+    drop((running, waiting));
+
+    let res = simulate().reply(&filter).await;
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grpc_admission_and_rate_limit() {
+    let queue = AdmissionQueue::new(1, 1);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let service = GrpcService::new(Simulator::new(get_config()))
+        .with_admission(queue.clone())
+        .with_rate_limit(RateLimiter::new(2))
+        .server(Default::default());
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+    );
+
+    let mut client = SimulatorClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let transfer = GrpcSimulationRequest {
+        chain_id: 1,
+        from: "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045".to_string(),
+        to: Some("0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3".to_string()),
+        gas_limit: 21000,
+        value: Some("1000000000000000000".to_string()),
+        block_number: Some(16968595),
+        ..Default::default()
+    };
+
+    // Calls share the queue of the HTTP API
+    let running = queue.admit().unwrap();
+    let waiting = queue.admit().unwrap();
+
+    let status = client.simulate(transfer.clone()).await.unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(status.message(), "QUEUE_FULL");
+
+    drop((running, waiting));
+
+    let response = client.simulate(transfer.clone()).await.unwrap();
+    assert!(response.into_inner().success);
+
+    // Both calls took a token, admitted or not
+    let status = client.simulate(transfer).await.unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(status.message(), "RATE_LIMITED");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_too_large() {
+    let mut config = get_config();
+    config.max_bundle_size = 1;
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let transaction = serde_json::json!({
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000"
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&serde_json::json!([transaction, transaction]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "BUNDLE_TOO_LARGE".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_body_limit() {
+    let mut config = get_config();
+    config.max_bundle_size = 20;
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let transaction = |data_size: usize| {
+        serde_json::json!({
+          "chainId": 1,
+          "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+          "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+          "data": format!("0xd0e30db0{}", "00".repeat(data_size)),
+          "gasLimit": 500000,
+          "value": "100000"
+        })
+    };
+
+    // Over the limit of a single transaction, but not of 20 of them: the bundle is read, then
+    // rejected for its number of transactions before executing
+    for path in ["/simulate-bundle", "/simulate-batch"] {
+        let res = warp::test::request()
+            .method("POST")
+            .path(path)
+            .json(&vec![transaction(1024); 21])
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 400);
+
+        let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+        assert_eq!(body.message, "BUNDLE_TOO_LARGE".to_string());
+    }
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .header("accept", "application/x-ndjson")
+        .json(&vec![transaction(1024); 21])
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&vec![transaction(16 * 1024); 21])
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 413);
+
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.message, "PAYLOAD_TOO_LARGE".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_prefetch() {
+    // The transfer spends the ether wrapped by the deposit, so it reverts when executed
+    // speculatively against the forked block, which must not change the bundle's outcome
+    let json = serde_json::json!([{
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16976359
+    }, {
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xa9059cbb00000000000000000000000093621dca56fe26cdee86e4f6b18e116e9758ff1100000000000000000000000000000000000000000000000000000000000186a0",
+      "gasLimit": 500000,
+      "blockNumber": 16976359
+    }]);
+
+    let mut bodies = vec![];
+    for bundle_prefetch in [0, 4] {
+        let mut config = get_config();
+        config.bundle_prefetch = bundle_prefetch;
+        let filter = warp::any()
+            .and(simulate_routes(config))
+            .recover(handle_rejection);
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate-bundle")
+            .json(&json)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let body: Vec<SimulationResponse> = serde_json::from_slice(&res.body()).unwrap();
+
+        assert_eq!(body.len(), 2);
+        assert_eq!(body[0].success, true);
+        assert_eq!(body[1].success, true);
+        bodies.push(body);
+    }
+
+    assert_eq!(bodies[0][1].gas_used, bodies[1][1].gas_used);
+    assert_eq!(bodies[0][1].logs, bodies[1][1].logs);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_ndjson() {
+    let filter = filter();
+
+    let json = serde_json::json!({
+      "transactions": [{
+        "chainId": 1,
+        "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+        "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+        "data": "0xd0e30db0",
+        "gasLimit": 500000,
+        "value": "100000",
+        "blockNumber": 16976359
+      }, {
+        "chainId": 1,
+        "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+        "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+        "data": "0xa9059cbb00000000000000000000000093621dca56fe26cdee86e4f6b18e116e9758ff1100000000000000000000000000000000000000000000000000000000000186a0",
+        "gasLimit": 500000,
+        "blockNumber": 16976359
+      }]
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .header("accept", "application/x-ndjson")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["content-type"], "application/x-ndjson");
+
+    let events: Vec<BundleEvent> = std::str::from_utf8(res.body())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(events.len(), 3);
+    let mut gas_used = 0;
+    for (index, event) in events[..2].iter().enumerate() {
+        match event {
+            BundleEvent::Transaction {
+                transaction,
+                status,
+                result,
+                ..
+            } => {
+                assert_eq!(*transaction, index);
+                assert_eq!(*status, TransactionStatus::Success);
+                gas_used += result.as_ref().expect("executed").gas_used;
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+    match &events[2] {
+        BundleEvent::Summary {
+            rolled_back,
+            bundle_summary,
+            ..
+        } => {
+            assert_eq!(*rolled_back, false);
+            assert_eq!(bundle_summary.total_gas_used, gas_used);
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+
+    // Without the header the bundle is answered as a whole
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.results.len(), 2);
+    assert_eq!(body.bundle_summary.total_gas_used, gas_used);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_concurrency_limit() {
+    let mut config = get_config();
+    config.max_concurrency = 1;
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let transaction = serde_json::json!({
+      "chainId": 1,
+      "from": "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e",
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    // The second simulation waits for the first one to finish
+    let (first, second) = tokio::join!(
+        warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&transaction)
+            .reply(&filter),
+        warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&transaction)
+            .reply(&filter)
+    );
+
+    assert_eq!(first.status(), 200);
+    assert_eq!(second.status(), 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_metrics() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: SimulationRequest =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/metrics")
+        .reply(&metrics())
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+
+    assert!(body.contains("ts_simulations_total{chain_id=\"1\",status=\"success\"}"));
+    assert!(body.contains("ts_simulation_duration_seconds_bucket"));
+    assert!(body.contains("ts_fork_duration_seconds_bucket"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pool_latest_block() {
+    let config = get_config();
+    let fork_url = config.chains[&1][0].clone();
+    // Counted apart from the forks of the other tests
+    let chain_id = 1_000_001;
+    let pool = EvmPool::new(4, 1);
+
+    let first = pool.get(chain_id, fork_url.clone(), None, 21000, None);
+    let second = pool.get(chain_id, fork_url, None, 21000, None);
+
+    assert_eq!(first.block_number(), second.block_number());
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/metrics")
+        .reply(&metrics())
+        .await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+
+    assert!(body.contains("ts_pool_requests_total{chain_id=\"1000001\",result=\"miss\"} 1"));
+    assert!(body.contains("ts_pool_requests_total{chain_id=\"1000001\",result=\"hit\"} 1"));
+    assert!(!body.contains("ts_pool_requests_total{chain_id=\"1000001\",result=\"latest\"}"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_health_and_ready() {
+    let res = warp::test::request()
+        .method("GET")
+        .path("/health")
+        .reply(&health())
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let mut config = get_config();
+    config.chains.retain(|chain_id, _| *chain_id == 1);
+    config.etherscan_key = None;
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/ready")
+        .reply(&ready(config.clone()))
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: ReadinessResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.ready, true);
+    assert_eq!(body.chains[&1].ok, true);
+    assert!(body.etherscan.is_none());
+
+    config
+        .chains
+        .insert(1, vec!["http://127.0.0.1:1".to_string()]);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/ready")
+        .reply(&ready(config))
+        .await;
+
+    assert_eq!(res.status(), 503);
+
+    let body: ReadinessResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.ready, false);
+    assert_eq!(body.chains[&1].ok, false);
+    assert!(body.chains[&1].error.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_fork_cache() {
+    let path = std::env::temp_dir().join(format!("fork-cache-{}.db", std::process::id()));
+    let mut config = get_config();
+    config.fork_cache = Some(path.to_str().unwrap().to_string());
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: SimulationRequest =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let mut gas_used = Vec::new();
+    for _ in 0..2 {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&json)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+        gas_used.push(body.gas_used);
+    }
+
+    assert_eq!(gas_used[0], gas_used[1]);
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let cached: u64 = connection
+        .query_row("SELECT COUNT(*) FROM rpc_cache", [], |row| row.get(0))
+        .unwrap();
+
+    assert!(cached > 0);
+
+    std::fs::remove_file(path).ok();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_rpc_failover() {
+    let mut config = get_config();
+    let urls = config.chains.get_mut(&1).unwrap();
+    // Nothing listens there, so every request fails over to the configured RPC
+    urls.insert(0, "http://127.0.0.1:1".to_string());
+    let filter = warp::any()
+        .and(simulate_routes(config))
+        .recover(handle_rejection);
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: SimulationRequest =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn simulate_stream() {
+    let filter = filter();
+
+    let file = File::open("tests/body.json").expect("file should open read only");
+    let json: SimulationRequest =
+        serde_json::from_reader(file).expect("file should be proper JSON");
+
+    let mut client = warp::test::ws()
+        .path("/simulate/stream")
+        .handshake(filter)
+        .await
+        .expect("handshake should succeed");
+
+    client
+        .send_text(serde_json::to_string(&vec![json]).unwrap())
+        .await;
+
+    let mut calls = 0;
+    let mut results = 0;
+    loop {
+        let message = client.recv().await.expect("socket should stay open");
+        let event: StreamEvent = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        match event {
+            StreamEvent::Call { transaction, .. } => {
+                assert_eq!(transaction, 0);
+                calls += 1;
+            }
+            StreamEvent::Log { .. } => {}
+            StreamEvent::Result {
+                transaction,
+                result,
+            } => {
+                assert_eq!(transaction, 0);
+                assert_eq!(result.success, true);
+                results += 1;
+            }
+            StreamEvent::Summary {
+                transactions,
+                success,
+                ..
+            } => {
+                assert_eq!(transactions, 1);
+                assert_eq!(success, true);
+                break;
+            }
+            StreamEvent::Error { error } => panic!("unexpected error {error:?}"),
+        }
+    }
+
+    assert!(calls > 0);
+    assert_eq!(results, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_atomically() {
+    let filter = filter();
+
+    let transfer = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    // Transfer of 1 USDC from an account without any
+    let reverting = serde_json::json!({
+      "chainId": 1,
+      "from": "0x0000000000000000000000000000000000000001",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000
+    });
+    let json = serde_json::json!({
+      "transactions": [transfer, reverting, transfer],
+      "bundleOptions": { "atomically": true }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.rolled_back, true);
+    assert_eq!(body.results.len(), 2);
+    assert_eq!(
+        body.statuses
+            .iter()
+            .map(|status| status.status)
+            .collect::<Vec<_>>(),
+        vec![
+            TransactionStatus::RolledBack,
+            TransactionStatus::Reverted,
+            TransactionStatus::Skipped
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_state_overrides() {
+    let filter = filter();
+
+    let sender = "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e";
+    // WETH balances are in the mapping at slot 3
+    let balance_slot = ethers::types::H256::from(ethers::utils::keccak256(ethers::abi::encode(&[
+        ethers::abi::Token::Address(sender.parse().unwrap()),
+        ethers::abi::Token::Uint(3.into()),
+    ])));
+
+    let deposit = serde_json::json!({
+      "chainId": 1,
+      "from": sender,
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0xd0e30db0",
+      "gasLimit": 500000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    // Withdraws the deposit after its WETH balance was wiped, between the two transactions
+    let withdraw = serde_json::json!({
+      "chainId": 1,
+      "from": sender,
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0x2e1a7d4d00000000000000000000000000000000000000000000000000000000000186a0",
+      "gasLimit": 500000,
+      "stateOverrides": {
+        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2": {
+          "storage": {
+            (format!("{balance_slot:?}")): "0x0000000000000000000000000000000000000000000000000000000000000000"
+          }
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&serde_json::json!([deposit, withdraw]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body[0].success, true);
+    assert_eq!(body[1].success, false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_internal_transfers() {
+    let filter = filter();
+
+    let sender = "0xdcd49c36e69bf85fa9c5a25dea9455602c0b289e";
+    let balance_slot = ethers::types::H256::from(ethers::utils::keccak256(ethers::abi::encode(&[
+        ethers::abi::Token::Address(sender.parse().unwrap()),
+        ethers::abi::Token::Uint(3.into()),
+    ])));
+
+    // WETH sends the withdrawn ether back to the sender
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": sender,
+      "to": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+      "data": "0x2e1a7d4d00000000000000000000000000000000000000000000000000000000000186a0",
+      "gasLimit": 500000,
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2": {
+          "storage": {
+            (format!("{balance_slot:?}")): "0x00000000000000000000000000000000000000000000000000000000000186a0"
+          }
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+    assert_eq!(body.internal_transfers.len(), 1);
+    assert_eq!(
+        body.internal_transfers[0].from,
+        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"
+            .parse::<Address>()
+            .unwrap()
+    );
+    assert_eq!(
+        body.internal_transfers[0].to,
+        sender.parse::<Address>().unwrap()
+    );
+    assert_eq!(body.internal_transfers[0].value, 100000.into());
+    assert_eq!(body.internal_transfers[0].depth, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_continue_on_failure() {
+    let filter = filter();
+
+    let transfer = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+    let reverting = serde_json::json!({
+      "chainId": 1,
+      "from": "0x0000000000000000000000000000000000000001",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000,
+      "blockNumber": 16784600
+    });
+    let json = serde_json::json!({
+      "transactions": [reverting, transfer],
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: BundleResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.rolled_back, false);
+    assert_eq!(body.results.len(), 2);
+    assert_eq!(body.statuses[0].status, TransactionStatus::Reverted);
+    assert_eq!(body.statuses[1].status, TransactionStatus::Success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_warnings() {
+    let filter = filter();
+
+    // approve(0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045, type(uint256).max) on USDC
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x0000000000000000000000000000000000001234",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0x095ea7b3000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "warnings": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+    // USDC delegatecalls its verified implementation, so the approval is the only warning
+    let warnings = body.warnings.unwrap();
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::UnlimitedApproval);
+    assert_eq!(
+        warnings[0].address,
+        "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"
+            .parse::<Address>()
+            .unwrap()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_decode_calls() {
+    let filter = filter();
+
+    // Transfer of 1 USDC from Binance 14 to vitalik.eth
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x28c6c06298d514db089934071355e5743bf21d60",
+      "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+      "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+      "gasLimit": 100000,
+      "blockNumber": 16784600,
+      "decodeCalls": true,
+      "fourByteLookup": true,
+      "nestTrace": true
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let decoded_call = body.trace[0].decoded_call.clone().unwrap();
+    assert_eq!(decoded_call.name, "transfer".to_string());
+    assert_eq!(
+        decoded_call.signature,
+        "transfer(address,uint256)".to_string()
+    );
+    assert_eq!(decoded_call.params.len(), 2);
+    assert_eq!(
+        decoded_call.params[0].value,
+        "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045".to_string()
+    );
+    assert_eq!(decoded_call.params[1].value, "1000000".to_string());
+    assert_eq!(body.nested_trace.unwrap().decoded_call, Some(decoded_call));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_fork_cheatcodes() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+    let holder = "0x0000000000000000000000000000000000001234";
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/set-balance", fork.fork_id))
+        .json(&serde_json::json!({
+          "address": holder,
+          "balance": "0xde0b6b3a7640000"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    // USDC balances are in the mapping at slot 9 of the proxy
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/deal", fork.fork_id))
+        .json(&serde_json::json!({
+          "token": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+          "holder": holder,
+          "amount": "0xf4240"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let deal: DealResponse = serde_json::from_slice(&res.body()).unwrap();
+    let expected_slot = ethers::utils::keccak256(ethers::abi::encode(&[
+        ethers::abi::Token::Address(holder.parse().unwrap()),
+        ethers::abi::Token::Uint(9.into()),
+    ]));
+
+    assert_eq!(deal.slot, expected_slot.into());
+
+    // Sends the whole USDC balance, paying for gas with the balance set above
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/simulate", fork.fork_id))
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": holder,
+          "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+          "data": "0xa9059cbb000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa9604500000000000000000000000000000000000000000000000000000000000f4240",
+          "gasLimit": 100000,
+          "gasPrice": "0x3b9aca00"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.success, true);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/set-code", fork.fork_id))
+        .json(&serde_json::json!({
+          "address": holder,
+          "code": "0x00"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_fork_token_balances() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+    let holder = "0x0000000000000000000000000000000000001234";
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/deal", fork.fork_id))
+        .json(&serde_json::json!({
+          "token": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+          "holder": holder,
+          "amount": "0xf4240"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    // The holder itself isn't a token, nor is USDC an ERC-721
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/token-balances", fork.fork_id))
+        .json(&serde_json::json!({
+          "holder": holder,
+          "tokens": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", holder],
+          "nfts": [{ "token": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "tokenId": 1 }]
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: TokenBalancesResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(body.tokens.len(), 2);
+    assert_eq!(body.tokens[0].balance, Some(0xf4240.into()));
+    assert_eq!(
+        body.tokens[0]
+            .token_info
+            .as_ref()
+            .and_then(|info| info.decimals),
+        Some(6)
+    );
+    assert_eq!(body.tokens[1].balance, None);
+    assert!(body.tokens[1].error.is_some());
+    assert_eq!(body.nfts.len(), 1);
+    assert_eq!(body.nfts[0].owned, false);
+    assert!(body.nfts[0].error.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_fork_snapshot_and_revert() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+    let holder = "0x0000000000000000000000000000000000001234";
+    let balance_path = format!("/fork/{}/balance?address={holder}", fork.fork_id);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&balance_path)
+        .reply(&filter)
+        .await;
+
+    let original: BalanceResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/snapshot", fork.fork_id))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    let snapshot: SnapshotResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(snapshot.snapshot_id, 1);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/set-balance", fork.fork_id))
+        .json(&serde_json::json!({
+          "address": holder,
+          "balance": "0xde0b6b3a7640000"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&balance_path)
+        .reply(&filter)
+        .await;
+
+    let balance: BalanceResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(balance.balance, ethers::utils::WEI_IN_ETHER);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/revert", fork.fork_id))
+        .json(&serde_json::json!({ "snapshotId": snapshot.snapshot_id }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 204);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(&balance_path)
+        .reply(&filter)
+        .await;
+
+    let reverted: BalanceResponse = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(reverted, original);
+
+    // Reverting dropped the snapshot
+    let res = warp::test::request()
+        .method("POST")
+        .path(&format!("/fork/{}/revert", fork.fork_id))
+        .json(&serde_json::json!({ "snapshotId": snapshot.snapshot_id }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+    let body: ErrorMessage = serde_json::from_slice(&res.body()).unwrap();
+    assert_eq!(body.message, "SNAPSHOT_NOT_FOUND");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_fork_rpc() {
+    let filter = filter();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fork")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    let fork: ForkResponse = serde_json::from_slice(&res.body()).unwrap();
+    let path = format!("/fork/{}/rpc", fork.fork_id);
+
+    // decimals() of USDC
+    let res = warp::test::request()
+        .method("POST")
+        .path(&path)
+        .json(&serde_json::json!([
+          { "jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": [] },
+          {
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "eth_call",
+            "params": [
+              { "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "data": "0x313ce567" },
+              "latest"
+            ]
+          },
+          { "jsonrpc": "2.0", "id": 3, "method": "eth_mining", "params": [] }
+        ]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let responses: Vec<RpcResponse> = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(responses[0].result, Some(serde_json::json!("0x1")));
+    assert_eq!(
+        responses[1].result,
+        Some(serde_json::json!(
+            "0x0000000000000000000000000000000000000000000000000000000000000006"
+        ))
+    );
+    assert_eq!(responses[2].error.as_ref().unwrap().code, -32601);
+
+    let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse()
+        .unwrap();
+    let wallet = wallet.with_chain_id(1u64);
+    let transaction: TypedTransaction = TransactionRequest::new()
+        .to("0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+            .parse::<Address>()
+            .unwrap())
+        .value(0)
+        .gas(21000)
+        .gas_price(0)
+        .nonce(0)
+        .chain_id(1)
+        .into();
+    let signature = wallet.sign_transaction_sync(&transaction).unwrap();
+    let raw_transaction = transaction.rlp_signed(&signature);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&path)
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0",
+          "id": 1,
+          "method": "eth_sendRawTransaction",
+          "params": [raw_transaction]
+        }))
+        .reply(&filter)
+        .await;
+
+    let response: RpcResponse = serde_json::from_slice(&res.body()).unwrap();
+
+    assert_eq!(
+        response.result,
+        Some(serde_json::json!(ethers::types::H256::from(
+            ethers::utils::keccak256(&raw_transaction)
+        )))
+    );
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(&path)
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0",
+          "id": 1,
+          "method": "debug_traceCall",
+          "params": [
+            { "to": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "data": "0x313ce567" },
+            "latest",
+            { "disableStorage": true }
+          ]
+        }))
+        .reply(&filter)
+        .await;
+
+    let response: RpcResponse = serde_json::from_slice(&res.body()).unwrap();
+    let trace: StructLogTrace = serde_json::from_value(response.result.unwrap()).unwrap();
+
+    assert!(!trace.failed);
+    assert!(!trace.struct_logs.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn simulator_simulate_bundle() {
+    let simulator = Simulator::new(get_config());
+
+    let transfer = SimulationRequest {
+        chain_id: 1,
+        from: "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+            .parse()
+            .unwrap(),
+        to: Some(
+            "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3"
+                .parse()
+                .unwrap(),
+        ),
+        gas_limit: 21000,
+        value: Some("1000000000000000000".to_string()),
+        block_number: Some(16968595),
+        ..Default::default()
+    };
+
+    let response = simulator.simulate(transfer.clone()).await.unwrap();
+
+    assert!(response.success);
+    assert_eq!(response.gas_used, 21000);
+
+    let response = simulator
+        .simulate_bundle(
+            vec![transfer.clone(), transfer.clone()],
+            BundleOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.results.len(), 2);
+    assert!(response.results.iter().all(|response| response.success));
+    assert!(response
+        .statuses
+        .iter()
+        .all(|status| status.status == TransactionStatus::Success));
+    assert_eq!(response.bundle_summary.total_gas_used, 42000);
+
+    // The second transfer can't pay for itself once the first one spent the balance
+    let expensive = SimulationRequest {
+        value: Some("1000000000000000000000000000".to_string()),
+        ..transfer.clone()
+    };
+    let response = simulator
+        .simulate_bundle(
+            vec![transfer.clone(), expensive],
+            BundleOptions {
+                atomically: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    assert!(response.rolled_back);
+    assert_eq!(response.statuses[0].status, TransactionStatus::RolledBack);
+    assert_eq!(response.statuses[1].status, TransactionStatus::Error);
+
+    let err = simulator
+        .simulate_bundle(vec![], BundleOptions::default())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, SimulationError::EmptyBundle));
+
+    let err = simulator
+        .simulate(SimulationRequest {
+            chain_id: 123456789,
+            ..transfer
+        })
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        SimulationError::ChainIdNotSupported(123456789)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cli_parse_args() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(cli::parse_args(&args(&[])), Some(Command::Serve));
+    assert_eq!(
+        cli::parse_args(&args(&["simulate"])),
+        Some(Command::Simulate(None))
+    );
+    assert_eq!(
+        cli::parse_args(&args(&["simulate", "-"])),
+        Some(Command::Simulate(None))
+    );
+    assert_eq!(
+        cli::parse_args(&args(&["simulate", "request.json"])),
+        Some(Command::Simulate(Some("request.json".to_string())))
+    );
+    assert_eq!(cli::parse_args(&args(&["--help"])), Some(Command::Help));
+    assert_eq!(cli::parse_args(&args(&["simulate", "a", "b"])), None);
+    assert_eq!(cli::parse_args(&args(&["serve"])), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cli_simulate() {
+    let simulator = Simulator::new(get_config());
+
+    let transfer = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3",
+      "gasLimit": 21000,
+      "value": "1000000000000000000",
+      "blockNumber": 16968595,
+      "quantityFormat": "hex"
+    });
+
+    let response = cli::simulate(&simulator, &transfer.to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(response["success"], true);
+    assert_eq!(response["gasUsed"], "0x5208");
+
+    let bundle = serde_json::json!([transfer, transfer]);
+    let responses = cli::simulate(&simulator, &bundle.to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(responses.as_array().unwrap().len(), 2);
+
+    let err = cli::simulate(&simulator, "{").await.unwrap_err();
+
+    assert!(matches!(err, CommandError::InvalidJson(_)));
+
+    let err = cli::simulate(&simulator, r#"{"chainId": 1}"#)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, CommandError::InvalidRequest(_)));
+
+    let err = cli::simulate(&simulator, r#"[{"chainId": 1}]"#)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, CommandError::InvalidBundle(_)));
+
+    let mut unsupported = transfer.clone();
+    unsupported["chainId"] = serde_json::json!(123456789);
+    let err = cli::simulate(&simulator, &unsupported.to_string())
+        .await
+        .unwrap_err();
+
+    match err {
+        CommandError::Simulation(error) => {
+            assert_eq!(error.code, 400);
+            assert_eq!(error.message, "CHAIN_ID_NOT_SUPPORTED");
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_simulate_on_fork() {
+    use transaction_simulator::client::{ClientError, SimulatorClient as HttpClient};
+
+    let (address, server) = warp::serve(filter()).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let client = HttpClient::new(format!("http://{address}"));
+
+    let transfer = SimulationRequest {
+        chain_id: 1,
+        from: "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+            .parse()
+            .unwrap(),
+        to: Some(
+            "0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3"
+                .parse()
+                .unwrap(),
+        ),
+        gas_limit: 21000,
+        value: Some("1000000000000000000".to_string()),
+        block_number: Some(16968595),
+        ..Default::default()
+    };
+
+    let response = client.simulate(&transfer).await.unwrap();
+
+    assert!(response.success);
+    assert_eq!(response.gas_used, 21000);
+
+    let fork = client.create_fork(1, Some(16968595)).await.unwrap();
+    let balance = client
+        .get_fork_balance(fork.fork_id, transfer.to.unwrap())
+        .await
+        .unwrap();
+    let response = client
+        .simulate_on_fork(fork.fork_id, &transfer)
+        .await
+        .unwrap();
+
+    assert!(response.success);
+    assert_eq!(
+        client
+            .get_fork_balance(fork.fork_id, transfer.to.unwrap())
+            .await
+            .unwrap()
+            .balance,
+        balance.balance + 1_000_000_000_000_000_000u64
+    );
+
+    client.delete_fork(fork.fork_id).await.unwrap();
+    let err = client.delete_fork(fork.fork_id).await.unwrap_err();
+
+    assert!(matches!(
+        err,
+        ClientError::Api { status, ref error }
+            if status == 404 && error.message == "FORK_NOT_FOUND"
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grpc_simulate_bundle() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let service = GrpcService::new(Simulator::new(get_config())).server(Default::default());
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+    );
+
+    let mut client = SimulatorClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let transfer = GrpcSimulationRequest {
+        chain_id: 1,
+        from: "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045".to_string(),
+        to: Some("0x66fe4806cD41BcD308c9d2f6815AEf6b2e38f9a3".to_string()),
+        gas_limit: 21000,
+        value: Some("1000000000000000000".to_string()),
+        block_number: Some(16968595),
+        options_json: r#"{"decodeLogs": true}"#.to_string(),
+        ..Default::default()
+    };
+
+    let response = client
+        .simulate(transfer.clone())
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert!(response.success);
+    assert_eq!(response.gas_used, 21000);
+    assert_eq!(response.block_number, 16968595);
+
+    let json: SimulationResponse = serde_json::from_str(&response.json).unwrap();
+
+    assert_eq!(json.simulation_id.to_string(), response.simulation_id);
+
+    let mut events = client
+        .simulate_bundle(BundleRequest {
+            transactions: vec![transfer.clone(), transfer.clone()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut results = 0;
+    let mut summary = None;
+    while let Some(event) = events.message().await.unwrap() {
+        match event.event.unwrap() {
+            Event::Result(result) => {
+                assert_eq!(result.transaction, results);
+                assert!(result.response.unwrap().success);
+                results += 1;
+            }
+            Event::Summary(event) => summary = Some(event),
+        }
+    }
+
+    assert_eq!(results, 2);
+
+    let summary = summary.unwrap();
+
+    assert_eq!(summary.transactions, 2);
+    assert_eq!(summary.gas_used, 42000);
+    assert!(summary.success);
+
+    let status = client
+        .simulate(GrpcSimulationRequest {
+            chain_id: 123456789,
+            ..transfer
+        })
+        .await
+        .unwrap_err();
 
-    assert_eq!(body.message, "MULTIPLE_BLOCK_NUMBERS".to_string());
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(status.message(), "CHAIN_ID_NOT_SUPPORTED");
 }